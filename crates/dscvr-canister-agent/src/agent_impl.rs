@@ -8,6 +8,8 @@ use std::sync::Arc;
 pub const MAX_ERROR_RETRIES: usize = 3;
 
 pub mod embedded_canister_impl;
+#[cfg(feature = "telemetry")]
+pub mod metered_impl;
 pub mod replica_impl;
 pub mod state_machine_impl;
 
@@ -25,8 +27,18 @@ pub trait AgentImpl: Sync + Send {
         prop: &str,
     ) -> Result<Vec<u8>>;
 
+    /// Verify a system certificate for the canister and return its certified data
+    async fn verify_certified_data(
+        &self,
+        canister_id: &Principal,
+        certificate: &[u8],
+    ) -> Result<Vec<u8>>;
+
     async fn clone_with_identity(&self, identity: Arc<dyn Identity>) -> Result<Arc<dyn AgentImpl>>;
 
+    /// Run the background jobs of the canisters, i.e. the heartbeat and the due timers
+    async fn tick(&self) -> Result<()>;
+
     fn get_principal(&self) -> Result<Principal>;
 }
 
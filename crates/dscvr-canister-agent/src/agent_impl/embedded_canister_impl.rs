@@ -1,23 +1,182 @@
 use candid::Principal;
-use dscvr_canister_context::{ImmutableContext, MutableContext, UpdateContext};
-use dscvr_canister_exports::{CanisterDefinition, CanisterMethod, CanisterUpdateMethod};
+use dscvr_canister_context::scoped_state::sync_scope;
+use dscvr_canister_context::{AsyncContext, ImmutableContext, MutableContext, UpdateContext};
+use dscvr_canister_exports::{CanisterDefinition, CanisterError, CanisterErrorCode};
 use dscvr_interface::edge::Edge;
+use dscvr_interface::{CallResult, Interface, RejectionCode};
+use futures::FutureExt;
 use ic_agent::Identity;
 use instrumented_error::{IntoInstrumentedError, Result};
-use std::sync::{Arc, Mutex};
-use tracing::debug;
+use std::future::Future;
+use std::sync::{Arc, Mutex, Weak};
+use tracing::{debug, warn};
 
 use super::AgentImpl;
 
+/// Canister embedded within the same process, shared by the agents calling it
+struct EmbeddedCanister<State>
+where
+    State: std::marker::Send + 'static,
+{
+    canister_id: Principal,
+    canister: CanisterDefinition<State>,
+    /// State of the canister, in scope of every dispatched message so that the state
+    /// accessors of the canister reach it, see `scoped_state`
+    state: Arc<Mutex<State>>,
+    /// Interface every message to the canister derives from, built once so that the
+    /// clock and call handlers are shared across calls
+    system: Edge,
+}
+
+impl<State> EmbeddedCanister<State>
+where
+    State: std::marker::Send + 'static,
+{
+    /// Dispatch the update `method` on behalf of `caller`
+    fn update(
+        &self,
+        state: &mut State,
+        caller: Principal,
+        method: &str,
+        args: &[u8],
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        let system = self.system.for_message(caller, method, args.len());
+        sync_scope(self.state.clone(), || {
+            self.canister.update(
+                method,
+                MutableContext::new(state, &system),
+                args,
+                UpdateContext::Primary,
+            )
+        })
+    }
+
+    /// Dispatch the update `method` that awaits on behalf of `caller`. The state is only
+    /// locked while the method reads or mutates it.
+    fn async_update(
+        &self,
+        caller: Principal,
+        method: &str,
+        args: &[u8],
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        let system = self.system.for_message(caller, method, args.len());
+        sync_scope(self.state.clone(), || {
+            completed(
+                method,
+                self.canister.async_update(
+                    method,
+                    AsyncContext::new(&*self.state, &system),
+                    args,
+                    UpdateContext::Primary,
+                ),
+            )
+        })
+    }
+
+    /// Dispatch the query or composite query `method` on behalf of `caller`
+    fn query(
+        &self,
+        state: &State,
+        caller: Principal,
+        method: &str,
+        args: &[u8],
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        let system = self.system.for_message(caller, method, args.len());
+        let ctx = ImmutableContext::new(state, &system);
+        sync_scope(self.state.clone(), || {
+            if !self.canister.composite_query_methods.contains_key(method) {
+                return self.canister.query(method, ctx, args);
+            }
+            completed(method, self.canister.composite_query(method, ctx, args))
+        })
+    }
+
+    /// Run the lifecycle method `name` with the locked state on behalf of `caller`
+    fn run_lifecycle(
+        &self,
+        caller: Principal,
+        name: &str,
+        arg_data_size: usize,
+        f: impl FnOnce(&mut State, &dyn Interface),
+    ) {
+        let mut locked_state: std::sync::MutexGuard<State> = self.state.lock().expect("valid");
+        let system = self.system.for_message(caller, name, arg_data_size);
+        sync_scope(self.state.clone(), || f(&mut locked_state, &system));
+    }
+
+    /// Answer an inter-canister call from another embedded canister
+    fn handle_call(&self, caller: Principal, method: &str, args: &[u8]) -> CallResult {
+        if self.canister.async_update_methods.contains_key(method) {
+            return self
+                .async_update(caller, method, args)
+                .map_err(|e| (RejectionCode::CanisterReject, e.to_reject_message()));
+        }
+        // the state is still locked if the canister calls itself
+        let Ok(mut state) = self.state.try_lock() else {
+            return Err((
+                RejectionCode::SysTransient,
+                format!("Canister {} is busy", self.canister_id),
+            ));
+        };
+        let result = if self.canister.update_methods.contains_key(method) {
+            self.update(&mut state, caller, method, args)
+        } else {
+            self.query(&state, caller, method, args)
+        };
+        result.map_err(|e| (RejectionCode::CanisterReject, e.to_reject_message()))
+    }
+
+    /// Answer the calls to the methods of the canister made through the call handlers
+    /// of its interface
+    fn register_call_handlers(self: &Arc<Self>, caller: Principal) {
+        let methods = self
+            .canister
+            .update_methods
+            .keys()
+            .chain(self.canister.async_update_methods.keys())
+            .chain(self.canister.query_methods.keys())
+            .chain(self.canister.composite_query_methods.keys());
+        for method in methods {
+            // the handlers are owned by the interface of the canister, hold it weakly
+            let canister = Arc::downgrade(self);
+            let name = method.clone();
+            self.system.register_call_handler(
+                self.canister_id,
+                method,
+                Arc::new(move |args, _payment| match Weak::upgrade(&canister) {
+                    Some(canister) => canister.handle_call(caller, &name, args),
+                    None => Err((
+                        RejectionCode::DestinationInvalid,
+                        "Embedded canister was dropped".to_owned(),
+                    )),
+                }),
+            );
+        }
+    }
+}
+
+/// Drive a method future of an embedded canister to completion. Embedded calls to other
+/// canisters resolve immediately, so it completes without being driven by an executor.
+fn completed(
+    method: &str,
+    future: impl Future<Output = std::result::Result<Vec<u8>, CanisterError>>,
+) -> std::result::Result<Vec<u8>, CanisterError> {
+    future.now_or_never().unwrap_or_else(|| {
+        Err(CanisterError::new(
+            CanisterErrorCode::Internal,
+            format!("method {method} did not complete"),
+        ))
+    })
+}
+
 /// Implementation that provides a agent-like abstraction a canister that's
 /// embedded within the same process via registered exports
 struct EmbeddedCanisterImpl<State>
 where
     State: std::marker::Send + 'static,
 {
-    canister: Arc<dscvr_canister_exports::CanisterDefinition<State>>,
+    canister: Arc<EmbeddedCanister<State>>,
     caller: Principal,
-    state: Arc<Mutex<State>>,
 }
 
 #[async_trait::async_trait]
@@ -26,41 +185,63 @@ where
     State: std::marker::Send + 'static,
 {
     async fn update(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
-        let method: &CanisterUpdateMethod<State> =
-            self.canister.update_methods.get(method).ok_or_else(|| {
-                format!(
-                    "Canister {} does not have an update method named {}",
-                    canister_id, method
-                )
-                .into_instrumented_error()
-            })?;
+        let is_async = self
+            .canister
+            .canister
+            .async_update_methods
+            .contains_key(method);
+        if !is_async && !self.canister.canister.update_methods.contains_key(method) {
+            return Err(format!(
+                "Canister {} does not have an update method named {}",
+                canister_id, method
+            )
+            .into_instrumented_error());
+        }
+        if let Some(deprecation) = self.canister.canister.deprecation(method) {
+            warn!(
+                "Canister {} method {} is {}",
+                canister_id, method, deprecation
+            );
+        }
 
-        let mut locked_state: std::sync::MutexGuard<State> = self.state.lock().expect("valid");
-        let system = Edge::new_with_caller_and_time(self.caller, None);
-
-        method(
-            MutableContext::new(&mut locked_state, &system),
-            args,
-            UpdateContext::Primary,
-        )
-        .map_err(|e| e.into_instrumented_error())
+        if is_async {
+            return self
+                .canister
+                .async_update(self.caller, method, args)
+                .map_err(instrumented_error::Error::from);
+        }
+        let mut locked_state: std::sync::MutexGuard<State> =
+            self.canister.state.lock().expect("valid");
+        self.canister
+            .update(&mut locked_state, self.caller, method, args)
+            .map_err(instrumented_error::Error::from)
     }
 
     async fn query(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
-        let method: &CanisterMethod<State> =
-            self.canister.query_methods.get(method).ok_or_else(|| {
-                format!(
-                    "Canister {} does not have an query method named {}",
-                    canister_id, method
-                )
-                .into_instrumented_error()
-            })?;
-
-        let locked_state: std::sync::MutexGuard<State> = self.state.lock().expect("valid");
-        let system = Edge::new_with_caller_and_time(self.caller, None);
+        if !self.canister.canister.query_methods.contains_key(method)
+            && !self
+                .canister
+                .canister
+                .composite_query_methods
+                .contains_key(method)
+        {
+            return Err(format!(
+                "Canister {} does not have an query method named {}",
+                canister_id, method
+            )
+            .into_instrumented_error());
+        }
+        if let Some(deprecation) = self.canister.canister.deprecation(method) {
+            warn!(
+                "Canister {} method {} is {}",
+                canister_id, method, deprecation
+            );
+        }
 
-        method(ImmutableContext::new(&locked_state, &system), args)
-            .map_err(|e| e.into_instrumented_error())
+        let locked_state: std::sync::MutexGuard<State> = self.canister.state.lock().expect("valid");
+        self.canister
+            .query(&locked_state, self.caller, method, args)
+            .map_err(instrumented_error::Error::from)
     }
 
     async fn read_state_canister_info(
@@ -71,11 +252,33 @@ where
         todo!();
     }
 
+    async fn verify_certified_data(
+        &self,
+        canister_id: &Principal,
+        _certificate: &[u8],
+    ) -> Result<Vec<u8>> {
+        Err(
+            format!("Embedded canister {canister_id} does not support certified data")
+                .into_instrumented_error(),
+        )
+    }
+
+    async fn tick(&self) -> Result<()> {
+        if let Some(heartbeat) = self.canister.canister.heartbeat {
+            self.canister
+                .run_lifecycle(self.caller, "canister_heartbeat", 0, |state, system| {
+                    heartbeat(MutableContext::new(state, system), UpdateContext::Primary)
+                });
+        }
+        // the global timer handler locks the state itself
+        self.canister.system.clock().tick();
+        Ok(())
+    }
+
     async fn clone_with_identity(&self, identity: Arc<dyn Identity>) -> Result<Arc<dyn AgentImpl>> {
         Ok(Arc::new(Self {
             canister: self.canister.clone(),
             caller: identity.sender().map_err(|e| e.into_instrumented_error())?,
-            state: self.state.clone(),
         }))
     }
 
@@ -84,11 +287,16 @@ where
     }
 }
 
+/// Embed `canister` as `canister_id`, deriving the interface of every message from
+/// `system`. The canister answers the calls made through the call handlers of `system`,
+/// so embedded canisters sharing them can call each other.
 pub fn new<State>(
     caller: Principal,
+    canister_id: Principal,
     canister: CanisterDefinition<State>,
     init_arguments: Vec<u8>,
-    mut state: State,
+    state: State,
+    system: Edge,
 ) -> Arc<dyn AgentImpl>
 where
     State: std::marker::Send + 'static,
@@ -96,16 +304,157 @@ where
     debug!("Update Method Count: {}", canister.update_methods.len());
     debug!("Query Method Count: {}", canister.query_methods.len());
 
-    let system = Edge::new_with_caller_and_time(caller, None);
-    (canister.init_method)(
-        MutableContext::new(&mut state, &system),
-        &init_arguments,
-        UpdateContext::Primary,
+    let canister = Arc::new(EmbeddedCanister {
+        canister_id,
+        canister,
+        state: Arc::new(Mutex::new(state)),
+        system,
+    });
+    canister.run_lifecycle(
+        caller,
+        "canister_init",
+        init_arguments.len(),
+        |state, system| {
+            canister
+                .canister
+                .run_init(state, system, &init_arguments, UpdateContext::Primary)
+        },
     );
+    canister.register_call_handlers(caller);
 
-    Arc::new(EmbeddedCanisterImpl {
-        caller,
-        canister: Arc::new(canister),
-        state: Arc::new(Mutex::new(state)),
-    })
+    if let Some(global_timer) = canister.canister.global_timer {
+        // fire the global timer whenever test code advances the shared clock past it
+        let weak_canister = Arc::downgrade(&canister);
+        canister.system.clock().on_global_timer(Arc::new(move || {
+            let Some(canister) = weak_canister.upgrade() else {
+                return;
+            };
+            canister.run_lifecycle(caller, "canister_global_timer", 0, |state, system| {
+                global_timer(MutableContext::new(state, system), UpdateContext::Primary)
+            });
+        }));
+    }
+
+    Arc::new(EmbeddedCanisterImpl { caller, canister })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dscvr_canister_exports::MethodFuture;
+    use dscvr_interface::edge::CallHandlers;
+
+    fn callee_id() -> Principal {
+        Principal::from_slice(&[1])
+    }
+
+    fn greet(
+        ctx: ImmutableContext<'_, String>,
+        args: &[u8],
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        let name = String::from_utf8_lossy(args);
+        Ok(format!("{}, {name}", ctx.state()).into_bytes())
+    }
+
+    fn rename(
+        mut ctx: MutableContext<'_, String>,
+        args: &[u8],
+        _update_context: UpdateContext<'_>,
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        ctx.mutate(|state| *state = String::from_utf8_lossy(args).into_owned());
+        Ok(vec![])
+    }
+
+    fn relay<'a>(ctx: ImmutableContext<'a, ()>, args: &'a [u8]) -> MethodFuture<'a> {
+        Box::pin(async move {
+            ctx.system()
+                .call_canister(callee_id(), "greet".to_owned(), args.to_vec(), 0)
+                .await
+                .map_err(|(_, message)| CanisterError::from(message))
+        })
+    }
+
+    fn relay_rename<'a>(
+        ctx: AsyncContext<'a, ()>,
+        args: &'a [u8],
+        _update_context: UpdateContext<'a>,
+    ) -> MethodFuture<'a> {
+        Box::pin(async move {
+            ctx.system()
+                .call_canister(callee_id(), "rename".to_owned(), args.to_vec(), 0)
+                .await
+                .map_err(|(_, message)| CanisterError::from(message))
+        })
+    }
+
+    #[test]
+    fn embedded_canisters_call_each_other() {
+        let caller = Principal::anonymous();
+        let call_handlers = CallHandlers::default();
+        let system = || Edge::default().with_call_handlers(call_handlers.clone());
+
+        let callee = CanisterDefinition::builder()
+            .query("greet", greet)
+            .update("rename", rename)
+            .build()
+            .unwrap();
+        let _callee = new(
+            caller,
+            callee_id(),
+            callee,
+            vec![],
+            "Hello".to_owned(),
+            system(),
+        );
+
+        let relay_id = Principal::from_slice(&[2]);
+        let relay = CanisterDefinition::builder()
+            .composite_query("relay", relay)
+            .async_update("relay_rename", relay_rename)
+            .build()
+            .unwrap();
+        let relay = new(caller, relay_id, relay, vec![], (), system());
+
+        futures::executor::block_on(async {
+            assert_eq!(
+                relay.query(&relay_id, "relay", b"world").await.unwrap(),
+                b"Hello, world"
+            );
+            relay
+                .update(&relay_id, "relay_rename", b"Goodbye")
+                .await
+                .unwrap();
+            assert_eq!(
+                relay.query(&relay_id, "relay", b"world").await.unwrap(),
+                b"Goodbye, world"
+            );
+        });
+    }
+
+    fn in_scope(
+        _ctx: ImmutableContext<'_, u8>,
+        _args: &[u8],
+    ) -> std::result::Result<Vec<u8>, CanisterError> {
+        let state = dscvr_canister_context::scoped_state::instance_state::<u8>();
+        Ok(vec![state.is_some() as u8])
+    }
+
+    #[test]
+    fn embedded_dispatch_runs_in_the_scope_of_the_state() {
+        let canister_id = Principal::from_slice(&[3]);
+        let canister = CanisterDefinition::builder()
+            .query("in_scope", in_scope)
+            .build()
+            .unwrap();
+        let agent = new(
+            Principal::anonymous(),
+            canister_id,
+            canister,
+            vec![],
+            0_u8,
+            Edge::default(),
+        );
+        let reply = futures::executor::block_on(agent.query(&canister_id, "in_scope", &[]));
+        assert_eq!(reply.unwrap(), [1]);
+    }
 }
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use candid::Principal;
+use dscvr_telemetry_util::cardinality::bounded_labels;
+use dscvr_telemetry_util::{
+    IC_REPLICA_REQUESTS_DURATION_SECONDS, IC_REPLICA_REQUESTS_TOTAL, IC_REPLICA_REQUEST_SIZE_BYTES,
+    IC_REPLICA_RESPONSE_SIZE_BYTES,
+};
+use ic_agent::Identity;
+use instrumented_error::Result;
+
+use super::AgentImpl;
+
+/// Records the count, latency and candid payload sizes of every replica call of the wrapped
+/// agent, labeled by canister, method, call type and result
+pub struct MeteredAgent {
+    inner: Arc<dyn AgentImpl>,
+}
+
+impl MeteredAgent {
+    /// Wrap `inner`, metering its replica calls
+    pub fn wrap(inner: Arc<dyn AgentImpl>) -> Arc<dyn AgentImpl> {
+        Arc::new(Self { inner })
+    }
+}
+
+/// Record a replica call that started at `start`, sending `request_size` bytes of arguments
+fn record(
+    canister_id: &Principal,
+    method: &str,
+    call_type: &'static str,
+    start: Instant,
+    request_size: Option<usize>,
+    result: &Result<Vec<u8>>,
+) {
+    let latency = start.elapsed().as_secs_f64();
+    let (result, response_size) = match result {
+        Ok(response) => ("ok".to_owned(), Some(response.len())),
+        Err(e) => (e.kind().to_string(), None),
+    };
+    let labels = bounded_labels([
+        ("canister", canister_id.to_text()),
+        ("method", method.to_owned()),
+        ("type", call_type.to_owned()),
+        ("result", result),
+    ]);
+
+    metrics::counter!(IC_REPLICA_REQUESTS_TOTAL, &labels).increment(1);
+    metrics::histogram!(IC_REPLICA_REQUESTS_DURATION_SECONDS, &labels).record(latency);
+    #[cfg(feature = "exemplars")]
+    dscvr_telemetry_util::exemplars::record(IC_REPLICA_REQUESTS_DURATION_SECONDS, &labels, latency);
+
+    if let Some(size) = request_size {
+        metrics::histogram!(IC_REPLICA_REQUEST_SIZE_BYTES, &labels).record(size as f64);
+    }
+    if let Some(size) = response_size {
+        metrics::histogram!(IC_REPLICA_RESPONSE_SIZE_BYTES, &labels).record(size as f64);
+    }
+}
+
+#[async_trait::async_trait]
+impl AgentImpl for MeteredAgent {
+    async fn update(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let result = self.inner.update(canister_id, method, args).await;
+        record(
+            canister_id,
+            method,
+            "update",
+            start,
+            Some(args.len()),
+            &result,
+        );
+        result
+    }
+
+    async fn query(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let result = self.inner.query(canister_id, method, args).await;
+        record(
+            canister_id,
+            method,
+            "query",
+            start,
+            Some(args.len()),
+            &result,
+        );
+        result
+    }
+
+    async fn read_state_canister_info(
+        &self,
+        canister_id: &Principal,
+        prop: &str,
+    ) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let result = self.inner.read_state_canister_info(canister_id, prop).await;
+        record(canister_id, prop, "read_state", start, None, &result);
+        result
+    }
+
+    async fn verify_certified_data(
+        &self,
+        canister_id: &Principal,
+        certificate: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.inner
+            .verify_certified_data(canister_id, certificate)
+            .await
+    }
+
+    async fn clone_with_identity(&self, identity: Arc<dyn Identity>) -> Result<Arc<dyn AgentImpl>> {
+        Ok(Self::wrap(self.inner.clone_with_identity(identity).await?))
+    }
+
+    async fn tick(&self) -> Result<()> {
+        self.inner.tick().await
+    }
+
+    fn get_principal(&self) -> Result<Principal> {
+        self.inner.get_principal()
+    }
+}
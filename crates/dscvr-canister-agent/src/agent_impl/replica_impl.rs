@@ -2,8 +2,12 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use candid::Principal;
+use ic_agent::hash_tree::LookupResult;
 use ic_agent::Agent;
+use ic_agent::AgentError;
+use ic_agent::Certificate;
 use ic_agent::Identity;
+use instrumented_error::ErrorKind;
 use instrumented_error::IntoInstrumentedError;
 use instrumented_error::Result;
 use tokio_retry::strategy::jitter;
@@ -12,6 +16,18 @@ use tokio_retry::Retry;
 
 use super::AgentImpl;
 
+/// Attach the category of an error returned by the agent
+fn categorized<T>(result: std::result::Result<T, AgentError>) -> Result<T> {
+    result.map_err(|e| {
+        let kind = match &e {
+            AgentError::TransportError(_) => ErrorKind::Network,
+            AgentError::CertifiedReject(_) => ErrorKind::CanisterReject,
+            _ => ErrorKind::Internal,
+        };
+        instrumented_error::Error::from(e).with_kind(kind)
+    })
+}
+
 struct WrappedAgent {
     agent: Agent,
     url: String,
@@ -31,21 +47,23 @@ impl WrappedAgent {
 #[async_trait::async_trait]
 impl AgentImpl for WrappedAgent {
     async fn query(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
-        Ok(self
-            .agent
-            .query(canister_id, method)
-            .with_arg(args)
-            .call()
-            .await?)
+        categorized(
+            self.agent
+                .query(canister_id, method)
+                .with_arg(args)
+                .call()
+                .await,
+        )
     }
 
     async fn update(&self, canister_id: &Principal, method: &str, args: &[u8]) -> Result<Vec<u8>> {
-        Ok(self
-            .agent
-            .update(canister_id, method)
-            .with_arg(args)
-            .call_and_wait()
-            .await?)
+        categorized(
+            self.agent
+                .update(canister_id, method)
+                .with_arg(args)
+                .call_and_wait()
+                .await,
+        )
     }
 
     fn get_principal(&self) -> Result<Principal> {
@@ -84,6 +102,30 @@ impl AgentImpl for WrappedAgent {
             .read_state_canister_info(canister_id.to_owned(), prop)
             .await?)
     }
+
+    async fn verify_certified_data(
+        &self,
+        canister_id: &Principal,
+        certificate: &[u8],
+    ) -> Result<Vec<u8>> {
+        let certificate: Certificate = serde_cbor::from_slice(certificate)?;
+        self.agent.verify(&certificate, *canister_id)?;
+        match certificate.tree.lookup_path([
+            "canister".as_bytes(),
+            canister_id.as_slice(),
+            "certified_data".as_bytes(),
+        ]) {
+            LookupResult::Found(data) => Ok(data.to_vec()),
+            _ => Err(
+                format!("Certificate has no certified data for canister {canister_id}")
+                    .into_instrumented_error(),
+            ),
+        }
+    }
+
+    async fn tick(&self) -> Result<()> {
+        Err("The replica runs background jobs on its own".into_instrumented_error())
+    }
 }
 
 pub async fn new<U: Into<String>>(
@@ -107,5 +149,8 @@ pub async fn new<U: Into<String>>(
 
     agent.fetch_root_key().await?;
 
+    #[cfg(feature = "telemetry")]
+    let agent = super::metered_impl::MeteredAgent::wrap(agent);
+
     Ok(agent)
 }
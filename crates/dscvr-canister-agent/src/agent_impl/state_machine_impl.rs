@@ -66,6 +66,19 @@ impl AgentImpl for WrappedStateMachine {
     ) -> Result<Vec<u8>> {
         unimplemented!()
     }
+
+    async fn verify_certified_data(
+        &self,
+        _canister_id: &Principal,
+        _certificate: &[u8],
+    ) -> Result<Vec<u8>> {
+        unimplemented!()
+    }
+
+    async fn tick(&self) -> Result<()> {
+        self.machine.lock().expect("lock failure").tick();
+        Ok(())
+    }
 }
 
 pub fn new(
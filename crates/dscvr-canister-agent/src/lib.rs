@@ -6,6 +6,7 @@ use candid::{CandidType, Decode};
 use dscvr_canister_config::canister_init_arguments::ControllerType;
 use dscvr_canister_config::schema::dscvr::{CanisterNetwork, DSCVRConfig};
 use dscvr_canister_exports::CanisterDefinition;
+use dscvr_interface::edge::{Edge, EdgeClock};
 use futures::{stream, StreamExt};
 use ic_agent::Identity;
 use ic_identity_util::create_identity_from_pem;
@@ -22,10 +23,12 @@ mod agent_impl;
 mod module_hash;
 mod stable_storage_restore_backup;
 mod stats;
+mod wasm_metadata;
 
 pub use agent_impl::get_route_provider_and_client;
 pub use agent_impl::AgentImpl;
 pub use agent_impl::MAX_ERROR_RETRIES;
+pub use wasm_metadata::{canister_metadata, read_canister_metadata};
 
 /// The content format stored in stable storage
 /// TODO: autogenerate from did
@@ -77,12 +80,66 @@ impl CanisterAgent {
         init_arguments: Vec<u8>,
         state: State,
     ) -> Result<Self>
+    where
+        State: std::marker::Send + 'static,
+    {
+        Self::new_embedded_canister_with_clock(
+            caller,
+            canister,
+            init_arguments,
+            state,
+            Arc::new(EdgeClock::new(None)),
+        )
+    }
+
+    /// Return an embedded canister whose calls all observe `clock`, so tests can control
+    /// time and timers across the whole run
+    #[tracing::instrument(skip(canister, state, init_arguments, clock))]
+    pub fn new_embedded_canister_with_clock<State>(
+        caller: Principal,
+        canister: CanisterDefinition<State>,
+        init_arguments: Vec<u8>,
+        state: State,
+        clock: Arc<EdgeClock>,
+    ) -> Result<Self>
+    where
+        State: std::marker::Send + 'static,
+    {
+        Self::new_embedded_canister_with_interface(
+            caller,
+            Principal::anonymous(),
+            canister,
+            init_arguments,
+            state,
+            Edge::new_with_caller_and_time(caller, None).with_clock(clock),
+        )
+    }
+
+    /// Return an embedded canister with id `canister_id` whose calls all derive from
+    /// `system`. Embedded canisters sharing the call handlers of their interfaces can
+    /// call each other.
+    #[tracing::instrument(skip(canister, state, init_arguments, system))]
+    pub fn new_embedded_canister_with_interface<State>(
+        caller: Principal,
+        canister_id: Principal,
+        canister: CanisterDefinition<State>,
+        init_arguments: Vec<u8>,
+        state: State,
+        system: Edge,
+    ) -> Result<Self>
     where
         State: std::marker::Send + 'static,
     {
         Ok(Self {
-            agent: embedded_canister_impl::new(caller, canister, init_arguments, state),
-            canister_id: Principal::anonymous(),
+            agent: embedded_canister_impl::new(
+                caller,
+                canister_id,
+                canister,
+                init_arguments,
+                state,
+                system,
+            ),
+            canister_id,
         })
     }
 
@@ -217,4 +274,9 @@ impl CanisterAgent {
     pub fn get_principal(&self) -> Result<Principal> {
         self.agent.get_principal()
     }
+
+    /// Run the background jobs of the canister, i.e. the heartbeat and the due timers
+    pub async fn tick(&self) -> Result<()> {
+        self.agent.tick().await
+    }
 }
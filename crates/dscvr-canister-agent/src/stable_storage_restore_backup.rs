@@ -6,16 +6,23 @@ use candid::Encode;
 use futures::TryStreamExt;
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, SinkExt};
 use ic_canister_stable_storage::{
-    data_format::DataFormatType, header::Header, transient::Transient,
+    certification::BackupCertificate, data_format::DataFormatType, header::Header,
+    manifest::BackupManifest, transient::Transient, WASM_PAGE_SIZE_IN_BYTES,
 };
-use instrumented_error::{BoxedInstrumentedError, Result};
+use instrumented_error::{BoxedInstrumentedError, IntoInstrumentedResult, Result};
 use serde_bytes::{ByteBuf, Bytes};
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tokio_retry::Retry;
-use tracing::debug;
+use tracing::{debug, warn};
 
 const BACKUP_CHUNK_SIZE: u64 = 1024 * 1024 * 5 / 2;
 const RESTORE_CHUNK_SIZE: u64 = 2096000;
+const DUMP_PAGES_PER_CHUNK: u64 = 32;
+/// Calls after which an unfinished certification is abandoned, each call hashes as many
+/// chunks as its instruction budget allows
+const MAX_CERTIFICATION_CALLS: usize = 10_000;
+/// Pages in the largest stable memory of a canister, 500 GiB
+const MAX_STABLE_MEMORY_PAGES: u64 = 500 * 1024 * 1024 * 1024 / WASM_PAGE_SIZE_IN_BYTES as u64;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct CanisterStats {
@@ -34,6 +41,12 @@ pub enum ErrorKind {
     BackupLengthMismatch(usize, usize),
     #[error("Canister stable storage not initialized")]
     CanisterStableStorageNotInitialized,
+    #[error("Canister did not return a backup certificate")]
+    MissingBackupCertificate,
+    #[error("Backup certification incomplete after {0} calls")]
+    CertificationIncomplete(usize),
+    #[error("Stable memory dump exceeded {0} pages")]
+    DumpTooLarge(u64),
 }
 
 impl CanisterAgent {
@@ -48,8 +61,13 @@ impl CanisterAgent {
         )?)
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn backup_stable_storage_chunk(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+    #[tracing::instrument(skip(self, certificate))]
+    async fn backup_stable_storage_chunk(
+        &self,
+        offset: u64,
+        len: u64,
+        certificate: Option<&BackupCertificate>,
+    ) -> Result<Vec<u8>> {
         if len <= offset {
             return Ok(vec![]);
         }
@@ -57,16 +75,115 @@ impl CanisterAgent {
         debug!("Fetching {} of {}", offset, len);
 
         let bytes = Encode!(&offset, &std::cmp::min(BACKUP_CHUNK_SIZE, len - offset))?;
-        Ok(Decode!(
+        let chunk = Decode!(
             self.query("backup_stable_storage", bytes).await?.as_slice(),
             ByteBuf
         )?
-        .into_vec())
+        .into_vec();
+
+        if let Some(certificate) = certificate {
+            certificate.verify_chunk(BACKUP_CHUNK_SIZE, offset, &chunk)?;
+        }
+
+        Ok(chunk)
+    }
+
+    /// Return the verified backup certificate of the canister.
+    /// Note: This replaces the certified data of the canister.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_stable_storage_backup_certificate(&self) -> Result<BackupCertificate> {
+        // the canister hashes its stable storage over as many calls as it needs
+        let mut calls = 0;
+        loop {
+            if calls == MAX_CERTIFICATION_CALLS {
+                return Err(ErrorKind::CertificationIncomplete(calls).into());
+            }
+            calls += 1;
+            let complete = Decode!(
+                self.update(
+                    "certify_stable_storage_backup",
+                    Encode!(&BACKUP_CHUNK_SIZE)?,
+                )
+                .await?
+                .as_slice(),
+                std::result::Result<bool, String>
+            )?
+            .into_instrumented_result()?;
+            if complete {
+                break;
+            }
+            debug!("Certification in progress");
+        }
+
+        let certificate = Decode!(
+            self.query("stable_storage_backup_certificate", Encode!()?)
+                .await?
+                .as_slice(),
+            Option<BackupCertificate>
+        )?
+        .ok_or(ErrorKind::MissingBackupCertificate)?;
+
+        let certified_data = self
+            .agent
+            .verify_certified_data(&self.canister_id, &certificate.certificate)
+            .await?;
+        certificate.verify_root_hash(&certified_data)?;
+        certificate.verify_layout(BACKUP_CHUNK_SIZE)?;
+
+        Ok(certificate)
     }
 
     /// Backup the stable storage of a canister to a writer
     #[tracing::instrument(skip_all)]
-    pub async fn backup_stable_storage<W>(&self, mut writer: W) -> Result<()>
+    pub async fn backup_stable_storage<W>(&self, writer: W) -> Result<()>
+    where
+        W: AsyncWriteExt + AsyncWrite + Unpin,
+    {
+        self.backup_stable_storage_impl(writer, None, false).await
+    }
+
+    /// Backup the stable storage of a canister to a writer, verifying every chunk against
+    /// the certified chunk hashes of the canister
+    #[tracing::instrument(skip_all)]
+    pub async fn backup_stable_storage_certified<W>(&self, writer: W) -> Result<()>
+    where
+        W: AsyncWriteExt + AsyncWrite + Unpin,
+    {
+        let certificate = self.get_stable_storage_backup_certificate().await?;
+        self.backup_stable_storage_impl(writer, Some(&certificate), false)
+            .await
+    }
+
+    /// Backup the stable storage of a canister to a writer, recording the
+    /// [`BackupManifest`] of the canister in the header of the backup, optionally verifying
+    /// every chunk like [`Self::backup_stable_storage_certified`].
+    ///
+    /// Note: The manifest is stored in the TLV header layout, which canisters built before
+    /// it was introduced refuse to restore.
+    #[tracing::instrument(skip(self, writer))]
+    pub async fn backup_stable_storage_with_manifest<W>(
+        &self,
+        writer: W,
+        certified: bool,
+    ) -> Result<()>
+    where
+        W: AsyncWriteExt + AsyncWrite + Unpin,
+    {
+        let certificate = if certified {
+            Some(self.get_stable_storage_backup_certificate().await?)
+        } else {
+            None
+        };
+        self.backup_stable_storage_impl(writer, certificate.as_ref(), true)
+            .await
+    }
+
+    async fn backup_stable_storage_impl<W>(
+        &self,
+        mut writer: W,
+        certificate: Option<&BackupCertificate>,
+        record_manifest: bool,
+    ) -> Result<()>
     where
         W: AsyncWriteExt + AsyncWrite + Unpin,
     {
@@ -76,13 +193,33 @@ impl CanisterAgent {
             return Err(ErrorKind::CanisterStableStorageNotInitialized.into());
         }
 
-        let len = header.num_all_fields_bytes() + header.content_length;
+        // the certified length is used over the one from the unverified header
+        let len = match certificate {
+            Some(certificate) => {
+                certificate.verify_layout(BACKUP_CHUNK_SIZE)?;
+                certificate.len
+            }
+            None => header.num_all_fields_bytes() + header.content_length,
+        };
         let count = len / BACKUP_CHUNK_SIZE + 1;
-        let mut total_written = 0;
-        stream::iter(0..count)
+
+        // the first chunk holds the header, which is rewritten to record the manifest if asked
+        let first_chunk = self
+            .backup_stable_storage_chunk(0, len, certificate)
+            .await?;
+        let first_chunk_len = first_chunk.len();
+        let first_chunk = if record_manifest {
+            with_manifest_header(first_chunk, &self.get_backup_manifest().await?)?
+        } else {
+            first_chunk
+        };
+        writer.write_all(&first_chunk).await?;
+        let mut total_written = first_chunk.len();
+
+        stream::iter(1..count)
             .map(|idx| {
                 let offset = idx * BACKUP_CHUNK_SIZE;
-                self.backup_stable_storage_chunk(offset, len)
+                self.backup_stable_storage_chunk(offset, len, certificate)
             })
             .buffered(10)
             .map(|item| {
@@ -97,7 +234,7 @@ impl CanisterAgent {
                     .sink_err_into::<BoxedInstrumentedError>(),
             )
             .await?;
-        let len = len as usize;
+        let len = len as usize + first_chunk.len() - first_chunk_len;
         if total_written != len {
             return Err(ErrorKind::BackupLengthMismatch(len, total_written)
                 .in_current_span()
@@ -107,18 +244,94 @@ impl CanisterAgent {
         Ok(())
     }
 
-    /// Restore the stable storage of a canister from a reader
+    /// Dump the raw pages of the stable storage of a canister to a writer, ignoring the
+    /// header's content length. This is meant for forensic images when the header is
+    /// corrupted and `backup_stable_storage` refuses to run.
+    /// Returns the number of bytes written.
+    #[tracing::instrument(skip_all)]
+    pub async fn dump_raw_stable_memory<W>(&self, mut writer: W) -> Result<u64>
+    where
+        W: AsyncWriteExt + AsyncWrite + Unpin,
+    {
+        let mut total_written = 0;
+        let mut offset_page = 0;
+        loop {
+            if offset_page > MAX_STABLE_MEMORY_PAGES {
+                return Err(ErrorKind::DumpTooLarge(MAX_STABLE_MEMORY_PAGES).into());
+            }
+            debug!(
+                "Dumping pages {} to {}",
+                offset_page,
+                offset_page + DUMP_PAGES_PER_CHUNK
+            );
+            let bytes = Encode!(&offset_page, &DUMP_PAGES_PER_CHUNK)?;
+            let chunk = Decode!(
+                self.query("dump_stable_pages", bytes).await?.as_slice(),
+                ByteBuf
+            )?;
+            writer.write_all(&chunk).await?;
+            total_written += chunk.len() as u64;
+
+            if (chunk.len() as u64) < DUMP_PAGES_PER_CHUNK * WASM_PAGE_SIZE_IN_BYTES as u64 {
+                break;
+            }
+            offset_page += DUMP_PAGES_PER_CHUNK;
+        }
+        writer.flush().await?;
+        Ok(total_written)
+    }
+
+    /// Restore the stable storage of a canister from a reader, refusing backups whose manifest
+    /// was recorded for a different canister
     #[tracing::instrument(skip_all)]
     pub async fn restore_stable_storage<R>(
+        &self,
+        reader: R,
+        restore_offest: Option<u64>,
+    ) -> Result<()>
+    where
+        R: AsyncReadExt + AsyncRead + Unpin + Send + 'static,
+    {
+        self.restore_stable_storage_impl(reader, restore_offest, false)
+            .await
+    }
+
+    /// Restore the stable storage of a canister from a reader, even if the manifest of the
+    /// backup was recorded for a different canister, e.g. to seed a staging canister
+    #[tracing::instrument(skip_all)]
+    pub async fn restore_stable_storage_across_canisters<R>(
+        &self,
+        reader: R,
+        restore_offest: Option<u64>,
+    ) -> Result<()>
+    where
+        R: AsyncReadExt + AsyncRead + Unpin + Send + 'static,
+    {
+        self.restore_stable_storage_impl(reader, restore_offest, true)
+            .await
+    }
+
+    async fn restore_stable_storage_impl<R>(
         &self,
         mut reader: R,
         restore_offest: Option<u64>,
+        allow_cross_canister: bool,
     ) -> Result<()>
     where
         R: AsyncReadExt + AsyncRead + Unpin + Send + 'static,
     {
-        let header = Header::new_from_reader_async(&mut reader).await?;
-        let len = header.num_content_and_header_bytes();
+        let (header, fields) = Header::new_from_reader_with_fields_async(&mut reader).await?;
+
+        if let Some(manifest) = BackupManifest::from_header_fields(&fields)? {
+            debug!("Restoring backup {:?}", manifest);
+            manifest.check_restore_target(&self.canister_id, allow_cross_canister)?;
+        }
+
+        // the header is always restored using the current layout, which may differ in
+        // size from the layout of the backup
+        let header_bytes = header.as_bytes_with_fields(&fields);
+        let header_bytes_len = header_bytes.len() as u64;
+        let len = header_bytes_len + header.content_length;
 
         // grow the stable storage to at least be the total size we need
         {
@@ -126,8 +339,6 @@ impl CanisterAgent {
             self.update("init_stable_storage", bytes).await?;
         }
 
-        let header_bytes = header.as_bytes();
-        let header_bytes_len = header_bytes.len() as u64;
         let restore_offset = restore_offest.unwrap_or(header_bytes_len);
 
         // restore the header
@@ -198,6 +409,28 @@ impl CanisterAgent {
         ret
     }
 
+    /// Return the manifest describing the canister to be stored alongside a backup
+    /// Note: This makes network calls to retrieve the stats and module hash of the canister.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_backup_manifest(&self) -> Result<BackupManifest> {
+        let git_version = match self.canister_stats::<CanisterStats>().await {
+            Ok(stats) => stats.version,
+            Err(e) => {
+                warn!(
+                    "Failed getting the canister version for the manifest {:?}",
+                    e
+                );
+                String::new()
+            }
+        };
+        Ok(BackupManifest {
+            canister_id: Some(self.canister_id),
+            module_hash: self.canister_module_hash().await?,
+            git_version,
+            created_at: OffsetDateTime::now_utc().unix_timestamp_nanos() as u64,
+        })
+    }
+
     /// Return the default file name to be used for stable storage backups
     /// Note: This makes a network call to retrieve the module hash of the canister.
     pub async fn get_default_stable_storage_backup_file_name(
@@ -217,3 +450,13 @@ impl CanisterAgent {
         ))
     }
 }
+
+// Replace the header at the start of the first backup chunk with one that records `manifest`
+fn with_manifest_header(chunk: Vec<u8>, manifest: &BackupManifest) -> Result<Vec<u8>> {
+    let mut reader = chunk.as_slice();
+    let (header, mut fields) = Header::new_from_reader_with_fields(&mut reader)?;
+    manifest.set_in_header_fields(&mut fields)?;
+    let mut bytes = header.as_bytes_with_fields(&fields);
+    bytes.extend_from_slice(reader);
+    Ok(bytes)
+}
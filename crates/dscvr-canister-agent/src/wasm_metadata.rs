@@ -0,0 +1,26 @@
+use dscvr_canister_exports::wasm_metadata::CanisterMetadata;
+use instrumented_error::Result;
+use std::io::Read;
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Read the build metadata embedded in a `.wasm` or `.wasm.gz` module, if it has any
+pub fn read_canister_metadata(wasm_file: &Path) -> Result<Option<CanisterMetadata>> {
+    let bytes = std::fs::read(wasm_file)?;
+    canister_metadata(&bytes)
+}
+
+/// Extract the build metadata embedded in a wasm module, possibly gzipped
+pub fn canister_metadata(bytes: &[u8]) -> Result<Option<CanisterMetadata>> {
+    let decompressed;
+    let module = if bytes.starts_with(GZIP_MAGIC) {
+        let mut buf = vec![];
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut buf)?;
+        decompressed = buf;
+        &decompressed[..]
+    } else {
+        bytes
+    };
+    Ok(CanisterMetadata::from_module(module)?)
+}
@@ -1,15 +1,23 @@
 pub mod dfx;
 pub mod dscvr;
 
+use crate::canister_init_arguments::ControllerType;
 use crate::prelude::*;
 use crate::schema::dfx::CanisterIds;
 use crate::schema::dscvr::CanisterInstance;
+use candid::Principal;
 use dfx::DfxConfig;
 use dscvr::DSCVRConfig;
-use instrumented_error::IntoInstrumentedResult;
+use ic_agent::{Agent, Identity};
+use ic_identity_util::{
+    add_canister_controller, generate_identity_to_pem, remove_canister_controller, KeyType,
+};
+use instrumented_error::{IntoInstrumentedError, IntoInstrumentedResult};
 use serde::{Deserialize, Serialize};
 use std::io::{BufReader, BufWriter};
 use std::path::Path;
+use std::sync::Arc;
+use tracing::{error, info};
 
 const DEFAULT_DFX_CONFIG_PATH: &str = "./dfx.json";
 const DEFAULT_DSCVR_CONFIG_PATH: &str = "./dscvr.json";
@@ -171,6 +179,163 @@ pub fn provision_canisters(
         .into_instrumented_result()
 }
 
+/// Outcome of [`rotate_controller`]
+#[derive(Debug, Clone)]
+pub struct ControllerRotation {
+    /// Principal of the replaced identity, no longer a controller
+    pub old_principal: Principal,
+    /// Principal of the generated identity
+    pub new_principal: Principal,
+    /// Canisters controlled by the rotated `ControllerGroup`
+    pub canister_ids: Vec<Principal>,
+}
+
+async fn management_agent(
+    identity: Arc<dyn Identity>,
+    provider: &str,
+    network: &str,
+) -> Result<Agent> {
+    let agent = Agent::builder()
+        .with_url(provider)
+        .with_arc_identity(identity)
+        .build()?;
+    if network != PRODUCTION_NETWORK_NAME {
+        agent.fetch_root_key().await?;
+    }
+    Ok(agent)
+}
+
+/// Rotates the key of `controller` in the `ControllerGroup` used by
+/// `canister` on `network`, as a single audited operation.
+///
+/// A new identity is generated to `new_pem_file` and added as a controller
+/// of every instance controlled by the group, by the current identity. Only
+/// once `dscvr.json` references the new identity is the old principal removed
+/// from the controllers, by the new identity. The old pem file is left in place.
+///
+/// If adding the new controller fails, it is removed again from the canisters
+/// it was already added to and the new pem file is deleted, so the rotation can
+/// be re-run. Canisters that couldn't be rolled back are logged, in which case
+/// the new pem file is kept. If removing the old controller fails, the
+/// canisters that still have it are logged.
+///
+/// ### Inputs
+/// - `canister: &str` - Canister whose `ControllerGroup` to rotate
+/// - `network: &str` - Network of the instances to update
+/// - `controller: ControllerType` - Controller to rotate within the group
+/// - `new_pem_file: &Path` - Path of the new pem file, must not exist
+/// - `key_type: KeyType` - Type of the new key
+///
+/// ### Returns
+/// - `Result<ControllerRotation>` - returns `Ok()` with the old and new
+///   principals and the updated canisters on success.
+#[tracing::instrument]
+pub async fn rotate_controller(
+    canister: &str,
+    network: &str,
+    controller: ControllerType,
+    new_pem_file: &Path,
+    key_type: KeyType,
+) -> Result<ControllerRotation> {
+    let mut dscvr_cfg = DSCVRConfig::try_new(network)?;
+    let controller_group = dscvr_cfg
+        .get_controller_group_name(canister, network)
+        .map_err(|err| format!("{err}"))
+        .into_instrumented_result()?;
+    let provider = dscvr_cfg
+        .get_canister_network(canister, network)
+        .map(|canister_network| canister_network.provider.clone())
+        .unwrap_or_default();
+    let old_identity = dscvr_cfg
+        .get_controller(canister, network, controller)
+        .ok_or_else(|| {
+            format!("No {controller:?} controller in {controller_group}").into_instrumented_error()
+        })?
+        .identity()?;
+    let old_principal = old_identity
+        .sender()
+        .map_err(|e| e.into_instrumented_error())?;
+    let canister_ids = dscvr_cfg
+        .get_instances_for_controller_group(network, &controller_group)
+        .into_iter()
+        .filter_map(|instance| instance.id)
+        .map(|id| Ok(Principal::from_text(id)?))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (new_identity, new_principal) = generate_identity_to_pem(new_pem_file, key_type)?;
+    info!(%controller_group, ?controller, %old_principal, %new_principal, "Generated controller");
+
+    let agent = management_agent(old_identity, &provider, network).await?;
+    for (idx, canister_id) in canister_ids.iter().enumerate() {
+        if let Err(err) = add_canister_controller(&agent, *canister_id, new_principal).await {
+            error!(%canister_id, %new_principal, "Failed adding controller, rolling back");
+            rollback_added_controller(&agent, &canister_ids[..idx], new_principal, new_pem_file)
+                .await;
+            return Err(err);
+        }
+        info!(%canister_id, %new_principal, "Added controller");
+    }
+
+    let new_identity_key = new_identity.identity()?;
+    dscvr_cfg
+        .set_controller(&controller_group, controller, new_identity)
+        .map_err(|err| format!("{err}"))
+        .into_instrumented_result()?;
+    dscvr_cfg.write_config(network)?;
+    info!(%controller_group, ?controller, %new_principal, "Updated controller group");
+
+    let agent = management_agent(new_identity_key, &provider, network).await?;
+    for (idx, canister_id) in canister_ids.iter().enumerate() {
+        if let Err(err) = remove_canister_controller(&agent, *canister_id, old_principal).await {
+            error!(
+                %old_principal,
+                remaining = ?&canister_ids[idx..],
+                "Failed removing controller, still a controller of the remaining canisters"
+            );
+            return Err(err);
+        }
+        info!(%canister_id, %old_principal, "Removed controller");
+    }
+
+    Ok(ControllerRotation {
+        old_principal,
+        new_principal,
+        canister_ids,
+    })
+}
+
+/// Remove `new_principal` from the controllers of `canister_ids` after a failed
+/// [`rotate_controller`]. The new pem file is only deleted once every canister
+/// was rolled back, so a key that is still a controller is never lost.
+async fn rollback_added_controller(
+    agent: &Agent,
+    canister_ids: &[Principal],
+    new_principal: Principal,
+    new_pem_file: &Path,
+) {
+    let mut failed = vec![];
+    for canister_id in canister_ids {
+        match remove_canister_controller(agent, *canister_id, new_principal).await {
+            Ok(_) => info!(%canister_id, %new_principal, "Rolled back added controller"),
+            Err(err) => {
+                error!(%canister_id, %new_principal, %err, "Failed rolling back added controller");
+                failed.push(*canister_id);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        error!(
+            %new_principal,
+            ?failed,
+            pem_file = %new_pem_file.display(),
+            "Rollback incomplete, the new pem file is kept"
+        );
+    } else if let Err(err) = std::fs::remove_file(new_pem_file) {
+        error!(pem_file = %new_pem_file.display(), %err, "Failed deleting the new pem file");
+    }
+}
+
 /// Commit a config object to file for a specific network.
 /// Use after a successful provisioning.
 pub fn commit_config(config: &DSCVRConfig, network: &str) -> Result<()> {
@@ -2,6 +2,7 @@
 mod allocate;
 mod persist;
 mod provision;
+mod rotate;
 
 use crate::canister_init_arguments::ControllerType;
 use instrumented_error::{IntoInstrumentedError, IntoInstrumentedResult};
@@ -650,4 +651,65 @@ mod test {
 
         cleanup()
     }
+
+    #[test]
+    fn test_rotate_shared_controller_group() {
+        let network = |controllers: &str, id: &str| CanisterNetwork {
+            provider: IC_PROVIDER.to_string(),
+            controllers: Some(controllers.to_string()),
+            provisioned_instances: Some(vec![CanisterInstance {
+                name: id.to_string(),
+                id: Some(id.to_string()),
+            }]),
+            available_instances: None,
+            wallet: None,
+        };
+        let canister = |controllers: &str, id: &str| Canister {
+            networks: HashMap::from([("ic".to_string(), network(controllers, id))]),
+            ..Default::default()
+        };
+        let mut prod_group = ControllerGroup {
+            controllers: Default::default(),
+        };
+        prod_group.controllers.insert(
+            ControllerType::Backup,
+            IdentityFromFile::from_str("./keys/prod-backup.pem").unwrap(),
+        );
+        let mut dscvr_config = DSCVRConfig {
+            canisters: HashMap::from([
+                ("society_rs".to_string(), canister("prod", "society")),
+                ("dscvr-event-router".to_string(), canister("prod", "router")),
+                ("stable-storage-test".to_string(), canister("test", "test")),
+            ]),
+            controller_groups: Some(HashMap::from([("prod".to_string(), prod_group)])),
+        };
+
+        let group = dscvr_config
+            .get_controller_group_name("society_rs", "ic")
+            .unwrap();
+        assert_eq!(group, "prod");
+        let mut ids: Vec<_> = dscvr_config
+            .get_instances_for_controller_group("ic", &group)
+            .into_iter()
+            .filter_map(|instance| instance.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["router".to_string(), "society".to_string()]);
+
+        let new_identity = IdentityFromFile::from_str("./keys/prod-backup-2.pem").unwrap();
+        let old_identity = dscvr_config
+            .set_controller(&group, ControllerType::Backup, new_identity.clone())
+            .unwrap();
+        assert_eq!(
+            old_identity,
+            Some(IdentityFromFile::from_str("./keys/prod-backup.pem").unwrap())
+        );
+        assert_eq!(
+            dscvr_config.get_controller("dscvr-event-router", "ic", ControllerType::Backup),
+            Some(&new_identity)
+        );
+        assert!(dscvr_config
+            .set_controller("test", ControllerType::Backup, new_identity)
+            .is_err());
+    }
 }
@@ -0,0 +1,57 @@
+use super::*;
+
+impl DSCVRConfig {
+    /// Gets the name of the `ControllerGroup` assigned to
+    /// a canister on a network.
+    pub(crate) fn get_controller_group_name(
+        &self,
+        canister_name: &str,
+        network: &str,
+    ) -> std::result::Result<String, Error> {
+        self.get_canister_network(canister_name, network)
+            .ok_or_else(|| MissingElement(format!("{canister_name}.{network}")))?
+            .controllers
+            .clone()
+            .ok_or_else(|| MissingElement(format!("{canister_name}.{network}.controllers")))
+    }
+
+    /// Gets every instance, of any canister, controlled by the
+    /// `ControllerGroup` named `controller_group` on a network.
+    /// Since groups are shared, rotating a key of the group
+    /// affects all of them.
+    pub(crate) fn get_instances_for_controller_group(
+        &self,
+        network: &str,
+        controller_group: &str,
+    ) -> Vec<CanisterInstance> {
+        self.canisters
+            .values()
+            .filter_map(|canister| canister.networks.get(network))
+            .filter(|canister_network| {
+                canister_network.controllers.as_deref() == Some(controller_group)
+            })
+            .flat_map(|canister_network| canister_network.get_all_instances())
+            .collect()
+    }
+
+    /// Replaces the identity of `controller` in the
+    /// `ControllerGroup` named `controller_group`.
+    ///
+    /// ### Returns
+    /// - `Result<Option<IdentityFromFile>, DSCVRGenerationError>` - returns
+    ///   `Ok()` with the replaced identity, if any.
+    pub(crate) fn set_controller(
+        &mut self,
+        controller_group: &str,
+        controller: ControllerType,
+        identity: IdentityFromFile,
+    ) -> std::result::Result<Option<IdentityFromFile>, Error> {
+        Ok(self
+            .controller_groups
+            .as_mut()
+            .and_then(|groups| groups.get_mut(controller_group))
+            .ok_or_else(|| MissingElement(format!("controller_groups.{controller_group}")))?
+            .controllers
+            .insert(controller, identity))
+    }
+}
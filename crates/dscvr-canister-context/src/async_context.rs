@@ -0,0 +1,191 @@
+//! Context for update methods that await.
+//!
+//! State can't stay borrowed across an await point: on the IC other messages run while
+//! an update awaits, and a `RefCell` borrow held across the await would make them trap.
+//! `AsyncContext` therefore never hands out a borrow that outlives a closure. The
+//! `*_async` variants let the closure prepare a future (e.g. an inter-canister call)
+//! that is awaited only after the borrow was released.
+
+use crate::reentrancy::{ReentrancyError, ReentrancyGuard};
+use dscvr_interface::Interface;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{Mutex, RwLock};
+use std::thread::LocalKey;
+
+/// Storage the state of an `AsyncContext` can be borrowed from
+pub trait StateCell<State> {
+    /// Run `f` with a shared borrow of the state
+    fn with_ref(&self, f: &mut dyn FnMut(&State));
+    /// Run `f` with a mutable borrow of the state
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State));
+}
+
+impl<State> StateCell<State> for RefCell<State> {
+    fn with_ref(&self, f: &mut dyn FnMut(&State)) {
+        f(&self.borrow())
+    }
+
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State)) {
+        f(&mut self.borrow_mut())
+    }
+}
+
+impl<State: 'static> StateCell<State> for &'static LocalKey<RefCell<State>> {
+    fn with_ref(&self, f: &mut dyn FnMut(&State)) {
+        self.with(|s| f(&s.borrow()))
+    }
+
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State)) {
+        self.with(|s| f(&mut s.borrow_mut()))
+    }
+}
+
+impl<State> StateCell<State> for Mutex<State> {
+    fn with_ref(&self, f: &mut dyn FnMut(&State)) {
+        f(&self.lock().expect("state lock"))
+    }
+
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State)) {
+        f(&mut self.lock().expect("state lock"))
+    }
+}
+
+impl<State> StateCell<State> for RwLock<State> {
+    fn with_ref(&self, f: &mut dyn FnMut(&State)) {
+        f(&self.read().expect("read lock"))
+    }
+
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State)) {
+        f(&mut self.write().expect("write lock"))
+    }
+}
+
+/// Context passed to update methods that await.
+pub struct AsyncContext<'a, State> {
+    state: &'a dyn StateCell<State>,
+    /// The system interface
+    system: &'a dyn Interface,
+}
+
+impl<'a, State> AsyncContext<'a, State> {
+    /// Create a new context
+    #[inline]
+    pub fn new(state: &'a dyn StateCell<State>, system: &'a dyn Interface) -> Self {
+        Self { state, system }
+    }
+
+    /// Read a state with function
+    pub fn read<F: FnOnce(&State) -> R, R>(&self, f: F) -> R {
+        let mut f = Some(f);
+        let mut result = None;
+        self.state.with_ref(&mut |state| {
+            result = f.take().map(|f| f(state));
+        });
+        result.expect("state cell ran the closure")
+    }
+
+    /// Mutate a state with function
+    pub fn mutate<F: FnOnce(&mut State) -> R, R>(&self, f: F) -> R {
+        crate::memo::invalidate_memoized(self.system);
+        let mut f = Some(f);
+        let mut result = None;
+        self.state.with_mut(&mut |state| {
+            result = f.take().map(|f| f(state));
+        });
+        result.expect("state cell ran the closure")
+    }
+
+    /// Read a state with function returning a future that is awaited once the state is
+    /// no longer borrowed
+    #[inline]
+    pub async fn read_async<F, Fut>(&self, f: F) -> Fut::Output
+    where
+        F: FnOnce(&State, &dyn Interface) -> Fut,
+        Fut: Future + 'static,
+    {
+        let future = self.read(|state| f(state, self.system));
+        future.await
+    }
+
+    /// Mutate a state with function returning a future that is awaited once the state
+    /// is no longer borrowed
+    #[inline]
+    pub async fn mutate_async<F, Fut>(&self, f: F) -> Fut::Output
+    where
+        F: FnOnce(&mut State, &dyn Interface) -> Fut,
+        Fut: Future + 'static,
+    {
+        let future = self.mutate(|state| f(state, self.system));
+        future.await
+    }
+
+    /// Await the future returned by `f` while holding the resource `key`.
+    ///
+    /// Fails without running `f` if another update holds the same resource.
+    pub async fn guarded<F, Fut>(&self, key: &str, f: F) -> Result<Fut::Output, ReentrancyError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        let _guard = ReentrancyGuard::acquire(self.system, key)?;
+        Ok(f().await)
+    }
+
+    /// Acquire the resource `key` until the returned guard is dropped
+    #[inline]
+    pub fn reentrancy_guard(&self, key: &str) -> Result<ReentrancyGuard, ReentrancyError> {
+        ReentrancyGuard::acquire(self.system, key)
+    }
+
+    /// Return the system
+    #[inline]
+    pub fn system(&self) -> &'a dyn Interface {
+        self.system
+    }
+}
+
+impl<State> Clone for AsyncContext<'_, State> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state,
+            system: self.system,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dscvr_interface::edge::Edge;
+    use futures::channel::oneshot;
+
+    #[test]
+    fn guarded_rejects_concurrent_entry_across_await() {
+        let system = Edge::default();
+        let state = Mutex::new(());
+        let ctx = AsyncContext::new(&state, &system);
+        let (sender, receiver) = oneshot::channel();
+
+        let first = ctx.guarded("concurrent", || async {
+            // other messages run while the first update awaits
+            receiver.await.expect("sent");
+            1
+        });
+        let second = async {
+            let second = ctx.guarded("concurrent", || async { 2 }).await;
+            sender.send(()).expect("receiver alive");
+            second
+        };
+        let (first, second) = futures::executor::block_on(async { futures::join!(first, second) });
+
+        assert_eq!(first, Ok(1));
+        assert_eq!(second, Err(ReentrancyError("concurrent".to_owned())));
+        assert!(!ReentrancyGuard::is_held(&system, "concurrent"));
+        assert_eq!(
+            futures::executor::block_on(ctx.guarded("concurrent", || async { 3 })),
+            Ok(3)
+        );
+    }
+}
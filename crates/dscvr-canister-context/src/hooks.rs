@@ -0,0 +1,99 @@
+//! Hooks run before and after canister methods.
+//!
+//! Hooks give a single place to plug in auditing, metrics, and rate limiting for every
+//! canister method. They are registered through the `canister_context` module generated
+//! by [`crate::define_state_interface`] and run by the dispatch of the canister
+//! definition.
+
+use candid::Principal;
+use dscvr_interface::Interface;
+use std::sync::RwLock;
+
+/// Description of a canister method call passed to hooks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodCall<'a> {
+    /// Candid name of the method
+    pub method_name: &'a str,
+    /// Caller of the method
+    pub caller: Principal,
+    /// Whether the method is an update
+    pub is_update: bool,
+}
+
+/// Error returned when a before hook rejects a call
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("call rejected by hook: {0}")]
+pub struct HookRejection(pub String);
+
+/// Hook run before a method. Returning an error rejects the call.
+pub type BeforeHook = fn(&MethodCall<'_>) -> Result<(), HookRejection>;
+
+/// Hook run after a method with the instructions it used
+pub type AfterHook = fn(&MethodCall<'_>, u64);
+
+/// Registered before and after hooks
+#[derive(Default)]
+pub struct Hooks {
+    before: RwLock<Vec<BeforeHook>>,
+    after: RwLock<Vec<AfterHook>>,
+}
+
+impl Hooks {
+    /// Create an empty set of hooks
+    pub const fn new() -> Self {
+        Self {
+            before: RwLock::new(vec![]),
+            after: RwLock::new(vec![]),
+        }
+    }
+
+    /// Register a hook run before every method
+    pub fn register_before(&self, hook: BeforeHook) {
+        self.before.write().expect("hooks poisoned").push(hook);
+    }
+
+    /// Register a hook run after every method
+    pub fn register_after(&self, hook: AfterHook) {
+        self.after.write().expect("hooks poisoned").push(hook);
+    }
+
+    /// Run the before hooks, failing on the first that rejects `call`
+    pub fn run_before(&self, call: &MethodCall<'_>) -> Result<(), HookRejection> {
+        // copy the hooks out so they can register hooks themselves
+        let before = self.before.read().expect("hooks poisoned").clone();
+        for hook in before {
+            hook(call)?;
+        }
+        Ok(())
+    }
+
+    /// Run the after hooks of `call`, which used `instructions`
+    pub fn run_after(&self, call: &MethodCall<'_>, instructions: u64) {
+        let after = self.after.read().expect("hooks poisoned").clone();
+        for hook in after {
+            hook(call, instructions);
+        }
+    }
+
+    /// Run `f` surrounded by the registered hooks.
+    ///
+    /// `f` is not run if a before hook rejects the call.
+    pub fn run<F: FnOnce() -> R, R>(
+        &self,
+        system: &dyn Interface,
+        method_name: &str,
+        is_update: bool,
+        f: F,
+    ) -> Result<R, HookRejection> {
+        let call = MethodCall {
+            method_name,
+            caller: system.caller(),
+            is_update,
+        };
+        self.run_before(&call)?;
+        let start = system.instruction_counter();
+        let result = f();
+        self.run_after(&call, system.instruction_counter().saturating_sub(start));
+        Ok(result)
+    }
+}
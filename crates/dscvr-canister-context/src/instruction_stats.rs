@@ -0,0 +1,169 @@
+//! Instructions consumed by canister methods, aggregated per canister and method.
+//!
+//! Context accesses to the state count the instructions they consume with a
+//! [`ScopedInstructionCounter`] and add them to the invocation in progress on the
+//! canister. The dispatch, which knows the method name, takes them with
+//! [`take_state_instructions`] and records the invocation once with
+//! [`record_method_instructions`]. The accesses of methods that await aren't attributed,
+//! as their awaits interleave with other invocations of the canister.
+
+use crate::Interface;
+use candid::{CandidType, Deserialize};
+use ic_canister_logger::scoped_instruction_counter::ScopedInstructionCounter;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Instruction stats keyed by canister id, then by method name
+type StatsByCanister = BTreeMap<Vec<u8>, BTreeMap<String, MethodInstructionStats>>;
+
+static STATS: Mutex<StatsByCanister> = Mutex::new(BTreeMap::new());
+
+/// Instructions consumed by the state accesses in progress, keyed by canister id
+static STATE_INSTRUCTIONS: Mutex<BTreeMap<Vec<u8>, u64>> = Mutex::new(BTreeMap::new());
+
+/// Instructions consumed by a method
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct MethodInstructionStats {
+    /// Number of recorded invocations
+    pub count: u64,
+    /// Total instructions consumed
+    pub total_instructions: u64,
+    /// Largest number of instructions consumed by a single invocation
+    pub max_instructions: u64,
+    /// Total instructions consumed by accesses to the state through a context
+    pub state_instructions: u64,
+}
+
+/// Run the state access `f`, counting its instructions for the invocation in progress
+#[inline]
+pub(crate) fn counted<F: FnOnce() -> R, R>(system: &dyn Interface, f: F) -> R {
+    let _counter = ScopedInstructionCounter::with_recorder(system, add_state_instructions);
+    f()
+}
+
+fn add_state_instructions(system: &dyn Interface, instructions: u64) {
+    let mut pending = STATE_INSTRUCTIONS.lock().expect("stats poisoned");
+    let pending = pending.entry(system.id().as_slice().to_vec()).or_default();
+    *pending = pending.saturating_add(instructions);
+}
+
+/// Take the instructions consumed by state accesses on the canister of `system` since
+/// the last call
+pub fn take_state_instructions(system: &dyn Interface) -> u64 {
+    STATE_INSTRUCTIONS
+        .lock()
+        .expect("stats poisoned")
+        .remove(system.id().as_slice())
+        .unwrap_or_default()
+}
+
+/// Record an invocation of `method_name` by the canister of `system` that consumed
+/// `instructions`, `state_instructions` of them in state accesses
+pub fn record_method_instructions(
+    system: &dyn Interface,
+    method_name: &str,
+    instructions: u64,
+    state_instructions: u64,
+) {
+    let mut stats = STATS.lock().expect("stats poisoned");
+    let stats = stats.entry(system.id().as_slice().to_vec()).or_default();
+    let entry = match stats.get_mut(method_name) {
+        Some(entry) => entry,
+        None => stats.entry(method_name.to_owned()).or_default(),
+    };
+    entry.count += 1;
+    entry.total_instructions = entry.total_instructions.saturating_add(instructions);
+    entry.max_instructions = entry.max_instructions.max(instructions);
+    entry.state_instructions = entry.state_instructions.saturating_add(state_instructions);
+}
+
+/// Return the instruction stats of every method recorded by the canister of `system`
+pub fn method_instruction_stats(system: &dyn Interface) -> Vec<(String, MethodInstructionStats)> {
+    STATS
+        .lock()
+        .expect("stats poisoned")
+        .get(system.id().as_slice())
+        .map(|stats| {
+            stats
+                .iter()
+                .map(|(name, stats)| (name.clone(), stats.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Drop the instruction stats recorded by the canister of `system`
+pub fn reset_method_instruction_stats(system: &dyn Interface) {
+    STATS
+        .lock()
+        .expect("stats poisoned")
+        .remove(system.id().as_slice());
+}
+
+/// Macro that defines the `method_instruction_stats` query.
+///
+/// An optional guard name can be passed, e.g.
+/// `define_method_instruction_stats_interface!(guard = "is_controller")`.
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! define_method_instruction_stats_interface {
+    () => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query]
+        fn method_instruction_stats(
+            ctx: crate::canister_context::ImmutableContext,
+        ) -> Vec<(String, $crate::instruction_stats::MethodInstructionStats)> {
+            $crate::instruction_stats::method_instruction_stats(ctx.system())
+        }
+    };
+    (guard = $guard:literal) => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query(guard = $guard)]
+        fn method_instruction_stats(
+            ctx: crate::canister_context::ImmutableContext,
+        ) -> Vec<(String, $crate::instruction_stats::MethodInstructionStats)> {
+            $crate::instruction_stats::method_instruction_stats(ctx.system())
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ImmutableContext, MutableContext};
+    use candid::Principal;
+    use dscvr_interface::unit_test::UnitTest;
+
+    #[test]
+    fn state_accesses_are_recorded_once_per_invocation() {
+        let system = UnitTest::builder()
+            .canister_id(Principal::from_slice(&[0xfd, 1]))
+            .instruction_counters(vec![10, 15, 20, 28])
+            .build();
+        let mut state = vec![1_u64];
+
+        take_state_instructions(&system);
+        let mut ctx = MutableContext::new(&mut state, &system);
+        ctx.mutate(|state| state.push(2));
+        ImmutableContext::new(&state, &system).read(|state| state.len());
+        let state_instructions = take_state_instructions(&system);
+        assert_eq!(state_instructions, 13);
+        record_method_instructions(&system, "push", 40, state_instructions);
+        record_method_instructions(&system, "push", 60, 0);
+
+        let expected = MethodInstructionStats {
+            count: 2,
+            total_instructions: 100,
+            max_instructions: 60,
+            state_instructions: 13,
+        };
+        assert_eq!(
+            method_instruction_stats(&system),
+            vec![("push".to_string(), expected)]
+        );
+        assert_eq!(take_state_instructions(&system), 0);
+
+        reset_method_instruction_stats(&system);
+        assert!(method_instruction_stats(&system).is_empty());
+    }
+}
@@ -2,14 +2,47 @@
 
 //! Common logic for managing global canister state and context.
 
-use dscvr_interface::Interface;
+pub use dscvr_interface::Interface;
+
+pub mod async_context;
+pub mod hooks;
+pub mod instruction_stats;
+pub mod memo;
+pub mod metrics;
+pub mod reentrancy;
+pub mod response_validation;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scoped_state;
+pub mod shard;
+pub mod snapshot;
+pub mod tx_log;
+
+pub use async_context::{AsyncContext, StateCell};
+pub use hooks::{HookRejection, Hooks, MethodCall};
+pub use metrics::MetricsRegistry;
+pub use reentrancy::{ReentrancyError, ReentrancyGuard};
+pub use response_validation::{ResponseComparison, ResponseDivergence};
+pub use shard::Shard;
+pub use snapshot::StateSnapshot;
+pub use tx_log::{TxLogEntry, TxLogSink};
+
+use instruction_stats::counted;
 
 /// Enum used to describe the sub type of an update.
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum UpdateContext<'a> {
     /// Update that runs on the primary and
     /// should be appended to the TxLog
     Primary,
+    /// Update that runs on the primary and carries the
+    /// call so it can be appended to the TxLog by the
+    /// context's [`TxLogSink`]
+    PrimaryWithTxLog {
+        /// Candid name of the update method
+        method_name: &'a str,
+        /// Raw candid arguments of the update
+        args: &'a [u8],
+    },
     /// Update that is replayed on the Secondary and
     /// should not be appended to the TxLog and does
     /// not require response validation
@@ -20,6 +53,14 @@ pub enum UpdateContext<'a> {
     SecondaryWithValidation(&'a [u8]),
 }
 
+impl UpdateContext<'_> {
+    /// Whether the update runs on the primary
+    #[inline]
+    pub fn is_primary(&self) -> bool {
+        matches!(self, Self::Primary | Self::PrimaryWithTxLog { .. })
+    }
+}
+
 /// Context that only allows read access to state.
 /// Passed as an argument to queries
 pub struct ImmutableContext<'a, State> {
@@ -32,13 +73,13 @@ impl<'a, State> ImmutableContext<'a, State> {
     /// Read a state with function
     #[inline]
     pub fn read<F: FnOnce(&State) -> R, R>(&self, f: F) -> R {
-        f(self.state)
+        counted(self.system, || f(self.state))
     }
 
     /// Read a state and system with function
     #[inline]
     pub fn read_with_system<F: FnOnce(&State, &dyn Interface) -> R, R>(&self, f: F) -> R {
-        f(self.state, self.system)
+        counted(self.system, || f(self.state, self.system))
     }
 
     /// Create a new context
@@ -49,7 +90,7 @@ impl<'a, State> ImmutableContext<'a, State> {
 
     /// Return the system
     #[inline]
-    pub fn system(&self) -> &dyn Interface {
+    pub fn system(&self) -> &'a dyn Interface {
         self.system
     }
 
@@ -76,42 +117,102 @@ pub struct MutableContext<'a, State> {
     state: &'a mut State,
     /// The system interface
     system: &'a dyn Interface,
+    /// Destination of successful primary updates
+    tx_log_sink: Option<&'a dyn TxLogSink>,
 }
 
 impl<'a, State> MutableContext<'a, State> {
     /// Read a state with function
     #[inline]
     pub fn read<F: FnOnce(&State) -> R, R>(&self, f: F) -> R {
-        f(self.state)
+        counted(self.system, || f(self.state))
     }
 
     /// Read a state and system with function
     #[inline]
     pub fn read_with_system<F: FnOnce(&State, &dyn Interface) -> R, R>(&self, f: F) -> R {
-        f(self.state, self.system)
+        counted(self.system, || f(self.state, self.system))
     }
 
     /// Mutate a state with function
     #[inline]
     pub fn mutate<F: FnOnce(&mut State) -> R, R>(&mut self, f: F) -> R {
-        f(self.state)
+        memo::invalidate_memoized(self.system);
+        counted(self.system, || f(self.state))
     }
 
     /// Mutate a state and system with function
     #[inline]
     pub fn mutate_with_system<F: FnOnce(&mut State, &dyn Interface) -> R, R>(&mut self, f: F) -> R {
-        f(self.state, self.system)
+        memo::invalidate_memoized(self.system);
+        counted(self.system, || f(self.state, self.system))
+    }
+
+    /// Mutate a state and system with function, restoring the state if `f` fails so that
+    /// partially applied changes are never observed or recorded in the TxLog.
+    ///
+    /// Note: This clones the whole state, see [`Self::mutate_transactional_part`] to only
+    /// snapshot the part that is touched.
+    #[inline]
+    pub fn mutate_transactional<F, T, E>(&mut self, f: F) -> Result<T, E>
+    where
+        State: Clone,
+        F: FnOnce(&mut State, &dyn Interface) -> Result<T, E>,
+    {
+        memo::invalidate_memoized(self.system);
+        let snapshot = self.state.clone();
+        let result = counted(self.system, || f(self.state, self.system));
+        if result.is_err() {
+            *self.state = snapshot;
+        }
+        result
+    }
+
+    /// Mutate the part of the state returned by `select`, restoring it if `f` fails
+    #[inline]
+    pub fn mutate_transactional_part<S, F, P, T, E>(&mut self, select: S, f: F) -> Result<T, E>
+    where
+        P: Clone,
+        S: FnOnce(&mut State) -> &mut P,
+        F: FnOnce(&mut P, &dyn Interface) -> Result<T, E>,
+    {
+        memo::invalidate_memoized(self.system);
+        let part = select(self.state);
+        let snapshot = part.clone();
+        let result = counted(self.system, || f(part, self.system));
+        if result.is_err() {
+            *part = snapshot;
+        }
+        result
+    }
+
+    /// Acquire the resource `key` until the returned guard is dropped, e.g. across the
+    /// await of an inter-canister call
+    #[inline]
+    pub fn reentrancy_guard(&self, key: &str) -> Result<ReentrancyGuard, ReentrancyError> {
+        ReentrancyGuard::acquire(self.system, key)
     }
 
     /// Create a new context
     #[inline]
     pub fn new(state: &'a mut State, system: &'a dyn Interface) -> Self {
-        Self { state, system }
+        Self {
+            state,
+            system,
+            tx_log_sink: None,
+        }
+    }
+
+    /// Append successful primary updates run through [`Self::run_update`] to `sink`
+    #[inline]
+    pub fn with_tx_log_sink(mut self, sink: &'a dyn TxLogSink) -> Self {
+        self.tx_log_sink = Some(sink);
+        self
     }
 
     /// Return the system
     #[inline]
-    pub fn system(&self) -> &dyn Interface {
+    pub fn system(&self) -> &'a dyn Interface {
         self.system
     }
 
@@ -124,8 +225,15 @@ impl<'a, State> MutableContext<'a, State> {
     /// Return the mutable state
     #[inline]
     pub fn state_mut(&mut self) -> &mut State {
+        memo::invalidate_memoized(self.system);
         self.state
     }
+
+    /// Drop the memoized responses of the canister, for writes that bypass the state
+    #[inline]
+    pub fn invalidate_memoized(&self) {
+        memo::invalidate_memoized(self.system);
+    }
 }
 
 impl<'a, 'b, State> From<&'b MutableContext<'a, State>> for ImmutableContext<'a, State>
@@ -164,29 +272,64 @@ impl<'a, State> From<MutableContext<'a, State>> for ImmutableContext<'a, State>
     }
 }
 
-/// Macro to define the global state interface that's used
-/// for canisters that supports off-chain canister mirroring.
+/// Macro to define the global state interface of a canister.
+///
+/// On wasm the state lives in a thread local. Off-chain, the `backend` decides
+/// where the state lives:
+/// - `mirror` (default): no global state, the state is owned by the mirror or the
+///   embedded canister and only reached through the contexts, or through
+///   [`StateSnapshot`] in the scope of the instance, see [`scoped_state`]
+/// - `rw_lock`: a process wide `RwLock` that canister instances can replace with their
+///   own state, see [`scoped_state`]
+///
+/// The state is created with `Default` unless an `init` expression is passed, e.g.
+/// `define_state_interface!(State, backend = rw_lock, init = State::new(1024))`.
+/// The initializer is evaluated lazily on the first access to the state, so it can
+/// build the state from init args stashed by the `init` method.
 ///
 /// Note: This is a macro since generics are not allowed in
 /// static instances.
-///
 #[macro_export]
-macro_rules! define_common_state_interface {
+macro_rules! define_state_interface {
     ($state: ty) => {
+        $crate::define_state_interface!($state, backend = mirror);
+    };
+    ($state: ty, backend = $backend: ident) => {
+        $crate::define_state_interface!(
+            $state,
+            backend = $backend,
+            init = std::default::Default::default()
+        );
+    };
+    ($state: ty, backend = $backend: ident, init = $init: expr) => {
         pub mod canister_context {
             use super::*;
-            pub use $crate::UpdateContext;
+            pub use $crate::{StateSnapshot, UpdateContext};
 
             pub type StateType = $state;
 
             pub type ImmutableContext<'a> = $crate::ImmutableContext<'a, $state>;
             pub type MutableContext<'a> = $crate::MutableContext<'a, $state>;
+            pub type AsyncContext<'a> = $crate::AsyncContext<'a, $state>;
+
+            /// Hooks run around the canister methods
+            pub static HOOKS: $crate::Hooks = $crate::Hooks::new();
+
+            /// Register a hook run before every canister method
+            pub fn register_before_hook(hook: $crate::hooks::BeforeHook) {
+                HOOKS.register_before(hook);
+            }
+
+            /// Register a hook run after every canister method
+            pub fn register_after_hook(hook: $crate::hooks::AfterHook) {
+                HOOKS.register_after(hook);
+            }
         }
 
         #[cfg(target_arch = "wasm32")]
         impl $state {
             thread_local! {
-                static STATE: std::cell::RefCell<$state> = std::cell::RefCell::default();
+                static STATE: std::cell::RefCell<$state> = std::cell::RefCell::new($init);
             }
 
             #[inline]
@@ -196,68 +339,116 @@ macro_rules! define_common_state_interface {
 
             #[inline]
             pub fn mutate_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
+                $crate::memo::clear_memoized();
                 Self::STATE.with(|s| f(&mut s.borrow_mut()))
             }
+
+            #[inline]
+            pub fn async_context(
+                system: &dyn $crate::Interface,
+            ) -> canister_context::AsyncContext<'_> {
+                $crate::AsyncContext::new(&&Self::STATE, system)
+            }
         }
+
+        #[cfg(target_arch = "wasm32")]
+        impl $crate::StateSnapshot for $state {
+            #[inline]
+            fn with_global_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
+                Self::mutate_state(f)
+            }
+        }
+
+        $crate::define_native_state_backend!($backend, $state, $init);
     };
 }
 
-/// Macro to define the global state interface that's used
-/// for canisters.
-///
-/// Note: This is a macro since generics are not allowed in
-/// static instances.
-///
-/// Note: This macro is deprecated
+/// Off-chain backend of [`define_state_interface`]
+#[doc(hidden)]
 #[macro_export]
-macro_rules! define_v1_common_state_interface {
-    ($state: ty) => {
-        pub mod canister_context {
-            use super::*;
-            pub use $crate::UpdateContext;
-
-            pub type StateType = $state;
-
-            pub type ImmutableContext<'a> = $crate::ImmutableContext<'a, $state>;
-            pub type MutableContext<'a> = $crate::MutableContext<'a, $state>;
+macro_rules! define_native_state_backend {
+    (mirror, $state: ty, $init: expr) => {
+        #[cfg(not(target_arch = "wasm32"))]
+        impl $crate::StateSnapshot for $state {
+            /// Panics outside of the scope of a canister instance, see `scoped_state`
+            #[inline]
+            fn with_global_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
+                let state = $crate::scoped_state::instance_state::<$state>()
+                    .expect("no canister instance in scope, mirror state has no global");
+                let mut state = $crate::scoped_state::lock_instance_state(&state);
+                f(&mut state)
+            }
         }
-
+    };
+    (rw_lock, $state: ty, $init: expr) => {
         #[cfg(not(target_arch = "wasm32"))]
         lazy_static::lazy_static! {
             static ref STATE: std::sync::Arc<std::sync::RwLock<$state>> = {
-                std::sync::Arc::new(std::sync::RwLock::new(std::default::Default::default()))
+                std::sync::Arc::new(std::sync::RwLock::new($init))
             };
         }
 
         #[cfg(not(target_arch = "wasm32"))]
         impl $state {
+            // State of the current canister instance, see `scoped_state`
+            const SCOPED_STATE: $crate::scoped_state::ScopedState<$state> =
+                $crate::scoped_state::ScopedState::new(|| &**STATE);
+
             #[inline]
             pub fn read_state<F: FnOnce(&Self) -> R, R>(f: F) -> R {
-                let state = STATE.read().expect("read lock");
-                f(&state)
+                Self::SCOPED_STATE.read(f)
             }
             #[inline]
             pub fn mutate_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
-                let mut state = STATE.write().expect("write lock");
-                f(&mut state)
-            }
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        impl $state {
-            thread_local! {
-                static STATE: std::cell::RefCell<$state> = std::cell::RefCell::default();
+                $crate::memo::clear_memoized();
+                Self::SCOPED_STATE.mutate(f)
             }
 
             #[inline]
-            pub fn read_state<F: FnOnce(&Self) -> R, R>(f: F) -> R {
-                Self::STATE.with(|s| f(&s.borrow()))
+            pub fn async_context(
+                system: &dyn $crate::Interface,
+            ) -> canister_context::AsyncContext<'_> {
+                $crate::AsyncContext::new(&Self::SCOPED_STATE, system)
             }
+        }
 
+        #[cfg(not(target_arch = "wasm32"))]
+        impl $crate::StateSnapshot for $state {
             #[inline]
-            pub fn mutate_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
-                Self::STATE.with(|s| f(&mut s.borrow_mut()))
+            fn with_global_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R {
+                Self::mutate_state(f)
             }
         }
     };
 }
+
+/// Macro to define the global state interface that's used
+/// for canisters that supports off-chain canister mirroring.
+///
+/// Shorthand for [`define_state_interface`] with the `mirror` backend,
+/// e.g. `define_common_state_interface!(State, State::new(1024))`.
+#[macro_export]
+macro_rules! define_common_state_interface {
+    ($state: ty) => {
+        $crate::define_state_interface!($state, backend = mirror);
+    };
+    ($state: ty, $init: expr) => {
+        $crate::define_state_interface!($state, backend = mirror, init = $init);
+    };
+}
+
+/// Macro to define the global state interface that's used
+/// for canisters.
+///
+/// Shorthand for [`define_state_interface`] with the `rw_lock` backend.
+///
+/// Note: This macro is deprecated
+#[macro_export]
+macro_rules! define_v1_common_state_interface {
+    ($state: ty) => {
+        $crate::define_state_interface!($state, backend = rw_lock);
+    };
+    ($state: ty, $init: expr) => {
+        $crate::define_state_interface!($state, backend = rw_lock, init = $init);
+    };
+}
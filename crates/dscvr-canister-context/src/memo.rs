@@ -0,0 +1,211 @@
+//! Memoization of query responses.
+//!
+//! Memoization is opt-in per method: a query recomputing an expensive aggregation
+//! declares a static [`Memo`] and serves its responses with
+//! [`ImmutableContext::memoized`]. Queries can't persist anything on the IC, so the cache
+//! is only filled from updates and timers with [`MutableContext::memoize`]. Responses are
+//! cached per canister, keyed by method, caller and a hash of the arguments. The arguments
+//! are stored with the response and compared on lookup, so a hash collision is a miss.
+//! Every mutable access to the state drops the cache so a stale response is never served.
+
+use crate::{ImmutableContext, MutableContext};
+use candid::Principal;
+use dscvr_interface::Interface;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Maximum number of responses cached per canister
+pub const MAX_MEMOIZED_RESPONSES: usize = 1024;
+
+/// Key of a memoized response: method, caller and args hash
+type MemoKey = (&'static str, Principal, u64);
+
+/// Cached args and responses keyed by canister id, then by [`MemoKey`]
+type MemoCache = BTreeMap<Vec<u8>, HashMap<MemoKey, (Vec<u8>, Vec<u8>)>>;
+
+static CACHE: Mutex<MemoCache> = Mutex::new(BTreeMap::new());
+
+/// Serializes the tests expecting a cache hit with the tests clearing the whole cache
+#[cfg(test)]
+pub(crate) static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn args_hash(args: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    args.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drop every memoized response of the canister of `system`
+#[inline]
+pub fn invalidate_memoized(system: &dyn Interface) {
+    let mut cache = CACHE.lock().expect("memo cache poisoned");
+    if !cache.is_empty() {
+        cache.remove(system.id().as_slice());
+    }
+}
+
+/// Drop the memoized responses of every canister, for writes to the state made without
+/// an [`Interface`] at hand
+#[inline]
+pub fn clear_memoized() {
+    let mut cache = CACHE.lock().expect("memo cache poisoned");
+    if !cache.is_empty() {
+        cache.clear();
+    }
+}
+
+/// Memoized responses of a query method
+pub struct Memo {
+    method: &'static str,
+}
+
+impl Memo {
+    /// Create the memo of `method`
+    #[inline]
+    pub const fn new(method: &'static str) -> Self {
+        Self { method }
+    }
+
+    fn get(&self, system: &dyn Interface, caller: Principal, args: &[u8]) -> Option<Vec<u8>> {
+        CACHE
+            .lock()
+            .expect("memo cache poisoned")
+            .get(system.id().as_slice())
+            .and_then(|responses| responses.get(&(self.method, caller, args_hash(args))))
+            .filter(|(memoized_args, _)| memoized_args == args)
+            .map(|(_, response)| response.clone())
+    }
+
+    fn insert(&self, system: &dyn Interface, caller: Principal, args: &[u8], response: Vec<u8>) {
+        let mut cache = CACHE.lock().expect("memo cache poisoned");
+        let responses = cache.entry(system.id().as_slice().to_vec()).or_default();
+        if responses.len() >= MAX_MEMOIZED_RESPONSES {
+            responses.clear();
+        }
+        responses.insert(
+            (self.method, caller, args_hash(args)),
+            (args.to_vec(), response),
+        );
+    }
+}
+
+impl<State> ImmutableContext<'_, State> {
+    /// Return the response of `memo` memoized for `caller` and `args`, or compute it with
+    /// `f` without caching it
+    pub fn memoized<F, E>(
+        &self,
+        memo: &Memo,
+        caller: Principal,
+        args: &[u8],
+        f: F,
+    ) -> Result<Vec<u8>, E>
+    where
+        F: FnOnce(&Self) -> Result<Vec<u8>, E>,
+    {
+        match memo.get(self.system, caller, args) {
+            Some(response) => Ok(response),
+            None => f(self),
+        }
+    }
+}
+
+impl<State> MutableContext<'_, State> {
+    /// Compute the response of `memo` for `caller` and `args` with `f` and cache it until
+    /// the state is mutated. Errors are not cached.
+    pub fn memoize<F, E>(&self, memo: &Memo, caller: Principal, args: &[u8], f: F) -> Result<(), E>
+    where
+        F: FnOnce(&ImmutableContext<'_, State>) -> Result<Vec<u8>, E>,
+    {
+        let response = f(&ImmutableContext::new(self.state, self.system))?;
+        memo.insert(self.system, caller, args, response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dscvr_interface::edge::Edge;
+
+    static TOTAL: Memo = Memo::new("total");
+
+    fn total(ctx: &ImmutableContext<'_, Vec<u64>>) -> Result<Vec<u8>, ()> {
+        Ok(ctx.read(|state| state.iter().sum::<u64>().to_le_bytes().to_vec()))
+    }
+
+    #[test]
+    fn memoized_responses_are_filled_by_updates_and_dropped_on_mutation() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let system = Edge::default();
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+        let mut state = vec![1, 2];
+
+        let mut ctx = MutableContext::new(&mut state, &system);
+        ctx.memoize(&TOTAL, alice, b"args", total).unwrap();
+        ctx.mutate(|state| state.push(3));
+        let ctx = ImmutableContext::new(&state, &system);
+        let fresh = |_: &ImmutableContext<'_, Vec<u64>>| Ok::<_, ()>(vec![0]);
+        assert_eq!(ctx.memoized(&TOTAL, alice, b"args", fresh), Ok(vec![0]));
+
+        let ctx = MutableContext::new(&mut state, &system);
+        ctx.memoize(&TOTAL, alice, b"args", total).unwrap();
+        let ctx = ImmutableContext::new(&state, &system);
+        let memoized = 6_u64.to_le_bytes().to_vec();
+        assert_eq!(ctx.memoized(&TOTAL, alice, b"args", fresh), Ok(memoized));
+        assert_eq!(ctx.memoized(&TOTAL, bob, b"args", fresh), Ok(vec![0]));
+        assert_eq!(ctx.memoized(&TOTAL, alice, b"other", fresh), Ok(vec![0]));
+    }
+
+    #[test]
+    fn queries_do_not_fill_the_cache() {
+        let system = Edge::default();
+        let caller = Principal::from_slice(&[3]);
+        let state = vec![4];
+        let ctx = ImmutableContext::new(&state, &system);
+        assert!(ctx.memoized(&TOTAL, caller, b"args", total).is_ok());
+        assert_eq!(TOTAL.get(&system, caller, b"args"), None);
+    }
+
+    #[test]
+    fn colliding_args_are_not_served() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let system = Edge::default();
+        let caller = Principal::from_slice(&[4]);
+        let state = vec![5];
+        TOTAL.insert(&system, caller, b"args", vec![5]);
+        CACHE
+            .lock()
+            .unwrap()
+            .get_mut(system.id().as_slice())
+            .unwrap()
+            .insert(
+                (TOTAL.method, caller, args_hash(b"other")),
+                (b"args".to_vec(), vec![5]),
+            );
+        let ctx = ImmutableContext::new(&state, &system);
+        let fresh = |_: &ImmutableContext<'_, Vec<u64>>| Ok::<_, ()>(vec![0]);
+        assert_eq!(ctx.memoized(&TOTAL, caller, b"other", fresh), Ok(vec![0]));
+        assert_eq!(ctx.memoized(&TOTAL, caller, b"args", fresh), Ok(vec![5]));
+    }
+
+    #[test]
+    fn clearing_drops_the_responses_of_every_canister() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let caller = Principal::from_slice(&[5]);
+        let systems = [1_u8, 2].map(|id| {
+            dscvr_interface::unit_test::UnitTest::builder()
+                .canister_id(Principal::from_slice(&[0xfe, id]))
+                .build()
+        });
+        for system in &systems {
+            TOTAL.insert(system, caller, b"args", vec![1]);
+        }
+        clear_memoized();
+        for system in &systems {
+            assert_eq!(TOTAL.get(system, caller, b"args"), None);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Metrics registry stored in canister state, rendered in the Prometheus text format.
+//!
+//! Canisters can't install a global metrics recorder, so the registry is a plain value kept
+//! in the state, updated by the methods and rendered by a query, see
+//! [`crate::define_metrics_interface`]. It's persisted with the rest of the state so counters
+//! survive upgrades.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Content type of the rendered metrics, for canisters serving them over HTTP
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Bucket upper bounds of histograms without configured buckets
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A metric name and its labels
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, CandidType, Serialize, Deserialize)]
+pub struct SeriesKey {
+    /// Name of the metric
+    pub name: String,
+    /// Labels of the series, sorted by name
+    pub labels: Vec<(String, String)>,
+}
+
+impl SeriesKey {
+    /// Create the key of the series `name` with `labels`
+    pub fn new(name: &str, labels: &[(&str, &str)]) -> Self {
+        let mut labels: Vec<_> = labels
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect();
+        labels.sort();
+        Self {
+            name: name.to_owned(),
+            labels,
+        }
+    }
+}
+
+/// Observations of a histogram series
+#[derive(Debug, Default, Clone, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct HistogramValue {
+    /// Upper bounds of the buckets, in ascending order
+    pub buckets: Vec<f64>,
+    /// Number of observations of each bucket, not cumulative
+    pub counts: Vec<u64>,
+    /// Sum of the observations
+    pub sum: f64,
+    /// Number of observations
+    pub count: u64,
+}
+
+impl HistogramValue {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.buckets.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Counters, gauges and histograms of a canister
+#[derive(Debug, Default, Clone, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct MetricsRegistry {
+    descriptions: BTreeMap<String, String>,
+    histogram_buckets: BTreeMap<String, Vec<f64>>,
+    counters: BTreeMap<SeriesKey, u64>,
+    gauges: BTreeMap<SeriesKey, f64>,
+    histograms: BTreeMap<SeriesKey, HistogramValue>,
+}
+
+impl MetricsRegistry {
+    /// Set the help text of the metric `name`
+    pub fn describe(&mut self, name: &str, description: &str) {
+        self.descriptions
+            .insert(name.to_owned(), description.to_owned());
+    }
+
+    /// Set the bucket upper bounds of the histogram `name`, applied to its new series
+    pub fn set_histogram_buckets(&mut self, name: &str, mut buckets: Vec<f64>) {
+        buckets.sort_by(f64::total_cmp);
+        self.histogram_buckets.insert(name.to_owned(), buckets);
+    }
+
+    /// Add `value` to the counter `name`
+    pub fn increment_counter(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let counter = self
+            .counters
+            .entry(SeriesKey::new(name, labels))
+            .or_default();
+        *counter = counter.saturating_add(value);
+    }
+
+    /// Set the gauge `name` to `value`
+    pub fn set_gauge(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.gauges.insert(SeriesKey::new(name, labels), value);
+    }
+
+    /// Add `delta` to the gauge `name`
+    pub fn add_gauge(&mut self, name: &str, labels: &[(&str, &str)], delta: f64) {
+        *self.gauges.entry(SeriesKey::new(name, labels)).or_default() += delta;
+    }
+
+    /// Record `value` in the histogram `name`
+    pub fn observe_histogram(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let buckets = &self.histogram_buckets;
+        self.histograms
+            .entry(SeriesKey::new(name, labels))
+            .or_insert_with(|| {
+                HistogramValue::new(buckets.get(name).map_or(DEFAULT_BUCKETS, Vec::as_slice))
+            })
+            .observe(value);
+    }
+
+    /// Return the value of a counter series
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        self.counters.get(&SeriesKey::new(name, labels)).copied()
+    }
+
+    /// Return the value of a gauge series
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.gauges.get(&SeriesKey::new(name, labels)).copied()
+    }
+
+    /// Return the observations of a histogram series
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Option<&HistogramValue> {
+        self.histograms.get(&SeriesKey::new(name, labels))
+    }
+
+    /// Drop every series, keeping the descriptions and bucket configuration
+    pub fn reset(&mut self) {
+        self.counters.clear();
+        self.gauges.clear();
+        self.histograms.clear();
+    }
+
+    /// Render every series in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let header = |out: &mut String, last: &mut Option<String>, name: &str, ty: &str| {
+            if last.as_deref() == Some(name) {
+                return;
+            }
+            let metric = metric_name(name);
+            if let Some(description) = self.descriptions.get(name) {
+                let _ = writeln!(out, "# HELP {metric} {}", escape_help(description));
+            }
+            let _ = writeln!(out, "# TYPE {metric} {ty}");
+            *last = Some(name.to_owned());
+        };
+
+        let mut last = None;
+        for (key, value) in &self.counters {
+            header(&mut out, &mut last, &key.name, "counter");
+            write_sample(&mut out, key, "", None, &value.to_string());
+        }
+        for (key, value) in &self.gauges {
+            header(&mut out, &mut last, &key.name, "gauge");
+            write_sample(&mut out, key, "", None, &float(*value));
+        }
+        for (key, histogram) in &self.histograms {
+            header(&mut out, &mut last, &key.name, "histogram");
+            let mut cumulative = 0;
+            for (bound, count) in histogram.buckets.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let le = Some(float(*bound));
+                write_sample(&mut out, key, "_bucket", le, &cumulative.to_string());
+            }
+            let count = histogram.count.to_string();
+            write_sample(&mut out, key, "_bucket", Some("+Inf".to_owned()), &count);
+            write_sample(&mut out, key, "_sum", None, &float(histogram.sum));
+            write_sample(&mut out, key, "_count", None, &count);
+        }
+        out
+    }
+}
+
+fn write_sample(out: &mut String, key: &SeriesKey, suffix: &str, le: Option<String>, value: &str) {
+    let _ = write!(out, "{}{suffix}", metric_name(&key.name));
+    let le = le.iter().map(|le| ("le", le.as_str()));
+    let mut labels = key
+        .labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain(le)
+        .peekable();
+    if labels.peek().is_some() {
+        out.push('{');
+        for (i, (k, v)) in labels.enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{}=\"{}\"", metric_name(k), escape_label_value(v));
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+// Prometheus names only allow `[a-zA-Z_:][a-zA-Z0-9_:]*`, so e.g. dashes become underscores
+fn metric_name(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' | ':' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Macro that defines the `metrics` query, returning the registry selected from the state
+/// rendered in the Prometheus text format, e.g.
+/// `define_metrics_interface!(registry = |state: &crate::State| &state.metrics)`.
+///
+/// An optional guard name can be passed, e.g.
+/// `define_metrics_interface!(registry = .., guard = "is_controller")`.
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! define_metrics_interface {
+    (registry = $registry:expr) => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query]
+        fn metrics(ctx: crate::canister_context::ImmutableContext) -> String {
+            ctx.read(|state| ($registry)(state).render())
+        }
+    };
+    (registry = $registry:expr, guard = $guard:literal) => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query(guard = $guard)]
+        fn metrics(ctx: crate::canister_context::ImmutableContext) -> String {
+            ctx.read(|state| ($registry)(state).render())
+        }
+    };
+}
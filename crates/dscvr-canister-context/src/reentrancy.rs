@@ -0,0 +1,48 @@
+//! Opt-in guard against overlapping updates of the same logical resource.
+//!
+//! Once an update awaits an inter-canister call, other messages can run and mutate
+//! state before it resumes. Holding a [`ReentrancyGuard`] for a resource across the
+//! await makes any other update trying to acquire the same resource fail instead of
+//! interleaving with it.
+
+use dscvr_interface::Interface;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// Resources currently held, keyed by canister id and resource key
+static HELD: Mutex<BTreeSet<(Vec<u8>, String)>> = Mutex::new(BTreeSet::new());
+
+/// Error returned when a resource is already held by another update
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("resource {0} is already in use by another update")]
+pub struct ReentrancyError(pub String);
+
+/// Guard holding a resource until it is dropped
+#[derive(Debug)]
+pub struct ReentrancyGuard {
+    entry: (Vec<u8>, String),
+}
+
+impl ReentrancyGuard {
+    /// Acquire `key` for the canister of `system`, failing if it is already held
+    pub fn acquire(system: &dyn Interface, key: &str) -> Result<Self, ReentrancyError> {
+        let entry = (system.id().as_slice().to_vec(), key.to_owned());
+        if !HELD.lock().expect("held poisoned").insert(entry.clone()) {
+            return Err(ReentrancyError(key.to_owned()));
+        }
+        Ok(Self { entry })
+    }
+
+    /// Whether `key` is currently held for the canister of `system`
+    pub fn is_held(system: &dyn Interface, key: &str) -> bool {
+        HELD.lock()
+            .expect("held poisoned")
+            .contains(&(system.id().as_slice().to_vec(), key.to_owned()))
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        HELD.lock().expect("held poisoned").remove(&self.entry);
+    }
+}
@@ -0,0 +1,147 @@
+//! Validation of replayed update responses against the ones recorded by the primary.
+//!
+//! A secondary replaying the TxLog with [`UpdateContext::SecondaryWithValidation`] is
+//! handed the response the primary produced. [`validate_response`] compares it against
+//! the response produced locally and reports how the two diverged, if they did.
+
+use crate::UpdateContext;
+use candid::IDLArgs;
+
+/// Hook comparing an expected and an actual response
+pub type ResponseValidationHook<'h> = &'h dyn Fn(&[u8], &[u8]) -> Result<(), String>;
+
+/// How a replayed response is compared against the expected one
+#[derive(Clone, Copy)]
+pub enum ResponseComparison<'h> {
+    /// The responses must be byte for byte identical
+    Exact,
+    /// The responses must decode to the same candid values,
+    /// regardless of how they were encoded
+    Candid,
+    /// The responses are compared by a user hook
+    Hook(ResponseValidationHook<'h>),
+}
+
+/// Divergence between a replayed response and the expected one
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ResponseDivergence {
+    /// The responses differ starting at `offset`
+    #[error("response differs at byte {offset} (expected {expected_len} bytes, got {actual_len})")]
+    Bytes {
+        /// Offset of the first differing byte
+        offset: usize,
+        /// Length of the expected response
+        expected_len: usize,
+        /// Length of the actual response
+        actual_len: usize,
+    },
+    /// The expected response is not valid candid
+    #[error("expected response is not valid candid: {0}")]
+    UndecodableExpected(String),
+    /// The actual response is not valid candid
+    #[error("actual response is not valid candid: {0}")]
+    UndecodableActual(String),
+    /// The responses hold a different number of candid values
+    #[error("response has {actual} candid values but {expected} were expected")]
+    Arity {
+        /// Number of expected values
+        expected: usize,
+        /// Number of actual values
+        actual: usize,
+    },
+    /// A candid value of the responses differs
+    #[error("candid value {index} differs: expected {expected}, got {actual}")]
+    Value {
+        /// Position of the value in the response
+        index: usize,
+        /// Expected value
+        expected: String,
+        /// Actual value
+        actual: String,
+    },
+    /// The validation hook rejected the response
+    #[error("response rejected by validation hook: {0}")]
+    Hook(String),
+}
+
+/// Compare `actual` against `expected`
+pub fn validate_response(
+    expected: &[u8],
+    actual: &[u8],
+    comparison: ResponseComparison<'_>,
+) -> Result<(), ResponseDivergence> {
+    match comparison {
+        ResponseComparison::Exact => validate_exact(expected, actual),
+        ResponseComparison::Candid => validate_candid(expected, actual),
+        ResponseComparison::Hook(hook) => hook(expected, actual).map_err(ResponseDivergence::Hook),
+    }
+}
+
+fn validate_exact(expected: &[u8], actual: &[u8]) -> Result<(), ResponseDivergence> {
+    if expected == actual {
+        return Ok(());
+    }
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or_else(|| expected.len().min(actual.len()));
+    Err(ResponseDivergence::Bytes {
+        offset,
+        expected_len: expected.len(),
+        actual_len: actual.len(),
+    })
+}
+
+fn validate_candid(expected: &[u8], actual: &[u8]) -> Result<(), ResponseDivergence> {
+    if expected == actual {
+        return Ok(());
+    }
+    let expected = IDLArgs::from_bytes(expected)
+        .map_err(|e| ResponseDivergence::UndecodableExpected(e.to_string()))?;
+    let actual = IDLArgs::from_bytes(actual)
+        .map_err(|e| ResponseDivergence::UndecodableActual(e.to_string()))?;
+    if expected.args.len() != actual.args.len() {
+        return Err(ResponseDivergence::Arity {
+            expected: expected.args.len(),
+            actual: actual.args.len(),
+        });
+    }
+    match expected
+        .args
+        .iter()
+        .zip(&actual.args)
+        .position(|(expected, actual)| expected != actual)
+    {
+        Some(index) => Err(ResponseDivergence::Value {
+            index,
+            expected: format!("{:?}", expected.args[index]),
+            actual: format!("{:?}", actual.args[index]),
+        }),
+        None => Ok(()),
+    }
+}
+
+impl UpdateContext<'_> {
+    /// The response recorded by the primary, if the update requires validation
+    #[inline]
+    pub fn expected_response(&self) -> Option<&[u8]> {
+        match self {
+            Self::SecondaryWithValidation(expected) => Some(expected),
+            _ => None,
+        }
+    }
+
+    /// Validate `response` against the one recorded by the primary.
+    /// Updates that do not require validation always succeed.
+    pub fn validate_response(
+        &self,
+        response: &[u8],
+        comparison: ResponseComparison<'_>,
+    ) -> Result<(), ResponseDivergence> {
+        match self.expected_response() {
+            Some(expected) => validate_response(expected, response, comparison),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Instance-scoped native state backend.
+//!
+//! Off-chain, the state interface macros keep the state in a process wide `RwLock`,
+//! which is shared by every embedded canister and every test running in the process.
+//! Running a canister instance inside [`scope`] or [`sync_scope`] makes the state
+//! accessors of the macros use the instance's own state instead. Embedded canisters
+//! run every dispatched message inside the scope of their state.
+
+use crate::StateCell;
+use std::any::{type_name, Any};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, TryLockError};
+
+tokio::task_local! {
+    /// State of the canister instance running in the current task
+    static INSTANCE: Arc<dyn Any + Send + Sync>;
+}
+
+/// Run `future` with `state` as the state of the current canister instance
+pub async fn scope<State, F>(state: Arc<Mutex<State>>, future: F) -> F::Output
+where
+    State: Send + 'static,
+    F: Future,
+{
+    INSTANCE.scope(state, future).await
+}
+
+/// Run `f` with `state` as the state of the current canister instance
+pub fn sync_scope<State, F, R>(state: Arc<Mutex<State>>, f: F) -> R
+where
+    State: Send + 'static,
+    F: FnOnce() -> R,
+{
+    INSTANCE.sync_scope(state, f)
+}
+
+/// Return the state of the current canister instance, or `None` outside of [`scope`].
+///
+/// Panics if the state of the instance in scope is not a `State`.
+pub fn instance_state<State: Send + 'static>() -> Option<Arc<Mutex<State>>> {
+    let instance = INSTANCE.try_with(|instance| instance.clone()).ok()?;
+    match instance.downcast::<Mutex<State>>() {
+        Ok(state) => Some(state),
+        Err(_) => panic!(
+            "the state of the canister instance in scope is not a {}",
+            type_name::<State>()
+        ),
+    }
+}
+
+/// Lock the state of a canister instance.
+///
+/// Panics if the state is already locked, e.g. by the dispatch of the running method,
+/// instead of deadlocking.
+pub fn lock_instance_state<State>(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    match state.try_lock() {
+        Ok(state) => state,
+        Err(TryLockError::WouldBlock) => panic!(
+            "the state of the canister instance is locked by the running method, access it \
+             through the context"
+        ),
+        Err(TryLockError::Poisoned(e)) => panic!("state lock poisoned: {e}"),
+    }
+}
+
+/// State of the current canister instance, falling back to a global state outside of
+/// [`scope`]
+pub struct ScopedState<State: 'static> {
+    global: fn() -> &'static RwLock<State>,
+}
+
+impl<State: Send + Sync + 'static> ScopedState<State> {
+    /// Create a scoped state falling back to the state returned by `global`
+    #[inline]
+    pub const fn new(global: fn() -> &'static RwLock<State>) -> Self {
+        Self { global }
+    }
+
+    /// Read the current state with function
+    #[inline]
+    pub fn read<F: FnOnce(&State) -> R, R>(&self, f: F) -> R {
+        match instance_state::<State>() {
+            Some(instance) => f(&lock_instance_state(&instance)),
+            None => f(&(self.global)().read().expect("read lock")),
+        }
+    }
+
+    /// Mutate the current state with function
+    #[inline]
+    pub fn mutate<F: FnOnce(&mut State) -> R, R>(&self, f: F) -> R {
+        match instance_state::<State>() {
+            Some(instance) => f(&mut lock_instance_state(&instance)),
+            None => f(&mut (self.global)().write().expect("write lock")),
+        }
+    }
+}
+
+impl<State: Send + Sync + 'static> StateCell<State> for ScopedState<State> {
+    fn with_ref(&self, f: &mut dyn FnMut(&State)) {
+        self.read(f)
+    }
+
+    fn with_mut(&self, f: &mut dyn FnMut(&mut State)) {
+        self.mutate(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static GLOBAL: RwLock<u64> = RwLock::new(0);
+    const STATE: ScopedState<u64> = ScopedState::new(|| &GLOBAL);
+
+    #[test]
+    fn scope_isolates_instance_state() {
+        let instance = Arc::new(Mutex::new(1_u64));
+        sync_scope(instance.clone(), || STATE.mutate(|state| *state += 1));
+        assert_eq!(*instance.lock().unwrap(), 2);
+        assert_eq!(STATE.read(|state| *state), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a u64")]
+    fn scope_of_another_state_type_panics() {
+        sync_scope(Arc::new(Mutex::new(1_u32)), || STATE.read(|state| *state));
+    }
+
+    #[test]
+    #[should_panic(expected = "locked by the running method")]
+    fn locked_instance_state_panics() {
+        let instance = Arc::new(Mutex::new(1_u64));
+        let _dispatched = instance.lock().unwrap();
+        sync_scope(instance.clone(), || STATE.read(|state| *state));
+    }
+}
@@ -0,0 +1,107 @@
+//! Split of the canister state into independent shards.
+//!
+//! Large canisters can hold each part of their state (e.g. `Users`, `Content`) in its
+//! own [`RefCell`] and implement [`Shard`] for every part with [`crate::define_shards`].
+//! Contexts then borrow every shard separately, so a shard can be mutated while another
+//! one is read, and overlapping borrows of the same shard are reported by name.
+
+use crate::instruction_stats::counted;
+use crate::memo::invalidate_memoized;
+use crate::{ImmutableContext, MutableContext};
+use std::cell::{Ref, RefCell, RefMut};
+
+/// A shard `S` of the state
+pub trait Shard<S> {
+    /// Name of the shard, used when reporting conflicting borrows
+    const NAME: &'static str;
+
+    /// Return the cell holding the shard
+    fn shard_cell(&self) -> &RefCell<S>;
+
+    /// Borrow the shard
+    #[inline]
+    fn borrow_shard(&self) -> Ref<'_, S> {
+        self.shard_cell()
+            .try_borrow()
+            .unwrap_or_else(|_| panic!("shard {} is already mutably borrowed", Self::NAME))
+    }
+
+    /// Mutably borrow the shard
+    #[inline]
+    fn borrow_shard_mut(&self) -> RefMut<'_, S> {
+        self.shard_cell()
+            .try_borrow_mut()
+            .unwrap_or_else(|_| panic!("shard {} is already borrowed", Self::NAME))
+    }
+}
+
+impl<State> ImmutableContext<'_, State> {
+    /// Read the shard `S` with function
+    #[inline]
+    pub fn read_shard<S, F: FnOnce(&S) -> R, R>(&self, f: F) -> R
+    where
+        State: Shard<S>,
+    {
+        counted(self.system, || f(&self.state.borrow_shard()))
+    }
+}
+
+impl<State> MutableContext<'_, State> {
+    /// Read the shard `S` with function
+    #[inline]
+    pub fn read_shard<S, F: FnOnce(&S) -> R, R>(&self, f: F) -> R
+    where
+        State: Shard<S>,
+    {
+        counted(self.system, || f(&self.state.borrow_shard()))
+    }
+
+    /// Mutate the shard `S` with function
+    #[inline]
+    pub fn mutate_shard<S, F: FnOnce(&mut S) -> R, R>(&mut self, f: F) -> R
+    where
+        State: Shard<S>,
+    {
+        invalidate_memoized(self.system);
+        counted(self.system, || f(&mut self.state.borrow_shard_mut()))
+    }
+
+    /// Mutate the shard `S` with function while reading the shard `O`
+    #[inline]
+    pub fn mutate_shard_reading<S, O, F: FnOnce(&mut S, &O) -> R, R>(&mut self, f: F) -> R
+    where
+        State: Shard<S> + Shard<O>,
+    {
+        invalidate_memoized(self.system);
+        let other = Shard::<O>::borrow_shard(&*self.state);
+        counted(self.system, || {
+            f(&mut Shard::<S>::borrow_shard_mut(&*self.state), &other)
+        })
+    }
+}
+
+/// Implement [`Shard`] for every `RefCell` field of a state.
+///
+/// ```ignore
+/// struct State {
+///     users: RefCell<Users>,
+///     content: RefCell<Content>,
+/// }
+///
+/// define_shards!(State { users: Users, content: Content });
+/// ```
+#[macro_export]
+macro_rules! define_shards {
+    ($state: ty { $($field: ident: $shard: ty),+ $(,)? }) => {
+        $(
+            impl $crate::shard::Shard<$shard> for $state {
+                const NAME: &'static str = stringify!($field);
+
+                #[inline]
+                fn shard_cell(&self) -> &std::cell::RefCell<$shard> {
+                    &self.$field
+                }
+            }
+        )+
+    };
+}
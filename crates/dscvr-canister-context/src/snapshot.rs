@@ -0,0 +1,146 @@
+//! In-memory checkpoints of the global canister state.
+//!
+//! Implemented for the state type by the state interface macros, so tests can
+//! checkpoint the state, run a scenario and roll back without rebuilding fixtures.
+
+use ic_canister_stable_storage::data_format::DataFormatType;
+use instrumented_error::Result;
+
+/// Snapshot and restore of the global state
+pub trait StateSnapshot: Sized {
+    /// Run `f` with mutable access to the global state
+    fn with_global_state<F: FnOnce(&mut Self) -> R, R>(f: F) -> R;
+
+    /// Take a snapshot of the global state by cloning it
+    fn snapshot() -> Self
+    where
+        Self: Clone,
+    {
+        Self::with_global_state(|state| state.clone())
+    }
+
+    /// Replace the global state with `snapshot`
+    fn restore(snapshot: Self) {
+        crate::memo::clear_memoized();
+        Self::with_global_state(|state| *state = snapshot);
+    }
+
+    /// Take a snapshot of the global state serialized with `format`
+    fn snapshot_bytes(format: DataFormatType) -> Result<Vec<u8>>
+    where
+        Self: serde::Serialize,
+    {
+        Self::with_global_state(|state| format.serde_serialize_bytes(state))
+    }
+
+    /// Replace the global state with a snapshot serialized with `format`
+    fn restore_bytes(format: DataFormatType, bytes: &[u8]) -> Result<()>
+    where
+        Self: for<'a> serde::Deserialize<'a>,
+    {
+        let snapshot = format.serde_deserialize_bytes(bytes)?;
+        Self::restore(snapshot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scoped_state::sync_scope;
+    use serde::{Deserialize, Serialize};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    pub struct State {
+        counter: u64,
+        names: Vec<String>,
+    }
+
+    crate::define_state_interface!(State);
+
+    #[test]
+    fn mirror_snapshot_round_trip() {
+        let _lock = crate::memo::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let instance = Arc::new(Mutex::new(State {
+            counter: 1,
+            names: vec!["a".to_string()],
+        }));
+        sync_scope(instance.clone(), || {
+            let snapshot = State::snapshot();
+            State::with_global_state(|state| {
+                state.counter += 1;
+                state.names.clear();
+            });
+            let system = dscvr_interface::unit_test::UnitTest::builder().build();
+            let memo = crate::memo::Memo::new("names");
+            let caller = candid::Principal::anonymous();
+            let mut memoized = State::default();
+            crate::MutableContext::new(&mut memoized, &system)
+                .memoize(&memo, caller, b"", |_| Ok::<_, ()>(vec![1]))
+                .unwrap();
+            State::restore(snapshot.clone());
+            assert_eq!(State::snapshot(), snapshot);
+            let ctx = crate::ImmutableContext::new(&memoized, &system);
+            let fresh = ctx.memoized(&memo, caller, b"", |_| Ok::<_, ()>(vec![0]));
+            assert_eq!(fresh, Ok(vec![0]));
+
+            let bytes = State::snapshot_bytes(DataFormatType::MsgPack).unwrap();
+            State::with_global_state(|state| state.counter = 7);
+            State::restore_bytes(DataFormatType::MsgPack, &bytes).unwrap();
+            assert_eq!(State::snapshot(), snapshot);
+        });
+        assert_eq!(instance.lock().unwrap().counter, 1);
+    }
+
+    #[test]
+    fn generated_canister_context_uses_the_state() {
+        use canister_context::StateSnapshot as _;
+
+        let _lock = crate::memo::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let system = dscvr_interface::unit_test::UnitTest::builder().build();
+        let mut state = canister_context::StateType::default();
+        canister_context::MutableContext::new(&mut state, &system).mutate(|s| s.counter = 2);
+        let ctx = canister_context::ImmutableContext::new(&state, &system);
+        assert_eq!(ctx.read(|s| s.counter), 2);
+        assert!(canister_context::UpdateContext::Primary.is_primary());
+
+        let cell = std::cell::RefCell::new(state);
+        let ctx = canister_context::AsyncContext::new(&cell, &system);
+        ctx.mutate(|s| s.names.push("b".to_string()));
+        assert_eq!(ctx.read(|s| s.names.clone()), vec!["b".to_string()]);
+
+        let instance = Arc::new(Mutex::new(cell.into_inner()));
+        sync_scope(instance, || assert_eq!(State::snapshot().counter, 2));
+
+        canister_context::register_before_hook(|call| {
+            if call.method_name == "rejected" {
+                return Err(crate::HookRejection("rejected".to_string()));
+            }
+            Ok(())
+        });
+        canister_context::register_after_hook(|_, _| {});
+        let call = |method_name| crate::MethodCall {
+            method_name,
+            caller: candid::Principal::anonymous(),
+            is_update: true,
+        };
+        assert!(canister_context::HOOKS
+            .run_before(&call("accepted"))
+            .is_ok());
+        assert!(canister_context::HOOKS
+            .run_before(&call("rejected"))
+            .is_err());
+        canister_context::HOOKS.run_after(&call("accepted"), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "no canister instance in scope")]
+    fn mirror_snapshot_outside_of_scope_panics() {
+        State::snapshot();
+    }
+}
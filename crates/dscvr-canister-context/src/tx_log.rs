@@ -0,0 +1,57 @@
+//! Capture of successful primary updates into a transaction log.
+//!
+//! A secondary canister mirrors the primary by replaying its TxLog. Updates dispatched
+//! with [`UpdateContext::PrimaryWithTxLog`] and run through
+//! [`MutableContext::run_update`] are handed to the context's [`TxLogSink`] once they
+//! succeed, so canisters no longer have to append to the log around the macro layer.
+
+use crate::{MutableContext, UpdateContext};
+use candid::Principal;
+
+/// A successful update to append to the TxLog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxLogEntry<'a> {
+    /// Candid name of the update method
+    pub method_name: &'a str,
+    /// Raw candid arguments of the update
+    pub args: &'a [u8],
+    /// Raw candid response of the update
+    pub response: &'a [u8],
+    /// Caller of the update
+    pub caller: Principal,
+    /// Time of the update in nanoseconds
+    pub time: u64,
+}
+
+/// Destination of the TxLog entries of successful primary updates
+pub trait TxLogSink {
+    /// Append an entry to the TxLog
+    fn append(&self, entry: &TxLogEntry<'_>);
+}
+
+impl<State> MutableContext<'_, State> {
+    /// Run an update method with function and append it to the TxLog sink if it succeeds
+    /// and `update_context` carries the call.
+    pub fn run_update<F, E>(
+        &mut self,
+        update_context: &UpdateContext<'_>,
+        f: F,
+    ) -> Result<Vec<u8>, E>
+    where
+        F: FnOnce(&mut Self) -> Result<Vec<u8>, E>,
+    {
+        let response = f(self)?;
+        if let (Some(sink), UpdateContext::PrimaryWithTxLog { method_name, args }) =
+            (self.tx_log_sink, update_context)
+        {
+            sink.append(&TxLogEntry {
+                method_name,
+                args,
+                response: &response,
+                caller: self.system.caller(),
+                time: self.system.time(),
+            });
+        }
+        Ok(response)
+    }
+}
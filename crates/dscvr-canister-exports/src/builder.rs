@@ -0,0 +1,243 @@
+//! Programmatic construction of a [`CanisterDefinition`].
+//!
+//! Host-side harnesses, mocks and partial canisters register their methods at runtime
+//! instead of through the `linkme` distributed slices of `define_canister_exports!`.
+
+use crate::{
+    CanisterAsyncUpdateMethod, CanisterCompositeQueryMethod, CanisterDefinition,
+    CanisterDefinitionError, CanisterGuard, CanisterInitMethod, CanisterInspectMessageMethod,
+    CanisterLifecycleMethod, CanisterMethod, CanisterUpdateMethod, MethodMetadata,
+};
+use dscvr_canister_context::Hooks;
+
+type Registrations<T> = Vec<(&'static str, T)>;
+type MethodRegistrations<T> = Vec<(&'static str, T, MethodMetadata)>;
+type HookRegistrations<T> = Vec<(&'static str, i32, T)>;
+
+/// Builder of a [`CanisterDefinition`]
+pub struct CanisterDefinitionBuilder<State> {
+    updates: MethodRegistrations<CanisterUpdateMethod<State>>,
+    async_updates: MethodRegistrations<CanisterAsyncUpdateMethod<State>>,
+    queries: MethodRegistrations<CanisterMethod<State>>,
+    composite_queries: MethodRegistrations<CanisterCompositeQueryMethod<State>>,
+    init: Registrations<CanisterInitMethod<State>>,
+    init_hooks: HookRegistrations<CanisterInitMethod<State>>,
+    post_upgrade: Registrations<CanisterLifecycleMethod<State>>,
+    post_upgrade_hooks: HookRegistrations<CanisterLifecycleMethod<State>>,
+    pre_upgrade: Registrations<CanisterLifecycleMethod<State>>,
+    pre_upgrade_hooks: HookRegistrations<CanisterLifecycleMethod<State>>,
+    global_timer: Registrations<CanisterLifecycleMethod<State>>,
+    heartbeat: Registrations<CanisterLifecycleMethod<State>>,
+    inspect_message: Registrations<CanisterInspectMessageMethod<State>>,
+    guards: Registrations<CanisterGuard<State>>,
+    hooks: Option<&'static Hooks>,
+    primary: bool,
+}
+
+impl<State> Default for CanisterDefinitionBuilder<State> {
+    fn default() -> Self {
+        Self {
+            updates: vec![],
+            async_updates: vec![],
+            queries: vec![],
+            composite_queries: vec![],
+            init: vec![],
+            init_hooks: vec![],
+            post_upgrade: vec![],
+            post_upgrade_hooks: vec![],
+            pre_upgrade: vec![],
+            pre_upgrade_hooks: vec![],
+            global_timer: vec![],
+            heartbeat: vec![],
+            inspect_message: vec![],
+            guards: vec![],
+            hooks: None,
+            primary: true,
+        }
+    }
+}
+
+impl<State> CanisterDefinitionBuilder<State> {
+    /// Create a builder of a primary canister without any registration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an update method
+    pub fn update(mut self, name: &'static str, method: CanisterUpdateMethod<State>) -> Self {
+        self.updates.push((name, method, MethodMetadata::update()));
+        self
+    }
+
+    /// Register an update method with metadata
+    pub fn update_with_metadata(
+        mut self,
+        name: &'static str,
+        method: CanisterUpdateMethod<State>,
+        metadata: MethodMetadata,
+    ) -> Self {
+        self.updates.push((name, method, metadata));
+        self
+    }
+
+    /// Register an update method that awaits
+    pub fn async_update(
+        mut self,
+        name: &'static str,
+        method: CanisterAsyncUpdateMethod<State>,
+    ) -> Self {
+        self.async_updates
+            .push((name, method, MethodMetadata::update()));
+        self
+    }
+
+    /// Register a query method
+    pub fn query(mut self, name: &'static str, method: CanisterMethod<State>) -> Self {
+        self.queries.push((name, method, MethodMetadata::query()));
+        self
+    }
+
+    /// Register a query method with metadata
+    pub fn query_with_metadata(
+        mut self,
+        name: &'static str,
+        method: CanisterMethod<State>,
+        metadata: MethodMetadata,
+    ) -> Self {
+        self.queries.push((name, method, metadata));
+        self
+    }
+
+    /// Register a composite query method
+    pub fn composite_query(
+        mut self,
+        name: &'static str,
+        method: CanisterCompositeQueryMethod<State>,
+    ) -> Self {
+        self.composite_queries
+            .push((name, method, MethodMetadata::query().composite()));
+        self
+    }
+
+    /// Register the init method
+    pub fn init(mut self, method: CanisterInitMethod<State>) -> Self {
+        self.init.push(("init", method));
+        self
+    }
+
+    /// Register an init hook, run in ascending `order`
+    pub fn init_hook(
+        mut self,
+        name: &'static str,
+        order: i32,
+        method: CanisterInitMethod<State>,
+    ) -> Self {
+        self.init_hooks.push((name, order, method));
+        self
+    }
+
+    /// Register the post upgrade method
+    pub fn post_upgrade(mut self, method: CanisterLifecycleMethod<State>) -> Self {
+        self.post_upgrade.push(("post_upgrade", method));
+        self
+    }
+
+    /// Register a post upgrade hook, run in ascending `order`
+    pub fn post_upgrade_hook(
+        mut self,
+        name: &'static str,
+        order: i32,
+        method: CanisterLifecycleMethod<State>,
+    ) -> Self {
+        self.post_upgrade_hooks.push((name, order, method));
+        self
+    }
+
+    /// Register the pre upgrade method
+    pub fn pre_upgrade(mut self, method: CanisterLifecycleMethod<State>) -> Self {
+        self.pre_upgrade.push(("pre_upgrade", method));
+        self
+    }
+
+    /// Register a pre upgrade hook, run in ascending `order`
+    pub fn pre_upgrade_hook(
+        mut self,
+        name: &'static str,
+        order: i32,
+        method: CanisterLifecycleMethod<State>,
+    ) -> Self {
+        self.pre_upgrade_hooks.push((name, order, method));
+        self
+    }
+
+    /// Register the global timer method
+    pub fn global_timer(mut self, method: CanisterLifecycleMethod<State>) -> Self {
+        self.global_timer.push(("global_timer", method));
+        self
+    }
+
+    /// Register the heartbeat method
+    pub fn heartbeat(mut self, method: CanisterLifecycleMethod<State>) -> Self {
+        self.heartbeat.push(("heartbeat", method));
+        self
+    }
+
+    /// Register the inspect message method
+    pub fn inspect_message(mut self, method: CanisterInspectMessageMethod<State>) -> Self {
+        self.inspect_message.push(("inspect_message", method));
+        self
+    }
+
+    /// Register a named guard
+    pub fn guard(mut self, name: &'static str, guard: CanisterGuard<State>) -> Self {
+        self.guards.push((name, guard));
+        self
+    }
+
+    /// Run `hooks` around every dispatched method
+    pub fn hooks(mut self, hooks: &'static Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Set whether this is the primary registration
+    pub fn primary(mut self, primary: bool) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    /// Build the definition, with lifecycle methods that do nothing for the required
+    /// ones that have no hook
+    pub fn build(mut self) -> Result<CanisterDefinition<State>, CanisterDefinitionError> {
+        if self.init.is_empty() && self.init_hooks.is_empty() {
+            self = self.init(|_, _, _| {});
+        }
+        if self.post_upgrade.is_empty() && self.post_upgrade_hooks.is_empty() {
+            self = self.post_upgrade(|_, _| {});
+        }
+        if self.pre_upgrade.is_empty() && self.pre_upgrade_hooks.is_empty() {
+            self = self.pre_upgrade(|_, _| {});
+        }
+        CanisterDefinition::try_new(
+            &self.updates,
+            &self.async_updates,
+            &self.queries,
+            &self.composite_queries,
+            &self.init,
+            &self.init_hooks,
+            &self.post_upgrade,
+            &self.post_upgrade_hooks,
+            &self.pre_upgrade,
+            &self.pre_upgrade_hooks,
+            &self.global_timer,
+            &self.heartbeat,
+            &self.inspect_message,
+            &self.guards,
+            self.primary,
+        )
+        .map(|definition| CanisterDefinition {
+            hooks: self.hooks,
+            ..definition
+        })
+    }
+}
@@ -0,0 +1,95 @@
+//! Generation of the candid service definition of registered exports.
+
+use crate::{CallType, CanisterDefinition, MethodMetadata};
+use candid::pretty::candid::compile;
+use candid::types::internal::TypeContainer;
+use candid::types::{FuncMode, Function, Type, TypeInner};
+use candid::CandidType;
+use std::path::Path;
+
+/// Candid types of the arguments and return values of a method, registering the
+/// named types they reference in the container
+pub type CandidSignature = fn(&mut TypeContainer) -> (Vec<Type>, Vec<Type>);
+
+/// Signature of a method taking `Args` and returning `Rets`, both given as tuples
+pub fn candid_signature<Args: CandidType, Rets: CandidType>(
+    env: &mut TypeContainer,
+) -> (Vec<Type>, Vec<Type>) {
+    (
+        argument_types(env.add::<Args>()),
+        argument_types(env.add::<Rets>()),
+    )
+}
+
+/// Split a tuple type into the types of its elements
+fn argument_types(ty: Type) -> Vec<Type> {
+    match ty.as_ref() {
+        TypeInner::Null => vec![],
+        TypeInner::Record(fields)
+            if fields
+                .iter()
+                .enumerate()
+                .all(|(i, field)| field.id.get_id() == i as u32) =>
+        {
+            fields.iter().map(|field| field.ty.clone()).collect()
+        }
+        _ => vec![ty],
+    }
+}
+
+impl MethodMetadata {
+    /// Candid function type of the method
+    fn candid_function(&self, env: &mut TypeContainer) -> Type {
+        let (args, rets) = match self.signature {
+            Some(signature) => signature(env),
+            None => (named_types(self.arg_types), named_types(self.ret_types)),
+        };
+        let modes = match (self.call_type, self.composite) {
+            (CallType::Query, true) => vec![FuncMode::CompositeQuery],
+            (CallType::Query, false) => vec![FuncMode::Query],
+            (CallType::Update, _) => vec![],
+        };
+        TypeInner::Func(Function { modes, args, rets }).into()
+    }
+}
+
+/// Reference types by candid name
+fn named_types(names: &[&str]) -> Vec<Type> {
+    names
+        .iter()
+        .map(|name| TypeInner::Var((*name).to_string()).into())
+        .collect()
+}
+
+impl<State> CanisterDefinition<State> {
+    /// Candid service definition of the registered update and query methods
+    pub fn to_candid_interface(&self) -> String {
+        let mut env = TypeContainer::new();
+        let mut methods: Vec<(String, Type)> = self
+            .method_metadata
+            .iter()
+            .map(|(name, metadata)| (name.clone(), metadata.candid_function(&mut env)))
+            .collect();
+        methods.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let service = compile(&env.env, &Some(TypeInner::Service(methods).into()));
+
+        // candid has no deprecation attribute, so surface it to readers of the file
+        let mut interface = String::new();
+        for (name, deprecation) in self.deprecated_methods() {
+            interface.push_str(&format!("// {name}: {deprecation}\n"));
+        }
+        interface.push_str(&service);
+        interface
+    }
+
+    /// Write the candid service definition to `path`, e.g. from a build script.
+    ///
+    /// The file is left untouched if it is up to date so it doesn't trigger rebuilds.
+    pub fn write_candid_interface(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let contents = self.to_candid_interface();
+        if std::fs::read_to_string(path.as_ref()).ok().as_deref() == Some(contents.as_str()) {
+            return Ok(());
+        }
+        std::fs::write(path, contents)
+    }
+}
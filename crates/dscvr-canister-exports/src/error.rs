@@ -0,0 +1,141 @@
+//! Error returned by canister methods.
+
+use candid::{CandidType, Deserialize};
+
+/// Category of a [`CanisterError`]
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, Hash, CandidType, Deserialize, derive_more::Display,
+)]
+pub enum CanisterErrorCode {
+    /// The arguments of the call are invalid
+    InvalidArgument,
+    /// The caller is not allowed to call the method
+    Unauthorized,
+    /// The requested entity does not exist
+    NotFound,
+    /// The call conflicts with the current state
+    Conflict,
+    /// The canister failed to handle the call
+    Internal,
+}
+
+/// Error returned by canister methods
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, thiserror::Error)]
+#[error("{code}: {message}")]
+pub struct CanisterError {
+    /// Category of the error
+    pub code: CanisterErrorCode,
+    /// Human readable message
+    pub message: String,
+    /// Candid encoded details of the error
+    pub payload: Option<Vec<u8>>,
+}
+
+impl CanisterError {
+    /// Create an error without payload
+    pub fn new(code: CanisterErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            payload: None,
+        }
+    }
+
+    /// Attach `details` to the error, encoded as candid
+    pub fn with_payload<T: CandidType>(mut self, details: &T) -> Self {
+        self.payload = candid::encode_one(details).ok();
+        self
+    }
+
+    /// Decode the details attached to the error
+    pub fn payload<T: CandidType + for<'a> Deserialize<'a>>(&self) -> Option<T> {
+        self.payload
+            .as_ref()
+            .and_then(|payload| candid::decode_one(payload).ok())
+    }
+
+    /// Message the call is rejected with
+    pub fn to_reject_message(&self) -> String {
+        self.to_string()
+    }
+
+    /// Reject the current call with this error
+    #[cfg(target_arch = "wasm32")]
+    pub fn reject(&self) {
+        ic_cdk::api::call::reject(&self.to_reject_message());
+    }
+}
+
+impl From<String> for CanisterError {
+    fn from(message: String) -> Self {
+        Self::new(CanisterErrorCode::Internal, message)
+    }
+}
+
+impl From<&str> for CanisterError {
+    fn from(message: &str) -> Self {
+        Self::new(CanisterErrorCode::Internal, message)
+    }
+}
+
+impl From<dscvr_canister_context::HookRejection> for CanisterError {
+    fn from(rejection: dscvr_canister_context::HookRejection) -> Self {
+        Self::new(CanisterErrorCode::Unauthorized, rejection.0)
+    }
+}
+
+/// Error returned when the registered exports don't form a valid canister
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CanisterDefinitionError {
+    /// A required lifecycle method was not registered
+    #[error("no {0} method was registered")]
+    MissingLifecycleMethod(&'static str),
+    /// A lifecycle method was registered more than once
+    #[error("{kind} was registered more than once: {names:?}")]
+    DuplicateLifecycleMethod {
+        /// Kind of lifecycle method
+        kind: &'static str,
+        /// Names of the registered methods
+        names: Vec<&'static str>,
+    },
+    /// A method name was registered more than once
+    #[error("method {0} was registered more than once")]
+    DuplicateMethod(String),
+    /// A guard name was registered more than once
+    #[error("guard {0} was registered more than once")]
+    DuplicateGuard(String),
+    /// A method references a guard that was not registered
+    #[error("method {method} references the unregistered guard {guard}")]
+    UnregisteredGuard {
+        /// Name of the method
+        method: String,
+        /// Name of the guard
+        guard: &'static str,
+    },
+}
+
+/// Error returned when the metadata section of a canister wasm is malformed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CanisterMetadataError {
+    /// A line of the header is not a `key: value` pair
+    #[error("invalid metadata line: {0}")]
+    InvalidLine(String),
+    /// The schema version is not a number
+    #[error("invalid schema version: {0}")]
+    InvalidSchemaVersion(String),
+    /// The module does not start with the wasm magic and version
+    #[error("not a wasm module")]
+    NotWasm,
+    /// A section is longer than the rest of the module
+    #[error("truncated wasm section")]
+    TruncatedSection,
+    /// A section length is not a valid LEB128 encoded u32
+    #[error("invalid wasm section length")]
+    InvalidSectionLength,
+    /// The metadata section is not valid UTF-8
+    #[error("metadata is not valid UTF-8")]
+    InvalidUtf8,
+    /// A field is missing from the header
+    #[error("metadata has no {0}")]
+    MissingField(&'static str),
+}
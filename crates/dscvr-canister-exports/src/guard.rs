@@ -0,0 +1,63 @@
+//! Named guards checked before canister methods run.
+//!
+//! Methods reference a guard by name through [`crate::MethodMetadata::with_guard`] and
+//! the guard functions are registered in the `GUARDS` slice, so both the canister and
+//! the embedded agent check calls the same way.
+
+use crate::{CanisterDefinition, CanisterError, CanisterErrorCode};
+use dscvr_canister_context::ImmutableContext;
+
+/// Aliased type for a guard, failing if the caller is not allowed to call the method
+pub type CanisterGuard<State> = fn(ImmutableContext<'_, State>) -> Result<(), CanisterError>;
+
+/// Guard allowing only the controllers of the canister
+pub fn controller_guard<State>(ctx: ImmutableContext<'_, State>) -> Result<(), CanisterError> {
+    let caller = ctx.system().caller();
+    if ctx.system().is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(CanisterError::new(
+            CanisterErrorCode::Unauthorized,
+            format!("{caller} is not a controller"),
+        ))
+    }
+}
+
+/// Guard rejecting the anonymous principal
+pub fn authenticated_guard<State>(ctx: ImmutableContext<'_, State>) -> Result<(), CanisterError> {
+    if ctx.system().caller() == candid::Principal::anonymous() {
+        Err(CanisterError::new(
+            CanisterErrorCode::Unauthorized,
+            "anonymous callers are not allowed",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl<State> CanisterDefinition<State> {
+    /// Check the guard of `method`, if it references one.
+    ///
+    /// Fails if the guard rejects the call. Guards referenced by the methods are checked
+    /// to be registered by [`CanisterDefinition::try_new`].
+    pub fn check_guard(
+        &self,
+        method: &str,
+        ctx: ImmutableContext<'_, State>,
+    ) -> Result<(), CanisterError> {
+        let Some(name) = self
+            .method_metadata
+            .get(method)
+            .and_then(|metadata| metadata.guard)
+        else {
+            return Ok(());
+        };
+        match self.guards.get(name) {
+            Some(guard) => guard(ctx),
+            None => Err(CanisterError::new(
+                CanisterErrorCode::Internal,
+                format!("method {method} references the unregistered guard {name}"),
+            )),
+        }
+    }
+}
@@ -3,7 +3,31 @@
 //! Functionality for registering canister lifecycle and methods for use
 // with the dscvr canister mirror
 
+mod builder;
+mod candid_interface;
+mod error;
+mod guard;
+mod lifecycle;
+mod metadata;
+pub mod method_stats;
+pub mod typed;
+pub mod wasm_metadata;
+
+pub use builder::CanisterDefinitionBuilder;
+pub use candid_interface::{candid_signature, CandidSignature};
+pub use error::{CanisterDefinitionError, CanisterError, CanisterErrorCode, CanisterMetadataError};
+pub use guard::{authenticated_guard, controller_guard, CanisterGuard};
+pub use lifecycle::{InitHooks, LifecycleHook, LifecycleHooks, DEFAULT_HOOK_ORDER};
+pub use metadata::{CallType, Deprecation, MethodMetadata};
+pub use method_stats::MethodStats;
+
+use dscvr_canister_context::{
+    AsyncContext, Hooks, ImmutableContext, Interface, MethodCall, MutableContext, UpdateContext,
+};
+use method_stats::{measured, record_method_call};
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 
 /// Define the types that allow exporting canister methods
 #[macro_export]
@@ -12,43 +36,91 @@ macro_rules! define_canister_exports {
     () => {
         pub mod canister_exports {
             /// Aliased type for a canister query method
-            pub type Method =
-                fn(crate::canister_context::ImmutableContext<'_>, &[u8]) -> Result<Vec<u8>, String>;
+            pub type Method = fn(
+                crate::canister_context::ImmutableContext<'_>,
+                &[u8],
+            ) -> Result<Vec<u8>, $crate::CanisterError>;
             /// Aliased type for a canister update method
             pub type UpdateMethod = fn(
                 crate::canister_context::MutableContext<'_>,
                 &[u8],
                 crate::canister_context::UpdateContext<'_>,
-            ) -> Result<Vec<u8>, String>;
+            ) -> Result<Vec<u8>, $crate::CanisterError>;
+            /// Aliased type for a canister composite query method, which can query
+            /// other canisters through `Interface::call_canister`
+            pub type CompositeQueryMethod = for<'a> fn(
+                crate::canister_context::ImmutableContext<'a>,
+                &'a [u8],
+            ) -> $crate::MethodFuture<'a>;
+            /// Aliased type for a canister update method that awaits
+            pub type AsyncUpdateMethod = for<'a> fn(
+                crate::canister_context::AsyncContext<'a>,
+                &'a [u8],
+                crate::canister_context::UpdateContext<'a>,
+            ) -> $crate::MethodFuture<'a>;
             /// Aliased type for a cansiter init method
             pub type Init = fn(
                 crate::canister_context::MutableContext<'_>,
                 &[u8],
                 crate::canister_context::UpdateContext<'_>,
             );
+            /// Aliased type for a canister inspect message method, called with the
+            /// candid name and raw arguments of the update to accept or reject
+            pub type InspectMessage = fn(
+                crate::canister_context::ImmutableContext<'_>,
+                &str,
+                &[u8],
+            ) -> Result<(), $crate::CanisterError>;
             /// Aliased type for a canister post upgrade and pre upgrade method
             pub type Lifecycle = fn(
                 crate::canister_context::MutableContext<'_>,
                 crate::canister_context::UpdateContext<'_>,
             );
+            /// Aliased type for a guard referenced by the method metadata
+            pub type Guard = fn(
+                crate::canister_context::ImmutableContext<'_>,
+            ) -> Result<(), $crate::CanisterError>;
 
             /// A canister query method registration
-            pub type MethodRegistration = (&'static str, Method);
+            pub type MethodRegistration = (&'static str, Method, $crate::MethodMetadata);
+            /// A canister composite query method registration
+            pub type CompositeQueryMethodRegistration =
+                (&'static str, CompositeQueryMethod, $crate::MethodMetadata);
             /// A canister update method registration
-            pub type UpdateMethodRegistration = (&'static str, UpdateMethod);
+            pub type UpdateMethodRegistration =
+                (&'static str, UpdateMethod, $crate::MethodMetadata);
+            /// A canister update method that awaits registration
+            pub type AsyncUpdateMethodRegistration =
+                (&'static str, AsyncUpdateMethod, $crate::MethodMetadata);
             /// Registration for init
             pub type InitRegistration = (&'static str, Init);
+            /// Registration for inspect message
+            pub type InspectMessageRegistration = (&'static str, InspectMessage);
             /// Registration for pre and post upgrade
             pub type LifecycleRegistration = (&'static str, Lifecycle);
+            /// Registration for an init hook, run in ascending order
+            pub type InitHookRegistration = (&'static str, i32, Init);
+            /// Registration for a pre or post upgrade hook, run in ascending order
+            pub type LifecycleHookRegistration = (&'static str, i32, Lifecycle);
+            /// Registration for a named guard
+            pub type GuardRegistration = (&'static str, Guard);
 
             /// Distributed slice for canister update methods
             #[linkme::distributed_slice]
             pub static UPDATE_METHODS: [UpdateMethodRegistration] = [..];
 
+            /// Distributed slice for canister update methods that await
+            #[linkme::distributed_slice]
+            pub static ASYNC_UPDATE_METHODS: [AsyncUpdateMethodRegistration] = [..];
+
             /// Distributed slice for canister update methods
             #[linkme::distributed_slice]
             pub static QUERY_METHODS: [MethodRegistration] = [..];
 
+            /// Distributed slice for canister composite query methods
+            #[linkme::distributed_slice]
+            pub static COMPOSITE_QUERY_METHODS: [CompositeQueryMethodRegistration] = [..];
+
             /// Distributed slice for canister post upgrade
             #[linkme::distributed_slice]
             pub static POST_UPGRADE: [LifecycleRegistration] = [..];
@@ -61,35 +133,115 @@ macro_rules! define_canister_exports {
             #[linkme::distributed_slice]
             pub static INIT: [InitRegistration] = [..];
 
-            pub fn definition(primary: bool) -> $crate::CanisterDefinition<crate::State> {
-                $crate::CanisterDefinition::new(
+            /// Distributed slice for ordered canister init hooks
+            #[linkme::distributed_slice]
+            pub static INIT_HOOKS: [InitHookRegistration] = [..];
+
+            /// Distributed slice for ordered canister post upgrade hooks
+            #[linkme::distributed_slice]
+            pub static POST_UPGRADE_HOOKS: [LifecycleHookRegistration] = [..];
+
+            /// Distributed slice for ordered canister pre upgrade hooks
+            #[linkme::distributed_slice]
+            pub static PRE_UPGRADE_HOOKS: [LifecycleHookRegistration] = [..];
+
+            /// Distributed slice for the canister global timer
+            #[linkme::distributed_slice]
+            pub static GLOBAL_TIMER: [LifecycleRegistration] = [..];
+
+            /// Distributed slice for the canister heartbeat
+            #[linkme::distributed_slice]
+            pub static HEARTBEAT: [LifecycleRegistration] = [..];
+
+            /// Distributed slice for canister inspect message
+            #[linkme::distributed_slice]
+            pub static INSPECT_MESSAGE: [InspectMessageRegistration] = [..];
+
+            /// Distributed slice for the guards referenced by the methods
+            #[linkme::distributed_slice]
+            pub static GUARDS: [GuardRegistration] = [..];
+
+            pub fn definition(
+                primary: bool,
+            ) -> Result<$crate::CanisterDefinition<crate::State>, $crate::CanisterDefinitionError>
+            {
+                $crate::CanisterDefinition::try_new(
                     &UPDATE_METHODS,
+                    &ASYNC_UPDATE_METHODS,
                     &QUERY_METHODS,
+                    &COMPOSITE_QUERY_METHODS,
                     &INIT,
+                    &INIT_HOOKS,
                     &POST_UPGRADE,
+                    &POST_UPGRADE_HOOKS,
                     &PRE_UPGRADE,
+                    &PRE_UPGRADE_HOOKS,
+                    &GLOBAL_TIMER,
+                    &HEARTBEAT,
+                    &INSPECT_MESSAGE,
+                    &GUARDS,
                     primary,
                 )
+                .map(|definition| definition.with_hooks(&crate::canister_context::HOOKS))
+            }
+
+            /// Candid interface of the canister, fetched by tooling such as the Candid UI
+            #[cfg(target_arch = "wasm32")]
+            #[dscvr_cdk_macros::query]
+            fn __get_candid_interface_tmp_hack(
+                _ctx: crate::canister_context::ImmutableContext,
+            ) -> String {
+                definition(true)
+                    .map(|definition| definition.to_candid_interface())
+                    .unwrap_or_else(|e| e.to_string())
+            }
+
+            /// Metadata of the canister methods as JSON
+            #[cfg(target_arch = "wasm32")]
+            #[dscvr_cdk_macros::query]
+            fn __get_method_metadata(_ctx: crate::canister_context::ImmutableContext) -> String {
+                definition(true)
+                    .map(|definition| definition.method_metadata_json())
+                    .unwrap_or_else(|e| e.to_string())
             }
         }
     };
 }
 
 /// Aliased type for a canister query method
-pub type CanisterMethod<State> =
-    fn(dscvr_canister_context::ImmutableContext<'_, State>, &[u8]) -> Result<Vec<u8>, String>;
+pub type CanisterMethod<State> = fn(
+    dscvr_canister_context::ImmutableContext<'_, State>,
+    &[u8],
+) -> Result<Vec<u8>, CanisterError>;
 /// Aliased type for a canister update method
 pub type CanisterUpdateMethod<State> = fn(
     dscvr_canister_context::MutableContext<'_, State>,
     &[u8],
     dscvr_canister_context::UpdateContext<'_>,
-) -> Result<Vec<u8>, String>;
+) -> Result<Vec<u8>, CanisterError>;
+/// Future returned by a canister method that awaits
+pub type MethodFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, CanisterError>> + 'a>>;
+/// Aliased type for a canister composite query method
+pub type CanisterCompositeQueryMethod<State> =
+    for<'a> fn(dscvr_canister_context::ImmutableContext<'a, State>, &'a [u8]) -> MethodFuture<'a>;
+/// Aliased type for a canister update method that awaits
+pub type CanisterAsyncUpdateMethod<State> = for<'a> fn(
+    dscvr_canister_context::AsyncContext<'a, State>,
+    &'a [u8],
+    dscvr_canister_context::UpdateContext<'a>,
+) -> MethodFuture<'a>;
 /// Aliased type for a cansiter init method
 pub type CanisterInitMethod<State> = fn(
     dscvr_canister_context::MutableContext<'_, State>,
     &[u8],
     dscvr_canister_context::UpdateContext<'_>,
 );
+/// Aliased type for a canister inspect message method
+pub type CanisterInspectMessageMethod<State> = fn(
+    dscvr_canister_context::ImmutableContext<'_, State>,
+    &str,
+    &[u8],
+) -> Result<(), CanisterError>;
 /// Aliased type for a cansiter lifecycle method
 pub type CanisterLifecycleMethod<State> = fn(
     dscvr_canister_context::MutableContext<'_, State>,
@@ -100,46 +252,384 @@ pub type CanisterLifecycleMethod<State> = fn(
 pub struct CanisterDefinition<State> {
     /// Hashmap of candid name to the update method
     pub update_methods: HashMap<String, CanisterUpdateMethod<State>>,
+    /// Hashmap of candid name to the update method that awaits
+    pub async_update_methods: HashMap<String, CanisterAsyncUpdateMethod<State>>,
     /// Hashmap of candid name to the query method
     pub query_methods: HashMap<String, CanisterMethod<State>>,
-    /// Init method
-    pub init_method: CanisterInitMethod<State>,
-    /// Pre upgrade method
-    pub pre_upgrade: CanisterLifecycleMethod<State>,
-    /// Post upgrade method
-    pub post_upgrade: CanisterLifecycleMethod<State>,
+    /// Hashmap of candid name to the composite query method
+    pub composite_query_methods: HashMap<String, CanisterCompositeQueryMethod<State>>,
+    /// Hashmap of candid name to the metadata of the update and query methods
+    pub method_metadata: HashMap<String, MethodMetadata>,
+    /// Hashmap of name to the guards referenced by the method metadata
+    pub guards: HashMap<String, CanisterGuard<State>>,
+    /// Init hooks, in the order they run
+    pub init_hooks: InitHooks<State>,
+    /// Pre upgrade hooks, in the order they run
+    pub pre_upgrade_hooks: LifecycleHooks<State>,
+    /// Post upgrade hooks, in the order they run
+    pub post_upgrade_hooks: LifecycleHooks<State>,
+    /// Global timer method, if the canister registered one
+    pub global_timer: Option<CanisterLifecycleMethod<State>>,
+    /// Heartbeat method, if the canister registered one
+    pub heartbeat: Option<CanisterLifecycleMethod<State>>,
+    /// Inspect message method run before updates, if the canister registered one
+    pub inspect_message: Option<CanisterInspectMessageMethod<State>>,
+    /// Hooks run around every dispatched method, if the canister registered them
+    pub hooks: Option<&'static Hooks>,
     /// Is this the primary registration
     pub primary: bool,
 }
 
+/// Return the single registration of the lifecycle method `kind`, if any
+fn single<T: Copy>(
+    kind: &'static str,
+    registrations: &[(&'static str, T)],
+) -> Result<Option<T>, CanisterDefinitionError> {
+    match registrations {
+        [] => Ok(None),
+        [(_, method)] => Ok(Some(*method)),
+        _ => Err(CanisterDefinitionError::DuplicateLifecycleMethod {
+            kind,
+            names: registrations.iter().map(|(name, _)| *name).collect(),
+        }),
+    }
+}
+
+/// Return the chain of hooks of the required lifecycle method `kind`
+fn required<T: Copy>(
+    kind: &'static str,
+    registrations: &[(&'static str, T)],
+    hooks: &[(&'static str, i32, T)],
+) -> Result<Vec<LifecycleHook<T>>, CanisterDefinitionError> {
+    let chain = lifecycle::chain(kind, registrations, hooks)?;
+    if chain.is_empty() {
+        return Err(CanisterDefinitionError::MissingLifecycleMethod(kind));
+    }
+    Ok(chain)
+}
+
 impl<State> CanisterDefinition<State> {
-    /// Returns a registration by reading from the registered slices
-    pub fn new(
-        updates: &[(&'static str, CanisterUpdateMethod<State>)],
-        queries: &[(&'static str, CanisterMethod<State>)],
+    /// Returns a builder to register methods at runtime
+    pub fn builder() -> CanisterDefinitionBuilder<State> {
+        CanisterDefinitionBuilder::new()
+    }
+
+    /// Run `hooks` around every dispatched method
+    pub fn with_hooks(mut self, hooks: &'static Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Returns a registration by reading from the registered slices, failing if a required
+    /// lifecycle method has no hook, anything was registered more than once or a method
+    /// references an unregistered guard
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        updates: &[(&'static str, CanisterUpdateMethod<State>, MethodMetadata)],
+        async_updates: &[(
+            &'static str,
+            CanisterAsyncUpdateMethod<State>,
+            MethodMetadata,
+        )],
+        queries: &[(&'static str, CanisterMethod<State>, MethodMetadata)],
+        composite_queries: &[(
+            &'static str,
+            CanisterCompositeQueryMethod<State>,
+            MethodMetadata,
+        )],
         init: &[(&'static str, CanisterInitMethod<State>)],
+        init_hooks: &[(&'static str, i32, CanisterInitMethod<State>)],
         post_upgrade: &[(&'static str, CanisterLifecycleMethod<State>)],
+        post_upgrade_hooks: &[(&'static str, i32, CanisterLifecycleMethod<State>)],
         pre_upgrade: &[(&'static str, CanisterLifecycleMethod<State>)],
+        pre_upgrade_hooks: &[(&'static str, i32, CanisterLifecycleMethod<State>)],
+        global_timer: &[(&'static str, CanisterLifecycleMethod<State>)],
+        heartbeat: &[(&'static str, CanisterLifecycleMethod<State>)],
+        inspect_message: &[(&'static str, CanisterInspectMessageMethod<State>)],
+        guards: &[(&'static str, CanisterGuard<State>)],
         primary: bool,
-    ) -> Self {
+    ) -> Result<Self, CanisterDefinitionError> {
         let mut update_methods = HashMap::new();
         let mut query_methods = HashMap::new();
+        let mut method_metadata = HashMap::new();
 
-        for (name, method) in updates {
+        for (name, method, metadata) in updates {
+            if method_metadata
+                .insert(name.to_string(), *metadata)
+                .is_some()
+            {
+                return Err(CanisterDefinitionError::DuplicateMethod(name.to_string()));
+            }
             update_methods.insert(name.to_string(), *method);
         }
 
-        for (name, method) in queries {
+        let mut async_update_methods = HashMap::new();
+        for (name, method, metadata) in async_updates {
+            if method_metadata
+                .insert(name.to_string(), *metadata)
+                .is_some()
+            {
+                return Err(CanisterDefinitionError::DuplicateMethod(name.to_string()));
+            }
+            async_update_methods.insert(name.to_string(), *method);
+        }
+
+        for (name, method, metadata) in queries {
+            if method_metadata
+                .insert(name.to_string(), *metadata)
+                .is_some()
+            {
+                return Err(CanisterDefinitionError::DuplicateMethod(name.to_string()));
+            }
             query_methods.insert(name.to_string(), *method);
         }
 
-        CanisterDefinition {
+        let mut composite_query_methods = HashMap::new();
+        for (name, method, metadata) in composite_queries {
+            let metadata = metadata.composite();
+            if method_metadata.insert(name.to_string(), metadata).is_some() {
+                return Err(CanisterDefinitionError::DuplicateMethod(name.to_string()));
+            }
+            composite_query_methods.insert(name.to_string(), *method);
+        }
+
+        let mut guard_methods = HashMap::new();
+        for (name, guard) in guards {
+            if guard_methods.insert(name.to_string(), *guard).is_some() {
+                return Err(CanisterDefinitionError::DuplicateGuard(name.to_string()));
+            }
+        }
+        for (method, metadata) in &method_metadata {
+            if let Some(guard) = metadata.guard {
+                if !guard_methods.contains_key(guard) {
+                    return Err(CanisterDefinitionError::UnregisteredGuard {
+                        method: method.clone(),
+                        guard,
+                    });
+                }
+            }
+        }
+
+        Ok(CanisterDefinition {
             update_methods,
+            async_update_methods,
             query_methods,
-            init_method: init[0].1,
-            post_upgrade: post_upgrade[0].1,
-            pre_upgrade: pre_upgrade[0].1,
+            composite_query_methods,
+            method_metadata,
+            guards: guard_methods,
+            init_hooks: required("init", init, init_hooks)?,
+            post_upgrade_hooks: required("post_upgrade", post_upgrade, post_upgrade_hooks)?,
+            pre_upgrade_hooks: required("pre_upgrade", pre_upgrade, pre_upgrade_hooks)?,
+            global_timer: single("global_timer", global_timer)?,
+            heartbeat: single("heartbeat", heartbeat)?,
+            inspect_message: single("inspect_message", inspect_message)?,
+            hooks: None,
             primary,
+        })
+    }
+}
+
+fn unknown_method(method: &str) -> CanisterError {
+    CanisterError::new(
+        CanisterErrorCode::NotFound,
+        format!("canister has no method named {method}"),
+    )
+}
+
+impl<State> CanisterDefinition<State> {
+    /// Run `f` surrounded by the hooks of the definition, if any
+    fn hooked<R>(
+        &self,
+        system: &dyn Interface,
+        method: &str,
+        is_update: bool,
+        f: impl FnOnce() -> Result<R, CanisterError>,
+    ) -> Result<R, CanisterError> {
+        match self.hooks {
+            Some(hooks) => hooks.run(system, method, is_update, f)?,
+            None => f(),
         }
     }
+
+    /// Await `f` surrounded by the hooks of the definition, if any, and record the
+    /// invocation metrics of `method`
+    async fn measured_async<R>(
+        &self,
+        system: &dyn Interface,
+        method: &str,
+        is_update: bool,
+        f: impl Future<Output = Result<R, CanisterError>>,
+    ) -> Result<R, CanisterError> {
+        let call = MethodCall {
+            method_name: method,
+            caller: system.caller(),
+            is_update,
+        };
+        let start = system.instruction_counter();
+        let result = match self.hooks.map(|hooks| hooks.run_before(&call)) {
+            Some(Err(rejection)) => Err(rejection.into()),
+            _ => f.await,
+        };
+        let instructions = system.instruction_counter().saturating_sub(start);
+        if let Some(hooks) = self.hooks {
+            hooks.run_after(&call, instructions);
+        }
+        // state accesses of awaiting methods interleave with other invocations
+        record_method_call(system, method, result.is_ok(), instructions, 0);
+        result
+    }
+
+    /// Dispatch the query `method`, checking its guard and recording its metrics
+    pub fn query(
+        &self,
+        method: &str,
+        ctx: ImmutableContext<'_, State>,
+        args: &[u8],
+    ) -> Result<Vec<u8>, CanisterError> {
+        let query_method = self
+            .query_methods
+            .get(method)
+            .ok_or_else(|| unknown_method(method))?;
+        let system = ctx.system();
+        measured(system, method, || {
+            self.hooked(system, method, false, || {
+                self.check_guard(method, ctx.clone())?;
+                query_method(ctx, args)
+            })
+        })
+    }
+
+    /// Dispatch the composite query `method`, checking its guard and recording its metrics
+    pub async fn composite_query(
+        &self,
+        method: &str,
+        ctx: ImmutableContext<'_, State>,
+        args: &[u8],
+    ) -> Result<Vec<u8>, CanisterError> {
+        let composite_query_method = self
+            .composite_query_methods
+            .get(method)
+            .ok_or_else(|| unknown_method(method))?;
+        let system = ctx.system();
+        self.measured_async(system, method, false, async {
+            self.check_guard(method, ctx.clone())?;
+            composite_query_method(ctx, args).await
+        })
+        .await
+    }
+
+    /// Dispatch the update `method`, running inspect message, checking its guard and
+    /// recording its metrics
+    pub fn update(
+        &self,
+        method: &str,
+        ctx: MutableContext<'_, State>,
+        args: &[u8],
+        update_context: UpdateContext<'_>,
+    ) -> Result<Vec<u8>, CanisterError> {
+        let update_method = self
+            .update_methods
+            .get(method)
+            .ok_or_else(|| unknown_method(method))?;
+        let system = ctx.system();
+        measured(system, method, || {
+            self.hooked(system, method, true, || {
+                if let Some(inspect_message) = self.inspect_message {
+                    inspect_message(ImmutableContext::new(ctx.state(), system), method, args)?;
+                }
+                self.check_guard(method, ImmutableContext::new(ctx.state(), system))?;
+                update_method(ctx, args, update_context)
+            })
+        })
+    }
+
+    /// Dispatch the update `method` that awaits, running inspect message, checking its
+    /// guard and recording its metrics
+    pub async fn async_update<'a>(
+        &self,
+        method: &str,
+        ctx: AsyncContext<'a, State>,
+        args: &'a [u8],
+        update_context: UpdateContext<'a>,
+    ) -> Result<Vec<u8>, CanisterError> {
+        let async_update_method = self
+            .async_update_methods
+            .get(method)
+            .ok_or_else(|| unknown_method(method))?;
+        let system = ctx.system();
+        self.measured_async(system, method, true, async {
+            ctx.read(|state| {
+                if let Some(inspect_message) = self.inspect_message {
+                    inspect_message(ImmutableContext::new(state, system), method, args)?;
+                }
+                self.check_guard(method, ImmutableContext::new(state, system))
+            })?;
+            async_update_method(ctx, args, update_context).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dscvr_canister_context::{HookRejection, ImmutableContext, MethodCall};
+    use dscvr_interface::edge::Edge;
+
+    static HOOKS: Hooks = Hooks::new();
+
+    fn reject_secret(call: &MethodCall<'_>) -> Result<(), HookRejection> {
+        match call.method_name {
+            "secret" => Err(HookRejection("secret is closed".to_owned())),
+            _ => Ok(()),
+        }
+    }
+
+    fn echo(_ctx: ImmutableContext<'_, ()>, args: &[u8]) -> Result<Vec<u8>, CanisterError> {
+        Ok(args.to_vec())
+    }
+
+    #[test]
+    fn dispatch_runs_hooks() {
+        HOOKS.register_before(reject_secret);
+        let definition = CanisterDefinition::builder()
+            .query("echo", echo)
+            .query("secret", echo)
+            .hooks(&HOOKS)
+            .build()
+            .unwrap();
+        let system = Edge::default();
+
+        assert_eq!(
+            definition.query("echo", ImmutableContext::new(&(), &system), b"hi"),
+            Ok(b"hi".to_vec())
+        );
+        assert_eq!(
+            definition
+                .query("secret", ImmutableContext::new(&(), &system), b"hi")
+                .unwrap_err()
+                .code,
+            CanisterErrorCode::Unauthorized
+        );
+    }
+
+    #[test]
+    fn unregistered_guards_fail_construction() {
+        let result = CanisterDefinition::builder()
+            .query_with_metadata("echo", echo, MethodMetadata::query().with_guard("admin"))
+            .build();
+        assert_eq!(
+            result.err(),
+            Some(CanisterDefinitionError::UnregisteredGuard {
+                method: "echo".to_string(),
+                guard: "admin",
+            })
+        );
+
+        let definition = CanisterDefinition::builder()
+            .query_with_metadata("echo", echo, MethodMetadata::query().with_guard("admin"))
+            .guard("admin", controller_guard)
+            .build();
+        assert!(definition.is_ok());
+    }
 }
@@ -0,0 +1,116 @@
+//! Ordered chains of init, post upgrade and pre upgrade hooks.
+//!
+//! Besides the single method of the `INIT`, `POST_UPGRADE` and `PRE_UPGRADE` slices,
+//! independent subsystems register their own hook with an ordering key in the
+//! `INIT_HOOKS`, `POST_UPGRADE_HOOKS` and `PRE_UPGRADE_HOOKS` slices. Hooks run in
+//! ascending order, with the single method ordered at `0`.
+
+use crate::{
+    CanisterDefinition, CanisterDefinitionError, CanisterInitMethod, CanisterLifecycleMethod,
+};
+use dscvr_canister_context::{Interface, MutableContext, UpdateContext};
+
+/// Ordering key of the single method of a lifecycle slice
+pub const DEFAULT_HOOK_ORDER: i32 = 0;
+
+/// A lifecycle method in a chain of hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifecycleHook<T> {
+    /// Name of the hook
+    pub name: &'static str,
+    /// Hooks run in ascending order
+    pub order: i32,
+    /// The lifecycle method
+    pub method: T,
+}
+
+/// Chain of init hooks
+pub type InitHooks<State> = Vec<LifecycleHook<CanisterInitMethod<State>>>;
+/// Chain of post upgrade or pre upgrade hooks
+pub type LifecycleHooks<State> = Vec<LifecycleHook<CanisterLifecycleMethod<State>>>;
+
+/// Build the chain of hooks of the lifecycle method `kind`, failing if more than one single
+/// method is registered or a name is registered more than once
+pub(crate) fn chain<T: Copy>(
+    kind: &'static str,
+    single: &[(&'static str, T)],
+    ordered: &[(&'static str, i32, T)],
+) -> Result<Vec<LifecycleHook<T>>, CanisterDefinitionError> {
+    let single = crate::single(kind, single)?.map(|method| LifecycleHook {
+        name: kind,
+        order: DEFAULT_HOOK_ORDER,
+        method,
+    });
+    let mut hooks: Vec<_> = single
+        .into_iter()
+        .chain(ordered.iter().map(|(name, order, method)| LifecycleHook {
+            name,
+            order: *order,
+            method: *method,
+        }))
+        .collect();
+    // link order of the slices is unspecified, so break ties by name
+    hooks.sort_by(|a, b| a.order.cmp(&b.order).then_with(|| a.name.cmp(b.name)));
+
+    let mut names: Vec<_> = hooks.iter().map(|hook| hook.name).collect();
+    names.sort_unstable();
+    let mut duplicates: Vec<_> = names
+        .windows(2)
+        .filter(|pair| pair[0] == pair[1])
+        .map(|pair| pair[0])
+        .collect();
+    if !duplicates.is_empty() {
+        duplicates.dedup();
+        return Err(CanisterDefinitionError::DuplicateLifecycleMethod {
+            kind,
+            names: duplicates,
+        });
+    }
+    Ok(hooks)
+}
+
+impl<State> CanisterDefinition<State> {
+    /// Run the init hooks in order
+    pub fn run_init(
+        &self,
+        state: &mut State,
+        system: &dyn Interface,
+        args: &[u8],
+        update_context: UpdateContext<'_>,
+    ) {
+        for hook in &self.init_hooks {
+            (hook.method)(MutableContext::new(state, system), args, update_context);
+        }
+    }
+
+    /// Run the post upgrade hooks in order
+    pub fn run_post_upgrade(
+        &self,
+        state: &mut State,
+        system: &dyn Interface,
+        update_context: UpdateContext<'_>,
+    ) {
+        run_lifecycle(&self.post_upgrade_hooks, state, system, update_context);
+    }
+
+    /// Run the pre upgrade hooks in order
+    pub fn run_pre_upgrade(
+        &self,
+        state: &mut State,
+        system: &dyn Interface,
+        update_context: UpdateContext<'_>,
+    ) {
+        run_lifecycle(&self.pre_upgrade_hooks, state, system, update_context);
+    }
+}
+
+fn run_lifecycle<State>(
+    hooks: &[LifecycleHook<CanisterLifecycleMethod<State>>],
+    state: &mut State,
+    system: &dyn Interface,
+    update_context: UpdateContext<'_>,
+) {
+    for hook in hooks {
+        (hook.method)(MutableContext::new(state, system), update_context);
+    }
+}
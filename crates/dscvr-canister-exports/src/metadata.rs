@@ -0,0 +1,185 @@
+//! Metadata attached to registered canister methods.
+
+use crate::candid_interface::{candid_signature, CandidSignature};
+use crate::CanisterDefinition;
+use candid::CandidType;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How a canister method is called
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum CallType {
+    /// Query method
+    Query,
+    /// Update method
+    Update,
+}
+
+/// Deprecation of a canister method
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Deprecation {
+    /// Version the method was deprecated in
+    pub since: &'static str,
+    /// Last version the method will be available in
+    pub removed_after: Option<&'static str>,
+    /// Candid name of the method replacing it
+    pub replacement: Option<&'static str>,
+}
+
+impl std::fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deprecated since {}", self.since)?;
+        if let Some(removed_after) = self.removed_after {
+            write!(f, ", removed after {removed_after}")?;
+        }
+        if let Some(replacement) = self.replacement {
+            write!(f, ", use {replacement} instead")?;
+        }
+        Ok(())
+    }
+}
+
+/// Metadata of a registered canister method
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct MethodMetadata {
+    /// How the method is called
+    pub call_type: CallType,
+    /// Name of the guard checked before the method runs
+    pub guard: Option<&'static str>,
+    /// Whether successful calls are kept out of the TxLog
+    pub skip_tx_log: bool,
+    /// Whether the query can call other canisters
+    pub composite: bool,
+    /// Candid type names of the arguments
+    pub arg_types: &'static [&'static str],
+    /// Candid type names of the return values
+    pub ret_types: &'static [&'static str],
+    /// Candid types of the arguments and return values, preferred over the type names
+    #[serde(skip)]
+    pub signature: Option<CandidSignature>,
+    /// Deprecation of the method, if it is deprecated
+    pub deprecation: Option<Deprecation>,
+}
+
+impl MethodMetadata {
+    /// Metadata of a query method
+    pub const fn query() -> Self {
+        Self::new(CallType::Query)
+    }
+
+    /// Metadata of an update method
+    pub const fn update() -> Self {
+        Self::new(CallType::Update)
+    }
+
+    const fn new(call_type: CallType) -> Self {
+        Self {
+            call_type,
+            guard: None,
+            skip_tx_log: false,
+            composite: false,
+            arg_types: &[],
+            ret_types: &[],
+            signature: None,
+            deprecation: None,
+        }
+    }
+
+    /// Set the guard checked before the method runs
+    pub const fn with_guard(mut self, guard: &'static str) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Keep successful calls out of the TxLog
+    pub const fn skip_tx_log(mut self) -> Self {
+        self.skip_tx_log = true;
+        self
+    }
+
+    /// Mark the query as composite
+    pub const fn composite(mut self) -> Self {
+        self.composite = true;
+        self
+    }
+
+    /// Set the candid type names of the arguments and return values
+    pub const fn with_types(
+        mut self,
+        arg_types: &'static [&'static str],
+        ret_types: &'static [&'static str],
+    ) -> Self {
+        self.arg_types = arg_types;
+        self.ret_types = ret_types;
+        self
+    }
+
+    /// Set the candid types of the arguments and return values, both given as tuples
+    pub const fn with_signature<Args: CandidType, Rets: CandidType>(mut self) -> Self {
+        self.signature = Some(candid_signature::<Args, Rets>);
+        self
+    }
+
+    /// Mark the method as deprecated since version `since`
+    pub const fn deprecated(mut self, since: &'static str) -> Self {
+        self.deprecation = Some(Deprecation {
+            since,
+            removed_after: None,
+            replacement: None,
+        });
+        self
+    }
+
+    /// Set the last version a deprecated method will be available in
+    pub const fn removed_after(mut self, version: &'static str) -> Self {
+        if let Some(deprecation) = self.deprecation {
+            self.deprecation = Some(Deprecation {
+                removed_after: Some(version),
+                ..deprecation
+            });
+        }
+        self
+    }
+
+    /// Set the method replacing a deprecated method
+    pub const fn replaced_by(mut self, method: &'static str) -> Self {
+        if let Some(deprecation) = self.deprecation {
+            self.deprecation = Some(Deprecation {
+                replacement: Some(method),
+                ..deprecation
+            });
+        }
+        self
+    }
+
+    /// Whether the method is an update
+    pub const fn is_update(&self) -> bool {
+        matches!(self.call_type, CallType::Update)
+    }
+}
+
+impl<State> CanisterDefinition<State> {
+    /// Deprecation of `method`, if it is deprecated
+    pub fn deprecation(&self, method: &str) -> Option<&Deprecation> {
+        self.method_metadata
+            .get(method)
+            .and_then(|metadata| metadata.deprecation.as_ref())
+    }
+
+    /// Metadata of the methods as a JSON object keyed by candid name
+    pub fn method_metadata_json(&self) -> String {
+        let metadata: BTreeMap<_, _> = self.method_metadata.iter().collect();
+        serde_json::to_string(&metadata).expect("metadata serializes to JSON")
+    }
+
+    /// Deprecated methods sorted by candid name
+    pub fn deprecated_methods(&self) -> Vec<(&str, &Deprecation)> {
+        let mut deprecated: Vec<_> = self
+            .method_metadata
+            .iter()
+            .filter_map(|(name, metadata)| Some((name.as_str(), metadata.deprecation.as_ref()?)))
+            .collect();
+        deprecated.sort_by_key(|(name, _)| *name);
+        deprecated
+    }
+}
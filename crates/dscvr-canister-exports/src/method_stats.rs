@@ -0,0 +1,170 @@
+//! Invocation metrics of canister methods, aggregated per method.
+//!
+//! Calls dispatched through [`crate::CanisterDefinition`] are recorded automatically, the
+//! wasm method wrappers record theirs with [`measured`]. The stats are kept per canister,
+//! so embedded canisters sharing a process don't mix their metrics, and can be exposed
+//! with [`crate::define_method_stats_interface`]. Instructions are recorded in the
+//! instruction stats of `dscvr_canister_context`, which these metrics read them from.
+
+use candid::{CandidType, Deserialize};
+use dscvr_canister_context::instruction_stats::{
+    method_instruction_stats, record_method_instructions, reset_method_instruction_stats,
+    take_state_instructions,
+};
+use dscvr_canister_context::Interface;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Invocation metrics keyed by canister id, then by method name
+type StatsByCanister = BTreeMap<Vec<u8>, BTreeMap<String, MethodStats>>;
+
+static STATS: Mutex<StatsByCanister> = Mutex::new(BTreeMap::new());
+
+/// Invocation metrics of a method
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct MethodStats {
+    /// Number of calls
+    pub calls: u64,
+    /// Number of calls that returned an error
+    pub errors: u64,
+    /// Total instructions consumed by the calls
+    pub instructions: u64,
+    /// Time of the last call in nanoseconds
+    pub last_called: u64,
+}
+
+/// Record a call of `method_name` by the canister of `system` that consumed
+/// `instructions`, `state_instructions` of them in accesses to the state
+pub fn record_method_call(
+    system: &dyn Interface,
+    method_name: &str,
+    succeeded: bool,
+    instructions: u64,
+    state_instructions: u64,
+) {
+    record_method_instructions(system, method_name, instructions, state_instructions);
+    let mut stats = STATS.lock().expect("stats poisoned");
+    let stats = stats.entry(system.id().as_slice().to_vec()).or_default();
+    let entry = match stats.get_mut(method_name) {
+        Some(entry) => entry,
+        None => stats.entry(method_name.to_owned()).or_default(),
+    };
+    entry.calls += 1;
+    if !succeeded {
+        entry.errors += 1;
+    }
+    entry.last_called = system.time();
+}
+
+/// Return the invocation metrics of every method recorded by the canister of `system`
+pub fn method_stats(system: &dyn Interface) -> Vec<(String, MethodStats)> {
+    let instructions: BTreeMap<_, _> = method_instruction_stats(system).into_iter().collect();
+    STATS
+        .lock()
+        .expect("stats poisoned")
+        .get(system.id().as_slice())
+        .map(|stats| {
+            stats
+                .iter()
+                .map(|(name, stats)| {
+                    let instructions = instructions
+                        .get(name)
+                        .map(|stats| stats.total_instructions)
+                        .unwrap_or_default();
+                    (
+                        name.clone(),
+                        MethodStats {
+                            instructions,
+                            ..stats.clone()
+                        },
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Drop the invocation metrics recorded by the canister of `system`
+pub fn reset_method_stats(system: &dyn Interface) {
+    reset_method_instruction_stats(system);
+    STATS
+        .lock()
+        .expect("stats poisoned")
+        .remove(system.id().as_slice());
+}
+
+/// Run the method `method_name` with `f` and record its invocation metrics
+pub fn measured<F, R, E>(system: &dyn Interface, method_name: &str, f: F) -> Result<R, E>
+where
+    F: FnOnce() -> Result<R, E>,
+{
+    take_state_instructions(system);
+    let start = system.instruction_counter();
+    let result = f();
+    let instructions = system.instruction_counter().saturating_sub(start);
+    record_method_call(
+        system,
+        method_name,
+        result.is_ok(),
+        instructions,
+        take_state_instructions(system),
+    );
+    result
+}
+
+/// Define a `method_stats` query returning the invocation metrics of every method
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! define_method_stats_interface {
+    () => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query]
+        fn method_stats(
+            ctx: crate::canister_context::ImmutableContext,
+        ) -> Vec<(String, $crate::method_stats::MethodStats)> {
+            $crate::method_stats::method_stats(ctx.system())
+        }
+    };
+    (guard = $guard:literal) => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query(guard = $guard)]
+        fn method_stats(
+            ctx: crate::canister_context::ImmutableContext,
+        ) -> Vec<(String, $crate::method_stats::MethodStats)> {
+            $crate::method_stats::method_stats(ctx.system())
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use candid::Principal;
+    use dscvr_interface::unit_test::UnitTest;
+
+    #[test]
+    fn stats_are_kept_per_canister() {
+        let first = UnitTest::builder()
+            .canister_id(Principal::from_slice(&[1]))
+            .instruction_counters(vec![0, 5, 5, 12])
+            .build();
+        let second = UnitTest::builder()
+            .canister_id(Principal::from_slice(&[2]))
+            .build();
+
+        let _ = measured(&first, "greet", || Ok::<_, ()>(()));
+        let _ = measured(&first, "greet", || Err::<(), _>(()));
+        let _ = measured(&second, "rename", || Ok::<_, ()>(()));
+
+        let stats = method_stats(&first);
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].0, "greet");
+        assert_eq!((stats[0].1.calls, stats[0].1.errors), (2, 1));
+        assert_eq!(stats[0].1.instructions, 12);
+        assert_eq!(method_stats(&second)[0].0, "rename");
+
+        reset_method_stats(&first);
+        assert!(method_stats(&first).is_empty());
+        assert_eq!(method_stats(&second).len(), 1);
+    }
+}
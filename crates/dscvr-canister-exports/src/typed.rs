@@ -0,0 +1,105 @@
+//! Typed canister methods with candid encoding handled by the registration.
+//!
+//! Methods written as `fn(ctx, Args) -> Result<Ret, CanisterError>` are adapted to the
+//! raw `&[u8]` signatures of the registrations with [`crate::typed_query`] and
+//! [`crate::typed_update`]:
+//!
+//! ```ignore
+//! fn get_user(ctx: ImmutableContext, id: u64) -> Result<User, CanisterError> { .. }
+//!
+//! #[linkme::distributed_slice(QUERY_METHODS)]
+//! static GET_USER: MethodRegistration = (
+//!     "get_user",
+//!     dscvr_canister_exports::typed_query!(get_user),
+//!     MethodMetadata::query().with_signature::<(u64,), (User,)>(),
+//! );
+//! ```
+
+use crate::{CanisterError, CanisterErrorCode};
+use candid::{CandidType, Deserialize};
+use dscvr_canister_context::{ImmutableContext, MutableContext, UpdateContext};
+
+/// Decode the candid arguments of a method
+pub fn decode_args<Args>(args: &[u8]) -> Result<Args, CanisterError>
+where
+    Args: CandidType + for<'de> Deserialize<'de>,
+{
+    candid::decode_one(args).map_err(|e| {
+        CanisterError::new(
+            CanisterErrorCode::InvalidArgument,
+            format!("failed to decode arguments: {e}"),
+        )
+    })
+}
+
+/// Encode the candid response of a method
+pub fn encode_response<Ret: CandidType>(response: &Ret) -> Result<Vec<u8>, CanisterError> {
+    candid::encode_one(response).map_err(|e| {
+        CanisterError::new(
+            CanisterErrorCode::Internal,
+            format!("failed to encode response: {e}"),
+        )
+    })
+}
+
+/// Call a typed query with raw candid arguments
+pub fn call_query<'a, State, Args, Ret, F>(
+    ctx: ImmutableContext<'a, State>,
+    args: &[u8],
+    method: F,
+) -> Result<Vec<u8>, CanisterError>
+where
+    Args: CandidType + for<'de> Deserialize<'de>,
+    Ret: CandidType,
+    F: FnOnce(ImmutableContext<'a, State>, Args) -> Result<Ret, CanisterError>,
+{
+    encode_response(&method(ctx, decode_args(args)?)?)
+}
+
+/// Call a typed update with raw candid arguments
+pub fn call_update<'a, State, Args, Ret, F>(
+    ctx: MutableContext<'a, State>,
+    args: &[u8],
+    update_context: UpdateContext<'a>,
+    method: F,
+) -> Result<Vec<u8>, CanisterError>
+where
+    Args: CandidType + for<'de> Deserialize<'de>,
+    Ret: CandidType,
+    F: FnOnce(MutableContext<'a, State>, Args, UpdateContext<'a>) -> Result<Ret, CanisterError>,
+{
+    encode_response(&method(ctx, decode_args(args)?, update_context)?)
+}
+
+/// Adapt a typed query `fn(ImmutableContext, Args) -> Result<Ret, CanisterError>` to
+/// the `Method` of a registration
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! typed_query {
+    ($method: path) => {{
+        fn typed_query(
+            ctx: crate::canister_context::ImmutableContext<'_>,
+            args: &[u8],
+        ) -> Result<Vec<u8>, $crate::CanisterError> {
+            $crate::typed::call_query(ctx, args, $method)
+        }
+        typed_query as crate::canister_exports::Method
+    }};
+}
+
+/// Adapt a typed update `fn(MutableContext, Args, UpdateContext) -> Result<Ret, CanisterError>`
+/// to the `UpdateMethod` of a registration
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! typed_update {
+    ($method: path) => {{
+        fn typed_update(
+            ctx: crate::canister_context::MutableContext<'_>,
+            args: &[u8],
+            update_context: crate::canister_context::UpdateContext<'_>,
+        ) -> Result<Vec<u8>, $crate::CanisterError> {
+            $crate::typed::call_update(ctx, args, update_context, $method)
+        }
+        typed_update as crate::canister_exports::UpdateMethod
+    }};
+}
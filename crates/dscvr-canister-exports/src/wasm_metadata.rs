@@ -0,0 +1,241 @@
+//! Build metadata embedded in a custom section of the canister wasm.
+//!
+//! [`crate::define_canister_metadata`] embeds the section at compile time so tooling can
+//! inspect a module before installing it, see [`CanisterMetadata::from_module`].
+
+use crate::CanisterMetadataError;
+
+/// Name of the custom section holding the metadata
+pub const METADATA_SECTION: &str = "icp:public dscvr:metadata";
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Build metadata of a canister wasm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanisterMetadata {
+    /// Version of the canister crate
+    pub version: String,
+    /// Git commit the canister was built from
+    pub git_hash: String,
+    /// Version of the stable state schema
+    pub schema_version: u32,
+    /// Candid service definition of the canister
+    pub candid: String,
+}
+
+impl CanisterMetadata {
+    /// Extract the metadata of an uncompressed wasm module, if it has any
+    pub fn from_module(module: &[u8]) -> Result<Option<Self>, CanisterMetadataError> {
+        let Some(section) = custom_section(module, METADATA_SECTION)? else {
+            return Ok(None);
+        };
+        let contents =
+            std::str::from_utf8(section).map_err(|_| CanisterMetadataError::InvalidUtf8)?;
+        Self::parse(contents).map(Some)
+    }
+
+    /// Parse the contents of the custom section: one `key: value` line per field, then
+    /// an empty line followed by the candid service definition
+    pub fn parse(contents: &str) -> Result<Self, CanisterMetadataError> {
+        let (header, candid) = contents
+            .split_once("\n\n")
+            .unwrap_or((contents.trim_end_matches('\n'), ""));
+        let mut version = None;
+        let mut git_hash = None;
+        let mut schema_version = None;
+        for line in header.lines() {
+            let (key, value) = line
+                .split_once(": ")
+                .ok_or_else(|| CanisterMetadataError::InvalidLine(line.to_string()))?;
+            match key {
+                "version" => version = Some(value.to_string()),
+                "git_hash" => git_hash = Some(value.to_string()),
+                "schema_version" => {
+                    schema_version = Some(value.parse().map_err(|_| {
+                        CanisterMetadataError::InvalidSchemaVersion(value.to_string())
+                    })?)
+                }
+                // unknown keys come from newer builds
+                _ => {}
+            }
+        }
+        Ok(Self {
+            version: version.ok_or(CanisterMetadataError::MissingField("version"))?,
+            git_hash: git_hash.ok_or(CanisterMetadataError::MissingField("git_hash"))?,
+            schema_version: schema_version
+                .ok_or(CanisterMetadataError::MissingField("schema_version"))?,
+            candid: candid.to_string(),
+        })
+    }
+}
+
+/// Return the contents of the custom section `name` of a wasm module
+pub fn custom_section<'a>(
+    module: &'a [u8],
+    name: &str,
+) -> Result<Option<&'a [u8]>, CanisterMetadataError> {
+    if !module.starts_with(WASM_MAGIC) || module.len() < 8 {
+        return Err(CanisterMetadataError::NotWasm);
+    }
+    // skip the magic and version
+    let mut rest = &module[8..];
+    while let Some((&id, tail)) = rest.split_first() {
+        let (size, tail) = read_leb128(tail)?;
+        let (contents, tail) = split_checked(tail, size)?;
+        rest = tail;
+        if id != CUSTOM_SECTION_ID {
+            continue;
+        }
+        let (name_len, contents) = read_leb128(contents)?;
+        let (section_name, contents) = split_checked(contents, name_len)?;
+        if section_name == name.as_bytes() {
+            return Ok(Some(contents));
+        }
+    }
+    Ok(None)
+}
+
+fn split_checked(bytes: &[u8], len: usize) -> Result<(&[u8], &[u8]), CanisterMetadataError> {
+    if bytes.len() < len {
+        return Err(CanisterMetadataError::TruncatedSection);
+    }
+    Ok(bytes.split_at(len))
+}
+
+/// Read an unsigned LEB128 encoded u32
+fn read_leb128(bytes: &[u8]) -> Result<(usize, &[u8]), CanisterMetadataError> {
+    let mut value = 0usize;
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[i + 1..]));
+        }
+    }
+    Err(CanisterMetadataError::InvalidSectionLength)
+}
+
+/// Copy `contents` into the byte array of the custom section
+pub const fn section_bytes<const N: usize>(contents: &str) -> [u8; N] {
+    let bytes = contents.as_bytes();
+    let mut section = [0; N];
+    let mut i = 0;
+    while i < N {
+        section[i] = bytes[i];
+        i += 1;
+    }
+    section
+}
+
+/// Embed the build metadata of the canister in the `icp:public dscvr:metadata` custom
+/// section of the wasm. The crate version is read from cargo, the other values must
+/// be literals, e.g.
+///
+/// ```ignore
+/// dscvr_canister_exports::define_canister_metadata!(
+///     schema_version = 3,
+///     git_hash = env!("GIT_HASH"),
+///     candid = include_str!(concat!(env!("OUT_DIR"), "/canister.did")),
+/// );
+/// ```
+#[macro_export]
+macro_rules! define_canister_metadata {
+    (schema_version = $schema_version:literal, git_hash = $git_hash:expr, candid = $candid:expr $(,)?) => {
+        #[cfg(target_arch = "wasm32")]
+        const _: () = {
+            const CONTENTS: &str = concat!(
+                "version: ",
+                env!("CARGO_PKG_VERSION"),
+                "\ngit_hash: ",
+                $git_hash,
+                "\nschema_version: ",
+                $schema_version,
+                "\n\n",
+                $candid
+            );
+            #[used]
+            #[link_section = "icp:public dscvr:metadata"]
+            static METADATA: [u8; CONTENTS.len()] = $crate::wasm_metadata::section_bytes(CONTENTS);
+        };
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut module = HEADER.to_vec();
+        for (id, contents) in sections {
+            module.push(*id);
+            module.push(contents.len() as u8);
+            module.extend_from_slice(contents);
+        }
+        module
+    }
+
+    fn custom(name: &str, contents: &str) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(contents.as_bytes());
+        section
+    }
+
+    #[test]
+    fn metadata_is_read_from_its_custom_section() {
+        let contents = "version: 1.2.3\ngit_hash: abc\nschema_version: 4\n\nservice : {}";
+        let module = module(&[
+            (1, b"\x00"),
+            (0, &custom("name", "")),
+            (0, &custom(METADATA_SECTION, contents)),
+        ]);
+        assert_eq!(
+            CanisterMetadata::from_module(&module),
+            Ok(Some(CanisterMetadata {
+                version: "1.2.3".to_string(),
+                git_hash: "abc".to_string(),
+                schema_version: 4,
+                candid: "service : {}".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn missing_section_is_none() {
+        let module = module(&[(1, b"\x00"), (0, &custom("name", ""))]);
+        assert_eq!(CanisterMetadata::from_module(&module), Ok(None));
+        assert_eq!(CanisterMetadata::from_module(HEADER), Ok(None));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        assert_eq!(
+            CanisterMetadata::from_module(b"\0asm\x01"),
+            Err(CanisterMetadataError::NotWasm)
+        );
+        let mut module = module(&[(0, &custom(METADATA_SECTION, "version: 1"))]);
+        module.truncate(module.len() - 1);
+        assert_eq!(
+            CanisterMetadata::from_module(&module),
+            Err(CanisterMetadataError::TruncatedSection)
+        );
+        let mut module = HEADER.to_vec();
+        module.extend_from_slice(&[0, 0x80]);
+        assert_eq!(
+            CanisterMetadata::from_module(&module),
+            Err(CanisterMetadataError::InvalidSectionLength)
+        );
+    }
+
+    #[test]
+    fn over_long_leb128_is_rejected() {
+        let mut module = HEADER.to_vec();
+        module.extend_from_slice(&[0, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]);
+        assert_eq!(
+            CanisterMetadata::from_module(&module),
+            Err(CanisterMetadataError::InvalidSectionLength)
+        );
+    }
+}
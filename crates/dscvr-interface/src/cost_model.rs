@@ -0,0 +1,115 @@
+//! Estimates of the cycles charged by the IC for common operations.
+//!
+//! The defaults follow the published fees of a 13 node application subnet. They are
+//! estimates meant for budgeting and forecasting, not an exact replica of the charging
+//! done by the system.
+
+/// An operation whose cost can be estimated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// An update call executing `instructions` with a request of `request_bytes`
+    UpdateCall {
+        instructions: u64,
+        request_bytes: u64,
+    },
+    /// Writing `bytes` to stable memory
+    StableWrite { bytes: u64 },
+    /// An HTTPS outcall
+    HttpOutcall {
+        request_bytes: u64,
+        max_response_bytes: u64,
+    },
+}
+
+/// Fees used to estimate the cost of an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    /// Number of nodes in the subnet
+    pub subnet_size: u64,
+    /// Base fee of executing an update message
+    pub update_message_execution_fee: u128,
+    /// Fee for every 10 instructions executed in an update message
+    pub ten_update_instructions_execution_fee: u128,
+    /// Base fee of receiving an ingress message
+    pub ingress_message_reception_fee: u128,
+    /// Fee for every byte of a received ingress message
+    pub ingress_byte_reception_fee: u128,
+    /// Instructions charged for every byte written to stable memory
+    pub stable_write_instructions_per_byte: u64,
+    /// Base fee of an HTTPS outcall, multiplied by the subnet size
+    pub http_request_linear_baseline_fee: u128,
+    /// Base fee of an HTTPS outcall, multiplied by the square of the subnet size
+    pub http_request_quadratic_baseline_fee: u128,
+    /// Fee for every request byte of an HTTPS outcall, multiplied by the subnet size
+    pub http_request_per_byte_fee: u128,
+    /// Fee for every response byte of an HTTPS outcall, multiplied by the subnet size
+    pub http_response_per_byte_fee: u128,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            subnet_size: 13,
+            update_message_execution_fee: 5_000_000,
+            ten_update_instructions_execution_fee: 4,
+            ingress_message_reception_fee: 1_200_000,
+            ingress_byte_reception_fee: 2_000,
+            stable_write_instructions_per_byte: 1,
+            http_request_linear_baseline_fee: 3_000_000,
+            http_request_quadratic_baseline_fee: 60_000,
+            http_request_per_byte_fee: 400,
+            http_response_per_byte_fee: 800,
+        }
+    }
+}
+
+impl CostModel {
+    /// Estimate the cycles charged for `op`
+    pub fn estimate(&self, op: &Operation) -> u128 {
+        match *op {
+            Operation::UpdateCall {
+                instructions,
+                request_bytes,
+            } => {
+                self.update_message_execution_fee
+                    + self.instructions_fee(instructions)
+                    + self.ingress_message_reception_fee
+                    + self.ingress_byte_reception_fee * request_bytes as u128
+            }
+            Operation::StableWrite { bytes } => {
+                self.instructions_fee(bytes.saturating_mul(self.stable_write_instructions_per_byte))
+            }
+            Operation::HttpOutcall {
+                request_bytes,
+                max_response_bytes,
+            } => {
+                let n = self.subnet_size as u128;
+                (self.http_request_linear_baseline_fee
+                    + self.http_request_quadratic_baseline_fee * n)
+                    * n
+                    + self.http_request_per_byte_fee * n * request_bytes as u128
+                    + self.http_response_per_byte_fee * n * max_response_bytes as u128
+            }
+        }
+    }
+
+    #[inline]
+    fn instructions_fee(&self, instructions: u64) -> u128 {
+        self.ten_update_instructions_execution_fee * instructions as u128 / 10
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_http_outcall_estimate() {
+        let model = CostModel::default();
+        let cost = model.estimate(&Operation::HttpOutcall {
+            request_bytes: 0,
+            max_response_bytes: 2_000_000,
+        });
+        assert_eq!(cost, 49_140_000 + 10_400 * 2_000_000);
+    }
+}
@@ -1,82 +1,575 @@
-use crate::{Interface, Principal};
+use crate::cost_model::{CostModel, Operation};
+use crate::http::{self, CanisterHttpRequestArgument, HttpOutcallFuture, HttpResponder};
+use crate::rand::SeededRng;
+use crate::timer::{dispatch_fired_timers, SimulatedTimers, TimerId};
+use crate::{
+    CallFuture, CallResult, ControllersFuture, Interface, Principal, StableMemoryError,
+    STABLE_PAGE_SIZE_IN_BYTES,
+};
 use ic_cdk::api::call::RejectionCode;
-use std::future::Future;
-use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use time::OffsetDateTime;
 
+/// Cycles attached to, accepted and burned by a simulated call
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedCycles {
+    pub attached: u128,
+    pub accepted: u128,
+    pub burned: u128,
+}
+
+/// Clock and timers shared by the `Edge` interfaces of a test run.
+///
+/// Without a configured time the clock follows wall-clock time, unless it is strict in
+/// which case reading the time panics. Setting or advancing the time pins the clock and
+/// runs the timers, and the global timer, that became due.
+#[derive(Default)]
+pub struct EdgeClock {
+    time: Mutex<Option<u64>>,
+    strict: bool,
+    timers: Mutex<SimulatedTimers>,
+    global_timer: Mutex<Option<u64>>,
+    global_timer_handler: Mutex<Option<GlobalTimerHandler>>,
+}
+
+/// Handler run when the global timer of a simulated canister fires
+pub type GlobalTimerHandler = Arc<dyn Fn() + Send + Sync>;
+
+impl EdgeClock {
+    /// Create a clock that follows wall-clock time until `time` is set
+    pub fn new(time: Option<u64>) -> Self {
+        Self {
+            time: Mutex::new(time),
+            strict: false,
+            timers: Mutex::new(SimulatedTimers::starting_at(time.unwrap_or_default())),
+            global_timer: Mutex::default(),
+            global_timer_handler: Mutex::default(),
+        }
+    }
+
+    /// Create a deterministic clock that panics if the time is read before it was set
+    pub fn strict(time: Option<u64>) -> Self {
+        Self {
+            strict: true,
+            ..Self::new(time)
+        }
+    }
+
+    /// Current time in nanoseconds
+    pub fn time(&self) -> u64 {
+        match *self.time.lock().expect("clock poisoned") {
+            Some(time) => time,
+            None if self.strict => panic!("Edge time was not configured in strict mode"),
+            None => OffsetDateTime::now_utc().unix_timestamp_nanos() as u64,
+        }
+    }
+
+    /// Set the global timer to fire at `timestamp`, or deactivate it with 0.
+    /// Returns the previously set timestamp, or 0 if it wasn't active.
+    pub fn set_global_timer(&self, timestamp: u64) -> u64 {
+        let mut global_timer = self.global_timer.lock().expect("clock poisoned");
+        let previous = global_timer.unwrap_or_default();
+        *global_timer = (timestamp != 0).then_some(timestamp);
+        previous
+    }
+
+    /// Run `handler` when the global timer fires
+    pub fn on_global_timer(&self, handler: GlobalTimerHandler) {
+        *self.global_timer_handler.lock().expect("clock poisoned") = Some(handler);
+    }
+
+    /// Set the time and run the callbacks of the timers, and the global timer handler,
+    /// that became due. Returns the number of callbacks run.
+    pub fn set_time(&self, time: u64) -> usize {
+        let fired = {
+            let mut current = self.time.lock().expect("clock poisoned");
+            let mut timers = self.timers();
+            if current.is_none() {
+                // timers were scheduled relative to an unpinned clock
+                timers.rebase(time.min(OffsetDateTime::now_utc().unix_timestamp_nanos() as u64));
+            }
+            *current = Some(time);
+            timers.advance_to(time)
+        };
+        dispatch_fired_timers(fired) + self.fire_global_timer(time)
+    }
+
+    /// Run the callbacks of the timers, and the global timer handler, that are due at the
+    /// current time. Returns the number of callbacks run.
+    ///
+    /// Note: Timers are only simulated once the time is set, an unpinned clock only
+    /// fires the global timer.
+    pub fn tick(&self) -> usize {
+        let pinned = *self.time.lock().expect("clock poisoned");
+        match pinned {
+            Some(time) => self.set_time(time),
+            None => self.fire_global_timer(self.time()),
+        }
+    }
+
+    /// Run the global timer handler if the global timer is due at `time`
+    fn fire_global_timer(&self, time: u64) -> usize {
+        if !self.global_timer_due(time) {
+            return 0;
+        }
+        // the global timer is deactivated once it fires, like on the IC
+        let handler = self
+            .global_timer_handler
+            .lock()
+            .expect("clock poisoned")
+            .clone();
+        match handler {
+            Some(handler) => {
+                handler();
+                1
+            }
+            None => 0,
+        }
+    }
+
+    fn global_timer_due(&self, time: u64) -> bool {
+        let mut global_timer = self.global_timer.lock().expect("clock poisoned");
+        match *global_timer {
+            Some(timestamp) if timestamp <= time => {
+                *global_timer = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Advance the time by `duration` and run the callbacks of the timers that became due.
+    /// Returns the number of callbacks run.
+    pub fn advance_time(&self, duration: Duration) -> usize {
+        self.set_time(self.time().saturating_add(duration.as_nanos() as u64))
+    }
+
+    /// The simulated timers scheduled against this clock
+    pub fn timers(&self) -> MutexGuard<'_, SimulatedTimers> {
+        self.timers.lock().expect("timers poisoned")
+    }
+}
+
+/// Handler computing the reply of a simulated inter-canister call from its arguments and
+/// the attached payment
+pub type CallHandler = Arc<dyn Fn(&[u8], u64) -> CallResult + Send + Sync>;
+
+/// Inter-canister call handlers, shareable between `Edge` interfaces
+#[derive(Clone, Default)]
+pub struct CallHandlers {
+    handlers: Arc<Mutex<HashMap<(Principal, String), CallHandler>>>,
+}
+
+impl CallHandlers {
+    /// Register `handler` to answer calls to `method` of `canister_id`
+    pub fn register(&self, canister_id: Principal, method: &str, handler: CallHandler) {
+        self.handlers
+            .lock()
+            .expect("call handlers poisoned")
+            .insert((canister_id, method.to_owned()), handler);
+    }
+
+    /// Answer a call with its registered handler
+    pub fn handle(
+        &self,
+        canister_id: Principal,
+        method: &str,
+        args: &[u8],
+        payment: u64,
+    ) -> CallResult {
+        let handler = self
+            .handlers
+            .lock()
+            .expect("call handlers poisoned")
+            .get(&(canister_id, method.to_owned()))
+            .cloned();
+        match handler {
+            Some(handler) => handler(args, payment),
+            None => Err((
+                RejectionCode::DestinationInvalid,
+                format!("No call handler registered for {canister_id} {method}"),
+            )),
+        }
+    }
+}
+
+/// Simulated instructions charged for every call into an `Edge` interface
+pub const DEFAULT_INSTRUCTIONS_PER_CALL: u64 = 1_000;
+
 pub struct Edge {
     caller: Principal,
-    time: Option<u64>,
+    clock: Arc<EdgeClock>,
+    call_handlers: CallHandlers,
+    rng: Arc<Mutex<SeededRng>>,
+    http_responder: Option<HttpResponder>,
+    canister_version: u64,
+    controllers: Vec<Principal>,
+    cycles: Mutex<SimulatedCycles>,
+    method_name: String,
+    arg_data_size: usize,
+    message_accepted: AtomicBool,
+    instructions: AtomicU64,
+    instructions_per_call: u64,
+    cost_model: CostModel,
+    stable_memory: Arc<Mutex<Vec<u8>>>,
 }
 
 impl Edge {
     pub fn new_with_caller_and_time(caller: Principal, time: Option<u64>) -> Self {
-        Self { caller, time }
+        Self {
+            caller,
+            clock: Arc::new(EdgeClock::new(time)),
+            call_handlers: CallHandlers::default(),
+            rng: Arc::default(),
+            http_responder: None,
+            canister_version: 0,
+            controllers: vec![],
+            cycles: Mutex::default(),
+            method_name: String::default(),
+            arg_data_size: 0,
+            message_accepted: AtomicBool::new(false),
+            instructions: AtomicU64::new(0),
+            instructions_per_call: DEFAULT_INSTRUCTIONS_PER_CALL,
+            cost_model: CostModel::default(),
+            stable_memory: Arc::default(),
+        }
+    }
+
+    /// Derive the interface of a message from `caller` to `method_name` with
+    /// `arg_data_size` bytes of arguments. The message shares the clock, call handlers,
+    /// randomness and stable memory of this interface, and starts with fresh cycles and
+    /// instruction counters.
+    pub fn for_message(&self, caller: Principal, method_name: &str, arg_data_size: usize) -> Self {
+        Self {
+            caller,
+            clock: self.clock.clone(),
+            call_handlers: self.call_handlers.clone(),
+            rng: self.rng.clone(),
+            http_responder: self.http_responder.clone(),
+            canister_version: self.canister_version,
+            controllers: self.controllers.clone(),
+            cycles: Mutex::default(),
+            method_name: method_name.to_owned(),
+            arg_data_size,
+            message_accepted: AtomicBool::new(false),
+            instructions: AtomicU64::new(0),
+            instructions_per_call: self.instructions_per_call,
+            cost_model: self.cost_model.clone(),
+            stable_memory: self.stable_memory.clone(),
+        }
+    }
+
+    /// Share the clock with other interfaces, e.g. across calls of an embedded canister
+    pub fn with_clock(mut self, clock: Arc<EdgeClock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replace the clock with a strict one that panics if the time is read before it
+    /// was configured
+    pub fn strict(mut self) -> Self {
+        let time = *self.clock.time.lock().expect("clock poisoned");
+        self.clock = Arc::new(EdgeClock::strict(time));
+        self
+    }
+
+    /// Share call handlers with other interfaces
+    pub fn with_call_handlers(mut self, call_handlers: CallHandlers) -> Self {
+        self.call_handlers = call_handlers;
+        self
+    }
+
+    /// Register `handler` to answer calls to `method` of `canister_id`
+    pub fn register_call_handler(
+        &self,
+        canister_id: Principal,
+        method: &str,
+        handler: CallHandler,
+    ) {
+        self.call_handlers.register(canister_id, method, handler);
+    }
+
+    /// The call handlers of this interface
+    pub fn call_handlers(&self) -> &CallHandlers {
+        &self.call_handlers
+    }
+
+    /// Seed the generator used by `raw_rand`
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(Mutex::new(SeededRng::new(seed)));
+        self
+    }
+
+    /// Answer HTTPS outcalls with `responder`
+    pub fn with_http_responder(mut self, responder: HttpResponder) -> Self {
+        self.http_responder = Some(responder);
+        self
+    }
+
+    /// Report `canister_version` as the version of the canister
+    pub fn with_canister_version(mut self, canister_version: u64) -> Self {
+        self.canister_version = canister_version;
+        self
+    }
+
+    /// Report `controllers` as the controllers of the canister
+    pub fn with_controllers(mut self, controllers: Vec<Principal>) -> Self {
+        self.controllers = controllers;
+        self
+    }
+
+    /// Attach `cycles` to the simulated call
+    pub fn with_attached_cycles(self, cycles: u128) -> Self {
+        self.cycles.lock().expect("cycles poisoned").attached = cycles;
+        self
+    }
+
+    /// Cycles attached to, accepted and burned by the simulated call so far
+    pub fn cycles(&self) -> SimulatedCycles {
+        *self.cycles.lock().expect("cycles poisoned")
+    }
+
+    /// Simulate a message calling `method_name` with `arg_data_size` bytes of arguments
+    pub fn with_message(mut self, method_name: &str, arg_data_size: usize) -> Self {
+        self.method_name = method_name.to_owned();
+        self.arg_data_size = arg_data_size;
+        self
+    }
+
+    /// Whether `accept_message` was called for the simulated message
+    pub fn message_accepted(&self) -> bool {
+        self.message_accepted.load(Ordering::SeqCst)
+    }
+
+    /// Charge `instructions_per_call` simulated instructions for every interface call
+    pub fn with_instructions_per_call(mut self, instructions_per_call: u64) -> Self {
+        self.instructions_per_call = instructions_per_call;
+        self
+    }
+
+    /// Charge simulated instructions for mocked work done outside of the interface
+    pub fn add_instructions(&self, instructions: u64) {
+        self.instructions.fetch_add(instructions, Ordering::SeqCst);
+    }
+
+    /// Charge an interface call and return the instruction counter after it
+    fn charge_call(&self) -> u64 {
+        self.instructions
+            .fetch_add(self.instructions_per_call, Ordering::SeqCst)
+            + self.instructions_per_call
+    }
+
+    /// Estimate costs with `cost_model`
+    pub fn with_cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = cost_model;
+        self
+    }
+
+    /// Share simulated stable memory with other interfaces
+    pub fn with_stable_memory(mut self, stable_memory: Arc<Mutex<Vec<u8>>>) -> Self {
+        self.stable_memory = stable_memory;
+        self
+    }
+
+    /// The simulated stable memory of this interface
+    pub fn stable_memory(&self) -> &Arc<Mutex<Vec<u8>>> {
+        &self.stable_memory
+    }
+
+    /// The clock of this interface
+    pub fn clock(&self) -> &Arc<EdgeClock> {
+        &self.clock
+    }
+
+    /// Set the time and run the callbacks of the timers that became due
+    pub fn set_time(&self, time: u64) -> usize {
+        self.clock.set_time(time)
+    }
+
+    /// Advance the time and run the callbacks of the timers that became due
+    pub fn advance_time(&self, duration: Duration) -> usize {
+        self.clock.advance_time(duration)
     }
 }
 
 impl Default for Edge {
     fn default() -> Self {
-        Self {
-            caller: Principal::from_text("aaaaa-aa").unwrap(),
-            time: None,
-        }
+        Self::new_with_caller_and_time(Principal::from_text("aaaaa-aa").unwrap(), None)
     }
 }
 
 impl Interface for Edge {
     fn time(&self) -> u64 {
-        self.time
-            .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp_nanos() as u64)
+        self.charge_call();
+        self.clock.time()
     }
 
     fn caller(&self) -> Principal {
+        self.charge_call();
         self.caller
     }
 
     fn canister_balance(&self) -> u64 {
+        self.charge_call();
         500_u64
     }
 
     fn call_canister(
         &self,
-        _canister_id: Principal,
-        _method: String,
-        _args: Vec<u8>,
-        _payment: u64,
-    ) -> Result<Vec<u8>, (RejectionCode, String)> {
-        unimplemented!();
+        canister_id: Principal,
+        method: String,
+        args: Vec<u8>,
+        payment: u64,
+    ) -> CallFuture {
+        self.charge_call();
+        let result = self
+            .call_handlers
+            .handle(canister_id, &method, &args, payment);
+        Box::pin(std::future::ready(result))
     }
 
     fn id(&self) -> Principal {
-        self.caller()
+        self.charge_call();
+        self.caller
     }
     fn get_memory_usage(&self) -> u64 {
+        self.charge_call();
         // FIXME
         0
     }
 
     fn performance_counter(&self, _counter_type: u32) -> u64 {
-        0
+        self.charge_call()
     }
 
     fn instruction_counter(&self) -> u64 {
-        0
+        self.charge_call()
     }
 
     fn stable64_size(&self) -> u64 {
-        0
+        self.charge_call();
+        let len = self
+            .stable_memory
+            .lock()
+            .expect("stable memory poisoned")
+            .len();
+        (len / STABLE_PAGE_SIZE_IN_BYTES) as u64
+    }
+
+    fn stable64_grow(&self, new_pages: u64) -> Result<u64, StableMemoryError> {
+        self.charge_call();
+        let mut stable_memory = self.stable_memory.lock().expect("stable memory poisoned");
+        let pages = (stable_memory.len() / STABLE_PAGE_SIZE_IN_BYTES) as u64;
+        let len = stable_memory.len() + new_pages as usize * STABLE_PAGE_SIZE_IN_BYTES;
+        stable_memory.resize(len, 0);
+        Ok(pages)
+    }
+
+    fn stable_write_chunked(&self, offset: u64, bytes: &[u8]) {
+        self.charge_call();
+        let offset = offset as usize;
+        self.stable_memory.lock().expect("stable memory poisoned")[offset..offset + bytes.len()]
+            .copy_from_slice(bytes);
+    }
+
+    fn set_timer(&self, delay: Duration, callback_id: u64) -> TimerId {
+        self.charge_call();
+        self.clock
+            .timers()
+            .set_timer(delay, self.caller, callback_id)
+    }
+
+    fn set_timer_interval(&self, interval: Duration, callback_id: u64) -> TimerId {
+        self.charge_call();
+        self.clock
+            .timers()
+            .set_timer_interval(interval, self.caller, callback_id)
+    }
+
+    fn clear_timer(&self, timer_id: TimerId) {
+        self.charge_call();
+        self.clock.timers().clear_timer(timer_id)
+    }
+
+    fn raw_rand(&self) -> CallFuture {
+        self.charge_call();
+        let bytes = self.rng.lock().expect("rng poisoned").raw_rand();
+        Box::pin(std::future::ready(Ok(bytes)))
+    }
+
+    fn http_request_outcall(
+        &self,
+        request: CanisterHttpRequestArgument,
+        _cycles: u128,
+    ) -> HttpOutcallFuture {
+        self.charge_call();
+        let result = match &self.http_responder {
+            Some(responder) => responder(&request),
+            None => http::no_responder(&request),
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn canister_version(&self) -> u64 {
+        self.charge_call();
+        self.canister_version
     }
-}
 
-struct TestFuture;
+    fn is_controller(&self, principal: &Principal) -> bool {
+        self.charge_call();
+        self.controllers.contains(principal)
+    }
 
-impl Future for TestFuture {
-    type Output = Result<Vec<u8>, (RejectionCode, String)>;
+    fn controllers(&self) -> ControllersFuture {
+        self.charge_call();
+        Box::pin(std::future::ready(Ok(self.controllers.clone())))
+    }
+
+    fn msg_cycles_available(&self) -> u128 {
+        self.charge_call();
+        let cycles = self.cycles.lock().expect("cycles poisoned");
+        cycles.attached - cycles.accepted
+    }
+
+    fn msg_cycles_accept(&self, amount: u128) -> u128 {
+        self.charge_call();
+        let mut cycles = self.cycles.lock().expect("cycles poisoned");
+        let accepted = amount.min(cycles.attached - cycles.accepted);
+        cycles.accepted += accepted;
+        accepted
+    }
+
+    fn cycles_burn(&self, amount: u128) -> u128 {
+        self.charge_call();
+        self.cycles.lock().expect("cycles poisoned").burned += amount;
+        amount
+    }
+
+    fn method_name(&self) -> String {
+        self.charge_call();
+        self.method_name.clone()
+    }
+
+    fn arg_data_size(&self) -> usize {
+        self.charge_call();
+        self.arg_data_size
+    }
+
+    fn accept_message(&self) {
+        self.charge_call();
+        self.message_accepted.store(true, Ordering::SeqCst);
+    }
+
+    fn set_global_timer(&self, timestamp: u64) -> u64 {
+        self.charge_call();
+        self.clock.set_global_timer(timestamp)
+    }
 
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let result = Ok(vec![]);
-        Poll::Ready(result)
+    fn estimate_cost(&self, op: &Operation) -> u128 {
+        self.charge_call();
+        self.cost_model.estimate(op)
     }
 }
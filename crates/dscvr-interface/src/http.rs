@@ -0,0 +1,29 @@
+//! HTTPS outcalls made through the `Interface`.
+
+use ic_cdk::api::call::RejectionCode;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+};
+
+/// Result of an HTTPS outcall
+pub type HttpOutcallResult = Result<HttpResponse, (RejectionCode, String)>;
+
+/// Future resolving to the result of an HTTPS outcall
+pub type HttpOutcallFuture = Pin<Box<dyn Future<Output = HttpOutcallResult>>>;
+
+/// Mock responder answering HTTPS outcalls of a simulated interface
+pub type HttpResponder =
+    Arc<dyn Fn(&CanisterHttpRequestArgument) -> HttpOutcallResult + Send + Sync>;
+
+/// Result returned by simulated interfaces without a responder
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn no_responder(request: &CanisterHttpRequestArgument) -> HttpOutcallResult {
+    Err((
+        RejectionCode::SysTransient,
+        format!("No http outcall responder registered for {}", request.url),
+    ))
+}
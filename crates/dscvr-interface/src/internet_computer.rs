@@ -1,10 +1,30 @@
-use crate::{Interface, Principal};
-use ic_cdk::api::call::RejectionCode;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::http::{CanisterHttpRequestArgument, HttpOutcallFuture};
+use crate::timer::{dispatch_timer_callback, TimerId, GLOBAL_TIMER_CALLBACK_ID};
+use crate::{
+    CallFuture, ControllersFuture, Interface, Principal, StableMemoryError,
+    STABLE_PAGE_SIZE_IN_BYTES,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 pub const SYSTEM: &dyn Interface = &InternetComputer;
 
+thread_local! {
+    static NEXT_TIMER_ID: Cell<u64> = const { Cell::new(0) };
+    static TIMERS: RefCell<BTreeMap<TimerId, ic_cdk_timers::TimerId>> = RefCell::default();
+    /// Deadline and `ic_cdk_timers` timer of the global timer, if set
+    static GLOBAL_TIMER: Cell<Option<(u64, ic_cdk_timers::TimerId)>> = const { Cell::new(None) };
+}
+
+fn next_timer_id() -> TimerId {
+    NEXT_TIMER_ID.with(|id| {
+        let next = id.get();
+        id.set(next + 1);
+        TimerId(next)
+    })
+}
+
 #[derive(Default)]
 pub struct InternetComputer;
 
@@ -27,25 +47,10 @@ impl Interface for InternetComputer {
         method: String,
         args: Vec<u8>,
         payment: u64,
-    ) -> Result<Vec<u8>, (RejectionCode, String)> {
-        // Ideally ic_cdk::spawn would allow returning a result, but it doesn't. so we go through
-        // some gymanistics to make it work.
-        let result: Rc<RefCell<Result<Vec<u8>, (RejectionCode, String)>>> = Rc::new(RefCell::new(
-            Err((RejectionCode::CanisterReject, "spawn failed".to_owned())),
-        ));
-        {
-            let caller_result = result.clone();
-            ic_cdk::spawn(async move {
-                let result =
-                    ic_cdk::api::call::call_raw(canister_id, &method, &args, payment).await;
-                let _ = caller_result.replace(result);
-            });
-        }
-        let mut mut_borrow = result.borrow_mut();
-        match &mut *mut_borrow {
-            Ok(result) => Ok(std::mem::take(result)),
-            Err((code, s)) => Err((code.clone(), std::mem::take(s))),
-        }
+    ) -> CallFuture {
+        Box::pin(
+            async move { ic_cdk::api::call::call_raw(canister_id, &method, &args, payment).await },
+        )
     }
 
     fn id(&self) -> Principal {
@@ -66,4 +71,120 @@ impl Interface for InternetComputer {
     fn stable64_size(&self) -> u64 {
         ic_cdk::api::stable::stable64_size()
     }
+
+    fn stable64_grow(&self, new_pages: u64) -> Result<u64, StableMemoryError> {
+        ic_cdk::api::stable::stable64_grow(new_pages)
+    }
+
+    fn stable_write_chunked(&self, mut offset: u64, bytes: &[u8]) {
+        for chunk in bytes.chunks(STABLE_PAGE_SIZE_IN_BYTES) {
+            ic_cdk::api::stable::stable64_write(offset, chunk);
+            offset += chunk.len() as u64;
+        }
+    }
+
+    fn set_timer(&self, delay: Duration, callback_id: u64) -> TimerId {
+        let timer_id = next_timer_id();
+        let ic_timer_id = ic_cdk_timers::set_timer(delay, move || {
+            TIMERS.with(|timers| timers.borrow_mut().remove(&timer_id));
+            dispatch_timer_callback(&InternetComputer, callback_id);
+        });
+        TIMERS.with(|timers| timers.borrow_mut().insert(timer_id, ic_timer_id));
+        timer_id
+    }
+
+    fn set_timer_interval(&self, interval: Duration, callback_id: u64) -> TimerId {
+        let timer_id = next_timer_id();
+        let ic_timer_id = ic_cdk_timers::set_timer_interval(interval, move || {
+            dispatch_timer_callback(&InternetComputer, callback_id);
+        });
+        TIMERS.with(|timers| timers.borrow_mut().insert(timer_id, ic_timer_id));
+        timer_id
+    }
+
+    fn clear_timer(&self, timer_id: TimerId) {
+        if let Some(ic_timer_id) = TIMERS.with(|timers| timers.borrow_mut().remove(&timer_id)) {
+            ic_cdk_timers::clear_timer(ic_timer_id);
+        }
+    }
+
+    fn raw_rand(&self) -> CallFuture {
+        Box::pin(async move {
+            ic_cdk::api::management_canister::main::raw_rand()
+                .await
+                .map(|(bytes,)| bytes)
+        })
+    }
+
+    fn http_request_outcall(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> HttpOutcallFuture {
+        Box::pin(async move {
+            ic_cdk::api::management_canister::http_request::http_request(request, cycles)
+                .await
+                .map(|(response,)| response)
+        })
+    }
+
+    fn canister_version(&self) -> u64 {
+        ic_cdk::api::canister_version()
+    }
+
+    fn is_controller(&self, principal: &Principal) -> bool {
+        ic_cdk::api::is_controller(principal)
+    }
+
+    fn controllers(&self) -> ControllersFuture {
+        // Only available when the canister is one of its own controllers
+        let canister_id = ic_cdk::api::id();
+        Box::pin(async move {
+            ic_cdk::api::management_canister::main::canister_status(
+                ic_cdk::api::management_canister::main::CanisterIdRecord { canister_id },
+            )
+            .await
+            .map(|(status,)| status.settings.controllers)
+        })
+    }
+
+    fn msg_cycles_available(&self) -> u128 {
+        ic_cdk::api::call::msg_cycles_available128()
+    }
+
+    fn msg_cycles_accept(&self, amount: u128) -> u128 {
+        ic_cdk::api::call::msg_cycles_accept128(amount)
+    }
+
+    fn cycles_burn(&self, amount: u128) -> u128 {
+        ic_cdk::api::cycles_burn(amount)
+    }
+
+    fn method_name(&self) -> String {
+        ic_cdk::api::call::method_name()
+    }
+
+    fn arg_data_size(&self) -> usize {
+        ic_cdk::api::call::arg_data_raw_size()
+    }
+
+    fn accept_message(&self) {
+        ic_cdk::api::call::accept_message()
+    }
+
+    fn set_global_timer(&self, timestamp: u64) -> u64 {
+        let previous = GLOBAL_TIMER.with(|global_timer| global_timer.take());
+        if let Some((_, ic_timer_id)) = previous {
+            ic_cdk_timers::clear_timer(ic_timer_id);
+        }
+        if timestamp != 0 {
+            let delay = Duration::from_nanos(timestamp.saturating_sub(ic_cdk::api::time()));
+            let ic_timer_id = ic_cdk_timers::set_timer(delay, || {
+                GLOBAL_TIMER.with(|global_timer| global_timer.set(None));
+                dispatch_timer_callback(&InternetComputer, GLOBAL_TIMER_CALLBACK_ID);
+            });
+            GLOBAL_TIMER.with(|global_timer| global_timer.set(Some((timestamp, ic_timer_id))));
+        }
+        previous.map(|(deadline, _)| deadline).unwrap_or_default()
+    }
 }
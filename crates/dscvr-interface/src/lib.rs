@@ -1,13 +1,37 @@
 use candid::Principal;
-use ic_cdk::api::call::RejectionCode;
+use cost_model::{CostModel, Operation};
+use http::{CanisterHttpRequestArgument, HttpOutcallFuture};
+pub use ic_cdk::api::call::RejectionCode;
+pub use ic_cdk::api::stable::StableMemoryError;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use timer::TimerId;
 
+pub mod cost_model;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod edge;
+pub mod http;
 #[cfg(target_arch = "wasm32")]
 pub mod internet_computer;
+pub mod rand;
+pub mod timer;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod unit_test;
 
+/// Size of a stable memory page
+pub const STABLE_PAGE_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// Result of an inter-canister call
+pub type CallResult = Result<Vec<u8>, (RejectionCode, String)>;
+
+/// Future resolving to the result of an inter-canister call
+pub type CallFuture = Pin<Box<dyn Future<Output = CallResult>>>;
+
+/// Future resolving to the controllers of the canister
+pub type ControllersFuture =
+    Pin<Box<dyn Future<Output = Result<Vec<Principal>, (RejectionCode, String)>>>>;
+
 pub trait Interface: Send + Sync {
     fn time(&self) -> u64;
     fn caller(&self) -> Principal;
@@ -18,10 +42,44 @@ pub trait Interface: Send + Sync {
         method: String,
         args: Vec<u8>,
         payment: u64,
-    ) -> Result<Vec<u8>, (RejectionCode, String)>;
+    ) -> CallFuture;
     fn id(&self) -> Principal;
     fn get_memory_usage(&self) -> u64;
     fn performance_counter(&self, counter_type: u32) -> u64;
     fn instruction_counter(&self) -> u64;
     fn stable64_size(&self) -> u64;
+    /// Grow stable memory by `new_pages`, returning the previous size in pages
+    fn stable64_grow(&self, new_pages: u64) -> Result<u64, StableMemoryError>;
+    /// Write a borrowed slice to stable memory in page sized chunks without copying it
+    /// into an intermediate buffer. Stable memory must already be large enough.
+    fn stable_write_chunked(&self, offset: u64, bytes: &[u8]);
+    fn set_timer(&self, delay: Duration, callback_id: u64) -> TimerId;
+    fn set_timer_interval(&self, interval: Duration, callback_id: u64) -> TimerId;
+    fn clear_timer(&self, timer_id: TimerId);
+    fn raw_rand(&self) -> CallFuture;
+    fn http_request_outcall(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> HttpOutcallFuture;
+    fn canister_version(&self) -> u64;
+    fn is_controller(&self, principal: &Principal) -> bool;
+    fn controllers(&self) -> ControllersFuture;
+    fn msg_cycles_available(&self) -> u128;
+    fn msg_cycles_accept(&self, amount: u128) -> u128;
+    fn cycles_burn(&self, amount: u128) -> u128;
+    fn method_name(&self) -> String;
+    fn arg_data_size(&self) -> usize;
+    fn accept_message(&self);
+    /// Set the global timer to fire at `timestamp`, or deactivate it with 0, returning the
+    /// previously set timestamp.
+    ///
+    /// On the IC `ic_cdk_timers` owns the `canister_global_timer` export, so the global
+    /// timer is a timer of `ic_cdk_timers` running the callback registered for
+    /// [`timer::GLOBAL_TIMER_CALLBACK_ID`]. Never call `ic_cdk::api::set_global_timer`
+    /// directly, it overwrites the deadline of the `ic_cdk_timers` timers.
+    fn set_global_timer(&self, timestamp: u64) -> u64;
+    fn estimate_cost(&self, op: &Operation) -> u128 {
+        CostModel::default().estimate(op)
+    }
 }
@@ -0,0 +1,43 @@
+//! Deterministic randomness for the simulated interfaces.
+
+/// Size in bytes of the randomness returned by `Interface::raw_rand`
+pub const RAW_RAND_LENGTH: usize = 32;
+
+/// Seedable SplitMix64 generator.
+///
+/// Not suitable for anything but reproducible tests.
+#[derive(Debug, Clone, Default)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    /// Create a generator from a seed
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Return the next pseudo random number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Fill `bytes` with pseudo random bytes
+    pub fn fill_bytes(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let value = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+
+    /// Return the same amount of pseudo random bytes as `raw_rand`
+    pub fn raw_rand(&mut self) -> Vec<u8> {
+        let mut bytes = vec![0; RAW_RAND_LENGTH];
+        self.fill_bytes(&mut bytes);
+        bytes
+    }
+}
@@ -0,0 +1,267 @@
+//! Timers scheduled through the `Interface`.
+//!
+//! Timer callbacks are plain functions registered under a numeric callback id, so the
+//! same canister code can schedule timers on the IC and against the simulated clock
+//! used by `edge`/`unit_test`. Callbacks are registered per canister, so embedded
+//! canisters sharing a process can reuse the same callback ids.
+
+use crate::{Interface, Principal};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Identifier of a scheduled timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(pub u64);
+
+/// Callback invoked when a timer fires
+pub type TimerCallback = fn();
+
+/// Callback id of the global timer set with `Interface::set_global_timer` on the IC
+pub const GLOBAL_TIMER_CALLBACK_ID: u64 = u64::MAX;
+
+/// Timer callbacks keyed by canister id, then by callback id
+type CallbacksByCanister = BTreeMap<Vec<u8>, BTreeMap<u64, TimerCallback>>;
+
+static CALLBACKS: RwLock<CallbacksByCanister> = RwLock::new(BTreeMap::new());
+
+/// Register the callback run by the timers of the canister of `system` scheduled with
+/// `callback_id`
+pub fn register_timer_callback(system: &dyn Interface, callback_id: u64, callback: TimerCallback) {
+    CALLBACKS
+        .write()
+        .expect("timer callbacks poisoned")
+        .entry(system.id().as_slice().to_vec())
+        .or_default()
+        .insert(callback_id, callback);
+}
+
+/// Run the callback registered by the canister of `system` for `callback_id`.
+///
+/// Returns false if no callback was registered.
+pub fn dispatch_timer_callback(system: &dyn Interface, callback_id: u64) -> bool {
+    run_callback(system.id().as_slice(), callback_id)
+}
+
+/// Run the callbacks of the timers that fired, returning the number of callbacks run
+pub(crate) fn dispatch_fired_timers(fired: Vec<(Principal, u64)>) -> usize {
+    fired
+        .into_iter()
+        .filter(|(canister_id, callback_id)| run_callback(canister_id.as_slice(), *callback_id))
+        .count()
+}
+
+fn run_callback(canister_id: &[u8], callback_id: u64) -> bool {
+    let callback = CALLBACKS
+        .read()
+        .expect("timer callbacks poisoned")
+        .get(canister_id)
+        .and_then(|callbacks| callbacks.get(&callback_id))
+        .copied();
+    match callback {
+        Some(callback) => {
+            callback();
+            true
+        }
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimulatedTimer {
+    deadline: u64,
+    interval: Option<u64>,
+    canister_id: Principal,
+    callback_id: u64,
+}
+
+/// Timers scheduled against a simulated clock
+#[derive(Debug, Default)]
+pub struct SimulatedTimers {
+    now: u64,
+    next_id: u64,
+    timers: BTreeMap<TimerId, SimulatedTimer>,
+}
+
+impl SimulatedTimers {
+    /// Create an empty set of timers with the clock at 0
+    pub const fn new() -> Self {
+        Self {
+            now: 0,
+            next_id: 0,
+            timers: BTreeMap::new(),
+        }
+    }
+
+    /// Create an empty set of timers with the clock at `now`
+    pub const fn starting_at(now: u64) -> Self {
+        Self {
+            now,
+            next_id: 0,
+            timers: BTreeMap::new(),
+        }
+    }
+
+    /// Current time of the simulated clock in nanoseconds
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Number of pending timers
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Whether there are no pending timers
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Schedule a timer of `canister_id` firing once after `delay`
+    pub fn set_timer(
+        &mut self,
+        delay: Duration,
+        canister_id: Principal,
+        callback_id: u64,
+    ) -> TimerId {
+        self.insert(delay, None, canister_id, callback_id)
+    }
+
+    /// Schedule a timer of `canister_id` firing every `interval`
+    pub fn set_timer_interval(
+        &mut self,
+        interval: Duration,
+        canister_id: Principal,
+        callback_id: u64,
+    ) -> TimerId {
+        // a zero interval would fire forever within a single advance
+        let interval = (interval.as_nanos() as u64).max(1);
+        let delay = Duration::from_nanos(interval);
+        self.insert(delay, Some(interval), canister_id, callback_id)
+    }
+
+    /// Cancel a pending timer
+    pub fn clear_timer(&mut self, timer_id: TimerId) {
+        self.timers.remove(&timer_id);
+    }
+
+    /// Move the clock to `now` without firing anything, keeping the remaining delay of
+    /// every pending timer
+    pub fn rebase(&mut self, now: u64) {
+        for timer in self.timers.values_mut() {
+            timer.deadline = now.saturating_add(timer.deadline.saturating_sub(self.now));
+        }
+        self.now = now;
+    }
+
+    /// Advance the clock by `duration` and return the canister and callback ids of the
+    /// timers that became due, in the order they fired
+    pub fn advance(&mut self, duration: Duration) -> Vec<(Principal, u64)> {
+        let now = self.now.saturating_add(duration.as_nanos() as u64);
+        self.advance_to(now)
+    }
+
+    /// Move the clock to `now` and return the canister and callback ids of the timers
+    /// that became due, in the order they fired. The clock never moves backwards.
+    pub fn advance_to(&mut self, now: u64) -> Vec<(Principal, u64)> {
+        let mut fired = vec![];
+        while let Some((timer_id, deadline)) = self
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .min_by_key(|(timer_id, timer)| (timer.deadline, **timer_id))
+            .map(|(timer_id, timer)| (*timer_id, timer.deadline))
+        {
+            self.now = self.now.max(deadline);
+            let timer = self.timers.get_mut(&timer_id).expect("timer exists");
+            fired.push((timer.canister_id, timer.callback_id));
+            match timer.interval {
+                Some(interval) => timer.deadline = deadline.saturating_add(interval),
+                None => {
+                    self.timers.remove(&timer_id);
+                }
+            }
+        }
+        self.now = self.now.max(now);
+        fired
+    }
+
+    fn insert(
+        &mut self,
+        delay: Duration,
+        interval: Option<u64>,
+        canister_id: Principal,
+        callback_id: u64,
+    ) -> TimerId {
+        let timer_id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.insert(
+            timer_id,
+            SimulatedTimer {
+                deadline: self.now.saturating_add(delay.as_nanos() as u64),
+                interval,
+                canister_id,
+                callback_id,
+            },
+        );
+        timer_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::unit_test::UnitTest;
+    use crate::Principal;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static FIRED: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn test_callbacks_are_registered_per_canister() {
+        let first = UnitTest::builder()
+            .canister_id(Principal::from_slice(&[0xfc, 1]))
+            .build();
+        let second = UnitTest::builder()
+            .canister_id(Principal::from_slice(&[0xfc, 2]))
+            .build();
+        register_timer_callback(&first, 7, || {
+            FIRED.fetch_add(1, Ordering::SeqCst);
+        });
+        register_timer_callback(&second, 7, || {
+            FIRED.fetch_add(10, Ordering::SeqCst);
+        });
+
+        assert!(dispatch_timer_callback(&first, 7));
+        assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+        assert!(dispatch_timer_callback(&second, 7));
+        assert_eq!(FIRED.load(Ordering::SeqCst), 11);
+        assert!(!dispatch_timer_callback(&first, 8));
+    }
+
+    #[test]
+    fn test_simulated_timers() {
+        let canister = Principal::anonymous();
+        let callback_ids = |fired: Vec<(Principal, u64)>| -> Vec<u64> {
+            fired
+                .into_iter()
+                .map(|(_, callback_id)| callback_id)
+                .collect()
+        };
+        let mut timers = SimulatedTimers::new();
+        let once = timers.set_timer(Duration::from_secs(5), canister, 1);
+        timers.set_timer_interval(Duration::from_secs(2), canister, 2);
+        assert!(timers.advance(Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            callback_ids(timers.advance(Duration::from_secs(5))),
+            vec![2, 2, 1, 2]
+        );
+        assert_eq!(timers.now(), 6_000_000_000);
+        timers.clear_timer(once);
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.advance(Duration::from_secs(2)), vec![(canister, 2)]);
+    }
+}
@@ -1,26 +1,158 @@
-use crate::{Interface, Principal};
-use ic_cdk::api::call::RejectionCode;
+use crate::http::{self, CanisterHttpRequestArgument, HttpOutcallFuture};
+use crate::rand::SeededRng;
+use crate::timer::{dispatch_fired_timers, SimulatedTimers, TimerId};
+use crate::{
+    CallFuture, CallResult, ControllersFuture, Interface, Principal, StableMemoryError,
+    STABLE_PAGE_SIZE_IN_BYTES,
+};
+use std::cell::{Cell, RefCell};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use time::OffsetDateTime;
 
-pub const SYSTEM: &dyn Interface = &UnitTest;
+thread_local! {
+    static TIMERS: RefCell<SimulatedTimers> = const { RefCell::new(SimulatedTimers::new()) };
+    static RNG: RefCell<SeededRng> = const { RefCell::new(SeededRng::new(0)) };
+    static GLOBAL_TIMER: Cell<u64> = const { Cell::new(0) };
+    static STABLE_MEMORY: RefCell<Vec<u8>> = const { RefCell::new(vec![]) };
+}
+
+/// Builder for a `UnitTest` interface
+pub struct UnitTestInterfaceBuilder {
+    caller: Principal,
+    canister_id: Option<Principal>,
+    balance: u64,
+    time: Option<u64>,
+    memory_usage: u64,
+    instruction_counters: Vec<u64>,
+}
+
+impl Default for UnitTestInterfaceBuilder {
+    fn default() -> Self {
+        Self {
+            caller: Principal::from_text("aaaaa-aa").unwrap(),
+            canister_id: None,
+            balance: 500,
+            time: None,
+            memory_usage: 0,
+            instruction_counters: vec![],
+        }
+    }
+}
 
-#[derive(Default)]
-pub struct UnitTest;
+impl UnitTestInterfaceBuilder {
+    /// Principal returned by `caller`
+    pub fn caller(mut self, caller: Principal) -> Self {
+        self.caller = caller;
+        self
+    }
+
+    /// Principal returned by `id`, defaults to the caller
+    pub fn canister_id(mut self, canister_id: Principal) -> Self {
+        self.canister_id = Some(canister_id);
+        self
+    }
+
+    /// Cycles returned by `canister_balance`
+    pub fn balance(mut self, balance: u64) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Fixed time in nanoseconds returned by `time`, defaults to wall-clock time
+    pub fn time(mut self, time: u64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Bytes returned by `get_memory_usage`
+    pub fn memory_usage(mut self, memory_usage: u64) -> Self {
+        self.memory_usage = memory_usage;
+        self
+    }
+
+    /// Values returned by successive reads of the instruction counter. The last value
+    /// is repeated once the sequence is exhausted.
+    pub fn instruction_counters(mut self, instruction_counters: Vec<u64>) -> Self {
+        self.instruction_counters = instruction_counters;
+        self
+    }
+
+    /// Build the interface
+    pub fn build(self) -> UnitTest {
+        UnitTest {
+            caller: self.caller,
+            canister_id: self.canister_id.unwrap_or(self.caller),
+            balance: self.balance,
+            time: self.time,
+            memory_usage: self.memory_usage,
+            instruction_counters: self.instruction_counters,
+            instruction_counter_reads: AtomicUsize::new(0),
+        }
+    }
+}
+
+pub struct UnitTest {
+    caller: Principal,
+    canister_id: Principal,
+    balance: u64,
+    time: Option<u64>,
+    memory_usage: u64,
+    instruction_counters: Vec<u64>,
+    instruction_counter_reads: AtomicUsize,
+}
+
+impl Default for UnitTest {
+    fn default() -> Self {
+        UnitTestInterfaceBuilder::default().build()
+    }
+}
+
+impl UnitTest {
+    /// Return a builder for a configured interface
+    pub fn builder() -> UnitTestInterfaceBuilder {
+        UnitTestInterfaceBuilder::default()
+    }
+
+    fn next_instruction_counter(&self) -> u64 {
+        let read = self
+            .instruction_counter_reads
+            .fetch_add(1, Ordering::SeqCst);
+        self.instruction_counters
+            .get(read)
+            .or(self.instruction_counters.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Seed the generator used by `raw_rand` on the current test thread
+    pub fn seed_rng(&self, seed: u64) {
+        RNG.with(|rng| *rng.borrow_mut() = SeededRng::new(seed));
+    }
+
+    /// Advance the simulated timer clock of the current test thread by `duration` and run
+    /// the callbacks of the timers that became due. Returns the number of callbacks run.
+    pub fn advance_timers(&self, duration: Duration) -> usize {
+        let fired = TIMERS.with(|timers| timers.borrow_mut().advance(duration));
+        dispatch_fired_timers(fired)
+    }
+}
 
 impl Interface for UnitTest {
     fn time(&self) -> u64 {
-        OffsetDateTime::now_utc().unix_timestamp_nanos() as u64
+        self.time
+            .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp_nanos() as u64)
     }
 
     fn caller(&self) -> Principal {
-        Principal::from_text("aaaaa-aa").unwrap()
+        self.caller
     }
 
     fn canister_balance(&self) -> u64 {
-        500_u64
+        self.balance
     }
 
     fn call_canister(
@@ -29,35 +161,124 @@ impl Interface for UnitTest {
         _method: String,
         _args: Vec<u8>,
         _payment: u64,
-    ) -> Result<Vec<u8>, (RejectionCode, String)> {
-        unimplemented!();
+    ) -> CallFuture {
+        Box::pin(TestFuture)
     }
 
     fn id(&self) -> Principal {
-        self.caller()
+        self.canister_id
     }
     fn get_memory_usage(&self) -> u64 {
-        // FIXME
-        0
+        self.memory_usage
     }
 
     fn performance_counter(&self, _counter_type: u32) -> u64 {
-        0
+        self.next_instruction_counter()
     }
 
     fn instruction_counter(&self) -> u64 {
-        0
+        self.next_instruction_counter()
     }
 
     fn stable64_size(&self) -> u64 {
+        STABLE_MEMORY
+            .with(|stable_memory| (stable_memory.borrow().len() / STABLE_PAGE_SIZE_IN_BYTES) as u64)
+    }
+
+    fn stable64_grow(&self, new_pages: u64) -> Result<u64, StableMemoryError> {
+        STABLE_MEMORY.with(|stable_memory| {
+            let mut stable_memory = stable_memory.borrow_mut();
+            let pages = (stable_memory.len() / STABLE_PAGE_SIZE_IN_BYTES) as u64;
+            let len = stable_memory.len() + new_pages as usize * STABLE_PAGE_SIZE_IN_BYTES;
+            stable_memory.resize(len, 0);
+            Ok(pages)
+        })
+    }
+
+    fn stable_write_chunked(&self, offset: u64, bytes: &[u8]) {
+        STABLE_MEMORY.with(|stable_memory| {
+            let offset = offset as usize;
+            stable_memory.borrow_mut()[offset..offset + bytes.len()].copy_from_slice(bytes);
+        })
+    }
+
+    fn set_timer(&self, delay: Duration, callback_id: u64) -> TimerId {
+        TIMERS.with(|timers| {
+            timers
+                .borrow_mut()
+                .set_timer(delay, self.canister_id, callback_id)
+        })
+    }
+
+    fn set_timer_interval(&self, interval: Duration, callback_id: u64) -> TimerId {
+        TIMERS.with(|timers| {
+            timers
+                .borrow_mut()
+                .set_timer_interval(interval, self.canister_id, callback_id)
+        })
+    }
+
+    fn clear_timer(&self, timer_id: TimerId) {
+        TIMERS.with(|timers| timers.borrow_mut().clear_timer(timer_id))
+    }
+
+    fn raw_rand(&self) -> CallFuture {
+        let bytes = RNG.with(|rng| rng.borrow_mut().raw_rand());
+        Box::pin(std::future::ready(Ok(bytes)))
+    }
+
+    fn http_request_outcall(
+        &self,
+        request: CanisterHttpRequestArgument,
+        _cycles: u128,
+    ) -> HttpOutcallFuture {
+        Box::pin(std::future::ready(http::no_responder(&request)))
+    }
+
+    fn canister_version(&self) -> u64 {
         0
     }
+
+    // the caller controls the canister so guarded methods can be exercised
+    fn is_controller(&self, principal: &Principal) -> bool {
+        *principal == self.caller()
+    }
+
+    fn controllers(&self) -> ControllersFuture {
+        Box::pin(std::future::ready(Ok(vec![self.caller()])))
+    }
+
+    fn msg_cycles_available(&self) -> u128 {
+        0
+    }
+
+    fn msg_cycles_accept(&self, _amount: u128) -> u128 {
+        0
+    }
+
+    fn cycles_burn(&self, amount: u128) -> u128 {
+        amount
+    }
+
+    fn method_name(&self) -> String {
+        String::default()
+    }
+
+    fn arg_data_size(&self) -> usize {
+        0
+    }
+
+    fn accept_message(&self) {}
+
+    fn set_global_timer(&self, timestamp: u64) -> u64 {
+        GLOBAL_TIMER.with(|global_timer| global_timer.replace(timestamp))
+    }
 }
 
 struct TestFuture;
 
 impl Future for TestFuture {
-    type Output = Result<Vec<u8>, (RejectionCode, String)>;
+    type Output = CallResult;
 
     fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
         let result = Ok(vec![]);
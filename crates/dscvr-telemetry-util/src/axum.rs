@@ -0,0 +1,246 @@
+//! Axum request metrics and Prometheus endpoint, for axum 0.6 (`axum-06` feature, default)
+//! and axum 0.7 (`axum-07` feature).
+//!
+//! The functions of the enabled version are re-exported here. When both versions are enabled,
+//! e.g. by two dependents of the crate, use them from [`v06`] and [`v07`] instead.
+
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder};
+
+// Defines the endpoints and helpers shared by the axum versions in the module of the
+// version, which only defines the `track_metrics` middleware since its request and body
+// types differ between the versions
+#[cfg(any(feature = "axum-06", feature = "axum-07"))]
+macro_rules! axum_endpoints {
+    ($axum:ident) => {
+        use metrics_exporter_prometheus::{BuildError, PrometheusHandle};
+        use $axum::{
+            http::{header, HeaderMap, StatusCode},
+            response::Response,
+            routing::get,
+            Router,
+        };
+
+        // Takes an existing axum router, installs the prometheus metrics recorder and
+        // injects the metrics endpoint into the router after the handler layer is installed so that
+        // `/metrics` route itself is not included in the routing layer metrics measured
+        pub fn install_metrics_layer<K, S, V>(
+            app: Router<S>,
+            global_buckets: Option<&[f64]>,
+            global_labels: Option<Vec<(K, V)>>,
+            matched_metric_buckets: Option<Vec<(&str, &[f64])>>,
+        ) -> Result<Router<S>, BuildError>
+        where
+            K: Into<String>,
+            S: Clone + Send + Sync + 'static,
+            V: Into<String>,
+        {
+            let handle = $crate::axum::metrics_builder(
+                global_buckets,
+                global_labels,
+                matched_metric_buckets,
+            )?
+            .install_recorder()?;
+            let (app, render) = install_metrics_layer_with_handle(app, handle);
+            Ok(app.route(
+                "/metrics",
+                get(move |headers| render_metrics(headers, render)),
+            ))
+        }
+
+        // Takes an existing axum router and the handle of a recorder installed by the application,
+        // injects the metrics middleware into the router and returns it with a closure rendering
+        // the metrics, leaving it to the application to expose them where it wants
+        pub fn install_metrics_layer_with_handle<S>(
+            app: Router<S>,
+            handle: PrometheusHandle,
+        ) -> (
+            Router<S>,
+            impl Fn() -> String + Clone + Send + Sync + 'static,
+        )
+        where
+            S: Clone + Send + Sync + 'static,
+        {
+            (
+                app.route_layer($axum::middleware::from_fn(track_metrics)),
+                move || handle.render(),
+            )
+        }
+
+        // Takes an existing axum router, installs a recorder exporting to the OTLP collector at
+        // `endpoint` every `export_interval` and injects the metrics middleware into the router.
+        // The returned provider must be shut down on exit to flush the last metrics.
+        #[cfg(feature = "otlp")]
+        pub fn install_otlp_metrics_layer<K, S, V>(
+            app: Router<S>,
+            endpoint: impl Into<String>,
+            export_interval: std::time::Duration,
+            global_labels: Option<Vec<(K, V)>>,
+        ) -> Result<
+            (Router<S>, opentelemetry_sdk::metrics::SdkMeterProvider),
+            $crate::otlp::OtlpError,
+        >
+        where
+            K: Into<String>,
+            S: Clone + Send + Sync + 'static,
+            V: Into<String>,
+        {
+            let provider =
+                $crate::otlp::install_otlp_recorder(endpoint, export_interval, global_labels)?;
+            Ok((
+                app.route_layer($axum::middleware::from_fn(track_metrics)),
+                provider,
+            ))
+        }
+
+        // Takes an existing axum router and mounts `/healthz`, answering as long as the process
+        // serves requests, and `/readyz`, running the checks and answering 503 if any fails.
+        // Install it after the metrics layer so the probes aren't included in the request metrics.
+        pub fn install_health_endpoints<S>(
+            app: Router<S>,
+            checks: $crate::health::HealthChecks,
+        ) -> Router<S>
+        where
+            S: Clone + Send + Sync + 'static,
+        {
+            app.route("/healthz", get(|| async { "ok" })).route(
+                "/readyz",
+                get(move || async move {
+                    match checks.run().await {
+                        (true, report) => (StatusCode::OK, report),
+                        (false, report) => (StatusCode::SERVICE_UNAVAILABLE, report),
+                    }
+                }),
+            )
+        }
+
+        // Renders the metrics in the OpenMetrics format, with the trace exemplars of the latency
+        // histograms, to the scrapers that accept it
+        #[cfg(feature = "exemplars")]
+        async fn render_metrics(headers: HeaderMap, render: impl Fn() -> String) -> Response {
+            use crate::exemplars::{render_openmetrics, OPENMETRICS_CONTENT_TYPE};
+            use $axum::response::IntoResponse;
+
+            let accept = headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok());
+            if $crate::axum::accepts_openmetrics(accept) {
+                (
+                    [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)],
+                    render_openmetrics(&render()),
+                )
+                    .into_response()
+            } else {
+                render().into_response()
+            }
+        }
+
+        #[cfg(not(feature = "exemplars"))]
+        async fn render_metrics(_headers: HeaderMap, render: impl Fn() -> String) -> String {
+            render()
+        }
+
+        // Size of a body known up front, streamed bodies without a content length aren't measured
+        fn body_size(headers: &HeaderMap, body: &impl $axum::body::HttpBody) -> Option<u64> {
+            body.size_hint().exact().or_else(|| {
+                let length = headers.get(header::CONTENT_LENGTH);
+                $crate::axum::parse_content_length(length.and_then(|length| length.to_str().ok()))
+            })
+        }
+    };
+}
+
+#[cfg(feature = "axum-06")]
+pub mod v06;
+#[cfg(feature = "axum-07")]
+pub mod v07;
+
+#[cfg(all(feature = "axum-06", not(feature = "axum-07")))]
+pub use v06::*;
+#[cfg(all(feature = "axum-07", not(feature = "axum-06")))]
+pub use v07::*;
+
+pub const AXUM_HTTP_REQUESTS_TOTAL: &str = "axum-http-requests-total";
+pub const AXUM_HTTP_REQUESTS_DURATION_SECONDS: &str = "axum-http-requests-duration-seconds";
+pub const AXUM_HTTP_REQUEST_SIZE_BYTES: &str = "axum-http-request-size-bytes";
+pub const AXUM_HTTP_RESPONSE_SIZE_BYTES: &str = "axum-http-response-size-bytes";
+
+// Configures a prometheus builder the same way as `install_metrics_layer`, for
+// applications that build and install the recorder themselves, e.g. with
+// `build_recorder()` to compose it with other recorders
+pub fn metrics_builder<K, V>(
+    global_buckets: Option<&[f64]>,
+    global_labels: Option<Vec<(K, V)>>,
+    matched_metric_buckets: Option<Vec<(&str, &[f64])>>,
+) -> Result<PrometheusBuilder, BuildError>
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    let builder = PrometheusBuilder::new();
+
+    let builder = if let Some(buckets) = global_buckets {
+        builder.set_buckets(buckets)?
+    } else {
+        builder
+    };
+
+    let builder = if let Some(labels) = global_labels {
+        labels
+            .into_iter()
+            .fold(builder, |b, (k, v)| b.add_global_label(k, v))
+    } else {
+        builder
+    };
+
+    if let Some(buckets) = matched_metric_buckets {
+        buckets.into_iter().try_fold(builder, |b, (k, v)| {
+            b.set_buckets_for_metric(Matcher::Full(k.to_owned()), v)
+        })
+    } else {
+        Ok(builder)
+    }
+}
+
+/// A request measured by the `track_metrics` middleware of either axum version
+#[cfg(any(feature = "axum-06", feature = "axum-07"))]
+pub(crate) struct TrackedRequest {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency: f64,
+    pub request_size: Option<u64>,
+    pub response_size: Option<u64>,
+}
+
+#[cfg(any(feature = "axum-06", feature = "axum-07"))]
+pub(crate) fn record_request(request: TrackedRequest) {
+    let labels = crate::cardinality::bounded_labels([
+        ("method", request.method),
+        ("path", request.path),
+        ("status", request.status.to_string()),
+    ]);
+    let latency = request.latency;
+
+    metrics::counter!(AXUM_HTTP_REQUESTS_TOTAL, &labels).increment(1);
+    metrics::histogram!(AXUM_HTTP_REQUESTS_DURATION_SECONDS, &labels).record(latency);
+    #[cfg(feature = "exemplars")]
+    crate::exemplars::record(AXUM_HTTP_REQUESTS_DURATION_SECONDS, &labels, latency);
+
+    if let Some(size) = request.request_size {
+        metrics::histogram!(AXUM_HTTP_REQUEST_SIZE_BYTES, &labels).record(size as f64);
+    }
+    if let Some(size) = request.response_size {
+        metrics::histogram!(AXUM_HTTP_RESPONSE_SIZE_BYTES, &labels).record(size as f64);
+    }
+}
+
+// Whether the scraper accepts the OpenMetrics format, which renders the trace exemplars
+#[cfg(all(feature = "exemplars", any(feature = "axum-06", feature = "axum-07")))]
+pub(crate) fn accepts_openmetrics(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| accept.contains("application/openmetrics-text"))
+}
+
+#[cfg(any(feature = "axum-06", feature = "axum-07"))]
+pub(crate) fn parse_content_length(length: Option<&str>) -> Option<u64> {
+    length.and_then(|length| length.parse().ok())
+}
@@ -0,0 +1,39 @@
+//! Request metrics and endpoints of axum 0.7
+
+use axum07::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+};
+use std::time::Instant;
+
+use super::{record_request, TrackedRequest};
+
+axum_endpoints!(axum07);
+
+// Defines a prometheus metrics collection function for defining a tower layer handler
+// as a function. Allows measuring metrics from a router endpoints without needing to expose
+// the metrics endpoint itself on the router or define the endpoint for rendering metrics gathered
+pub async fn track_metrics(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+    let method = req.method().to_string();
+    let request_size = body_size(req.headers(), req.body());
+
+    let response = next.run(req).await;
+
+    if let Some(path) = path {
+        record_request(TrackedRequest {
+            method,
+            path,
+            status: response.status().as_u16(),
+            latency: start.elapsed().as_secs_f64(),
+            request_size,
+            response_size: body_size(response.headers(), response.body()),
+        });
+    }
+
+    response
+}
@@ -0,0 +1,94 @@
+//! Bounds on the distinct values of high-cardinality labels, such as path parameters or
+//! canister ids, so a misbehaving client can't explode the number of series.
+//!
+//! Labels without a policy are recorded as is. Install the policies once at startup with
+//! [`set_cardinality_guard`], they apply to every metric recorded by this crate.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Mutex, OnceLock},
+};
+
+/// Value of the labels rejected by their policy
+pub const OTHER: &str = "other";
+
+static GUARD: OnceLock<CardinalityGuard> = OnceLock::new();
+
+/// Bound on the distinct values of a label
+#[derive(Debug, Clone)]
+pub enum LabelPolicy {
+    /// Values outside of the list are recorded as [`OTHER`]
+    AllowList(HashSet<String>),
+    /// The first `n` distinct values are recorded, later ones as [`OTHER`]
+    Limit(usize),
+    /// Values are hashed into `n` buckets
+    Hash(u64),
+}
+
+/// Policies of the bounded labels
+#[derive(Debug, Default)]
+pub struct CardinalityGuard {
+    policies: HashMap<String, LabelPolicy>,
+    seen: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl CardinalityGuard {
+    /// Create a guard without any policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bound the values of `label` with `policy`
+    pub fn with_policy(mut self, label: impl Into<String>, policy: LabelPolicy) -> Self {
+        self.policies.insert(label.into(), policy);
+        self
+    }
+
+    /// Return the value recorded for `value` of `label`
+    pub fn bound(&self, label: &str, value: String) -> String {
+        match self.policies.get(label) {
+            None => value,
+            Some(LabelPolicy::AllowList(allowed)) if allowed.contains(&value) => value,
+            Some(LabelPolicy::AllowList(_)) => OTHER.to_owned(),
+            Some(LabelPolicy::Limit(limit)) => {
+                let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+                let seen = seen.entry(label.to_owned()).or_default();
+                if seen.contains(&value) {
+                    value
+                } else if seen.len() < *limit {
+                    seen.insert(value.clone());
+                    value
+                } else {
+                    OTHER.to_owned()
+                }
+            }
+            Some(LabelPolicy::Hash(buckets)) => {
+                let mut hasher = DefaultHasher::new();
+                value.hash(&mut hasher);
+                format!("bucket-{}", hasher.finish() % (*buckets).max(1))
+            }
+        }
+    }
+}
+
+/// Install the label policies, failing if they were already installed
+pub fn set_cardinality_guard(guard: CardinalityGuard) -> Result<(), CardinalityGuard> {
+    GUARD.set(guard)
+}
+
+/// Return the value recorded for `value` of `label` under the installed policies
+#[inline]
+pub fn bounded(label: &str, value: String) -> String {
+    match GUARD.get() {
+        Some(guard) => guard.bound(label, value),
+        None => value,
+    }
+}
+
+/// Bound the values of `labels` under the installed policies
+pub fn bounded_labels<const N: usize>(
+    labels: [(&'static str, String); N],
+) -> [(&'static str, String); N] {
+    labels.map(|(label, value)| (label, bounded(label, value)))
+}
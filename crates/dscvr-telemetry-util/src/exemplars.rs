@@ -0,0 +1,169 @@
+//! Trace exemplars of the latency histograms, so a slow bucket links to the traces of the
+//! requests that landed in it.
+//!
+//! The trace id is taken from the OpenTelemetry context of the current tracing span, requests
+//! recorded outside of a sampled span have no exemplar. Only the latest [`MAX_EXEMPLARS`]
+//! observations of each series are kept, and they're only rendered for scrapers negotiating
+//! the OpenMetrics format, the Prometheus text format has no exemplar syntax. Histograms are
+//! rendered as summaries without buckets unless buckets are configured for them.
+
+use opentelemetry::trace::TraceContextExt;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Content type of the metrics rendered with exemplars
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Number of recent exemplars kept per histogram series
+pub const MAX_EXEMPLARS: usize = 32;
+
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+// Exemplars by sanitized metric name, then by the sorted labels of the series
+type Store = HashMap<String, HashMap<Vec<(String, String)>, VecDeque<Exemplar>>>;
+
+static EXEMPLARS: LazyLock<Mutex<Store>> = LazyLock::new(Mutex::default);
+
+/// Return the trace id of the current span, if it's part of a valid trace
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// Keep the observation `value` of the histogram `name` as an exemplar of the current trace
+pub fn record(name: &str, labels: &[(&'static str, String)], value: f64) {
+    let Some(trace_id) = current_trace_id() else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64());
+    let mut labels: Vec<_> = labels
+        .iter()
+        .map(|(k, v)| (metric_name(k), v.clone()))
+        .collect();
+    labels.sort();
+
+    let mut store = EXEMPLARS.lock().unwrap_or_else(|e| e.into_inner());
+    let exemplars = store
+        .entry(metric_name(name))
+        .or_default()
+        .entry(labels)
+        .or_default();
+    if exemplars.len() == MAX_EXEMPLARS {
+        exemplars.pop_front();
+    }
+    exemplars.push_back(Exemplar {
+        trace_id,
+        value,
+        timestamp,
+    });
+}
+
+/// Convert metrics rendered in the Prometheus text format to the OpenMetrics format, attaching
+/// to each histogram bucket the latest exemplar that falls in it
+pub fn render_openmetrics(rendered: &str) -> String {
+    let store = EXEMPLARS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::with_capacity(rendered.len());
+    let mut lower_bound = f64::NEG_INFINITY;
+
+    for line in rendered.lines() {
+        out.push_str(line);
+        if let Some((name, labels)) = parse_bucket(line) {
+            let le = labels
+                .iter()
+                .find(|(k, _)| k == "le")
+                .and_then(|(_, v)| parse_float(v))
+                .unwrap_or(f64::INFINITY);
+            let exemplar = store
+                .get(name)
+                .into_iter()
+                .flatten()
+                .find(|(series, _)| series.iter().all(|label| labels.contains(label)))
+                .and_then(|(_, exemplars)| {
+                    exemplars
+                        .iter()
+                        .rev()
+                        .find(|e| e.value > lower_bound && e.value <= le)
+                });
+            if let Some(e) = exemplar {
+                let _ = write!(
+                    out,
+                    " # {{trace_id=\"{}\"}} {} {:.3}",
+                    e.trace_id, e.value, e.timestamp
+                );
+            }
+            lower_bound = if le.is_infinite() {
+                f64::NEG_INFINITY
+            } else {
+                le
+            };
+        }
+        out.push('\n');
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+// Parses `{name}_bucket{labels} value` into the name and the labels of the line
+fn parse_bucket(line: &str) -> Option<(&str, Vec<(String, String)>)> {
+    let (name, rest) = line.split_once('{')?;
+    let name = name.strip_suffix("_bucket")?;
+    let mut labels = Vec::new();
+    let mut rest = rest;
+    loop {
+        let (key, value) = rest.split_once("=\"")?;
+        let mut parsed = String::new();
+        let mut chars = value.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => parsed.push('\n'),
+                    c => parsed.push(c),
+                },
+                (i, '"') => break i,
+                (_, c) => parsed.push(c),
+            }
+        };
+        labels.push((key.trim_start_matches(',').to_owned(), parsed));
+        rest = &value[end + 1..];
+        if rest.starts_with('}') {
+            return Some((name, labels));
+        }
+    }
+}
+
+fn parse_float(value: &str) -> Option<f64> {
+    match value {
+        "+Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        value => value.parse().ok(),
+    }
+}
+
+// Prometheus names only allow `[a-zA-Z_:][a-zA-Z0-9_:]*`, the exporter replaces the rest
+// with underscores
+fn metric_name(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' | ':' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
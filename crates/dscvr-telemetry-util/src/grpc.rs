@@ -0,0 +1,95 @@
+//! Metrics of gRPC services, mirroring the axum [`crate::axum::track_metrics`] middleware.
+
+use http02::{Request, Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+
+use crate::cardinality::bounded_labels;
+
+pub const GRPC_REQUESTS_TOTAL: &str = "grpc-requests-total";
+pub const GRPC_REQUESTS_DURATION_SECONDS: &str = "grpc-requests-duration-seconds";
+
+/// Tower layer recording the count and latency of gRPC requests, labeled by service,
+/// method and status. Add it to a tonic server with `Server::builder().layer(..)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetrics { inner }
+    }
+}
+
+/// Service recording the metrics of the requests of the inner service
+#[derive(Debug, Clone)]
+pub struct GrpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let start = Instant::now();
+        let (service, method) = service_and_method(req.uri().path());
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = future.await;
+            let latency = start.elapsed().as_secs_f64();
+            let status = match &result {
+                Ok(response) => grpc_status(response),
+                Err(_) => "transport-error".to_owned(),
+            };
+
+            let labels =
+                bounded_labels([("service", service), ("method", method), ("status", status)]);
+
+            metrics::counter!(GRPC_REQUESTS_TOTAL, &labels).increment(1);
+            metrics::histogram!(GRPC_REQUESTS_DURATION_SECONDS, &labels).record(latency);
+            #[cfg(feature = "exemplars")]
+            crate::exemplars::record(GRPC_REQUESTS_DURATION_SECONDS, &labels, latency);
+
+            result
+        })
+    }
+}
+
+// gRPC paths are `/{package}.{service}/{method}`, anything else is labeled unknown so
+// arbitrary paths don't create new series
+fn service_and_method(path: &str) -> (String, String) {
+    match path.strip_prefix('/').and_then(|path| path.split_once('/')) {
+        Some((service, method)) if !service.is_empty() && !method.contains('/') => {
+            (service.to_owned(), method.to_owned())
+        }
+        _ => ("unknown".to_owned(), "unknown".to_owned()),
+    }
+}
+
+// Failed calls return their status in the headers, while successful calls return it in
+// the trailers, which are only sent once the body is streamed, so a missing status is `0` (OK)
+fn grpc_status<B>(response: &Response<B>) -> String {
+    response
+        .headers()
+        .get("grpc-status")
+        .and_then(|status| status.to_str().ok())
+        .unwrap_or("0")
+        .to_owned()
+}
@@ -0,0 +1,64 @@
+//! Readiness checks registered by the application, e.g. "can reach replica" or "backup store
+//! writable", served by the `install_health_endpoints` of the [`crate::axum`] versions.
+
+use futures::future::{join_all, BoxFuture};
+use std::{fmt::Display, future::Future, sync::Arc};
+
+/// Gauge set to 1 when the labeled check passed on the last readiness probe, 0 otherwise
+pub const HEALTH_CHECK_STATUS: &str = "health-check-status";
+
+type Check = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// Named readiness checks
+#[derive(Clone, Default)]
+pub struct HealthChecks {
+    checks: Vec<(String, Check)>,
+}
+
+impl HealthChecks {
+    /// Create an empty set of checks, always ready
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the check `name`, failing readiness when the future returned by `check` fails
+    pub fn with_check<F, Fut, E>(mut self, name: impl Into<String>, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let check = Arc::new(check);
+        self.checks.push((
+            name.into(),
+            Arc::new(move || {
+                let check = check.clone();
+                Box::pin(async move { check().await.map_err(|e| e.to_string()) })
+            }),
+        ));
+        self
+    }
+
+    /// Run every check concurrently, returning whether they all passed and a line per check
+    pub async fn run(&self) -> (bool, String) {
+        let results = join_all(self.checks.iter().map(|(_, check)| check())).await;
+
+        let mut ready = true;
+        let mut report = String::new();
+        for ((name, _), result) in self.checks.iter().zip(results) {
+            let status = match result {
+                Ok(()) => {
+                    report.push_str(&format!("{name}: ok\n"));
+                    1.0
+                }
+                Err(e) => {
+                    report.push_str(&format!("{name}: {e}\n"));
+                    ready = false;
+                    0.0
+                }
+            };
+            metrics::gauge!(HEALTH_CHECK_STATUS, "check" => name.clone()).set(status);
+        }
+        (ready, report)
+    }
+}
@@ -0,0 +1,254 @@
+//! OTLP export of the axum and IC metrics, for deployments on managed observability stacks
+//! that don't scrape a Prometheus endpoint.
+
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use opentelemetry::{
+    metrics::{Meter, MeterProvider, MetricsError},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{metrics::SdkMeterProvider, runtime, Resource};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[cfg(any(
+    all(feature = "axum-06", not(feature = "axum-07")),
+    all(feature = "axum-07", not(feature = "axum-06"))
+))]
+pub use crate::axum::install_otlp_metrics_layer;
+
+/// Name of the meter recording the metrics
+pub const METER_NAME: &str = "dscvr-telemetry-util";
+
+/// Errors installing the OTLP exporter
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)] // self documenting
+pub enum OtlpError {
+    #[error("otlp pipeline {0}")]
+    Pipeline(#[from] MetricsError),
+    #[error("a metrics recorder is already installed")]
+    RecorderInstalled,
+}
+
+// Installs a recorder exporting to the OTLP collector at `endpoint` every `export_interval`.
+// The returned provider must be shut down on exit to flush the last metrics.
+pub fn install_otlp_recorder<K, V>(
+    endpoint: impl Into<String>,
+    export_interval: Duration,
+    global_labels: Option<Vec<(K, V)>>,
+) -> Result<SdkMeterProvider, OtlpError>
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    let resource = Resource::new(
+        global_labels
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| KeyValue::new(k.into(), v.into())),
+    );
+    let provider = opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_period(export_interval)
+        .with_resource(resource)
+        .build()?;
+
+    metrics::set_global_recorder(OtlpRecorder::new(provider.meter(METER_NAME)))
+        .map_err(|_| OtlpError::RecorderInstalled)?;
+    Ok(provider)
+}
+
+/// Recorder forwarding the metrics to an OpenTelemetry meter
+pub struct OtlpRecorder {
+    meter: Meter,
+    state: Mutex<RecorderState>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    descriptions: HashMap<String, (Option<Unit>, String)>,
+    counters: HashMap<Key, Counter>,
+    gauges: HashMap<Key, Gauge>,
+    histograms: HashMap<Key, Histogram>,
+}
+
+impl OtlpRecorder {
+    /// Create a recorder forwarding to `meter`
+    pub fn new(meter: Meter) -> Self {
+        Self {
+            meter,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn attributes(key: &Key) -> Vec<KeyValue> {
+    key.labels()
+        .map(|label| KeyValue::new(label.key().to_owned(), label.value().to_owned()))
+        .collect()
+}
+
+impl RecorderState {
+    fn describe(&mut self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.descriptions
+            .insert(key.as_str().to_owned(), (unit, description.to_string()));
+    }
+
+    fn description(&self, key: &Key) -> (Option<&'static str>, Option<String>) {
+        self.descriptions
+            .get(key.name())
+            .map_or((None, None), |(unit, description)| {
+                (
+                    unit.map(|unit| unit.as_canonical_label()),
+                    Some(description.clone()),
+                )
+            })
+    }
+}
+
+impl Recorder for OtlpRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.state().describe(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.state().describe(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.state().describe(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        let mut state = self.state();
+        if let Some(counter) = state.counters.get(key) {
+            return counter.clone();
+        }
+        let (unit, description) = state.description(key);
+        let mut builder = self.meter.u64_counter(key.name().to_owned());
+        if let Some(unit) = unit {
+            builder = builder.with_unit(unit);
+        }
+        if let Some(description) = description {
+            builder = builder.with_description(description);
+        }
+        let counter = Counter::from_arc(Arc::new(OtlpCounter {
+            counter: builder.init(),
+            attributes: attributes(key),
+        }));
+        state.counters.insert(key.clone(), counter.clone());
+        counter
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        let mut state = self.state();
+        if let Some(gauge) = state.gauges.get(key) {
+            return gauge.clone();
+        }
+        let (unit, description) = state.description(key);
+        let mut builder = self.meter.f64_gauge(key.name().to_owned());
+        if let Some(unit) = unit {
+            builder = builder.with_unit(unit);
+        }
+        if let Some(description) = description {
+            builder = builder.with_description(description);
+        }
+        let gauge = Gauge::from_arc(Arc::new(OtlpGauge {
+            gauge: builder.init(),
+            attributes: attributes(key),
+            value: Mutex::default(),
+        }));
+        state.gauges.insert(key.clone(), gauge.clone());
+        gauge
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        let mut state = self.state();
+        if let Some(histogram) = state.histograms.get(key) {
+            return histogram.clone();
+        }
+        let (unit, description) = state.description(key);
+        let mut builder = self.meter.f64_histogram(key.name().to_owned());
+        if let Some(unit) = unit {
+            builder = builder.with_unit(unit);
+        }
+        if let Some(description) = description {
+            builder = builder.with_description(description);
+        }
+        let histogram = Histogram::from_arc(Arc::new(OtlpHistogram {
+            histogram: builder.init(),
+            attributes: attributes(key),
+        }));
+        state.histograms.insert(key.clone(), histogram.clone());
+        histogram
+    }
+}
+
+struct OtlpCounter {
+    counter: opentelemetry::metrics::Counter<u64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl CounterFn for OtlpCounter {
+    fn increment(&self, value: u64) {
+        self.counter.add(value, &self.attributes);
+    }
+
+    // OTLP sums only accumulate increments
+    fn absolute(&self, _value: u64) {}
+}
+
+struct OtlpGauge {
+    gauge: opentelemetry::metrics::Gauge<f64>,
+    attributes: Vec<KeyValue>,
+    // OTLP gauges are only set, so relative updates are applied to the last value
+    value: Mutex<f64>,
+}
+
+impl OtlpGauge {
+    fn update(&self, f: impl FnOnce(f64) -> f64) {
+        let mut value = self.value.lock().unwrap_or_else(|e| e.into_inner());
+        *value = f(*value);
+        self.gauge.record(*value, &self.attributes);
+    }
+}
+
+impl GaugeFn for OtlpGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value);
+    }
+}
+
+struct OtlpHistogram {
+    histogram: opentelemetry::metrics::Histogram<f64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl HistogramFn for OtlpHistogram {
+    fn record(&self, value: f64) {
+        self.histogram.record(value, &self.attributes);
+    }
+}
@@ -0,0 +1,90 @@
+//! Sampling of the tokio runtime metrics into the installed recorder, to attribute latency
+//! to saturated workers or a backed up blocking pool.
+//!
+//! The blocking pool metrics are only exposed by tokio when built with
+//! `RUSTFLAGS="--cfg tokio_unstable"`, they aren't recorded otherwise.
+
+use std::time::{Duration, Instant};
+use tokio::{
+    runtime::{Handle, RuntimeMetrics},
+    task::JoinHandle,
+};
+
+pub const TOKIO_RUNTIME_WORKERS: &str = "tokio-runtime-workers";
+pub const TOKIO_RUNTIME_ALIVE_TASKS: &str = "tokio-runtime-alive-tasks";
+pub const TOKIO_RUNTIME_GLOBAL_QUEUE_DEPTH: &str = "tokio-runtime-global-queue-depth";
+pub const TOKIO_RUNTIME_WORKER_BUSY_RATIO: &str = "tokio-runtime-worker-busy-ratio";
+pub const TOKIO_RUNTIME_BLOCKING_THREADS: &str = "tokio-runtime-blocking-threads";
+pub const TOKIO_RUNTIME_IDLE_BLOCKING_THREADS: &str = "tokio-runtime-idle-blocking-threads";
+pub const TOKIO_RUNTIME_BLOCKING_QUEUE_DEPTH: &str = "tokio-runtime-blocking-queue-depth";
+
+/// Samples the metrics of a runtime, keeping the previous sample to compute the share of
+/// time each worker was busy in between
+pub struct RuntimeCollector {
+    metrics: RuntimeMetrics,
+    last_sample: Instant,
+    busy: Vec<Duration>,
+}
+
+impl RuntimeCollector {
+    /// Create a collector of the runtime of `handle`
+    pub fn new(handle: &Handle) -> Self {
+        let metrics = handle.metrics();
+        let busy = (0..metrics.num_workers())
+            .map(|worker| metrics.worker_total_busy_duration(worker))
+            .collect();
+        Self {
+            metrics,
+            last_sample: Instant::now(),
+            busy,
+        }
+    }
+
+    /// Record the current runtime metrics as gauges
+    pub fn sample(&mut self) {
+        let metrics = &self.metrics;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sample).as_secs_f64();
+        self.last_sample = now;
+
+        metrics::gauge!(TOKIO_RUNTIME_WORKERS).set(metrics.num_workers() as f64);
+        metrics::gauge!(TOKIO_RUNTIME_ALIVE_TASKS).set(metrics.num_alive_tasks() as f64);
+        metrics::gauge!(TOKIO_RUNTIME_GLOBAL_QUEUE_DEPTH).set(metrics.global_queue_depth() as f64);
+
+        for (worker, last_busy) in self.busy.iter_mut().enumerate() {
+            let busy = metrics.worker_total_busy_duration(worker);
+            if elapsed > 0.0 {
+                let ratio = busy.saturating_sub(*last_busy).as_secs_f64() / elapsed;
+                metrics::gauge!(TOKIO_RUNTIME_WORKER_BUSY_RATIO, "worker" => worker.to_string())
+                    .set(ratio.min(1.0));
+            }
+            *last_busy = busy;
+        }
+
+        #[cfg(tokio_unstable)]
+        {
+            metrics::gauge!(TOKIO_RUNTIME_BLOCKING_THREADS)
+                .set(metrics.num_blocking_threads() as f64);
+            metrics::gauge!(TOKIO_RUNTIME_IDLE_BLOCKING_THREADS)
+                .set(metrics.num_idle_blocking_threads() as f64);
+            metrics::gauge!(TOKIO_RUNTIME_BLOCKING_QUEUE_DEPTH)
+                .set(metrics.blocking_queue_depth() as f64);
+        }
+    }
+}
+
+// Spawns a task on the current runtime sampling its metrics every `interval` into the
+// installed recorder, until the returned handle is aborted or the runtime shuts down.
+// Must be called from within a tokio runtime, after the recorder is installed.
+pub fn spawn_runtime_metrics_collector(interval: Duration) -> JoinHandle<()> {
+    let handle = Handle::current();
+    let mut collector = RuntimeCollector::new(&handle);
+    handle.spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            collector.sample();
+        }
+    })
+}
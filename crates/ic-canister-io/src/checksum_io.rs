@@ -0,0 +1,149 @@
+//! IO wrappers that compute a running checksum of the bytes read or written
+
+use sha2::Digest;
+use std::io::{Read, Write};
+
+/// A running checksum
+pub trait Checksum: Default {
+    /// The final digest
+    type Output;
+
+    /// Add bytes to the checksum
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Return the final digest
+    fn finalize(self) -> Self::Output;
+}
+
+/// CRC32 checksum
+pub type Crc32 = crc32fast::Hasher;
+
+/// SHA-256 checksum
+pub type Sha256 = sha2::Sha256;
+
+impl Checksum for Crc32 {
+    type Output = u32;
+
+    #[inline]
+    fn update(&mut self, bytes: &[u8]) {
+        crc32fast::Hasher::update(self, bytes);
+    }
+
+    #[inline]
+    fn finalize(self) -> Self::Output {
+        crc32fast::Hasher::finalize(self)
+    }
+}
+
+impl Checksum for Sha256 {
+    type Output = [u8; 32];
+
+    #[inline]
+    fn update(&mut self, bytes: &[u8]) {
+        Digest::update(self, bytes);
+    }
+
+    #[inline]
+    fn finalize(self) -> Self::Output {
+        Digest::finalize(self).into()
+    }
+}
+
+/// Reader that computes a checksum of the bytes read from the underlying reader
+pub struct ChecksumReader<R: Read, C: Checksum> {
+    /// The underlying reader
+    reader: R,
+    /// The running checksum
+    checksum: C,
+}
+
+impl<R: Read, C: Checksum> ChecksumReader<R, C> {
+    /// Create a new checksum reader
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            checksum: C::default(),
+        }
+    }
+
+    /// Return the underlying reader and the checksum of the bytes read
+    #[inline]
+    pub fn finalize(self) -> (R, C::Output) {
+        (self.reader, self.checksum.finalize())
+    }
+}
+
+impl<R: Read, C: Checksum> Read for ChecksumReader<R, C> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Writer that computes a checksum of the bytes written to the underlying writer
+pub struct ChecksumWriter<W: Write, C: Checksum> {
+    /// The underlying writer
+    writer: W,
+    /// The running checksum
+    checksum: C,
+}
+
+impl<W: Write, C: Checksum> ChecksumWriter<W, C> {
+    /// Create a new checksum writer
+    #[inline]
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            checksum: C::default(),
+        }
+    }
+
+    /// Return the underlying writer and the checksum of the bytes written
+    #[inline]
+    pub fn finalize(self) -> (W, C::Output) {
+        (self.writer, self.checksum.finalize())
+    }
+}
+
+impl<W: Write, C: Checksum> Write for ChecksumWriter<W, C> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let bytes = b"123456789";
+
+        let mut writer = ChecksumWriter::<_, Crc32>::new(vec![]);
+        writer.write_all(bytes).unwrap();
+        let (written, crc) = writer.finalize();
+        assert_eq!(crc, 0xcbf43926);
+
+        let mut reader = ChecksumReader::<_, Crc32>::new(written.as_slice());
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(reader.finalize().1, crc);
+
+        let mut writer = ChecksumWriter::<_, Sha256>::new(vec![]);
+        writer.write_all(bytes).unwrap();
+        let mut reader = ChecksumReader::<_, Sha256>::new(read.as_slice());
+        reader.read_to_end(&mut vec![]).unwrap();
+        assert_eq!(writer.finalize().1, reader.finalize().1);
+    }
+}
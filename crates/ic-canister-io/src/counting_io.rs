@@ -0,0 +1,85 @@
+//! IO wrappers that count the number of bytes read or written
+
+use std::io::{Read, Write};
+
+/// Reader that tracks the number of bytes read from the underlying reader.
+/// Unlike `Seek::stream_position`, this works for readers that can't seek.
+pub struct CountingReader<R: Read> {
+    /// The underlying reader
+    reader: R,
+    /// Number of bytes read so far
+    count: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// Create a new counting reader
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        Self { reader, count: 0 }
+    }
+
+    /// Return the number of bytes read so far
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Return the underlying reader
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// Writer that tracks the number of bytes written to the underlying writer.
+/// Unlike `Seek::stream_position`, this works for writers that can't seek such as
+/// sockets and compression streams.
+pub struct CountingWriter<W: Write> {
+    /// The underlying writer
+    writer: W,
+    /// Number of bytes written so far
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// Create a new counting writer
+    #[inline]
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+
+    /// Return the number of bytes written so far
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Return the underlying writer
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -1 +1,3 @@
+pub mod checksum_io;
+pub mod counting_io;
 pub mod movable_io;
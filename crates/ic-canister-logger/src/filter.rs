@@ -0,0 +1,262 @@
+//! Filter of the canister logs, adjustable at runtime without an upgrade, e.g. to turn on debug
+//! logs of a misbehaving canister or to silence a noisy module.
+//!
+//! Filters are written as `EnvFilter`-style directives, e.g. `debug,serde=off,app::sync=trace`:
+//! a bare level sets the default level and `target=level` the level of a target, i.e. a
+//! module path prefix. A bare target enables all its logs. Span and field directives are not
+//! supported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{reload, Registry};
+
+/// Level of the logs enabled by [`crate::init_logger`]
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::INFO;
+
+static FILTER: Mutex<LogFilter> = Mutex::new(LogFilter {
+    level: DEFAULT_LOG_LEVEL,
+    targets: BTreeMap::new(),
+});
+static HANDLE: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+
+/// Error changing the log filter
+#[derive(Debug, thiserror::Error)]
+pub enum LogFilterError {
+    #[error("invalid log level {0}")]
+    InvalidLevel(String),
+    #[error("invalid log filter directive {0}")]
+    InvalidDirective(String),
+    #[error("failed to reload the log filter: {0}")]
+    Reload(String),
+}
+
+/// Level of the logs enabled by default and per target, i.e. module path prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Level of the targets without their own level
+    pub level: LevelFilter,
+    /// Level per target
+    pub targets: BTreeMap<String, LevelFilter>,
+}
+
+impl LogFilter {
+    fn to_targets(&self) -> Targets {
+        Targets::new()
+            .with_default(self.level)
+            .with_targets(self.targets.clone())
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = LogFilterError;
+
+    /// Parse `EnvFilter`-style directives. Without a bare level, the default level stays
+    /// [`DEFAULT_LOG_LEVEL`] so silencing a target doesn't mute the whole canister.
+    fn from_str(directives: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter {
+            level: DEFAULT_LOG_LEVEL,
+            targets: BTreeMap::new(),
+        };
+        for directive in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+        {
+            if directive.contains(['[', ']', '{', '}']) {
+                return Err(LogFilterError::InvalidDirective(directive.to_owned()));
+            }
+            match directive.split_once('=') {
+                Some((target, level)) if !target.is_empty() => {
+                    filter
+                        .targets
+                        .insert(target.to_owned(), parse_level(level)?);
+                }
+                Some(_) => return Err(LogFilterError::InvalidDirective(directive.to_owned())),
+                None => match parse_level(directive) {
+                    Ok(level) => filter.level = level,
+                    Err(_) => {
+                        filter
+                            .targets
+                            .insert(directive.to_owned(), LevelFilter::TRACE);
+                    }
+                },
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (target, level) in &self.targets {
+            write!(f, "{target}={level},")?;
+        }
+        write!(f, "{}", self.level)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, LogFilterError> {
+    LevelFilter::from_str(level).map_err(|_| LogFilterError::InvalidLevel(level.to_owned()))
+}
+
+/// Return the reloadable filter layer installed by [`crate::init_logger`], starting with
+/// `filter` if set
+#[allow(dead_code)]
+pub(crate) fn reloadable_filter(filter: Option<LogFilter>) -> reload::Layer<Targets, Registry> {
+    if let Some(filter) = filter {
+        *FILTER.lock().expect("log filter poisoned") = filter;
+    }
+    let (layer, handle) = reload::Layer::new(current_log_filter().to_targets());
+    let _ = HANDLE.set(handle);
+    layer
+}
+
+fn update(f: impl FnOnce(&mut LogFilter)) -> Result<(), LogFilterError> {
+    let mut filter = FILTER.lock().expect("log filter poisoned");
+    let mut updated = filter.clone();
+    f(&mut updated);
+    if let Some(handle) = HANDLE.get() {
+        handle
+            .reload(updated.to_targets())
+            .map_err(|e| LogFilterError::Reload(e.to_string()))?;
+    }
+    *filter = updated;
+    Ok(())
+}
+
+/// Return the current log filter
+pub fn current_log_filter() -> LogFilter {
+    FILTER.lock().expect("log filter poisoned").clone()
+}
+
+/// Replace the log filter with the `EnvFilter`-style `directives`, e.g. `info,serde=off`
+pub fn set_log_filter(directives: &str) -> Result<(), LogFilterError> {
+    let parsed = LogFilter::from_str(directives)?;
+    update(|filter| *filter = parsed)
+}
+
+/// Set the level of the targets without their own level, e.g. `debug`
+pub fn set_log_level(level: &str) -> Result<(), LogFilterError> {
+    let level = parse_level(level)?;
+    update(|filter| filter.level = level)
+}
+
+/// Set the level of `target` and the modules below it, or reset it to the default level
+/// if `level` is `None`
+pub fn set_target_log_level(target: &str, level: Option<&str>) -> Result<(), LogFilterError> {
+    let level = level.map(parse_level).transpose()?;
+    update(|filter| match level {
+        Some(level) => {
+            filter.targets.insert(target.to_owned(), level);
+        }
+        None => {
+            filter.targets.remove(target);
+        }
+    })
+}
+
+/// Macro that defines the guarded `set_log_filter`, `set_log_level` and `set_target_log_level`
+/// updates and the `log_filter` query, to change the log filter of a canister without an
+/// upgrade, e.g.
+/// `define_log_filter_interface!(guard = "is_controller")`.
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! define_log_filter_interface {
+    (guard = $guard:literal) => {
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::update(guard = $guard, skip_tx_log = true)]
+        fn set_log_filter(
+            _ctx: crate::canister_context::MutableContext,
+            directives: String,
+        ) -> Result<(), String> {
+            $crate::filter::set_log_filter(&directives).map_err(|e| e.to_string())
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::update(guard = $guard, skip_tx_log = true)]
+        fn set_log_level(
+            _ctx: crate::canister_context::MutableContext,
+            level: String,
+        ) -> Result<(), String> {
+            $crate::filter::set_log_level(&level).map_err(|e| e.to_string())
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::update(guard = $guard, skip_tx_log = true)]
+        fn set_target_log_level(
+            _ctx: crate::canister_context::MutableContext,
+            target: String,
+            level: Option<String>,
+        ) -> Result<(), String> {
+            $crate::filter::set_target_log_level(&target, level.as_deref())
+                .map_err(|e| e.to_string())
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query(guard = $guard)]
+        fn log_filter(_ctx: crate::canister_context::ImmutableContext) -> String {
+            $crate::filter::current_log_filter().to_string()
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bare_level_sets_the_default_level() {
+        let filter = LogFilter::from_str("debug").unwrap();
+        assert_eq!(filter.level, LevelFilter::DEBUG);
+        assert!(filter.targets.is_empty());
+    }
+
+    #[test]
+    fn bare_target_enables_all_its_logs() {
+        let filter = LogFilter::from_str("app::sync").unwrap();
+        assert_eq!(filter.level, DEFAULT_LOG_LEVEL);
+        assert_eq!(filter.targets["app::sync"], LevelFilter::TRACE);
+    }
+
+    #[test]
+    fn target_levels_are_parsed() {
+        let filter = LogFilter::from_str(" warn, serde=off ,app::sync=trace").unwrap();
+        assert_eq!(filter.level, LevelFilter::WARN);
+        assert_eq!(filter.targets["serde"], LevelFilter::OFF);
+        assert_eq!(filter.targets["app::sync"], LevelFilter::TRACE);
+        assert!(matches!(
+            LogFilter::from_str("serde=loud"),
+            Err(LogFilterError::InvalidLevel(level)) if level == "loud"
+        ));
+    }
+
+    #[test]
+    fn level_without_target_is_rejected() {
+        assert!(matches!(
+            LogFilter::from_str("=debug"),
+            Err(LogFilterError::InvalidDirective(directive)) if directive == "=debug"
+        ));
+    }
+
+    #[test]
+    fn span_and_field_directives_are_rejected() {
+        for directive in ["app[sync]=debug", "app[{id=1}]=trace", "[span]"] {
+            assert!(matches!(
+                LogFilter::from_str(directive),
+                Err(LogFilterError::InvalidDirective(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let filter = LogFilter::from_str("debug,serde=off,app::sync=trace").unwrap();
+        assert_eq!(filter.to_string(), "app::sync=trace,serde=off,debug");
+        assert_eq!(LogFilter::from_str(&filter.to_string()).unwrap(), filter);
+    }
+}
@@ -5,6 +5,8 @@ use std::io::Write;
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::time::FormatTime;
 
+pub mod filter;
+pub mod rate_limit;
 pub mod scoped_instruction_counter;
 
 #[allow(dead_code)]
@@ -48,22 +50,84 @@ impl FormatTime for IcTimer {
     }
 }
 
-/// Init the logger for canisters
-#[cfg(target_arch = "wasm32")]
+/// Format of the log records
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Free-form text lines
+    #[default]
+    Text,
+    /// One JSON object per record with the IC time as `timestamp`, `level`, `target`, `message`
+    /// and the fields of the event and its spans, for ingestion as structured records
+    Json,
+}
+
+/// Configuration of the canister logger
+#[derive(Debug, Default, Clone)]
+pub struct LoggerConfig {
+    /// Format of the log records
+    pub format: LogFormat,
+    /// `EnvFilter`-style directives compiled into the canister, e.g. `info,serde=off`, see
+    /// [`filter`]. Invalid directives are reported and the default filter is kept.
+    pub filter: Option<String>,
+    /// Rate limit of the events of every callsite, see [`rate_limit`]
+    pub rate_limit: Option<rate_limit::RateLimit>,
+}
+
+/// Init the logger for canisters, logging text at [`filter::DEFAULT_LOG_LEVEL`] until the
+/// filter is changed, see [`filter::set_log_level`]
 pub fn init_logger() {
-    use tracing::Level;
-    use tracing_subscriber::fmt::writer::MakeWriterExt;
+    init_logger_with_config(LoggerConfig::default())
+}
+
+/// Init the logger for canisters with `config`
+#[cfg(target_arch = "wasm32")]
+pub fn init_logger_with_config(config: LoggerConfig) {
     use tracing_subscriber::fmt::Layer;
     use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::util::SubscriberInitExt;
     use tracing_subscriber::Registry;
 
+    let filter = config
+        .filter
+        .as_deref()
+        .map(str::parse::<filter::LogFilter>);
     let make_writer = || IcStdout;
-    let log_layer = Layer::default()
-        .with_writer(make_writer.with_max_level(Level::INFO))
-        .with_timer(IcTimer);
+    let (text_layer, json_layer) = match config.format {
+        LogFormat::Text => (
+            Some(
+                Layer::default()
+                    .with_writer(make_writer)
+                    .with_timer(IcTimer),
+            ),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                Layer::default()
+                    .json()
+                    .flatten_event(true)
+                    .with_writer(make_writer)
+                    .with_timer(IcTimer),
+            ),
+        ),
+    };
 
-    Registry::default().with(log_layer).init();
+    Registry::default()
+        .with(filter::reloadable_filter(
+            filter
+                .as_ref()
+                .and_then(|filter| filter.as_ref().ok())
+                .cloned(),
+        ))
+        .with(config.rate_limit.map(rate_limit::RateLimitLayer::new))
+        .with(text_layer)
+        .with(json_layer)
+        .init();
+
+    if let Some(Err(e)) = filter {
+        tracing::warn!("Ignoring the log filter of the logger config: {e}");
+    }
 }
 #[cfg(not(target_arch = "wasm32"))]
-pub fn init_logger() {}
+pub fn init_logger_with_config(_config: LoggerConfig) {}
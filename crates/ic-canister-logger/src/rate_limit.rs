@@ -0,0 +1,228 @@
+//! Rate limiting of the logs of hot paths, which otherwise burn instructions formatting
+//! identical lines and drown the replica logs.
+//!
+//! Every callsite has a token bucket: each event takes a token and events without one are
+//! dropped before being formatted. Dropped events are counted and reported as a
+//! "suppressed N events" summary once the callsite logs again, or by [`report_suppressed`],
+//! e.g. from a timer.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Target of the summaries of the suppressed events, which are never rate limited
+pub const SUMMARY_TARGET: &str = "ic_canister_logger::rate_limit";
+
+static BUCKETS: Mutex<BTreeMap<usize, Bucket>> = Mutex::new(BTreeMap::new());
+
+/// Number of events a callsite can log at once and the rate at which it can log more
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Events logged before the callsite is limited
+    pub burst: u32,
+    /// Interval after which a limited callsite can log one more event
+    pub refill_interval: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+struct Bucket {
+    metadata: &'static Metadata<'static>,
+    tokens: u32,
+    refilled_at: u64,
+    suppressed: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: u64) {
+        let interval = (limit.refill_interval.as_nanos() as u64).max(1);
+        let refills = now.saturating_sub(self.refilled_at) / interval;
+        if refills > 0 {
+            self.tokens = (self.tokens as u64 + refills).min(limit.burst as u64) as u32;
+            self.refilled_at = if self.tokens == limit.burst {
+                now
+            } else {
+                self.refilled_at + refills * interval
+            };
+        }
+    }
+}
+
+fn report(metadata: &Metadata<'static>, suppressed: u64) {
+    tracing::warn!(
+        target: SUMMARY_TARGET,
+        callsite = metadata.name(),
+        log_target = metadata.target(),
+        suppressed,
+        "Suppressed {suppressed} events of {}",
+        metadata.name()
+    );
+}
+
+/// Report the events suppressed since their callsite last logged
+pub fn report_suppressed() {
+    let pending: Vec<_> = BUCKETS
+        .lock()
+        .expect("rate limit buckets poisoned")
+        .values_mut()
+        .filter(|bucket| bucket.suppressed > 0)
+        .map(|bucket| (bucket.metadata, std::mem::take(&mut bucket.suppressed)))
+        .collect();
+    for (metadata, suppressed) in pending {
+        report(metadata, suppressed);
+    }
+}
+
+/// Layer dropping the events of a callsite exceeding its [`RateLimit`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateLimitLayer {
+    limit: RateLimit,
+}
+
+impl RateLimitLayer {
+    /// Create a layer limiting every callsite to `limit`
+    pub fn new(limit: RateLimit) -> Self {
+        Self { limit }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RateLimitLayer {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        let metadata = event.metadata();
+        if metadata.target() == SUMMARY_TARGET {
+            return true;
+        }
+        let now = crate::current_time_nanos();
+        let mut buckets = BUCKETS.lock().expect("rate limit buckets poisoned");
+        let bucket = buckets
+            .entry(metadata as *const Metadata<'static> as usize)
+            .or_insert_with(|| Bucket {
+                metadata,
+                tokens: self.limit.burst,
+                refilled_at: now,
+                suppressed: 0,
+            });
+        bucket.refill(&self.limit, now);
+        if bucket.tokens == 0 {
+            bucket.suppressed += 1;
+            return false;
+        }
+        bucket.tokens -= 1;
+        let suppressed = std::mem::take(&mut bucket.suppressed);
+        // Report outside of the lock, the summary is an event of its own
+        drop(buckets);
+        if suppressed > 0 {
+            report(metadata, suppressed);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use tracing::callsite::{Callsite, Identifier};
+    use tracing::field::{Field, FieldSet, Visit};
+    use tracing::metadata::Kind;
+    use tracing::subscriber::Interest;
+    use tracing::Level;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    struct TestCallsite;
+    static CALLSITE: TestCallsite = TestCallsite;
+    static METADATA: Metadata<'static> = Metadata::new(
+        "test",
+        "test",
+        Level::INFO,
+        None,
+        None,
+        None,
+        FieldSet::new(&[], Identifier(&CALLSITE)),
+        Kind::EVENT,
+    );
+
+    impl Callsite for TestCallsite {
+        fn set_interest(&self, _interest: Interest) {}
+
+        fn metadata(&self) -> &Metadata<'_> {
+            &METADATA
+        }
+    }
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn refill_adds_a_token_per_elapsed_interval_up_to_the_burst() {
+        let limit = RateLimit {
+            burst: 3,
+            refill_interval: Duration::from_secs(1),
+        };
+        let mut bucket = Bucket {
+            metadata: &METADATA,
+            tokens: 0,
+            refilled_at: 0,
+            suppressed: 0,
+        };
+
+        bucket.refill(&limit, SECOND / 2);
+        assert_eq!((bucket.tokens, bucket.refilled_at), (0, 0));
+        // the remainder of the interval carries over to the next refill
+        bucket.refill(&limit, 3 * SECOND / 2);
+        assert_eq!((bucket.tokens, bucket.refilled_at), (1, SECOND));
+        bucket.refill(&limit, 2 * SECOND);
+        assert_eq!((bucket.tokens, bucket.refilled_at), (2, 2 * SECOND));
+        bucket.refill(&limit, 100 * SECOND);
+        assert_eq!((bucket.tokens, bucket.refilled_at), (3, 100 * SECOND));
+    }
+
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Messages {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0.lock().unwrap().push(format!("{value:?}"));
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Messages {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn events_over_the_limit_are_suppressed_and_summarized() {
+        let messages = Messages::default();
+        let subscriber = Registry::default()
+            .with(RateLimitLayer::new(RateLimit {
+                burst: 2,
+                refill_interval: Duration::from_secs(3600),
+            }))
+            .with(messages.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..5 {
+                tracing::info!("hot path {i}");
+            }
+            report_suppressed();
+        });
+
+        let messages = messages.0.lock().unwrap();
+        assert_eq!(messages[..2], ["hot path 0", "hot path 1"]);
+        assert_eq!(messages.len(), 3);
+        assert!(messages[2].starts_with("Suppressed 3 events of "));
+    }
+}
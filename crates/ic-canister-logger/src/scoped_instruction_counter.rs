@@ -1,41 +1,55 @@
-// TODO: use generic system interface
+use dscvr_interface::Interface;
+
+/// Callback receiving the system and instruction count of a finished scope
+pub type InstructionRecorder = fn(&dyn Interface, u64);
 
 // Counts the number of instructions for the liftetime of this object
-#[cfg(target_arch = "wasm32")]
-mod internal {
-    pub struct ScopedInstructionCounter<'a> {
-        name: &'a str,
-        start: u64,
-        system: &'a dyn dscvr_interface::Interface,
-    }
+pub struct ScopedInstructionCounter<'a> {
+    #[cfg(target_arch = "wasm32")]
+    name: &'a str,
+    start: u64,
+    system: &'a dyn Interface,
+    recorder: Option<InstructionRecorder>,
+}
 
-    impl<'a> ScopedInstructionCounter<'a> {
-        pub fn new(name: &'a str, system: &'a dyn dscvr_interface::Interface) -> Self {
-            Self {
-                name,
-                start: system.instruction_counter(),
-                system,
-            }
+impl<'a> ScopedInstructionCounter<'a> {
+    /// Count the instructions of the scope and log them under `name` when it ends
+    #[inline]
+    pub fn new(name: &'a str, system: &'a dyn Interface) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = name;
+        Self {
+            #[cfg(target_arch = "wasm32")]
+            name,
+            start: system.instruction_counter(),
+            system,
+            recorder: None,
         }
     }
 
-    impl<'a> Drop for ScopedInstructionCounter<'a> {
-        fn drop(&mut self) {
-            let end = self.system.instruction_counter();
-            tracing::info!("{} {}", self.name, end - self.start);
+    /// Count the instructions of the scope and hand them to `recorder` instead of
+    /// logging them when it ends
+    #[inline]
+    pub fn with_recorder(system: &'a dyn Interface, recorder: InstructionRecorder) -> Self {
+        Self {
+            #[cfg(target_arch = "wasm32")]
+            name: "",
+            start: system.instruction_counter(),
+            system,
+            recorder: Some(recorder),
         }
     }
 }
-#[cfg(not(target_arch = "wasm32"))]
-mod internal {
-    pub struct ScopedInstructionCounter;
 
-    impl ScopedInstructionCounter {
-        #[inline]
-        pub fn new(_name: &str, _system: &dyn dscvr_interface::Interface) -> Self {
-            Self
+impl Drop for ScopedInstructionCounter<'_> {
+    fn drop(&mut self) {
+        let instructions = self.system.instruction_counter().saturating_sub(self.start);
+        match self.recorder {
+            Some(recorder) => recorder(self.system, instructions),
+            #[cfg(target_arch = "wasm32")]
+            None => tracing::info!("{} {}", self.name, instructions),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => {}
         }
     }
 }
-
-pub use internal::ScopedInstructionCounter;
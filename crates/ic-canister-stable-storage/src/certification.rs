@@ -0,0 +1,146 @@
+//! Certification of stable storage backups.
+//!
+//! The canister hashes stable storage in fixed size chunks and sets its certified data to
+//! the hash of the certified length, the chunk size and the chunk hashes. A backup client
+//! can then verify the certificate once and check every downloaded chunk against its hash,
+//! so backups taken through untrusted boundary infrastructure can't be tampered with.
+
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+/// Errors related to verifying a certified backup
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)] // self documenting
+pub enum Error {
+    #[error("Certified data does not match the chunk hashes")]
+    RootHashMismatch,
+    #[error("Backup chunk size must be positive")]
+    InvalidChunkSize,
+    #[error("Backup has {0} chunks but {1} are certified")]
+    ChunkCountMismatch(u64, u64),
+    #[error("Backup chunk size {0} does not match certified chunk size {1}")]
+    ChunkSizeMismatch(u64, u64),
+    #[error("Backup chunk {0} is not certified")]
+    MissingChunk(usize),
+    #[error("Backup chunk {0} does not match its certified hash")]
+    ChunkHashMismatch(usize),
+}
+
+/// Certificate and chunk hashes returned by the canister for a certified backup
+#[derive(Debug, CandidType, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BackupCertificate {
+    /// The system certificate covering the canister's certified data
+    pub certificate: ByteBuf,
+    /// The number of certified bytes of stable storage
+    pub len: u64,
+    /// The size of each hashed chunk
+    pub chunk_size: u64,
+    /// Hash of each chunk of stable storage
+    pub chunk_hashes: Vec<ByteBuf>,
+}
+
+impl BackupCertificate {
+    /// Check that the certified data matches the chunk hashes
+    pub fn verify_root_hash(&self, certified_data: &[u8]) -> Result<(), Error> {
+        if root_hash(self.len, self.chunk_size, &self.chunk_hashes)[..] != *certified_data {
+            return Err(Error::RootHashMismatch);
+        }
+        Ok(())
+    }
+
+    /// Check that the certificate covers its length in chunks of `chunk_size`
+    pub fn verify_layout(&self, chunk_size: u64) -> Result<(), Error> {
+        if chunk_size == 0 || self.chunk_size == 0 {
+            return Err(Error::InvalidChunkSize);
+        }
+        if chunk_size != self.chunk_size {
+            return Err(Error::ChunkSizeMismatch(chunk_size, self.chunk_size));
+        }
+        let count = self.len.div_ceil(self.chunk_size);
+        if count != self.chunk_hashes.len() as u64 {
+            return Err(Error::ChunkCountMismatch(
+                count,
+                self.chunk_hashes.len() as u64,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the chunk at `offset` matches its certified hash
+    pub fn verify_chunk(&self, chunk_size: u64, offset: u64, bytes: &[u8]) -> Result<(), Error> {
+        self.verify_layout(chunk_size)?;
+        let index = (offset / self.chunk_size) as usize;
+        let expected = self
+            .chunk_hashes
+            .get(index)
+            .ok_or(Error::MissingChunk(index))?;
+        if hash_chunk(bytes)[..] != expected[..] {
+            return Err(Error::ChunkHashMismatch(index));
+        }
+        Ok(())
+    }
+}
+
+/// Hash a single chunk of stable storage
+#[inline]
+pub fn hash_chunk(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+/// Hash the certified length, the chunk size and the chunk hashes into the value that's set
+/// as certified data
+pub fn root_hash(len: u64, chunk_size: u64, chunk_hashes: &[ByteBuf]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_le_bytes());
+    hasher.update(chunk_size.to_le_bytes());
+    for hash in chunk_hashes {
+        hasher.update(hash);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn certificate(len: u64, chunk_size: u64, bytes: &[u8]) -> BackupCertificate {
+        let chunk_hashes = bytes
+            .chunks(chunk_size as usize)
+            .map(|chunk| ByteBuf::from(hash_chunk(chunk).to_vec()))
+            .collect::<Vec<_>>();
+        BackupCertificate {
+            certificate: ByteBuf::new(),
+            len,
+            chunk_size,
+            chunk_hashes,
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let bytes = (0..10).collect::<Vec<u8>>();
+        let certificate = certificate(10, 4, &bytes);
+        certificate
+            .verify_root_hash(&root_hash(10, 4, &certificate.chunk_hashes))
+            .unwrap();
+        assert!(certificate
+            .verify_root_hash(&root_hash(12, 4, &certificate.chunk_hashes))
+            .is_err());
+
+        certificate.verify_chunk(4, 0, &bytes[..4]).unwrap();
+        certificate.verify_chunk(4, 8, &bytes[8..]).unwrap();
+        assert!(certificate.verify_chunk(4, 4, &bytes[..4]).is_err());
+        assert!(certificate.verify_chunk(0, 0, &bytes[..4]).is_err());
+        assert!(certificate.verify_chunk(5, 0, &bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn test_verify_layout() {
+        let bytes = (0..10).collect::<Vec<u8>>();
+        certificate(10, 4, &bytes).verify_layout(4).unwrap();
+        assert!(certificate(20, 4, &bytes).verify_layout(4).is_err());
+        assert!(certificate(10, 4, &bytes).verify_layout(0).is_err());
+    }
+}
@@ -2,6 +2,8 @@
 
 use candid::{CandidType, Deserialize};
 use instrumented_error::IntoInstrumentedError;
+
+use crate::indexed::IndexedBincodeAdapter;
 use serde::Serialize;
 use std::io::{Read, Write};
 
@@ -17,6 +19,9 @@ pub enum DataFormatType {
     MsgPack = 1,
     /// Bincode
     Bincode = 2,
+    /// Bincode with principals written as indexes into a principal table, see
+    /// [`crate::indexed`]
+    IndexedBincode = 3,
 }
 
 impl Default for DataFormatType {
@@ -30,6 +35,7 @@ impl From<u64> for DataFormatType {
         match value {
             1 => Self::MsgPack,
             2 => Self::Bincode,
+            3 => Self::IndexedBincode,
             _ => Self::Unknown,
         }
     }
@@ -47,6 +53,7 @@ impl DataFormatType {
         match self {
             Self::Bincode => Ok(BincodeAdapter::deserialize(reader)?),
             Self::MsgPack => Ok(MsgPackAdapter::deserialize(reader)?),
+            Self::IndexedBincode => Ok(IndexedBincodeAdapter::deserialize(reader)?),
             f => Err(format!("Incompatible format {}", f).into_instrumented_error()),
         }
     }
@@ -71,6 +78,7 @@ impl DataFormatType {
         match self {
             Self::Bincode => Ok(BincodeAdapter::serialize(writer, t)?),
             Self::MsgPack => Ok(MsgPackAdapter::serialize(writer, t)?),
+            Self::IndexedBincode => Ok(IndexedBincodeAdapter::serialize(writer, t)?),
             f => Err(format!("Incompatible format {}", f).into_instrumented_error()),
         }
     }
@@ -1,13 +1,15 @@
+use candid::Principal;
 use dscvr_interface::edge::Edge;
 /// Utilities for restore/saving to v2 version of the stable storage format
 use instrumented_error::Result;
 use std::{
     fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Write},
+    io::{BufReader, BufWriter, Cursor, Write},
 };
 
+use crate::manifest::BackupManifest;
 use crate::transient::Transient;
-use crate::v2::{restore, save};
+use crate::v2::{restore, restore_content, save};
 use crate::{header::Header, migration};
 
 /// Save state to a file
@@ -38,3 +40,41 @@ where
     let mut reader = BufReader::new(File::open(file)?);
     Ok(restore(&Edge::default(), &mut reader)?)
 }
+
+/// Restore state from a memory-mapped file.
+///
+/// Deserializes directly from the mapped pages instead of copying them through a
+/// `BufReader`, which is noticeably faster for multi-GB backups.
+#[tracing::instrument]
+pub fn restore_from_mmap<T>(file: &str) -> Result<(Header, Transient, T)>
+where
+    for<'a> T: serde::Deserialize<'a>,
+{
+    let file = File::open(file)?;
+    // Safety: the backup file must not be modified while it is being restored
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    let mut reader = Cursor::new(&mmap[..]);
+    Ok(restore(&Edge::default(), &mut reader)?)
+}
+
+/// Restore state from a file along with the manifest recorded in its header.
+///
+/// When `expected_canister_id` is set, restoring a backup whose manifest was recorded
+/// for a different canister is refused.
+#[tracing::instrument]
+pub fn restore_from_file_with_manifest<T>(
+    file: &str,
+    expected_canister_id: Option<Principal>,
+) -> Result<(Header, Transient, T, Option<BackupManifest>)>
+where
+    for<'a> T: serde::Deserialize<'a>,
+{
+    let mut reader = BufReader::new(File::open(file)?);
+    let (header, fields) = Header::new_from_reader_with_fields(&mut reader)?;
+    let manifest = BackupManifest::from_header_fields(&fields)?;
+    if let (Some(manifest), Some(expected)) = (manifest.as_ref(), expected_canister_id.as_ref()) {
+        manifest.check_restore_target(expected, false)?;
+    }
+    let (header, transient, t) = restore_content(&Edge::default(), &mut reader, header, &fields)?;
+    Ok((header, transient, t, manifest))
+}
@@ -1,5 +1,16 @@
 //! Header for stable storage
 //!
+//! The header is stored as a sequence of little-endian u64 words. The first word describes
+//! the layout of the remaining words:
+//!
+//! - Legacy layout: the first word is the number of fields (at most 4) followed by the
+//!   content length, content format, content schema version and pre-upgrade instruction count.
+//! - TLV layout: the first word has [`TLV_LAYOUT_FLAG`] set and the remaining bits hold the
+//!   number of words that follow. The words contain a sequence of entries made of a u32 tag,
+//!   a u32 value length and the value bytes, each entry padded to a multiple of 8 bytes.
+//!
+//! The TLV layout is only written when the header carries [`HeaderField`]s, so headers without
+//! them keep the legacy layout and stay readable by older versions of this crate.
 use std::{
     io::{Read, Write},
     mem::size_of,
@@ -8,6 +19,7 @@ use std::{
 use candid::{CandidType, Deserialize};
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use serde::Serialize;
+use serde_bytes::ByteBuf;
 
 use super::data_format::DataFormatType;
 
@@ -21,13 +33,58 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Invalid header length {0} expecting {1}")]
     InvalidHeaderLength(u64, u64),
+    #[error("Invalid header field with tag {0}")]
+    InvalidField(u32),
+}
+
+/// Flag set on the first header word when the header uses the TLV layout
+pub const TLV_LAYOUT_FLAG: u64 = 1 << 63;
+
+/// Tags below this value are reserved for fields known to this crate
+pub const MIN_EXTRA_FIELD_TAG: u32 = 256;
+
+/// Upper bound on the number of TLV words, to avoid allocating for corrupted headers
+const MAX_TLV_WORDS: u64 = 64 * 1024;
+
+/// A field stored in the TLV layout in addition to the fixed header fields
+#[derive(Debug, CandidType, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HeaderField {
+    /// Tag of the field
+    pub tag: u32,
+    /// Raw value of the field
+    pub value: ByteBuf,
+}
+
+impl HeaderField {
+    /// Return the value of the field with `tag`
+    pub fn find(fields: &[HeaderField], tag: u32) -> Option<&[u8]> {
+        fields
+            .iter()
+            .find(|f| f.tag == tag)
+            .map(|f| f.value.as_slice())
+    }
+
+    /// Set the value of the field with `tag`, replacing any existing value
+    pub fn set(fields: &mut Vec<HeaderField>, tag: u32, value: Vec<u8>) {
+        let value = ByteBuf::from(value);
+        if let Some(field) = fields.iter_mut().find(|f| f.tag == tag) {
+            field.value = value;
+        } else {
+            fields.push(HeaderField { tag, value });
+        }
+    }
+
+    /// Remove the field with `tag`
+    pub fn remove(fields: &mut Vec<HeaderField>, tag: u32) {
+        fields.retain(|f| f.tag != tag);
+    }
 }
 
 /// The header contains information that's critical to serializing the contents
 /// and the footer
 #[derive(Debug, CandidType, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
 pub struct Header {
-    /// Length of the header
+    /// Length of the header in u64 words, excluding the leading length word
     pub header_length: u64,
     /// Length of the content
     pub content_length: u64,
@@ -39,7 +96,7 @@ pub struct Header {
     pub pre_upgrade_instruction_count: u64,
 }
 
-// Index of the fields in the header struct
+// Index of the fields in the legacy header layout
 #[derive(PartialEq, PartialOrd, Eq, Ord)]
 enum FieldIndex {
     ContentLength = 0,
@@ -49,7 +106,17 @@ enum FieldIndex {
     NumFields,
 }
 
+// Tags of the fixed fields in the TLV header layout
+#[repr(u32)]
+enum FieldTag {
+    ContentLength = 1,
+    ContentFormat,
+    ContentSchemaVersion,
+    PreUpgradeInstructionCount,
+}
+
 const U64_SIZE: usize = size_of::<u64>();
+const U32_SIZE: usize = size_of::<u32>();
 
 impl Header {
     /// Create a header with format and schema version
@@ -63,59 +130,171 @@ impl Header {
         }
     }
 
-    /// Create a header from a reader
+    /// Create a header from a reader, ignoring any [`HeaderField`]s
     pub fn new_from_reader<R: Read>(reader: &mut R) -> std::result::Result<Self, Error> {
-        let header_length = Self::read_u64(reader)?;
-        if header_length > FieldIndex::NumFields as u64 {
-            return Err(Error::InvalidHeaderLength(
-                header_length,
-                FieldIndex::NumFields as u64,
-            ));
-        }
+        Ok(Self::new_from_reader_with_fields(reader)?.0)
+    }
 
-        let fields = Self::read_n_u64(reader, header_length as usize)?;
+    /// Create a header and its [`HeaderField`]s from a reader
+    pub fn new_from_reader_with_fields<R: Read>(
+        reader: &mut R,
+    ) -> std::result::Result<(Self, Vec<HeaderField>), Error> {
+        let first_word = Self::read_u64(reader)?;
+        let word_count = Self::word_count(first_word)?;
+        let words = Self::read_n_u64(reader, word_count as usize)?;
 
-        Self::new_from_vec(fields)
+        Self::new_from_words(first_word, words)
     }
 
-    /// Create a header from an async reader
+    /// Create a header from an async reader, ignoring any [`HeaderField`]s
     pub async fn new_from_reader_async<R: AsyncRead + AsyncReadExt + Unpin>(
         reader: &mut R,
     ) -> std::result::Result<Self, Error> {
-        let header_length = Self::read_u64_async(reader).await?;
-        if header_length > FieldIndex::NumFields as u64 {
-            return Err(Error::InvalidHeaderLength(
-                header_length,
+        Ok(Self::new_from_reader_with_fields_async(reader).await?.0)
+    }
+
+    /// Create a header and its [`HeaderField`]s from an async reader
+    pub async fn new_from_reader_with_fields_async<R: AsyncRead + AsyncReadExt + Unpin>(
+        reader: &mut R,
+    ) -> std::result::Result<(Self, Vec<HeaderField>), Error> {
+        let first_word = Self::read_u64_async(reader).await?;
+        let word_count = Self::word_count(first_word)?;
+        let words = Self::read_n_u64_async(reader, word_count as usize).await?;
+
+        Self::new_from_words(first_word, words)
+    }
+
+    // Return the number of words following the first header word
+    fn word_count(first_word: u64) -> std::result::Result<u64, Error> {
+        if first_word & TLV_LAYOUT_FLAG != 0 {
+            let word_count = first_word & !TLV_LAYOUT_FLAG;
+            if word_count > MAX_TLV_WORDS {
+                return Err(Error::InvalidHeaderLength(word_count, MAX_TLV_WORDS));
+            }
+            Ok(word_count)
+        } else if first_word > FieldIndex::NumFields as u64 {
+            Err(Error::InvalidHeaderLength(
+                first_word,
                 FieldIndex::NumFields as u64,
-            ));
+            ))
+        } else {
+            Ok(first_word)
         }
+    }
 
-        let fields = Self::read_n_u64_async(reader, header_length as usize).await?;
-
-        Self::new_from_vec(fields)
+    // Create a header from the first header word and the words that follow it
+    fn new_from_words(
+        first_word: u64,
+        words: Vec<u64>,
+    ) -> std::result::Result<(Self, Vec<HeaderField>), Error> {
+        if first_word & TLV_LAYOUT_FLAG != 0 {
+            let bytes = words
+                .into_iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect::<Vec<u8>>();
+            Self::new_from_tlv(&bytes)
+        } else {
+            Ok((Self::new_from_vec(words)?, vec![]))
+        }
     }
 
-    /// Create a header from a vector of u64
+    /// Create a header from a vector of u64 using the legacy layout
     fn new_from_vec(fields: Vec<u64>) -> std::result::Result<Self, Error> {
-        let content_format = fields[FieldIndex::ContentFormat as usize].into();
+        let field = |index: FieldIndex| fields.get(index as usize).copied().unwrap_or_default();
+
+        let raw_content_format = field(FieldIndex::ContentFormat);
+        let content_format = raw_content_format.into();
         if content_format == DataFormatType::Unknown {
-            return Err(Error::InvalidContentFormat(fields[3]));
+            return Err(Error::InvalidContentFormat(raw_content_format));
         }
 
         Ok(Self {
             header_length: fields.len() as u64,
-            content_length: fields[FieldIndex::ContentLength as usize],
+            content_length: field(FieldIndex::ContentLength),
             content_format,
-            content_schema_version: fields[FieldIndex::ContentSchemaVersion as usize],
-            pre_upgrade_instruction_count: fields[FieldIndex::PreUpgradeInstructionCount as usize],
+            content_schema_version: field(FieldIndex::ContentSchemaVersion),
+            pre_upgrade_instruction_count: field(FieldIndex::PreUpgradeInstructionCount),
         })
     }
 
+    /// Create a header from TLV encoded entries
+    fn new_from_tlv(bytes: &[u8]) -> std::result::Result<(Self, Vec<HeaderField>), Error> {
+        let mut header = Self {
+            header_length: (bytes.len() / U64_SIZE) as u64,
+            ..Default::default()
+        };
+        let mut fields = vec![];
+        let mut raw_content_format = 0;
+
+        let mut pos = 0;
+        while pos + 2 * U32_SIZE <= bytes.len() {
+            let tag = u32::from_le_bytes(bytes[pos..pos + U32_SIZE].try_into().unwrap());
+            let len = u32::from_le_bytes(
+                bytes[pos + U32_SIZE..pos + 2 * U32_SIZE]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            let start = pos + 2 * U32_SIZE;
+            // a corrupted length can overflow on wasm32
+            let end = match start.checked_add(len) {
+                Some(end) if end <= bytes.len() => end,
+                _ => return Err(Error::InvalidField(tag)),
+            };
+            let value = &bytes[start..end];
+
+            match tag {
+                t if t == FieldTag::ContentLength as u32 => {
+                    header.content_length = Self::field_to_u64(tag, value)?;
+                }
+                t if t == FieldTag::ContentFormat as u32 => {
+                    raw_content_format = Self::field_to_u64(tag, value)?;
+                }
+                t if t == FieldTag::ContentSchemaVersion as u32 => {
+                    header.content_schema_version = Self::field_to_u64(tag, value)?;
+                }
+                t if t == FieldTag::PreUpgradeInstructionCount as u32 => {
+                    header.pre_upgrade_instruction_count = Self::field_to_u64(tag, value)?;
+                }
+                // zeroed padding
+                0 if len == 0 => {}
+                _ => fields.push(HeaderField {
+                    tag,
+                    value: ByteBuf::from(value.to_vec()),
+                }),
+            }
+
+            pos = end.next_multiple_of(U64_SIZE);
+        }
+
+        header.content_format = raw_content_format.into();
+        if header.content_format == DataFormatType::Unknown {
+            return Err(Error::InvalidContentFormat(raw_content_format));
+        }
+
+        Ok((header, fields))
+    }
+
+    // Helper to decode a u64 field value
+    fn field_to_u64(tag: u32, value: &[u8]) -> std::result::Result<u64, Error> {
+        Ok(u64::from_le_bytes(
+            value.try_into().map_err(|_| Error::InvalidField(tag))?,
+        ))
+    }
+
     /// Write the header
     pub fn write<W: Write>(&self, writer: &mut W) -> std::result::Result<(), Error> {
         Ok(writer.write_all(&self.as_bytes())?)
     }
 
+    /// Write the header along with its [`HeaderField`]s
+    pub fn write_with_fields<W: Write>(
+        &self,
+        fields: &[HeaderField],
+        writer: &mut W,
+    ) -> std::result::Result<(), Error> {
+        Ok(writer.write_all(&self.as_bytes_with_fields(fields))?)
+    }
+
     /// Write the header async
     pub async fn write_async<W: AsyncWrite + AsyncWriteExt + Unpin>(
         &self,
@@ -124,10 +303,10 @@ impl Header {
         Ok(writer.write_all(&self.as_bytes()).await?)
     }
 
-    /// Return the number of bytes needed by all fields of the header
+    /// Return the number of bytes used by all fields of the header as stored,
+    /// including the leading length word
     pub fn num_all_fields_bytes(&self) -> u64 {
-        // NumFields + 1 to include the header length
-        (FieldIndex::NumFields as u64 + 1) * U64_SIZE as u64
+        (self.header_length + 1) * U64_SIZE as u64
     }
 
     /// Return the number of bytes needed by used by both the header and content
@@ -135,6 +314,16 @@ impl Header {
         self.header_length * U64_SIZE as u64 + U64_SIZE as u64 + self.content_length
     }
 
+    /// Return the number of words needed to write the header along with `fields`,
+    /// excluding the leading length word
+    pub fn num_field_words(&self, fields: &[HeaderField]) -> u64 {
+        if fields.is_empty() {
+            FieldIndex::NumFields as u64
+        } else {
+            (self.tlv_bytes(fields).len() / U64_SIZE) as u64
+        }
+    }
+
     // Helper to read a single u64 from a reader
     fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
         let mut bytes = [0_u8; U64_SIZE];
@@ -176,6 +365,35 @@ impl Header {
             .collect::<Vec<_>>()
     }
 
+    // Helper to append a TLV entry padded to a multiple of 8 bytes
+    fn push_tlv_field(bytes: &mut Vec<u8>, tag: u32, value: &[u8]) {
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(value);
+        bytes.resize(bytes.len().next_multiple_of(U64_SIZE), 0);
+    }
+
+    // Return the TLV encoded fields of the header
+    fn tlv_bytes(&self, fields: &[HeaderField]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let vals = [
+            (FieldTag::ContentLength, self.content_length),
+            (FieldTag::ContentFormat, self.content_format as u64),
+            (FieldTag::ContentSchemaVersion, self.content_schema_version),
+            (
+                FieldTag::PreUpgradeInstructionCount,
+                self.pre_upgrade_instruction_count,
+            ),
+        ];
+        for (tag, val) in vals {
+            Self::push_tlv_field(&mut bytes, tag as u32, &val.to_le_bytes());
+        }
+        for field in fields {
+            Self::push_tlv_field(&mut bytes, field.tag, &field.value);
+        }
+        bytes
+    }
+
     /// Return the header as bytes
     pub fn as_bytes(&self) -> Vec<u8> {
         let vals = [
@@ -189,6 +407,20 @@ impl Header {
             .flat_map(|v| v.to_le_bytes())
             .collect::<Vec<u8>>()
     }
+
+    /// Return the header along with its [`HeaderField`]s as bytes.
+    /// This uses the legacy layout when there are no fields.
+    pub fn as_bytes_with_fields(&self, fields: &[HeaderField]) -> Vec<u8> {
+        if fields.is_empty() {
+            return self.as_bytes();
+        }
+        let tlv = self.tlv_bytes(fields);
+        let word_count = (tlv.len() / U64_SIZE) as u64;
+        let mut bytes = Vec::with_capacity(U64_SIZE + tlv.len());
+        bytes.extend_from_slice(&(TLV_LAYOUT_FLAG | word_count).to_le_bytes());
+        bytes.extend_from_slice(&tlv);
+        bytes
+    }
 }
 
 #[cfg(test)]
@@ -244,4 +476,59 @@ mod test {
             bytes.len() as u64 + header.content_length,
         );
     }
+
+    #[test]
+    fn test_legacy_layout_without_fields() {
+        let header = Header::new_from_format_and_schema(DataFormatType::Bincode, 10);
+        assert_eq!(header.as_bytes_with_fields(&[]), header.as_bytes());
+        assert_eq!(header.num_field_words(&[]), FieldIndex::NumFields as u64);
+    }
+
+    #[test]
+    fn test_tlv_layout_with_fields() {
+        let mut header = Header::new_from_format_and_schema(DataFormatType::Bincode, 10);
+        header.content_length = 100;
+        header.pre_upgrade_instruction_count = 200;
+        let mut fields = vec![];
+        HeaderField::set(&mut fields, MIN_EXTRA_FIELD_TAG, b"key-id".to_vec());
+        header.header_length = header.num_field_words(&fields);
+
+        let mut bytes = vec![];
+        header.write_with_fields(&fields, &mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, header.num_all_fields_bytes());
+        assert_ne!(
+            u64::from_le_bytes(bytes[..U64_SIZE].try_into().unwrap()) & TLV_LAYOUT_FLAG,
+            0
+        );
+
+        let (roundtrip_header, roundtrip_fields) =
+            Header::new_from_reader_with_fields(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header, roundtrip_header);
+        assert_eq!(
+            HeaderField::find(&roundtrip_fields, MIN_EXTRA_FIELD_TAG),
+            Some(b"key-id".as_slice())
+        );
+        assert_eq!(
+            Header::new_from_reader(&mut bytes.as_slice()).unwrap(),
+            header
+        );
+    }
+
+    #[test]
+    fn test_tlv_field_length_overflow() {
+        // the largest length a TLV entry can hold, overflowing `usize` on wasm32
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&MIN_EXTRA_FIELD_TAG.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0; U64_SIZE]);
+        assert!(matches!(
+            Header::new_from_tlv(&bytes),
+            Err(Error::InvalidField(MIN_EXTRA_FIELD_TAG))
+        ));
+        // a corrupted word count is rejected before anything is allocated
+        assert!(matches!(
+            Header::new_from_reader(&mut u64::MAX.to_le_bytes().as_slice()),
+            Err(Error::InvalidHeaderLength(..))
+        ));
+    }
 }
@@ -0,0 +1,161 @@
+//! Bincode with the principals of the state written as indexes into a principal table.
+//!
+//! The content layout is the following:
+//! - Length of the state in bytes (u64 little-endian)
+//! - State, serialized as bincode with each `RcPrincipal` written as a u32 index
+//! - Principal table, serialized as bincode
+//!
+//! The table is written once the state is serialized, so seekable writers stream the state
+//! and only patch its length, while plain writers buffer the state.
+
+use ic_rc_principal::{PrincipalTable, RcPrincipal};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::data_format::{DataFormatType, SerdeDataFormat};
+
+const U64_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Indexed bincode adapter
+pub struct IndexedBincodeAdapter;
+
+impl IndexedBincodeAdapter {
+    /// Serialize to a seekable writer without buffering the state
+    pub fn serialize_seekable<W, T>(mut writer: W, t: &T) -> Result<(), bincode::Error>
+    where
+        W: Write + Seek,
+        T: serde::Serialize,
+    {
+        let length_pos = writer.stream_position()?;
+        writer.write_all(&0_u64.to_le_bytes())?;
+        let (ret, table) = RcPrincipal::encode_indexed(|| bincode::serialize_into(&mut writer, t));
+        ret?;
+        let table_pos = writer.stream_position()?;
+        bincode::serialize_into(&mut writer, &table)?;
+        let end_pos = writer.stream_position()?;
+
+        writer.seek(SeekFrom::Start(length_pos))?;
+        writer.write_all(&(table_pos - length_pos - U64_SIZE).to_le_bytes())?;
+        writer.seek(SeekFrom::Start(end_pos))?;
+        Ok(())
+    }
+
+    /// Deserialize from a seekable reader without buffering the content
+    pub fn deserialize_seekable<R, T>(mut reader: R) -> Result<T, bincode::Error>
+    where
+        R: Read + Seek,
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        let length = Self::read_length(&mut reader)?;
+        let state_pos = reader.stream_position()?;
+        reader.seek(SeekFrom::Start(state_pos + length))?;
+        let table: PrincipalTable = bincode::deserialize_from(&mut reader)?;
+        let end_pos = reader.stream_position()?;
+
+        reader.seek(SeekFrom::Start(state_pos))?;
+        let t = RcPrincipal::decode_indexed(&table, || bincode::deserialize_from(&mut reader))?;
+        reader.seek(SeekFrom::Start(end_pos))?;
+        Ok(t)
+    }
+
+    fn read_length<R: Read>(reader: &mut R) -> Result<u64, bincode::Error> {
+        let mut bytes = [0; U64_SIZE as usize];
+        reader.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl SerdeDataFormat for IndexedBincodeAdapter {
+    type DeserializeError = bincode::Error;
+    type SerializeError = bincode::Error;
+
+    fn serialize<W, T>(writer: W, t: &T) -> Result<(), Self::SerializeError>
+    where
+        W: Write,
+        T: serde::Serialize,
+    {
+        let mut writer = writer;
+        let (state, table) = RcPrincipal::encode_indexed(|| bincode::serialize(t));
+        let state = state?;
+        writer.write_all(&(state.len() as u64).to_le_bytes())?;
+        writer.write_all(&state)?;
+        bincode::serialize_into(writer, &table)
+    }
+
+    fn deserialize<R, T>(reader: R) -> Result<T, Self::DeserializeError>
+    where
+        R: Read,
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        let mut reader = reader;
+        let length = Self::read_length(&mut reader)?;
+        let mut state = vec![];
+        (&mut reader).take(length).read_to_end(&mut state)?;
+        let table: PrincipalTable = bincode::deserialize_from(reader)?;
+        RcPrincipal::decode_indexed(&table, || bincode::deserialize(&state))
+    }
+
+    fn format_type() -> DataFormatType {
+        DataFormatType::IndexedBincode
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use candid::Principal;
+    use ic_rc_principal::RcPrincipal;
+    use serde::{Deserialize, Serialize};
+    use std::io::Cursor;
+
+    use super::IndexedBincodeAdapter;
+    use crate::data_format::{BincodeAdapter, SerdeDataFormat};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+    struct State {
+        pub owners: Vec<RcPrincipal>,
+        pub name: String,
+        pub admin: Principal,
+    }
+
+    fn state() -> State {
+        State {
+            owners: (0..100u8)
+                .map(|i| RcPrincipal::new(Principal::from_slice(&[1, 2, i % 4])))
+                .collect(),
+            name: "hello".to_owned(),
+            admin: Principal::from_slice(&[1, 2, 3]),
+        }
+    }
+
+    #[test]
+    fn test_indexed_roundtrip() {
+        let state = state();
+        let mut bytes = vec![];
+        IndexedBincodeAdapter::serialize(&mut bytes, &state).unwrap();
+        let roundtrip: State = IndexedBincodeAdapter::deserialize(bytes.as_slice()).unwrap();
+        assert_eq!(roundtrip, state);
+
+        let mut plain = vec![];
+        BincodeAdapter::serialize(&mut plain, &state).unwrap();
+        assert!(bytes.len() < plain.len());
+    }
+
+    #[test]
+    fn test_indexed_seekable_roundtrip() {
+        let state = state();
+        let mut cursor = Cursor::new(vec![0xff; 8]);
+        cursor.set_position(8);
+        IndexedBincodeAdapter::serialize_seekable(&mut cursor, &state).unwrap();
+        let end = cursor.position();
+        cursor.get_mut().extend_from_slice(&[0xff; 8]);
+
+        // the seekable and buffered layouts are the same
+        let mut bytes = vec![];
+        IndexedBincodeAdapter::serialize(&mut bytes, &state).unwrap();
+        assert_eq!(&cursor.get_ref()[8..end as usize], bytes.as_slice());
+
+        cursor.set_position(8);
+        let roundtrip: State = IndexedBincodeAdapter::deserialize_seekable(&mut cursor).unwrap();
+        assert_eq!(roundtrip, state);
+        assert_eq!(cursor.position(), end);
+    }
+}
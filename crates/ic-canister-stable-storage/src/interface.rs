@@ -1,18 +1,44 @@
 //! Common stable storage logic for use in canisters
 
+use dscvr_interface::Interface;
 use ic_cdk::api::stable::StableReader;
-use ic_cdk::api::stable::StableWriter;
 use serde_bytes::ByteBuf;
 use std::cell::RefCell;
-use std::io::Read;
-use tracing::info;
+use std::io::Write;
+use tracing::{info, warn};
 
+use crate::certification::{hash_chunk, root_hash, BackupCertificate};
+use crate::manifest::BackupManifest;
+use crate::stable_writer::ChunkedStableWriter;
 use crate::Error;
 use crate::{header::Header, transient::Transient, WASM_PAGE_SIZE_IN_BYTES};
 
 thread_local! {
     static HEADER: RefCell<Header> = RefCell::default();
     static TRANSIENT: RefCell<Transient> = RefCell::default();
+    static BACKUP_CERTIFICATION: RefCell<Option<BackupCertification>> = RefCell::default();
+}
+
+/// Number of instructions after which `certify_stable_storage_backup` stops hashing,
+/// well below the instruction limit of an update call
+const CERTIFY_INSTRUCTION_BUDGET: u64 = 5_000_000_000;
+
+// Progress of `certify_stable_storage_backup`
+struct BackupCertification {
+    len: u64,
+    chunk_size: u64,
+    chunk_hashes: Vec<ByteBuf>,
+}
+
+impl BackupCertification {
+    // Offset of the next chunk to hash
+    fn offset(&self) -> u64 {
+        std::cmp::min(self.chunk_hashes.len() as u64 * self.chunk_size, self.len)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.offset() == self.len
+    }
 }
 
 /// Return the stable storage header and transient structures
@@ -32,9 +58,150 @@ pub fn backup_stable_storage(offset: u64, limit: usize) -> ByteBuf {
     ByteBuf::from(bytes)
 }
 
-/// Initialize the stable storage with the given length
+/// Dump `count` raw pages of stable storage starting at `offset_page`, ignoring the header.
+/// Pages beyond the current stable storage size are not returned.
+pub fn dump_stable_pages(offset_page: u64, count: u64) -> ByteBuf {
+    let stable_size = ic_cdk::api::stable::stable_size();
+    let count = std::cmp::min(count, stable_size.saturating_sub(offset_page));
+    let mut bytes = vec![0; count as usize * WASM_PAGE_SIZE_IN_BYTES];
+    ic_cdk::api::stable::stable_read(offset_page * WASM_PAGE_SIZE_IN_BYTES as u64, &mut bytes);
+    ByteBuf::from(bytes)
+}
+
+/// Hash the used stable storage in chunks of `chunk_size` and, once every chunk is hashed,
+/// set the certified data to the root hash of the chunks, so that backups can be verified
+/// by the client.
+///
+/// Hashing stops when the instruction budget of the call is used up, in which case `false`
+/// is returned and the call must be repeated with the same chunk size until it returns
+/// `true`. Calling it after a completed certification or with a different chunk size
+/// starts over.
+///
+/// Note: This replaces any certified data previously set by the canister.
+pub fn certify_stable_storage_backup(chunk_size: u64) -> Result<bool, String> {
+    if chunk_size == 0 {
+        return Err("Backup chunk size must be positive".to_string());
+    }
+    let len = HEADER.with(|h| h.borrow().num_content_and_header_bytes());
+    BACKUP_CERTIFICATION.with(|c| {
+        let mut c = c.borrow_mut();
+        let resume = c
+            .as_ref()
+            .is_some_and(|c| !c.is_complete() && c.chunk_size == chunk_size && c.len == len);
+        if !resume {
+            *c = None;
+        }
+        let certification = c.get_or_insert_with(|| BackupCertification {
+            len,
+            chunk_size,
+            chunk_hashes: vec![],
+        });
+
+        let mut bytes = vec![0; std::cmp::min(chunk_size, len) as usize];
+        while !certification.is_complete()
+            && ic_cdk::api::instruction_counter() < CERTIFY_INSTRUCTION_BUDGET
+        {
+            let offset = certification.offset();
+            let size = std::cmp::min(chunk_size, len - offset) as usize;
+            ic_cdk::api::stable::stable_read(offset, &mut bytes[..size]);
+            certification
+                .chunk_hashes
+                .push(ByteBuf::from(hash_chunk(&bytes[..size]).to_vec()));
+        }
+
+        if !certification.is_complete() {
+            info!(
+                "Certified {} of {} bytes",
+                certification.offset(),
+                certification.len
+            );
+            return Ok(false);
+        }
+        ic_cdk::api::set_certified_data(&root_hash(
+            certification.len,
+            certification.chunk_size,
+            &certification.chunk_hashes,
+        ));
+        Ok(true)
+    })
+}
+
+/// Return the certificate and chunk hashes of the certification completed by
+/// `certify_stable_storage_backup`. This must be called from a query.
+pub fn stable_storage_backup_certificate() -> Option<BackupCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    BACKUP_CERTIFICATION.with(|c| {
+        c.borrow()
+            .as_ref()
+            .filter(|c| c.is_complete())
+            .map(|c| BackupCertificate {
+                certificate: ByteBuf::from(certificate),
+                len: c.len,
+                chunk_size: c.chunk_size,
+                chunk_hashes: c.chunk_hashes.clone(),
+            })
+    })
+}
+
+/// Return whether the next save is skipped
+#[inline]
+pub fn skip_next_save() -> bool {
+    TRANSIENT.with(|t| t.borrow().skip_next_save)
+}
+
+/// Set whether the next save is skipped
+#[inline]
+pub fn set_skip_next_save(flag: bool) {
+    TRANSIENT.with(|t| t.borrow_mut().skip_next_save = flag);
+}
+
+/// Return whether a restore is currently writing into stable storage
+#[inline]
+pub fn is_restore_in_progress() -> bool {
+    TRANSIENT.with(|t| t.borrow().restore_in_progress)
+}
+
+/// Set whether a restore is currently writing into stable storage
+#[inline]
+pub fn set_restore_in_progress(flag: bool) {
+    TRANSIENT.with(|t| t.borrow_mut().restore_in_progress = flag);
+}
+
+/// Return the manifest of the backup that was last restored
+#[inline]
+pub fn last_restore_source() -> Option<BackupManifest> {
+    TRANSIENT.with(|t| t.borrow().last_restore_source.clone())
+}
+
+/// Set the manifest of the backup that was last restored
+#[inline]
+pub fn set_last_restore_source(manifest: Option<BackupManifest>) {
+    TRANSIENT.with(|t| t.borrow_mut().last_restore_source = manifest);
+}
+
+/// Return the schema version override for the next save
+#[inline]
+pub fn schema_override() -> Option<u64> {
+    TRANSIENT.with(|t| t.borrow().schema_override)
+}
+
+/// Set the schema version override for the next save, it is cleared once a save used it
+#[inline]
+pub fn set_schema_override(version: Option<u64>) {
+    TRANSIENT.with(|t| t.borrow_mut().schema_override = version);
+}
+
+/// Return the number of instructions used for post-upgrade
+#[inline]
+pub fn post_upgrade_instruction_count() -> u64 {
+    TRANSIENT.with(|t| t.borrow().post_upgrade_instruction_count)
+}
+
+/// Initialize the stable storage with the given length.
+/// This marks the start of a restore.
 #[inline]
 pub fn init_stable_storage(len: u64) {
+    set_restore_in_progress(true);
     let page_count = len / WASM_PAGE_SIZE_IN_BYTES as u64 + 1;
     let current = ic_cdk::api::stable::stable_size();
     if page_count > current {
@@ -45,28 +212,50 @@ pub fn init_stable_storage(len: u64) {
 
 /// Restore the stable storage
 #[inline]
-pub fn restore_stable_storage(offset: u64, bytes: ByteBuf) {
-    ic_cdk::api::stable::stable_write(offset, &bytes.into_vec());
+pub fn restore_stable_storage(
+    system: &dyn Interface,
+    offset: u64,
+    bytes: ByteBuf,
+) -> Result<(), String> {
+    let mut writer = ChunkedStableWriter::new_at(system, offset);
+    writer
+        .write_all(&bytes)
+        .map_err(|e| format!("Failed restoring at {}: {}", writer.offset(), e))
 }
 
-/// Restore the stable storage from a compressed array of byte buffers
+/// Restore the stable storage from a compressed array of byte buffers.
+/// Each buffer is decompressed straight into stable storage.
 #[inline]
-pub fn restore_stable_storage_compressed(mut offset: u64, compressed_bytes_vec: Vec<ByteBuf>) {
-    let mut read_buffer = vec![];
-    for bytes in compressed_bytes_vec.into_iter() {
-        flate2::read::GzDecoder::new(&bytes.into_vec()[..])
-            .read_to_end(&mut read_buffer)
-            .unwrap();
-        ic_cdk::api::stable::stable_write(offset, &read_buffer);
-        offset += read_buffer.len() as u64;
-        read_buffer.clear();
+pub fn restore_stable_storage_compressed(
+    system: &dyn Interface,
+    offset: u64,
+    compressed_bytes_vec: Vec<ByteBuf>,
+) -> Result<(), String> {
+    let mut writer = ChunkedStableWriter::new_at(system, offset);
+    for bytes in compressed_bytes_vec.iter() {
+        let mut decoder = flate2::read::GzDecoder::new(&bytes[..]);
+        std::io::copy(&mut decoder, &mut writer)
+            .map_err(|e| format!("Failed decompressing at {}: {}", writer.offset(), e))?;
     }
+    Ok(())
 }
 
-/// Set the flag that skips saving the stable storage on next upgrade
+/// Set the flag that skips saving the stable storage on next upgrade.
+/// This marks the end of a restore, the manifest recorded in the restored header
+/// becomes the last restore source.
 #[inline]
 pub fn set_restore_from_stable_storage(flag: bool) {
-    TRANSIENT.with(|t| t.borrow_mut().skip_next_save = flag);
+    set_skip_next_save(flag);
+    set_restore_in_progress(false);
+    if flag {
+        let source = Header::new_from_reader_with_fields(&mut StableReader::default())
+            .map_err(Error::from)
+            .and_then(|(_, fields)| BackupManifest::from_header_fields(&fields));
+        match source {
+            Ok(source) => set_last_restore_source(source),
+            Err(e) => warn!("Failed reading the restored header {:?}", e),
+        }
+    }
 }
 
 /// v1 implementation for stable storage
@@ -81,7 +270,7 @@ pub mod v1 {
     where
         T: serde::Serialize,
     {
-        super::super::v1::save(interface, &mut StableWriter::default(), t)
+        super::super::v1::save(interface, &mut ChunkedStableWriter::new(interface), t)
     }
 
     /// Deserialize using v1 layout into canister stable storage
@@ -120,17 +309,23 @@ pub mod v2 {
 
         let mut header = HEADER.with(|h| h.borrow().clone());
         header.content_format = format;
-        header.content_schema_version = version;
+        let schema_override = schema_override();
+        header.content_schema_version = schema_override.unwrap_or(version);
 
         TRANSIENT.with(|transient| {
             super::super::v2::save(
                 interface,
-                &mut StableWriter::default(),
+                &mut ChunkedStableWriter::new(interface),
                 t,
                 header,
                 &transient.borrow(),
             )
-        })
+        })?;
+        // the override only applies to a single save
+        if schema_override.is_some() {
+            set_schema_override(None);
+        }
+        Ok(())
     }
 
     /// Deserialize using v2 layout into canister stable storage
@@ -172,12 +367,36 @@ pub mod v1_v2 {
 /// Note: We don't want these logged in the TX log in case this mechanism is
 /// ever used in production, so we use the dscvr_cdk_macros crate to use
 /// the ic-cdk macros directly.
+///
+/// By default the queries are guarded by `is_backup_service` and the updates by
+/// `is_restore_service`. Different guards and extra attributes applied to every
+/// generated method can be passed in:
+///
+/// ```ignore
+/// define_common_stable_storage_interface!(
+///     backup_guard = "is_controller",
+///     restore_guard = "is_controller",
+///     attrs = { #[allow(dead_code)] }
+/// );
+/// ```
 #[macro_export]
 #[allow(clippy::crate_in_macro_def)]
 macro_rules! define_common_stable_storage_interface {
     () => {
+        $crate::define_common_stable_storage_interface!(
+            backup_guard = "is_backup_service",
+            restore_guard = "is_restore_service"
+        );
+    };
+    (
+        backup_guard = $backup_guard:literal,
+        restore_guard = $restore_guard:literal
+        $(, attrs = { $(#[$attr:meta])* })?
+        $(,)?
+    ) => {
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::query(guard = "is_backup_service")]
+        #[dscvr_cdk_macros::query(guard = $backup_guard)]
+        $($(#[$attr])*)?
         fn stable_storage_info(
             _ctx: crate::canister_context::ImmutableContext,
         ) -> ($crate::header::Header, $crate::transient::Transient) {
@@ -185,7 +404,8 @@ macro_rules! define_common_stable_storage_interface {
         }
 
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::query(guard = "is_backup_service")]
+        #[dscvr_cdk_macros::query(guard = $backup_guard)]
+        $($(#[$attr])*)?
         fn backup_stable_storage(
             _ctx: crate::canister_context::ImmutableContext,
             offset: u64,
@@ -195,38 +415,142 @@ macro_rules! define_common_stable_storage_interface {
         }
 
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::update(guard = "is_restore_service", skip_tx_log = true)]
+        #[dscvr_cdk_macros::query(guard = $backup_guard)]
+        $($(#[$attr])*)?
+        fn dump_stable_pages(
+            _ctx: crate::canister_context::ImmutableContext,
+            offset_page: u64,
+            count: u64,
+        ) -> serde_bytes::ByteBuf {
+            $crate::interface::dump_stable_pages(offset_page, count)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::query(guard = $backup_guard)]
+        $($(#[$attr])*)?
+        fn stable_storage_backup_certificate(
+            _ctx: crate::canister_context::ImmutableContext,
+        ) -> Option<$crate::certification::BackupCertificate> {
+            $crate::interface::stable_storage_backup_certificate()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::update(guard = $backup_guard, skip_tx_log = true)]
+        $($(#[$attr])*)?
+        fn certify_stable_storage_backup(
+            _ctx: crate::canister_context::MutableContext,
+            chunk_size: u64,
+        ) -> Result<bool, String> {
+            $crate::interface::certify_stable_storage_backup(chunk_size)
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[dscvr_cdk_macros::update(guard = $restore_guard, skip_tx_log = true)]
+        $($(#[$attr])*)?
         fn init_stable_storage(_ctx: crate::canister_context::MutableContext, len: u64) {
             $crate::interface::init_stable_storage(len);
         }
 
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::update(guard = "is_restore_service", skip_tx_log = true)]
+        #[dscvr_cdk_macros::update(guard = $restore_guard, skip_tx_log = true)]
+        $($(#[$attr])*)?
         fn restore_stable_storage(
-            _ctx: crate::canister_context::MutableContext,
+            ctx: crate::canister_context::MutableContext,
             offset: u64,
             bytes: serde_bytes::ByteBuf,
         ) {
-            $crate::interface::restore_stable_storage(offset, bytes);
+            ctx.invalidate_memoized();
+            // traps like it always did, the candid signature is relied upon by the tooling
+            if let Err(e) = $crate::interface::restore_stable_storage(ctx.system(), offset, bytes)
+            {
+                panic!("{e}");
+            }
         }
 
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::update(guard = "is_restore_service", skip_tx_log = true)]
+        #[dscvr_cdk_macros::update(guard = $restore_guard, skip_tx_log = true)]
+        $($(#[$attr])*)?
         fn restore_stable_storage_compressed(
-            _ctx: crate::canister_context::MutableContext,
+            ctx: crate::canister_context::MutableContext,
             offset: u64,
             compressed_bytes_vec: Vec<serde_bytes::ByteBuf>,
         ) {
-            $crate::interface::restore_stable_storage_compressed(offset, compressed_bytes_vec);
+            ctx.invalidate_memoized();
+            // traps like it always did, the candid signature is relied upon by the tooling
+            if let Err(e) = $crate::interface::restore_stable_storage_compressed(
+                ctx.system(),
+                offset,
+                compressed_bytes_vec,
+            ) {
+                panic!("{e}");
+            }
         }
 
         #[cfg(target_arch = "wasm32")]
-        #[dscvr_cdk_macros::update(guard = "is_restore_service", skip_tx_log = true)]
+        #[dscvr_cdk_macros::update(guard = $restore_guard, skip_tx_log = true)]
+        $($(#[$attr])*)?
         fn set_restore_from_stable_storage(
-            _ctx: crate::canister_context::MutableContext,
+            ctx: crate::canister_context::MutableContext,
             flag: bool,
         ) {
+            ctx.invalidate_memoized();
             $crate::interface::set_restore_from_stable_storage(flag);
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_format::DataFormatType;
+    use dscvr_interface::edge::Edge;
+    use flate2::write::GzEncoder;
+
+    #[test]
+    fn restore_writes_into_stable_storage() {
+        let system = Edge::default();
+        restore_stable_storage(&system, 4, ByteBuf::from(vec![9; 8])).unwrap();
+        let stable_memory = system.stable_memory().lock().unwrap();
+        assert_eq!(stable_memory[..12], [0, 0, 0, 0, 9, 9, 9, 9, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn restore_compressed_decompresses_into_stable_storage() {
+        let system = Edge::default();
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&[7; 100]).unwrap();
+        let compressed = ByteBuf::from(encoder.finish().unwrap());
+
+        restore_stable_storage_compressed(&system, 10, vec![compressed.clone(), compressed])
+            .unwrap();
+        let stable_memory = system.stable_memory().lock().unwrap();
+        assert_eq!(stable_memory[..10], [0; 10]);
+        assert_eq!(stable_memory[10..210], [7; 200]);
+    }
+
+    #[test]
+    fn schema_override_is_cleared_by_the_save_using_it() {
+        let system = Edge::default();
+        let saved_schema_version = || {
+            let stable_memory = system.stable_memory().lock().unwrap().clone();
+            Header::new_from_reader(&mut stable_memory.as_slice())
+                .unwrap()
+                .content_schema_version
+        };
+
+        set_schema_override(Some(5));
+        v2::save(&system, &1_u64, DataFormatType::MsgPack, 1).unwrap();
+        assert_eq!(saved_schema_version(), 5);
+        assert_eq!(schema_override(), None);
+
+        v2::save(&system, &1_u64, DataFormatType::MsgPack, 1).unwrap();
+        assert_eq!(saved_schema_version(), 1);
+    }
+
+    #[test]
+    fn restore_compressed_rejects_invalid_data() {
+        let system = Edge::default();
+        let invalid = ByteBuf::from(vec![1, 2, 3]);
+        assert!(restore_stable_storage_compressed(&system, 0, vec![invalid]).is_err());
+    }
+}
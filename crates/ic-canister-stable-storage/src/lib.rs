@@ -4,23 +4,30 @@
 //! The stable storage layout is the following:
 //!
 //! V2:
-//! - Header (serialized as raw binary)
-//! - Contents (serialized as bincode or msgpack)
+//! - Header (serialized as raw binary, see [`header`] for the layout)
+//! - Contents (serialized as bincode, indexed bincode or msgpack, see [`indexed`])
 //!
 //! V1:
 //! - Contents (serialized as msgpack)
+//!
+//! Backups taken by the agent additionally carry a manifest in their header (see [`manifest`])
+//! that records the canister they were taken from.
 
+pub mod certification;
 pub mod data_format;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod file_util;
 pub mod header;
+pub mod indexed;
 pub mod interface;
+pub mod manifest;
 pub mod migration;
+pub mod stable_writer;
 pub mod transient;
 pub mod v1;
 pub mod v2;
 
-pub(crate) use ic_canister_io::movable_io;
+pub(crate) use ic_canister_io::{counting_io, movable_io};
 
 /// Stable Storage Error
 #[derive(Debug, thiserror::Error)]
@@ -36,6 +43,10 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("header")]
     Header(#[from] header::Error),
+    #[error("certification")]
+    Certification(#[from] certification::Error),
+    #[error("backup taken from canister {0} cannot be restored into canister {1}")]
+    CrossCanisterRestore(candid::Principal, candid::Principal),
 }
 
 /// Size of a stable storage page
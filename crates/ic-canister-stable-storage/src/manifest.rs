@@ -0,0 +1,96 @@
+//! Manifest describing where a stable storage backup came from.
+//!
+//! The manifest is stored as a [`HeaderField`] of the backup, which makes the header use the
+//! TLV layout. Backups taken by the agent record it, and it's kept when the backup is restored
+//! so the canister knows the source of its last restore.
+
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::header::HeaderField;
+use crate::Error;
+
+/// Tag of the header field holding the manifest
+pub const MANIFEST_FIELD_TAG: u32 = 16;
+
+/// Information about the canister a backup was taken from
+#[derive(Debug, CandidType, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct BackupManifest {
+    /// Id of the canister the backup was taken from
+    pub canister_id: Option<Principal>,
+    /// Module hash of the wasm installed when the backup was taken
+    #[serde(with = "serde_bytes")]
+    pub module_hash: Vec<u8>,
+    /// Git version of the wasm installed when the backup was taken
+    pub git_version: String,
+    /// Wall-clock time in nanoseconds when the backup was taken
+    pub created_at: u64,
+}
+
+impl BackupManifest {
+    /// Return the manifest recorded in the header fields of a backup, if any
+    pub fn from_header_fields(fields: &[HeaderField]) -> Result<Option<Self>, Error> {
+        HeaderField::find(fields, MANIFEST_FIELD_TAG)
+            .map(rmp_serde::from_slice)
+            .transpose()
+            .map_err(Error::from)
+    }
+
+    /// Record the manifest in the header fields of a backup, replacing any previous one
+    pub fn set_in_header_fields(&self, fields: &mut Vec<HeaderField>) -> Result<(), Error> {
+        HeaderField::set(fields, MANIFEST_FIELD_TAG, rmp_serde::to_vec_named(self)?);
+        Ok(())
+    }
+
+    /// Check whether the backup can be restored into `target`.
+    ///
+    /// Restoring into a different canister than the one the backup was taken from
+    /// is refused unless `allow_cross_canister` is set.
+    pub fn check_restore_target(
+        &self,
+        target: &Principal,
+        allow_cross_canister: bool,
+    ) -> Result<(), Error> {
+        match self.canister_id {
+            Some(source) if source != *target && !allow_cross_canister => {
+                Err(Error::CrossCanisterRestore(source, *target))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_field_roundtrip() {
+        let manifest = BackupManifest {
+            canister_id: Some(Principal::management_canister()),
+            module_hash: vec![1, 2, 3],
+            git_version: "abc".to_string(),
+            created_at: 10,
+        };
+        let mut fields = vec![];
+        assert_eq!(BackupManifest::from_header_fields(&fields).unwrap(), None);
+
+        manifest.set_in_header_fields(&mut fields).unwrap();
+        manifest.set_in_header_fields(&mut fields).unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(
+            BackupManifest::from_header_fields(&fields).unwrap(),
+            Some(manifest.clone())
+        );
+
+        assert!(manifest
+            .check_restore_target(&Principal::management_canister(), false)
+            .is_ok());
+        assert!(manifest
+            .check_restore_target(&Principal::anonymous(), false)
+            .is_err());
+        assert!(manifest
+            .check_restore_target(&Principal::anonymous(), true)
+            .is_ok());
+    }
+}
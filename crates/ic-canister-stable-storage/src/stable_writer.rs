@@ -0,0 +1,114 @@
+//! Writer that forwards borrowed slices straight to stable memory through the `Interface`.
+
+use dscvr_interface::{Interface, STABLE_PAGE_SIZE_IN_BYTES};
+use std::io::{Seek, SeekFrom, Write};
+
+/// Minimum number of pages stable memory is grown by when a write exceeds its size
+const GROW_CHUNK_PAGES: u64 = 16;
+
+/// Writer over stable memory that hands every written slice to
+/// `Interface::stable_write_chunked` without buffering or copying it
+pub struct ChunkedStableWriter<'a> {
+    /// The system interface
+    interface: &'a dyn Interface,
+    /// The current offset into stable memory
+    offset: u64,
+    /// The size of stable memory in pages
+    capacity: u64,
+}
+
+impl<'a> ChunkedStableWriter<'a> {
+    /// Create a new writer starting at the beginning of stable memory
+    #[inline]
+    pub fn new(interface: &'a dyn Interface) -> Self {
+        Self::new_at(interface, 0)
+    }
+
+    /// Create a new writer starting at `offset`
+    #[inline]
+    pub fn new_at(interface: &'a dyn Interface, offset: u64) -> Self {
+        Self {
+            interface,
+            offset,
+            capacity: interface.stable64_size(),
+        }
+    }
+
+    /// The current offset into stable memory
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Grow stable memory in chunks of at least `GROW_CHUNK_PAGES` so that it holds `end`
+    /// bytes
+    fn reserve(&mut self, end: u64) -> std::io::Result<()> {
+        let required = end.div_ceil(STABLE_PAGE_SIZE_IN_BYTES as u64);
+        if required <= self.capacity {
+            return Ok(());
+        }
+        let new_pages = (required - self.capacity).max(GROW_CHUNK_PAGES);
+        self.interface
+            .stable64_grow(new_pages)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::OutOfMemory, format!("{e:?}")))?;
+        self.capacity += new_pages;
+        Ok(())
+    }
+}
+
+impl Write for ChunkedStableWriter<'_> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.reserve(self.offset + buf.len() as u64)?;
+        self.interface.stable_write_chunked(self.offset, buf);
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ChunkedStableWriter<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => {
+                (self.capacity * STABLE_PAGE_SIZE_IN_BYTES as u64).checked_add_signed(delta)
+            }
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+        };
+        self.offset = offset.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dscvr_interface::edge::Edge;
+
+    #[test]
+    fn writes_grow_stable_memory_in_chunks() {
+        let system = Edge::default();
+        let mut writer = ChunkedStableWriter::new(&system);
+        writer.write_all(&[1; 10]).unwrap();
+        assert_eq!(system.stable64_size(), GROW_CHUNK_PAGES);
+
+        let end = GROW_CHUNK_PAGES * STABLE_PAGE_SIZE_IN_BYTES as u64;
+        writer.seek(SeekFrom::Start(end - 5)).unwrap();
+        writer.write_all(&[2; 10]).unwrap();
+        assert_eq!(system.stable64_size(), 2 * GROW_CHUNK_PAGES);
+
+        let stable_memory = system.stable_memory().lock().unwrap();
+        assert_eq!(stable_memory[..10], [1; 10]);
+        assert_eq!(stable_memory[end as usize - 5..end as usize + 5], [2; 10]);
+    }
+}
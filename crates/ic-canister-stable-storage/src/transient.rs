@@ -1,8 +1,18 @@
 //! State related to stable storage, but that isn't persisted.
+//!
+//! The restore related fields are the exception, they're kept in the header (see
+//! [`Transient::header_fields`]) so they survive upgrades.
 
 use candid::{CandidType, Deserialize};
 use serde::Serialize;
 
+use crate::header::HeaderField;
+use crate::manifest::BackupManifest;
+use crate::Error;
+
+/// Tag of the header field set while a restore is in progress
+pub const RESTORE_IN_PROGRESS_FIELD_TAG: u32 = 17;
+
 /// Transient information related to stable storage
 #[derive(Debug, CandidType, Serialize, Deserialize, Default, Clone)]
 pub struct Transient {
@@ -10,4 +20,62 @@ pub struct Transient {
     pub skip_next_save: bool,
     /// Number of instructions used for post-upgrade
     pub post_upgrade_instruction_count: u64,
+    /// Set while a restore is writing into stable storage
+    pub restore_in_progress: bool,
+    /// Manifest of the backup that was last restored into stable storage
+    pub last_restore_source: Option<BackupManifest>,
+    /// When set, overrides the schema version recorded on the next save
+    pub schema_override: Option<u64>,
+}
+
+impl Transient {
+    /// Return the header fields persisting the restore related fields.
+    /// Fields are only returned when set, so the header keeps the legacy layout otherwise.
+    pub fn header_fields(&self) -> Result<Vec<HeaderField>, Error> {
+        let mut fields = vec![];
+        if self.restore_in_progress {
+            HeaderField::set(&mut fields, RESTORE_IN_PROGRESS_FIELD_TAG, vec![1]);
+        }
+        if let Some(manifest) = self.last_restore_source.as_ref() {
+            manifest.set_in_header_fields(&mut fields)?;
+        }
+        Ok(fields)
+    }
+
+    /// Create the transient information from the restore related header fields
+    pub fn new_from_header_fields(fields: &[HeaderField]) -> Result<Self, Error> {
+        Ok(Self {
+            restore_in_progress: HeaderField::find(fields, RESTORE_IN_PROGRESS_FIELD_TAG)
+                .is_some_and(|value| value == [1]),
+            last_restore_source: BackupManifest::from_header_fields(fields)?,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_fields_roundtrip() {
+        assert!(Transient::default().header_fields().unwrap().is_empty());
+
+        let transient = Transient {
+            skip_next_save: true,
+            restore_in_progress: true,
+            last_restore_source: Some(BackupManifest {
+                git_version: "abc".to_string(),
+                ..Default::default()
+            }),
+            schema_override: Some(2),
+            ..Default::default()
+        };
+        let roundtrip =
+            Transient::new_from_header_fields(&transient.header_fields().unwrap()).unwrap();
+        assert!(!roundtrip.skip_next_save);
+        assert!(roundtrip.restore_in_progress);
+        assert_eq!(roundtrip.last_restore_source, transient.last_restore_source);
+        assert_eq!(roundtrip.schema_override, None);
+    }
 }
@@ -4,7 +4,8 @@ use dscvr_interface::Interface;
 use std::io::{Read, Seek, Write};
 use tracing::info;
 
-use super::movable_io::{MovableReader, MovableWriter};
+use super::counting_io::CountingReader;
+use super::movable_io::MovableWriter;
 use crate::data_format::DataFormatType;
 use crate::data_format::{MsgPackAdapter, SerdeDataFormat};
 use crate::header::Header;
@@ -29,7 +30,7 @@ where
 
 /// Deserialize using v1 layout
 #[tracing::instrument(skip(reader, system))]
-pub fn restore<R: Read + Seek, T>(
+pub fn restore<R: Read, T>(
     system: &dyn Interface,
     reader: &mut R,
 ) -> Result<(Header, Transient, T), Error>
@@ -37,9 +38,10 @@ where
     MsgPackAdapter: SerdeDataFormat,
     T: for<'a> serde::Deserialize<'a>,
 {
-    let t: T = MsgPackAdapter::deserialize(MovableReader::new(reader))?;
+    let mut reader = CountingReader::new(reader);
+    let t: T = MsgPackAdapter::deserialize(&mut reader)?;
     let header = Header {
-        content_length: reader.stream_position()?,
+        content_length: reader.count(),
         content_format: DataFormatType::MsgPack,
         ..Default::default()
     };
@@ -7,12 +7,13 @@ use tracing::info;
 use tracing::warn;
 
 use super::data_format::{BincodeAdapter, MsgPackAdapter, SerdeDataFormat};
-use super::header::Header;
+use super::header::{Header, HeaderField};
 use super::movable_io::{MovableReader, MovableWriter};
 use super::transient::Transient;
 use super::Error;
 use crate::data_format::DataFormatType;
 use crate::header;
+use crate::indexed::IndexedBincodeAdapter;
 use crate::migration::set_stored_schema_version;
 
 /// Serialize using v2 layout
@@ -35,6 +36,8 @@ where
         info!("Starting save");
 
         // write the contents first
+        let fields = transient.header_fields()?;
+        header.header_length = header.num_field_words(&fields);
         let header_len = header.num_all_fields_bytes();
         let start_pos = writer.stream_position()?;
 
@@ -49,6 +52,9 @@ where
             DataFormatType::Bincode => {
                 BincodeAdapter::serialize(MovableWriter::new(writer), t)?;
             }
+            DataFormatType::IndexedBincode => {
+                IndexedBincodeAdapter::serialize_seekable(MovableWriter::new(writer), t)?;
+            }
             _ => {
                 return Err(
                     header::Error::InvalidContentFormat(header.content_format as u64).into(),
@@ -64,7 +70,7 @@ where
 
         // save header
         writer.seek(SeekFrom::Start(start_pos))?;
-        header.write(writer)?;
+        header.write_with_fields(&fields, writer)?;
 
         info!(
             "finished inst_count={} memory_usage={}",
@@ -86,7 +92,20 @@ where
 {
     info!("started inst_count={}", interface.instruction_counter());
 
-    let header = Header::new_from_reader(reader)?;
+    let (header, fields) = Header::new_from_reader_with_fields(reader)?;
+    restore_content(interface, reader, header, &fields)
+}
+
+/// Deserialize the content following an already parsed `header` and its `fields`
+pub(crate) fn restore_content<R: Read + Seek, T>(
+    interface: &dyn Interface,
+    reader: &mut R,
+    header: Header,
+    fields: &[HeaderField],
+) -> Result<(Header, Transient, T), Error>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
     info!(
         "read header schema_version={}",
         header.content_schema_version
@@ -99,6 +118,9 @@ where
     let t: T = match header.content_format {
         DataFormatType::MsgPack => MsgPackAdapter::deserialize(MovableReader::new(reader))?,
         DataFormatType::Bincode => BincodeAdapter::deserialize(MovableReader::new(reader))?,
+        DataFormatType::IndexedBincode => {
+            IndexedBincodeAdapter::deserialize_seekable(MovableReader::new(reader))?
+        }
         _ => {
             return Err(header::Error::InvalidContentFormat(header.content_format as u64).into());
         }
@@ -116,7 +138,7 @@ where
     let count = interface.instruction_counter();
     let transient = Transient {
         post_upgrade_instruction_count: count,
-        ..Default::default()
+        ..Transient::new_from_header_fields(fields)?
     };
     info!(
         "finished inst_count={} memory_usage={}",
@@ -0,0 +1,112 @@
+//! Management of the controllers of a canister through the management canister
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_agent::{export::Principal, Agent, AgentError};
+use instrumented_error::{ErrorKind, Result, ResultExt};
+
+#[derive(CandidType)]
+struct CanisterIdRecord {
+    canister_id: Principal,
+}
+
+#[derive(CandidType)]
+struct CanisterSettings {
+    controllers: Option<Vec<Principal>>,
+}
+
+#[derive(CandidType)]
+struct UpdateSettingsArgument {
+    canister_id: Principal,
+    settings: CanisterSettings,
+}
+
+/// Subset of the `canister_status` result, the other fields are skipped while decoding
+#[derive(CandidType, Deserialize)]
+struct CanisterStatusResult {
+    settings: DefiniteCanisterSettings,
+}
+
+#[derive(CandidType, Deserialize)]
+struct DefiniteCanisterSettings {
+    controllers: Vec<Principal>,
+}
+
+fn categorized<T>(result: std::result::Result<T, AgentError>) -> Result<T> {
+    result.map_err(|e| {
+        let kind = match &e {
+            AgentError::TransportError(_) => ErrorKind::Network,
+            AgentError::CertifiedReject(_) => ErrorKind::CanisterReject,
+            _ => ErrorKind::Internal,
+        };
+        instrumented_error::Error::from(e).with_kind(kind)
+    })
+}
+
+/// Return the controllers of `canister_id`. The identity of `agent` must be a controller.
+#[tracing::instrument(skip(agent))]
+pub async fn canister_controllers(agent: &Agent, canister_id: Principal) -> Result<Vec<Principal>> {
+    let bytes = categorized(
+        agent
+            .update(&Principal::management_canister(), "canister_status")
+            .with_effective_canister_id(canister_id)
+            .with_arg(Encode!(&CanisterIdRecord { canister_id }).with_kind(ErrorKind::Internal)?)
+            .call_and_wait()
+            .await,
+    )?;
+    let status = Decode!(&bytes, CanisterStatusResult).with_kind(ErrorKind::Decode)?;
+    Ok(status.settings.controllers)
+}
+
+/// Replace the controllers of `canister_id` with `controllers`
+#[tracing::instrument(skip(agent))]
+pub async fn set_canister_controllers(
+    agent: &Agent,
+    canister_id: Principal,
+    controllers: Vec<Principal>,
+) -> Result<()> {
+    let argument = UpdateSettingsArgument {
+        canister_id,
+        settings: CanisterSettings {
+            controllers: Some(controllers),
+        },
+    };
+    categorized(
+        agent
+            .update(&Principal::management_canister(), "update_settings")
+            .with_effective_canister_id(canister_id)
+            .with_arg(Encode!(&argument).with_kind(ErrorKind::Internal)?)
+            .call_and_wait()
+            .await,
+    )?;
+    Ok(())
+}
+
+/// Add `controller` to the controllers of `canister_id` if missing and return the controllers
+#[tracing::instrument(skip(agent))]
+pub async fn add_canister_controller(
+    agent: &Agent,
+    canister_id: Principal,
+    controller: Principal,
+) -> Result<Vec<Principal>> {
+    let mut controllers = canister_controllers(agent, canister_id).await?;
+    if !controllers.contains(&controller) {
+        controllers.push(controller);
+        set_canister_controllers(agent, canister_id, controllers.clone()).await?;
+    }
+    Ok(controllers)
+}
+
+/// Remove `controller` from the controllers of `canister_id` and return the controllers
+#[tracing::instrument(skip(agent))]
+pub async fn remove_canister_controller(
+    agent: &Agent,
+    canister_id: Principal,
+    controller: Principal,
+) -> Result<Vec<Principal>> {
+    let mut controllers = canister_controllers(agent, canister_id).await?;
+    if controllers.contains(&controller) {
+        controllers.retain(|c| *c != controller);
+        set_canister_controllers(agent, canister_id, controllers.clone()).await?;
+    }
+    Ok(controllers)
+}
@@ -0,0 +1,175 @@
+//! Delegations from a parent key to a short lived session key, e.g. for service identities
+//! or to exercise the ingress validator.
+//!
+//! Chains are serialized in the JSON format of agent-js and Internet Identity, so they can be
+//! exchanged with other tooling.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ic_agent::{
+    export::Principal,
+    identity::{DelegatedIdentity, Delegation, SignedDelegation},
+    Identity,
+};
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+
+/// Delegations from the key `public_key` to the session key of the last delegation
+#[derive(Debug, Clone)]
+pub struct DelegationChain {
+    /// DER encoded public key of the root of the chain
+    pub public_key: Vec<u8>,
+    /// Delegations from the root, each signed by the key delegated to by the previous one
+    pub delegations: Vec<SignedDelegation>,
+}
+
+/// Sign a delegation from `parent` to `session_public_key`, valid for `ttl` and restricted to
+/// `targets` if set, extending the delegation chain of `parent` if it's itself delegated
+#[tracing::instrument(skip(parent))]
+pub fn delegate(
+    parent: &dyn Identity,
+    session_public_key: Vec<u8>,
+    ttl: Duration,
+    targets: Option<Vec<Principal>>,
+) -> Result<DelegationChain> {
+    let expiration = (SystemTime::now() + ttl)
+        .duration_since(UNIX_EPOCH)
+        .map(|expiration| expiration.as_nanos() as u64)?;
+    let delegation = Delegation {
+        pubkey: session_public_key,
+        expiration,
+        targets,
+    };
+    let signature = parent
+        .sign_delegation(&delegation)
+        .map_err(|e| e.into_instrumented_error().with_kind(ErrorKind::Auth))?;
+
+    let (public_key, mut delegations) = match signature.delegations {
+        // A delegated parent signs with its session key, under its own chain
+        Some(delegations) => (signature.public_key, delegations),
+        None => (parent.public_key(), vec![]),
+    };
+    let Some(public_key) = public_key else {
+        return Err("the parent identity has no public key"
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Auth));
+    };
+    let Some(signature) = signature.signature else {
+        return Err("the parent identity can't sign delegations"
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Auth));
+    };
+    delegations.push(SignedDelegation {
+        delegation,
+        signature,
+    });
+    Ok(DelegationChain {
+        public_key,
+        delegations,
+    })
+}
+
+/// Delegate from `parent` to `session` and return the identity signing with `session` on
+/// behalf of `parent`, see [`delegate`]
+pub fn create_delegated_identity(
+    parent: &dyn Identity,
+    session: Box<dyn Identity>,
+    ttl: Duration,
+    targets: Option<Vec<Principal>>,
+) -> Result<DelegatedIdentity> {
+    let Some(session_public_key) = session.public_key() else {
+        return Err("the session identity has no public key"
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Auth));
+    };
+    delegate(parent, session_public_key, ttl, targets)?.into_identity(session)
+}
+
+impl DelegationChain {
+    /// Return the identity signing with `session`, the key of the last delegation, on behalf
+    /// of the root of the chain
+    pub fn into_identity(self, session: Box<dyn Identity>) -> Result<DelegatedIdentity> {
+        DelegatedIdentity::new(self.public_key, session, self.delegations)
+            .with_kind(ErrorKind::Auth)
+    }
+
+    /// Serialize the chain to the JSON format of agent-js
+    pub fn to_json(&self) -> String {
+        let chain = JsonChain {
+            public_key: hex::encode(&self.public_key),
+            delegations: self
+                .delegations
+                .iter()
+                .map(|signed| JsonSignedDelegation {
+                    delegation: JsonDelegation {
+                        pubkey: hex::encode(&signed.delegation.pubkey),
+                        expiration: format!("{:x}", signed.delegation.expiration),
+                        targets: signed.delegation.targets.as_ref().map(|targets| {
+                            targets
+                                .iter()
+                                .map(|target| hex::encode(target.as_slice()))
+                                .collect()
+                        }),
+                    },
+                    signature: hex::encode(&signed.signature),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&chain).expect("the chain is serializable")
+    }
+
+    /// Parse a chain in the JSON format of agent-js
+    pub fn from_json(json: &str) -> Result<Self> {
+        let chain: JsonChain = serde_json::from_str(json).with_kind(ErrorKind::Decode)?;
+        let delegations = chain
+            .delegations
+            .into_iter()
+            .map(|signed| {
+                let targets = signed
+                    .delegation
+                    .targets
+                    .map(|targets| {
+                        targets
+                            .iter()
+                            .map(|target| Ok(Principal::try_from_slice(&hex::decode(target)?)?))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                    .transpose()?;
+                Ok(SignedDelegation {
+                    delegation: Delegation {
+                        pubkey: hex::decode(signed.delegation.pubkey)?,
+                        expiration: u64::from_str_radix(&signed.delegation.expiration, 16)?,
+                        targets,
+                    },
+                    signature: hex::decode(signed.signature)?,
+                })
+            })
+            .collect::<Result<_>>()
+            .with_kind(ErrorKind::Decode)?;
+        Ok(Self {
+            public_key: hex::decode(chain.public_key).with_kind(ErrorKind::Decode)?,
+            delegations,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonChain {
+    delegations: Vec<JsonSignedDelegation>,
+    public_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonSignedDelegation {
+    delegation: JsonDelegation,
+    signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonDelegation {
+    pubkey: String,
+    expiration: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    targets: Option<Vec<String>>,
+}
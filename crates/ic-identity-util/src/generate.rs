@@ -0,0 +1,87 @@
+//! Generation of new identities persisted to pem files, in the formats written by dfx
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use ic_agent::export::Principal;
+use instrumented_error::{ErrorKind, Result, ResultExt};
+use pkcs8::{der::pem, LineEnding};
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::Ed25519KeyPair,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{principal_from_pem, IdentityFromFile, IdentitySource};
+
+/// Type of the key of an identity, e.g. to generate
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    /// Ed25519 key, written as a PKCS#8 `PRIVATE KEY`
+    #[default]
+    Ed25519,
+    /// secp256k1 key, written as a SEC1 `EC PRIVATE KEY`
+    Secp256k1,
+    /// prime256v1 (P-256) key, written as a SEC1 `EC PRIVATE KEY`
+    Prime256v1,
+}
+
+/// Generate a new key, write it to the pem file `path`, only readable by the current user,
+/// and return the identity with its principal. Fails if the file already exists.
+#[tracing::instrument]
+pub fn generate_identity_to_pem(
+    path: &Path,
+    key_type: KeyType,
+) -> Result<(IdentityFromFile, Principal)> {
+    let rng = SystemRandom::new();
+    let pem = match key_type {
+        KeyType::Ed25519 => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)?;
+            pem::encode_string("PRIVATE KEY", LineEnding::LF, pkcs8.as_ref())
+                .with_kind(ErrorKind::Decode)?
+        }
+        KeyType::Secp256k1 => {
+            let key = loop {
+                let mut bytes = [0u8; 32];
+                rng.fill(&mut bytes)?;
+                // Out of range scalars are astronomically unlikely, draw again if one comes up
+                if let Ok(key) = k256::SecretKey::from_slice(&bytes) {
+                    break key;
+                }
+            };
+            key.to_sec1_pem(LineEnding::LF)
+                .with_kind(ErrorKind::Decode)?
+                .to_string()
+        }
+        KeyType::Prime256v1 => {
+            let key = loop {
+                let mut bytes = [0u8; 32];
+                rng.fill(&mut bytes)?;
+                if let Ok(key) = p256::SecretKey::from_slice(&bytes) {
+                    break key;
+                }
+            };
+            key.to_sec1_pem(LineEnding::LF)
+                .with_kind(ErrorKind::Decode)?
+                .to_string()
+        }
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_field("path", parent.display())?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(pem.as_bytes()))
+        .with_field("path", path.display())?;
+
+    let principal = principal_from_pem(path)?;
+    Ok((
+        IdentityFromFile(IdentitySource::Pem(path.to_path_buf(), None)),
+        principal,
+    ))
+}
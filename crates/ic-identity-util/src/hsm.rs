@@ -0,0 +1,69 @@
+//! Identities backed by a key stored in a PKCS#11 token, e.g. a YubiHSM, so the key never
+//! leaves the device
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use ic_agent::Identity;
+use ic_identity_hsm::HardwareIdentity;
+use instrumented_error::{ErrorKind, Result, ResultExt};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable holding the PIN of the token when it isn't set in the config
+pub const DEFAULT_PIN_ENV: &str = "HSM_PIN";
+
+fn default_pin_env() -> String {
+    DEFAULT_PIN_ENV.to_owned()
+}
+
+/// Location of a key in a PKCS#11 token and how to unlock it
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HsmIdentityConfig {
+    /// Path of the PKCS#11 module of the token, e.g. `/usr/lib/libyubihsm_pkcs11.so`
+    pub pkcs11_lib_path: PathBuf,
+    /// Index of the slot holding the token
+    pub slot_index: usize,
+    /// Id of the key in the token, in hex
+    pub key_id: String,
+    /// PIN of the token, read from `pin_env` when not set. Never serialized.
+    #[serde(default, skip_serializing)]
+    pub pin: Option<String>,
+    /// Environment variable holding the PIN
+    #[serde(default = "default_pin_env")]
+    pub pin_env: String,
+}
+
+impl fmt::Debug for HsmIdentityConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HsmIdentityConfig")
+            .field("pkcs11_lib_path", &self.pkcs11_lib_path)
+            .field("slot_index", &self.slot_index)
+            .field("key_id", &self.key_id)
+            .field("pin", &self.pin.as_ref().map(|_| "<redacted>"))
+            .field("pin_env", &self.pin_env)
+            .finish()
+    }
+}
+
+impl HsmIdentityConfig {
+    /// Open a session on the token and return an identity signing with the key
+    #[tracing::instrument]
+    pub fn identity(&self) -> Result<Arc<dyn Identity>> {
+        let pin = self.pin.clone();
+        let pin_env = self.pin_env.clone();
+        let identity = HardwareIdentity::new(
+            &self.pkcs11_lib_path,
+            self.slot_index,
+            &self.key_id,
+            move || match pin {
+                Some(pin) => Ok(pin),
+                None => std::env::var(&pin_env)
+                    .map_err(|_| format!("the token PIN is not set in {pin_env}")),
+            },
+        )
+        .with_kind(ErrorKind::Auth)
+        .with_field("pkcs11_lib_path", self.pkcs11_lib_path.display())
+        .with_field("slot_index", self.slot_index)
+        .with_field("key_id", &self.key_id)?;
+        Ok(Arc::new(identity))
+    }
+}
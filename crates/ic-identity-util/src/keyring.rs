@@ -0,0 +1,70 @@
+//! Identities stored in the OS keyring, i.e. the macOS Keychain, the Secret Service (libsecret)
+//! or the Windows Credential Manager, so key material doesn't need to live next to the repo.
+//!
+//! Entries hold the PEM of the key under the service [`KEYRING_SERVICE`] and a label
+//! referenced from the config.
+
+use std::{path::Path, sync::Arc};
+
+use ic_agent::{export::Principal, Identity};
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+use keyring::Entry;
+
+use crate::{identity_from_pem, IdentityFromFile};
+
+/// Service of the keyring entries holding identities
+pub const KEYRING_SERVICE: &str = "dscvr-identity";
+
+fn entry(label: &str) -> Result<Entry> {
+    Entry::new(KEYRING_SERVICE, label)
+        .with_kind(ErrorKind::Config)
+        .with_field("label", label)
+}
+
+/// Create an identity from the pem stored in the keyring under `label`
+#[tracing::instrument]
+pub fn create_identity_from_keyring(label: &str) -> Result<Arc<dyn Identity>> {
+    let pem = match entry(label)?.get_password() {
+        Ok(pem) => pem,
+        Err(keyring::Error::NoEntry) => {
+            return Err(format!("no identity is stored in the keyring as {label}")
+                .into_instrumented_error()
+                .with_kind(ErrorKind::Config))
+        }
+        Err(e) => return Err(e.into_instrumented_error().with_field("label", label)),
+    };
+    identity_from_pem(&pem).with_field("label", label)
+}
+
+/// Store the unencrypted `pem` in the keyring under `label`, replacing any identity already
+/// stored there, and return its principal
+#[tracing::instrument(skip(pem))]
+pub fn store_identity_in_keyring(label: &str, pem: &str) -> Result<Principal> {
+    let principal = identity_from_pem(pem)?
+        .sender()
+        .map_err(|e| e.into_instrumented_error())?;
+    entry(label)?.set_password(pem).with_field("label", label)?;
+    Ok(principal)
+}
+
+/// Copy the identity of the unencrypted pem file `pem_file` to the keyring under `label` and
+/// return the identity referencing it with its principal. The file is left in place.
+#[tracing::instrument]
+pub fn import_pem_to_keyring(
+    label: &str,
+    pem_file: &Path,
+) -> Result<(IdentityFromFile, Principal)> {
+    let pem =
+        std::fs::read_to_string(pem_file).with_sensitive_field("pem_file", pem_file.display())?;
+    let principal = store_identity_in_keyring(label, &pem)?;
+    Ok((IdentityFromFile::from_keyring(label), principal))
+}
+
+/// Remove the identity stored in the keyring under `label`, if any
+#[tracing::instrument]
+pub fn delete_identity_from_keyring(label: &str) -> Result<()> {
+    match entry(label)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into_instrumented_error().with_field("label", label)),
+    }
+}
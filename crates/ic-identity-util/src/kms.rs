@@ -0,0 +1,172 @@
+//! Identities signing with an asymmetric key of a cloud KMS, so the private key never exists
+//! outside of the KMS, e.g. for deploy pipelines acting as controllers.
+//!
+//! Both secp256k1 and P-256 keys are supported, the curve is detected from the public key,
+//! which is fetched once when the identity is created.
+
+use std::{future::Future, sync::Arc};
+
+use ic_agent::{
+    agent::EnvelopeContent,
+    export::Principal,
+    identity::{Delegation, Signature},
+    Identity,
+};
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+use p256::pkcs8::DecodePublicKey;
+use sha2::{Digest, Sha256};
+use tokio::runtime::{Builder, Runtime};
+
+#[cfg(feature = "aws-kms")]
+mod aws;
+#[cfg(feature = "gcp-kms")]
+mod gcp;
+
+#[cfg(feature = "aws-kms")]
+pub use aws::AwsKmsSigner;
+#[cfg(feature = "gcp-kms")]
+pub use gcp::GcpKmsSigner;
+
+/// Asymmetric signing key of a KMS
+#[async_trait::async_trait]
+pub trait KmsSigner: Send + Sync + 'static {
+    /// Return the DER encoded SubjectPublicKeyInfo of the key
+    async fn public_key_der(&self) -> Result<Vec<u8>>;
+
+    /// Sign the SHA-256 `digest`, returning the DER encoded ECDSA signature
+    async fn sign_digest(&self, digest: [u8; 32]) -> Result<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Curve {
+    Secp256k1,
+    P256,
+}
+
+/// Identity signing with a [`KmsSigner`]
+pub struct KmsIdentity {
+    signer: Arc<dyn KmsSigner>,
+    runtime: KmsRuntime,
+    public_key: Vec<u8>,
+    principal: Principal,
+    curve: Curve,
+}
+
+impl KmsIdentity {
+    /// Create an identity signing with `signer`, fetching its public key
+    #[tracing::instrument(skip(signer))]
+    pub fn new(signer: impl KmsSigner) -> Result<Self> {
+        let signer: Arc<dyn KmsSigner> = Arc::new(signer);
+        let runtime = KmsRuntime::new()?;
+        let public_key = {
+            let signer = signer.clone();
+            runtime.block_on(async move { signer.public_key_der().await })?
+        };
+        let curve = if k256::PublicKey::from_public_key_der(&public_key).is_ok() {
+            Curve::Secp256k1
+        } else if p256::PublicKey::from_public_key_der(&public_key).is_ok() {
+            Curve::P256
+        } else {
+            return Err("the KMS key is neither a secp256k1 nor a P-256 key"
+                .into_instrumented_error()
+                .with_kind(ErrorKind::Config));
+        };
+        Ok(Self {
+            signer,
+            runtime,
+            principal: Principal::self_authenticating(&public_key),
+            public_key,
+            curve,
+        })
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Signature> {
+        let digest = Sha256::digest(message).into();
+        let signer = self.signer.clone();
+        let der = self
+            .runtime
+            .block_on(async move { signer.sign_digest(digest).await })?;
+
+        // The IC expects the raw `r || s` encoding with a low `s`
+        let signature = match self.curve {
+            Curve::Secp256k1 => {
+                let signature =
+                    k256::ecdsa::Signature::from_der(&der).with_kind(ErrorKind::Decode)?;
+                signature
+                    .normalize_s()
+                    .unwrap_or(signature)
+                    .to_bytes()
+                    .to_vec()
+            }
+            Curve::P256 => {
+                let signature =
+                    p256::ecdsa::Signature::from_der(&der).with_kind(ErrorKind::Decode)?;
+                signature
+                    .normalize_s()
+                    .unwrap_or(signature)
+                    .to_bytes()
+                    .to_vec()
+            }
+        };
+        Ok(Signature {
+            public_key: Some(self.public_key.clone()),
+            signature: Some(signature),
+            delegations: None,
+        })
+    }
+}
+
+// `Identity::sign` is blocking, so the KMS calls run on a runtime of their own, which also
+// works when signing from within the runtime of the application
+struct KmsRuntime(Option<Runtime>);
+
+impl KmsRuntime {
+    fn new() -> Result<Self> {
+        Ok(Self(Some(
+            Builder::new_current_thread().enable_all().build()?,
+        )))
+    }
+
+    // Runs `future` to completion from a scoped thread, since the calling thread may already
+    // be driving a runtime
+    fn block_on<T: Send>(&self, future: impl Future<Output = Result<T>> + Send) -> Result<T> {
+        let runtime = self.0.as_ref().expect("the runtime is only taken on drop");
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| runtime.block_on(future))
+                .join()
+                .unwrap_or_else(|_| Err("the KMS call panicked".into_instrumented_error()))
+        })
+    }
+}
+
+impl Drop for KmsRuntime {
+    // Dropping a runtime blocks, which panics within the runtime of the application
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl Identity for KmsIdentity {
+    fn sender(&self) -> std::result::Result<Principal, String> {
+        Ok(self.principal)
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Some(self.public_key.clone())
+    }
+
+    fn sign(&self, content: &EnvelopeContent) -> std::result::Result<Signature, String> {
+        self.sign_arbitrary(&content.to_request_id().signable())
+    }
+
+    fn sign_delegation(&self, content: &Delegation) -> std::result::Result<Signature, String> {
+        self.sign_arbitrary(&content.signable())
+    }
+
+    fn sign_arbitrary(&self, content: &[u8]) -> std::result::Result<Signature, String> {
+        self.sign_message(content).map_err(|e| e.to_string())
+    }
+}
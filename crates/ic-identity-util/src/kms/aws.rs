@@ -0,0 +1,72 @@
+//! Signing with an `ECC_SECG_P256K1` or `ECC_NIST_P256` key of AWS KMS
+
+use aws_sdk_kms::{
+    primitives::Blob,
+    types::{MessageType, SigningAlgorithmSpec},
+    Client,
+};
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+
+use super::KmsSigner;
+
+/// Key of AWS KMS, authenticated with the default AWS credential chain
+pub struct AwsKmsSigner {
+    client: Client,
+    key_id: String,
+}
+
+impl AwsKmsSigner {
+    /// Create a signer with the key `key_id`, an id, ARN or alias of the key
+    pub async fn new(key_id: impl Into<String>) -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Self::with_client(Client::new(&config), key_id)
+    }
+
+    /// Create a signer with the key `key_id` using `client`
+    pub fn with_client(client: Client, key_id: impl Into<String>) -> Self {
+        Self {
+            client,
+            key_id: key_id.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl KmsSigner for AwsKmsSigner {
+    async fn public_key_der(&self) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .get_public_key()
+            .key_id(&self.key_id)
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)
+            .with_field("key_id", &self.key_id)?;
+        match output.public_key {
+            Some(public_key) => Ok(public_key.into_inner()),
+            None => Err("AWS KMS returned no public key"
+                .into_instrumented_error()
+                .with_field("key_id", &self.key_id)),
+        }
+    }
+
+    async fn sign_digest(&self, digest: [u8; 32]) -> Result<Vec<u8>> {
+        let output = self
+            .client
+            .sign()
+            .key_id(&self.key_id)
+            .message(Blob::new(digest))
+            .message_type(MessageType::Digest)
+            .signing_algorithm(SigningAlgorithmSpec::EcdsaSha256)
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)
+            .with_field("key_id", &self.key_id)?;
+        match output.signature {
+            Some(signature) => Ok(signature.into_inner()),
+            None => Err("AWS KMS returned no signature"
+                .into_instrumented_error()
+                .with_field("key_id", &self.key_id)),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Signing with an `EC_SIGN_SECP256K1_SHA256` or `EC_SIGN_P256_SHA256` key of GCP Cloud KMS
+
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gcp_auth::TokenProvider;
+use instrumented_error::{ErrorKind, Result, ResultExt};
+use pkcs8::der::Document;
+use serde::Deserialize;
+
+use super::KmsSigner;
+
+const CLOUD_KMS_URL: &str = "https://cloudkms.googleapis.com/v1";
+const CLOUD_KMS_SCOPE: &str = "https://www.googleapis.com/auth/cloudkms";
+
+/// Key version of GCP Cloud KMS, authenticated with the application default credentials
+pub struct GcpKmsSigner {
+    client: reqwest::Client,
+    auth: Arc<dyn TokenProvider>,
+    key_version: String,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyResponse {
+    pem: String,
+}
+
+#[derive(Deserialize)]
+struct AsymmetricSignResponse {
+    signature: String,
+}
+
+impl GcpKmsSigner {
+    /// Create a signer with the key version `key_version`, i.e.
+    /// `projects/../locations/../keyRings/../cryptoKeys/../cryptoKeyVersions/..`
+    pub async fn new(key_version: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::new(),
+            auth: gcp_auth::provider().await.with_kind(ErrorKind::Auth)?,
+            key_version: key_version.into(),
+        })
+    }
+
+    async fn request(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let token = self
+            .auth
+            .token(&[CLOUD_KMS_SCOPE])
+            .await
+            .with_kind(ErrorKind::Auth)?;
+        request
+            .bearer_auth(token.as_str())
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .error_for_status()
+            .with_kind(ErrorKind::Network)
+            .with_field("key_version", &self.key_version)
+    }
+}
+
+#[async_trait::async_trait]
+impl KmsSigner for GcpKmsSigner {
+    async fn public_key_der(&self) -> Result<Vec<u8>> {
+        let url = format!("{CLOUD_KMS_URL}/{}/publicKey", self.key_version);
+        let response: PublicKeyResponse = self
+            .request(self.client.get(url))
+            .await?
+            .json()
+            .await
+            .with_kind(ErrorKind::Decode)?;
+        let (_, document) = Document::from_pem(&response.pem).with_kind(ErrorKind::Decode)?;
+        Ok(document.into_vec())
+    }
+
+    async fn sign_digest(&self, digest: [u8; 32]) -> Result<Vec<u8>> {
+        let url = format!("{CLOUD_KMS_URL}/{}:asymmetricSign", self.key_version);
+        let body = serde_json::json!({ "digest": { "sha256": STANDARD.encode(digest) } });
+        let response: AsymmetricSignResponse = self
+            .request(self.client.post(url).json(&body))
+            .await?
+            .json()
+            .await
+            .with_kind(ErrorKind::Decode)?;
+        Ok(STANDARD
+            .decode(response.signature)
+            .with_kind(ErrorKind::Decode)?)
+    }
+}
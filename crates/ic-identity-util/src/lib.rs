@@ -7,53 +7,208 @@ use std::{
 };
 
 use ic_agent::{
-    identity::{BasicIdentity, Secp256k1Identity},
+    export::Principal,
+    identity::{BasicIdentity, Prime256v1Identity, Secp256k1Identity},
     Identity,
 };
-use instrumented_error::Result;
+use instrumented_error::{IntoInstrumentedError, Result, ResultExt};
 use ring::signature::Ed25519KeyPair;
 use serde::{Deserialize, Serialize};
 
+mod controllers;
+mod delegation;
+mod generate;
+#[cfg(feature = "hsm")]
+mod hsm;
+#[cfg(feature = "keyring")]
+mod keyring;
+#[cfg(feature = "kms")]
+mod kms;
+mod passphrase;
+mod pem;
+mod seed_phrase;
+
+pub use controllers::{
+    add_canister_controller, canister_controllers, remove_canister_controller,
+    set_canister_controllers,
+};
+pub use delegation::{create_delegated_identity, delegate, DelegationChain};
+pub use generate::{generate_identity_to_pem, KeyType};
+#[cfg(feature = "hsm")]
+pub use hsm::{HsmIdentityConfig, DEFAULT_PIN_ENV};
+#[cfg(feature = "keyring")]
+pub use keyring::{
+    create_identity_from_keyring, delete_identity_from_keyring, import_pem_to_keyring,
+    store_identity_in_keyring, KEYRING_SERVICE,
+};
+#[cfg(feature = "aws-kms")]
+pub use kms::AwsKmsSigner;
+#[cfg(feature = "gcp-kms")]
+pub use kms::GcpKmsSigner;
+#[cfg(feature = "kms")]
+pub use kms::{KmsIdentity, KmsSigner};
+pub use passphrase::{create_identity_from_encrypted_pem, Passphrase, DEFAULT_PASSPHRASE_ENV};
+pub use seed_phrase::{create_identity_from_seed_phrase, DEFAULT_DERIVATION_PATH};
+
 /// Wrapper to implement our own deserialize method to initialize
-/// an identity from a pem file path
-#[derive(Debug, Clone, Serialize, Eq, PartialEq)]
-pub struct IdentityFromFile(PathBuf);
+/// an identity from a pem file path, either a plain path,
+/// `{ path = "..", passphrase = { env = "VAR" } }` for an encrypted pem file or
+/// `{ keyring = "label" }` for a pem stored in the OS keyring
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IdentityFromFile(IdentitySource);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum IdentitySource {
+    Pem(PathBuf, Option<Passphrase>),
+    Keyring(String),
+}
 
 impl FromStr for IdentityFromFile {
     type Err = ();
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        Ok(IdentityFromFile(PathBuf::from(s)))
+        Ok(IdentityFromFile(IdentitySource::Pem(
+            PathBuf::from(s),
+            None,
+        )))
     }
 }
 
 impl IdentityFromFile {
+    /// Reference the identity stored in the OS keyring under `label`
+    pub fn from_keyring(label: impl Into<String>) -> Self {
+        Self(IdentitySource::Keyring(label.into()))
+    }
+
     /// Return the inner Identity
     #[tracing::instrument]
     pub fn identity(&self) -> Result<Arc<dyn Identity>> {
-        create_identity_from_pem(&self.0)
+        match &self.0 {
+            IdentitySource::Pem(path, Some(passphrase)) => {
+                create_identity_from_encrypted_pem(path, passphrase)
+            }
+            IdentitySource::Pem(path, None) => create_identity_from_pem(path),
+            #[cfg(feature = "keyring")]
+            IdentitySource::Keyring(label) => create_identity_from_keyring(label),
+            #[cfg(not(feature = "keyring"))]
+            IdentitySource::Keyring(label) => Err(format!(
+                "the identity {label} is stored in the keyring, which requires the keyring feature"
+            )
+            .into_instrumented_error()
+            .with_kind(instrumented_error::ErrorKind::Config)),
+        }
+    }
+
+    /// Return the principal of the identity
+    pub fn principal(&self) -> Result<Principal> {
+        self.identity()?
+            .sender()
+            .map_err(|e| e.into_instrumented_error())
+    }
+
+    /// Decrypt the pem file with the passphrase from `passphrase`, e.g. to prompt for it.
+    /// Identities in the keyring are stored unencrypted and ignore it.
+    pub fn with_passphrase(mut self, passphrase: Passphrase) -> Self {
+        if let IdentitySource::Pem(_, current) = &mut self.0 {
+            *current = Some(passphrase);
+        }
+        self
     }
 
     /// Join the parent path to the inner path.
     /// This is needed since the identity may have been initialized without the parent
     /// path during deserialization.
     pub fn join_parent(&mut self, parent: &Path) {
-        self.0 = parent.join(&self.0);
+        if let IdentitySource::Pem(path, _) = &mut self.0 {
+            *path = parent.join(&*path);
+        }
+    }
+
+    /// Return the path of the pem file, if the identity is not in the keyring
+    pub fn path(&self) -> Option<&Path> {
+        match &self.0 {
+            IdentitySource::Pem(path, _) => Some(path),
+            IdentitySource::Keyring(_) => None,
+        }
     }
 
-    /// Return the path
-    pub fn path(&self) -> &Path {
-        &self.0
+    /// Return the label of the identity in the keyring, if it's stored there
+    pub fn keyring_label(&self) -> Option<&str> {
+        match &self.0 {
+            IdentitySource::Keyring(label) => Some(label),
+            IdentitySource::Pem(..) => None,
+        }
     }
 }
 
-/// Create an identity from a pem file
+/// Create an identity from a pem file. Encrypted pem files are decrypted with the passphrase
+/// in [`DEFAULT_PASSPHRASE_ENV`], see [`create_identity_from_encrypted_pem`] for other sources.
 #[tracing::instrument()]
 pub fn create_identity_from_pem(pem_file: &Path) -> Result<Arc<dyn Identity>> {
-    if let Ok(id) = BasicIdentity::from_pem_file(pem_file) {
-        Ok(Arc::new(id))
+    let pem =
+        std::fs::read_to_string(pem_file).with_sensitive_field("pem_file", pem_file.display())?;
+    if pem.contains(passphrase::ENCRYPTED_PEM_LABEL) {
+        create_identity_from_encrypted_pem(pem_file, &Passphrase::default())
     } else {
-        Ok(Arc::new(Secp256k1Identity::from_pem_file(pem_file)?))
+        identity_from_pem(&pem).with_sensitive_field("pem_file", pem_file.display())
+    }
+}
+
+/// Parse an unencrypted pem holding an Ed25519, a secp256k1 or a prime256v1 key
+pub(crate) fn identity_from_pem(pem: &str) -> Result<Arc<dyn Identity>> {
+    if pem.contains(passphrase::ENCRYPTED_PEM_LABEL) {
+        return Err("expected an unencrypted pem"
+            .into_instrumented_error()
+            .with_kind(instrumented_error::ErrorKind::Decode));
+    }
+    let key_type = pem::key_type_of_pem(pem)?;
+    let identity: Arc<dyn Identity> = match key_type {
+        KeyType::Ed25519 => Arc::new(BasicIdentity::from_pem(pem.as_bytes())?),
+        KeyType::Secp256k1 => Arc::new(Secp256k1Identity::from_pem(pem.as_bytes())?),
+        KeyType::Prime256v1 => Arc::new(Prime256v1Identity::from_pem(pem.as_bytes())?),
+    };
+    Ok(identity)
+}
+
+/// Return the self-authenticating principal of the identity of a pem file, e.g. to report
+/// which principal a controller file stands for
+#[tracing::instrument()]
+pub fn principal_from_pem(pem_file: &Path) -> Result<Principal> {
+    create_identity_from_pem(pem_file)?
+        .sender()
+        .map_err(|e| e.into_instrumented_error())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum IdentityFromFileRepr {
+    Path(PathBuf),
+    Encrypted {
+        path: PathBuf,
+        #[serde(default)]
+        passphrase: Passphrase,
+    },
+    Keyring {
+        keyring: String,
+    },
+}
+
+impl Serialize for IdentityFromFile {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match &self.0 {
+            IdentitySource::Pem(path, None) => IdentityFromFileRepr::Path(path.clone()),
+            IdentitySource::Pem(path, Some(passphrase)) => IdentityFromFileRepr::Encrypted {
+                path: path.clone(),
+                passphrase: passphrase.clone(),
+            },
+            IdentitySource::Keyring(label) => IdentityFromFileRepr::Keyring {
+                keyring: label.clone(),
+            },
+        }
+        .serialize(serializer)
     }
 }
 
@@ -63,7 +218,13 @@ impl<'de> Deserialize<'de> for IdentityFromFile {
         D: serde::Deserializer<'de>,
     {
         Ok(IdentityFromFile(
-            Path::new(&String::deserialize(deserializer)?).to_path_buf(),
+            match IdentityFromFileRepr::deserialize(deserializer)? {
+                IdentityFromFileRepr::Path(path) => IdentitySource::Pem(path, None),
+                IdentityFromFileRepr::Encrypted { path, passphrase } => {
+                    IdentitySource::Pem(path, Some(passphrase))
+                }
+                IdentityFromFileRepr::Keyring { keyring } => IdentitySource::Keyring(keyring),
+            },
         ))
     }
 }
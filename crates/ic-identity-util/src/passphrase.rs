@@ -0,0 +1,129 @@
+//! Passphrase protected PEM files, in the PKCS#8 `ENCRYPTED PRIVATE KEY` format, e.g. as
+//! written by `openssl pkcs8 -topk8 -v2 aes-256-cbc`
+
+use std::{fmt, path::Path, sync::Arc};
+
+use ic_agent::{
+    identity::{BasicIdentity, Prime256v1Identity, Secp256k1Identity},
+    Identity,
+};
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+use pkcs8::{DecodePrivateKey, EncryptedPrivateKeyInfo, SecretDocument};
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+
+use crate::{pem::key_type_of_pkcs8, KeyType};
+
+/// Environment variable holding the passphrase of encrypted PEM files without one configured
+pub const DEFAULT_PASSPHRASE_ENV: &str = "IDENTITY_PASSPHRASE";
+
+/// Label of the encrypted PKCS#8 PEM files
+pub(crate) const ENCRYPTED_PEM_LABEL: &str = "ENCRYPTED PRIVATE KEY";
+
+type PromptFn = dyn Fn(&Path) -> Result<String> + Send + Sync;
+
+/// Source of the passphrase of an encrypted PEM file
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Passphrase {
+    /// Read from the environment variable
+    Env(String),
+    /// Provided by the config. Never serialized.
+    #[serde(skip_serializing)]
+    Secret(String),
+    /// Returned by the callback, e.g. prompting the operator, given the path of the file
+    #[serde(skip)]
+    Prompt(Arc<PromptFn>),
+}
+
+impl Default for Passphrase {
+    fn default() -> Self {
+        Self::Env(DEFAULT_PASSPHRASE_ENV.to_owned())
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            Self::Secret(_) => f.write_str("Secret(<redacted>)"),
+            Self::Prompt(_) => f.write_str("Prompt"),
+        }
+    }
+}
+
+impl PartialEq for Passphrase {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Env(a), Self::Env(b)) | (Self::Secret(a), Self::Secret(b)) => a == b,
+            (Self::Prompt(a), Self::Prompt(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Passphrase {}
+
+impl Passphrase {
+    /// Create a source prompting for the passphrase with `prompt`
+    pub fn prompt(prompt: impl Fn(&Path) -> Result<String> + Send + Sync + 'static) -> Self {
+        Self::Prompt(Arc::new(prompt))
+    }
+
+    /// Return the passphrase of `pem_file`
+    fn resolve(&self, pem_file: &Path) -> Result<String> {
+        match self {
+            Self::Env(var) => std::env::var(var).map_err(|_| {
+                format!("the passphrase of the identity is not set in {var}")
+                    .into_instrumented_error()
+                    .with_kind(ErrorKind::Config)
+            }),
+            Self::Secret(secret) => Ok(secret.clone()),
+            Self::Prompt(prompt) => prompt(pem_file),
+        }
+    }
+}
+
+/// Create an identity from a passphrase protected pem file
+#[tracing::instrument]
+pub fn create_identity_from_encrypted_pem(
+    pem_file: &Path,
+    passphrase: &Passphrase,
+) -> Result<Arc<dyn Identity>> {
+    let pem =
+        std::fs::read_to_string(pem_file).with_sensitive_field("pem_file", pem_file.display())?;
+    identity_from_encrypted_pem(&pem, &passphrase.resolve(pem_file)?)
+        .with_sensitive_field("pem_file", pem_file.display())
+}
+
+/// Decrypt an encrypted PKCS#8 pem holding an Ed25519, a secp256k1 or a prime256v1 key
+pub(crate) fn identity_from_encrypted_pem(
+    pem: &str,
+    passphrase: &str,
+) -> Result<Arc<dyn Identity>> {
+    let (label, document) = SecretDocument::from_pem(pem).with_kind(ErrorKind::Decode)?;
+    if label != ENCRYPTED_PEM_LABEL {
+        return Err(
+            format!("expected an {ENCRYPTED_PEM_LABEL} pem, got {label}")
+                .into_instrumented_error()
+                .with_kind(ErrorKind::Decode),
+        );
+    }
+    let key = EncryptedPrivateKeyInfo::try_from(document.as_bytes())
+        .with_kind(ErrorKind::Decode)?
+        .decrypt(passphrase)
+        .with_kind(ErrorKind::Auth)?;
+
+    let identity: Arc<dyn Identity> = match key_type_of_pkcs8(key.as_bytes())? {
+        KeyType::Ed25519 => Arc::new(BasicIdentity::from_key_pair(
+            Ed25519KeyPair::from_pkcs8_maybe_unchecked(key.as_bytes())?,
+        )),
+        KeyType::Secp256k1 => Arc::new(Secp256k1Identity::from_private_key(
+            k256::SecretKey::from_pkcs8_der(key.as_bytes()).with_kind(ErrorKind::Decode)?,
+        )),
+        KeyType::Prime256v1 => Arc::new(Prime256v1Identity::from_private_key(
+            p256::SecretKey::from_pkcs8_der(key.as_bytes()).with_kind(ErrorKind::Decode)?,
+        )),
+    };
+    Ok(identity)
+}
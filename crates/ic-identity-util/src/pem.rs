@@ -0,0 +1,87 @@
+//! Detection of the type of the key of a pem, from the object identifiers of its algorithm and
+//! curve, so unsupported keys are reported as such instead of failing to parse as another type
+
+use instrumented_error::{ErrorKind, IntoInstrumentedError, Result, ResultExt};
+use pkcs8::{
+    der::{pem, Decode},
+    ObjectIdentifier, PrivateKeyInfo,
+};
+
+use crate::KeyType;
+
+const ED25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+const EC_PUBLIC_KEY_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256K1_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.10");
+const PRIME256V1_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+
+/// Return the type of the key of an unencrypted pem, either a PKCS#8 `PRIVATE KEY` or a SEC1
+/// `EC PRIVATE KEY`, optionally preceded by its `EC PARAMETERS` as written by dfx
+pub(crate) fn key_type_of_pem(pem: &str) -> Result<KeyType> {
+    let mut parameters = None;
+    for block in pem_blocks(pem) {
+        let (label, der) = pem::decode_vec(block.as_bytes()).with_kind(ErrorKind::Decode)?;
+        match label {
+            "PRIVATE KEY" => return key_type_of_pkcs8(&der),
+            "EC PARAMETERS" => {
+                parameters = Some(ObjectIdentifier::from_der(&der).with_kind(ErrorKind::Decode)?);
+            }
+            "EC PRIVATE KEY" => {
+                let key =
+                    sec1::EcPrivateKey::try_from(der.as_slice()).with_kind(ErrorKind::Decode)?;
+                let curve = key
+                    .parameters
+                    .and_then(|parameters| parameters.named_curve())
+                    .or(parameters);
+                return match curve {
+                    Some(curve) => key_type_of_curve(curve),
+                    None => Err("the EC private key doesn't name its curve"
+                        .into_instrumented_error()
+                        .with_kind(ErrorKind::Decode)),
+                };
+            }
+            _ => {}
+        }
+    }
+    Err("the pem holds no PRIVATE KEY or EC PRIVATE KEY"
+        .into_instrumented_error()
+        .with_kind(ErrorKind::Decode))
+}
+
+/// Return the type of the key of a DER encoded PKCS#8 private key
+pub(crate) fn key_type_of_pkcs8(der: &[u8]) -> Result<KeyType> {
+    let key = PrivateKeyInfo::try_from(der).with_kind(ErrorKind::Decode)?;
+    match key.algorithm.oid {
+        ED25519_OID => Ok(KeyType::Ed25519),
+        EC_PUBLIC_KEY_OID => key_type_of_curve(
+            key.algorithm
+                .parameters_oid()
+                .with_kind(ErrorKind::Decode)?,
+        ),
+        oid => Err(format!("unsupported key algorithm {oid}")
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Decode)),
+    }
+}
+
+fn key_type_of_curve(curve: ObjectIdentifier) -> Result<KeyType> {
+    match curve {
+        SECP256K1_OID => Ok(KeyType::Secp256k1),
+        PRIME256V1_OID => Ok(KeyType::Prime256v1),
+        curve => Err(format!("unsupported elliptic curve {curve}")
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Decode)),
+    }
+}
+
+/// Split the pem into its `-----BEGIN ..-----` to `-----END ..-----` blocks
+fn pem_blocks(pem: &str) -> impl Iterator<Item = &str> {
+    let mut rest = pem;
+    std::iter::from_fn(move || {
+        let start = rest.find("-----BEGIN ")?;
+        let end = rest[start..].find("-----END ")? + start;
+        let end = rest[end + "-----END ".len()..].find("-----")? + end + "-----END -----".len();
+        let block = &rest[start..end];
+        rest = &rest[end..];
+        Some(block)
+    })
+}
@@ -0,0 +1,32 @@
+//! Identities derived from a BIP39 mnemonic the same way as `dfx identity import --seed-file`
+//! and `quill`, so a seed phrase gives the same principal across the tools
+
+use std::sync::Arc;
+
+use bip32::{DerivationPath, Language, Mnemonic, XPrv};
+use ic_agent::{identity::Secp256k1Identity, Identity};
+use instrumented_error::{ErrorKind, Result, ResultExt};
+
+/// BIP32 path of the secp256k1 key derived by dfx and quill, under the IC coin type 223
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/223'/0'/0/0";
+
+/// Create a secp256k1 identity from an English BIP39 seed phrase, derived at
+/// `derivation_path` or [`DEFAULT_DERIVATION_PATH`]
+#[tracing::instrument(skip(phrase))]
+pub fn create_identity_from_seed_phrase(
+    phrase: &str,
+    derivation_path: Option<&str>,
+) -> Result<Arc<dyn Identity>> {
+    let mnemonic = Mnemonic::new(phrase.trim(), Language::English).with_kind(ErrorKind::Config)?;
+    let derivation_path = derivation_path.unwrap_or(DEFAULT_DERIVATION_PATH);
+    let path: DerivationPath = derivation_path
+        .parse()
+        .with_kind(ErrorKind::Config)
+        .with_field("derivation_path", derivation_path)?;
+
+    // quill and dfx derive the seed without a BIP39 passphrase
+    let seed = mnemonic.to_seed("");
+    let key = XPrv::derive_from_path(seed.as_bytes(), &path)?;
+    let key = k256::SecretKey::from_bytes(&key.private_key().to_bytes())?;
+    Ok(Arc::new(Secp256k1Identity::from_private_key(key)))
+}
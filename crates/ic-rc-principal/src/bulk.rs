@@ -0,0 +1,60 @@
+//! Interning of batches of principals, such as the principals of a restored state.
+
+use crate::{lru, map, InnerType, InternEntry, RcPrincipal};
+use candid::Principal;
+use std::cell::Cell;
+
+impl RcPrincipal {
+    /// Intern a batch of principals, borrowing the map once instead of once per principal
+    pub fn intern_all<'a>(principals: impl IntoIterator<Item = &'a Principal>) -> Vec<RcPrincipal> {
+        let now = lru::tick();
+        let mut misses = 0;
+        let interned: Vec<_> = map::with_shards(|shards| {
+            principals
+                .into_iter()
+                .map(|p| {
+                    let (rc_p, interned) = Self::lookup(shards.shard(p), p, now);
+                    misses += interned as u64;
+                    rc_p
+                })
+                .collect()
+        });
+        Self::record_lookups(interned.len() as u64 - misses, misses);
+        interned
+    }
+
+    /// Replace each principal by its interned instance, e.g. after a post upgrade restored
+    /// principals that were evicted from the map or decoded without it. Returns the number of
+    /// replaced principals, whose duplicate allocations are freed once no longer referenced.
+    pub fn reintern_all<'a>(principals: impl IntoIterator<Item = &'a mut RcPrincipal>) -> usize {
+        let now = lru::tick();
+        let mut lookups = 0;
+        let mut misses = 0;
+        let mut replaced = 0;
+        map::with_shards(|shards| {
+            for rc_p in principals {
+                lookups += 1;
+                let map = shards.shard(rc_p.inner());
+                if let Some(entry) = map.get(rc_p.inner()) {
+                    entry.last_used.set(now);
+                    if !InnerType::ptr_eq(&entry.principal.0, &rc_p.0) {
+                        *rc_p = entry.principal.clone();
+                        replaced += 1;
+                    }
+                } else {
+                    // the principal itself becomes the interned instance
+                    map.insert(
+                        rc_p.clone(),
+                        InternEntry {
+                            principal: rc_p.clone(),
+                            last_used: Cell::new(now),
+                        },
+                    );
+                    misses += 1;
+                }
+            }
+        });
+        Self::record_lookups(lookups - misses, misses);
+        replaced
+    }
+}
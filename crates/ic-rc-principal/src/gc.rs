@@ -0,0 +1,43 @@
+//! Eviction of interned principals that are no longer referenced.
+
+use crate::map::{self, MAP_REFERENCES};
+use crate::{InnerType, RcPrincipal};
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Interns between automatic collections, 0 if disabled
+static GC_INTERVAL: AtomicU64 = AtomicU64::new(0);
+static INTERNS_SINCE_GC: AtomicU64 = AtomicU64::new(0);
+
+/// Record `count` newly interned principals, collecting garbage if the interval elapsed
+pub(crate) fn record_interns(count: u64) {
+    let interval = GC_INTERVAL.load(Ordering::Relaxed);
+    if interval == 0 || count == 0 {
+        return;
+    }
+    if INTERNS_SINCE_GC.fetch_add(count, Ordering::Relaxed) + count >= interval {
+        RcPrincipal::gc();
+    }
+}
+
+impl RcPrincipal {
+    /// Evict the interned principals that are only referenced by the map, returning the
+    /// number of evicted principals
+    pub fn gc() -> usize {
+        INTERNS_SINCE_GC.store(0, Ordering::Relaxed);
+        let mut evicted = 0;
+        map::for_each_shard(|map| {
+            let len = map.len();
+            map.retain(|p, _| InnerType::strong_count(&p.0) > MAP_REFERENCES);
+            evicted += len - map.len();
+        });
+        evicted
+    }
+
+    /// Run [`Self::gc`] automatically every `interval` newly interned principals, or
+    /// never if `None`, which is the default
+    pub fn set_gc_interval(interval: Option<NonZeroU64>) {
+        GC_INTERVAL.store(interval.map_or(0, NonZeroU64::get), Ordering::Relaxed);
+        INTERNS_SINCE_GC.store(0, Ordering::Relaxed);
+    }
+}
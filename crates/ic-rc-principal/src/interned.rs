@@ -0,0 +1,240 @@
+//! Interning of arbitrary values, such as the usernames and portal slugs that duplicate
+//! heavily in canister state.
+//!
+//! Values are interned per thread in one map per type. Unlike [`crate::RcPrincipal`],
+//! interned values are neither counted nor bounded, and are only evicted by
+//! [`Interned::gc`].
+
+use candid::CandidType;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{Any, TypeId},
+    borrow::Borrow,
+    cell::RefCell,
+    fmt::{Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+#[cfg(target_arch = "wasm32")]
+type Shared<T> = std::rc::Rc<T>;
+#[cfg(not(target_arch = "wasm32"))]
+type Shared<T> = std::sync::Arc<T>;
+
+thread_local! {
+    /// Set of the interned values of each type
+    static SETS: RefCell<FxHashMap<TypeId, Box<dyn Any>>> = RefCell::default();
+}
+
+/// An interned string
+pub type RcString = Interned<str>;
+
+/// A ref-counted value shared with every equal value interned on the same thread
+pub struct Interned<T: ?Sized>(Shared<T>);
+
+/// Run `f` with the set of the interned values of type `T`
+fn with_set<T, R>(f: impl FnOnce(&mut FxHashSet<Interned<T>>) -> R) -> R
+where
+    T: ?Sized + Eq + Hash + 'static,
+{
+    SETS.with(|sets| {
+        let mut sets = sets.borrow_mut();
+        let set = sets
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<FxHashSet<Interned<T>>>::default())
+            .downcast_mut()
+            .expect("interned set keyed by its type");
+        f(set)
+    })
+}
+
+impl<T> Interned<T>
+where
+    T: ?Sized + Eq + Hash + ToOwned + 'static,
+    Shared<T>: From<T::Owned>,
+{
+    /// Return the interned value equal to `value`, interning a copy if there's none
+    pub fn get(value: &T) -> Self {
+        with_set(|set| {
+            if let Some(interned) = set.get(value) {
+                return interned.clone();
+            }
+            let interned = Self(Shared::from(value.to_owned()));
+            set.insert(interned.clone());
+            interned
+        })
+    }
+
+    /// Return the interned value equal to `value`, interning `value` if there's none
+    pub fn new(value: T::Owned) -> Self {
+        with_set(|set| {
+            if let Some(interned) = set.get(value.borrow()) {
+                return interned.clone();
+            }
+            let interned = Self(Shared::from(value));
+            set.insert(interned.clone());
+            interned
+        })
+    }
+}
+
+impl<T: ?Sized + Eq + Hash + 'static> Interned<T> {
+    /// Evict the interned values of type `T` that are only referenced by the map,
+    /// returning the number of evicted values
+    pub fn gc() -> usize {
+        with_set::<T, _>(|set| {
+            let len = set.len();
+            set.retain(|value| Shared::strong_count(&value.0) > 1);
+            len - set.len()
+        })
+    }
+}
+
+impl<T: ?Sized> Clone for Interned<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Deref for Interned<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for Interned<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0) || *self.0 == *other.0
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Interned<T> {}
+
+impl<T: ?Sized + Hash> Hash for Interned<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Interned<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Interned<T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+// Passhtru implementations of Debug and Display
+impl<T: ?Sized + Debug> Debug for Interned<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: ?Sized + Display> Display for Interned<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Implementation of Deserialize, which resolves to the interned value.
+impl<'de, T> Deserialize<'de> for Interned<T>
+where
+    T: ?Sized + Eq + Hash + ToOwned + 'static,
+    T::Owned: Deserialize<'de>,
+    Shared<T>: From<T::Owned>,
+{
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::Owned::deserialize(deserializer).map(Self::new)
+    }
+}
+
+// Passhtru implementation of Serialize
+impl<T: ?Sized + Serialize> Serialize for Interned<T> {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+// Passhtru implementation of CandidType
+impl<T: ?Sized + CandidType> CandidType for Interned<T> {
+    #[inline]
+    fn _ty() -> candid::types::Type {
+        T::_ty()
+    }
+
+    #[inline]
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        self.0.idl_serialize(serializer)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: ?Sized + deepsize::DeepSizeOf> deepsize::DeepSizeOf for Interned<T> {
+    fn deep_size_of_children(&self, context: &mut deepsize::Context) -> usize {
+        self.0.deep_size_of_children(context)
+    }
+}
+
+// Borrow implementation.
+//
+// Allows `Interned<T>` to be used a key in the hashmap and lookup to be performed via `T`,
+// e.g. `&str` for `RcString`, without interning the key.
+impl<T: ?Sized> Borrow<T> for Interned<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Interned<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl From<&str> for RcString {
+    #[inline]
+    fn from(s: &str) -> Self {
+        RcString::get(s)
+    }
+}
+
+impl From<String> for RcString {
+    #[inline]
+    fn from(s: String) -> Self {
+        RcString::new(s)
+    }
+}
+
+impl From<&RcString> for String {
+    #[inline]
+    fn from(s: &RcString) -> Self {
+        s.0.to_string()
+    }
+}
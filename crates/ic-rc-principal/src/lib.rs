@@ -16,12 +16,29 @@
 //! principal. This can be mitigated by performing the lookup just prior to insertion into the
 //! store.
 use candid::{CandidType, Deserialize, Principal};
-use rustc_hash::FxHashMap;
 use serde::Serialize;
-use std::{borrow::Borrow, cell::RefCell};
+use std::{borrow::Borrow, cell::Cell};
 
-thread_local! {
-    pub static MAP: RefCell<FxHashMap<RcPrincipal, RcPrincipal>> = RefCell::default();
+mod bulk;
+mod gc;
+mod interned;
+mod lru;
+mod map;
+mod stats;
+mod table;
+
+pub use interned::{Interned, RcString};
+pub use map::InternMap;
+#[cfg(any(target_arch = "wasm32", not(feature = "concurrent")))]
+pub use map::MAP;
+pub use stats::InternStats;
+pub use table::PrincipalTable;
+
+/// An interned principal of the map
+pub struct InternEntry {
+    principal: RcPrincipal,
+    /// Logical time of the last lookup
+    last_used: Cell<u64>,
 }
 
 /// A unit-struct that wraps aroudn a ref-counted implementation to facilitate
@@ -52,15 +69,45 @@ impl RcPrincipal {
     }
 
     pub fn get(p: &Principal) -> RcPrincipal {
-        MAP.with(|map| {
-            if let Some(principal) = map.borrow().get(p) {
-                return principal.clone();
-            }
+        let now = lru::tick();
+        let (rc_p, interned) = map::with_shard(p, |map| Self::lookup(map, p, now));
+        // the map is released so these can visit every shard
+        if interned {
+            Self::record_lookups(0, 1);
+        } else {
+            Self::record_lookups(1, 0);
+        }
+        rc_p
+    }
+
+    /// Return the interned principal of `p` in `map`, interning it if needed, and whether
+    /// it was interned
+    #[inline]
+    fn lookup(map: &mut InternMap, p: &Principal, now: u64) -> (RcPrincipal, bool) {
+        if let Some(entry) = map.get(p) {
+            entry.last_used.set(now);
+            return (entry.principal.clone(), false);
+        }
+
+        let rc_p = RcPrincipal(InnerType::new(*p));
+        map.insert(
+            rc_p.clone(),
+            InternEntry {
+                principal: rc_p.clone(),
+                last_used: Cell::new(now),
+            },
+        );
+        (rc_p, true)
+    }
 
-            let rc_p = RcPrincipal(InnerType::new(*p));
-            map.borrow_mut().insert(rc_p.clone(), rc_p.clone());
-            rc_p
-        })
+    /// Record lookups once the map is released, as collection and eviction visit every
+    /// shard
+    fn record_lookups(hits: u64, misses: u64) {
+        stats::record_lookups(hits, misses);
+        if misses > 0 {
+            gc::record_interns(misses);
+            lru::enforce_capacity();
+        }
     }
 }
 
@@ -78,19 +125,28 @@ impl<'de> Deserialize<'de> for RcPrincipal {
     where
         D: serde::Deserializer<'de>,
     {
+        if table::is_decoding() {
+            let index = u32::deserialize(deserializer)?;
+            return table::decode(index).ok_or_else(|| {
+                serde::de::Error::custom(format!("principal index {index} out of the table"))
+            });
+        }
         let p = Principal::deserialize(deserializer)?;
         Ok(RcPrincipal::get(&p))
     }
 }
 
-// Passhtru implementation of Serialize
+// Passhtru implementation of Serialize, or index into the table while encoding indexed
 impl Serialize for RcPrincipal {
     #[inline]
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        self.0.serialize(serializer)
+        match table::encode(&self.0) {
+            Some(index) => serializer.serialize_u32(index),
+            None => self.0.serialize(serializer),
+        }
     }
 }
 
@@ -148,3 +204,70 @@ impl Borrow<Principal> for RcPrincipal {
         &self.0
     }
 }
+
+// AsRef implementation of the raw bytes.
+//
+// Allows byte-keyed stable structures to use `RcPrincipal` directly.
+impl AsRef<[u8]> for RcPrincipal {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+// Ordering of `Principal`, so `RcPrincipal` can key `BTreeMap`s looked up via `Principal`
+impl PartialOrd for RcPrincipal {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RcPrincipal {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn principals() -> Vec<Principal> {
+        vec![
+            Principal::from_slice(&[2]),
+            Principal::from_slice(&[1, 9]),
+            Principal::from_slice(&[1]),
+            Principal::anonymous(),
+            Principal::management_canister(),
+            Principal::from_slice(&[0; 29]),
+        ]
+    }
+
+    #[test]
+    fn ordering_matches_principal() {
+        let mut principals = principals();
+        let mut rc_principals: Vec<_> = principals.iter().map(RcPrincipal::from).collect();
+        principals.sort();
+        rc_principals.sort();
+        let sorted: Vec<Principal> = rc_principals.iter().map(Principal::from).collect();
+        assert_eq!(sorted, principals);
+    }
+
+    #[test]
+    fn maps_are_looked_up_via_principal() {
+        let btree: BTreeMap<_, _> = principals()
+            .into_iter()
+            .enumerate()
+            .map(|(i, principal)| (RcPrincipal::from(principal), i))
+            .collect();
+        let hash: HashMap<_, _> = btree.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        for (i, principal) in principals().iter().enumerate() {
+            assert_eq!(btree.get(principal), Some(&i));
+            assert_eq!(hash.get(principal), Some(&i));
+        }
+        assert_eq!(btree.get(&Principal::from_slice(&[3])), None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Capacity limit of the intern map, evicting the least recently used principals.
+
+use crate::map::{self, InternMap, SHARDS};
+use crate::RcPrincipal;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of interned principals, 0 if unbounded
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// Return the logical time of a lookup
+#[inline]
+pub(crate) fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Evict the least recently used principals if the map exceeds its capacity
+pub(crate) fn enforce_capacity() {
+    let capacity = CAPACITY.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return;
+    }
+    map::for_each_shard(|map| evict(map, capacity.div_ceil(SHARDS)));
+}
+
+/// Evict the least recently used principals of `map` beyond `capacity`.
+///
+/// A tenth of the capacity is evicted at once so the cost of finding the least recently
+/// used principals is amortized over the following interns.
+fn evict(map: &mut InternMap, capacity: usize) {
+    if map.len() <= capacity {
+        return;
+    }
+    let retained = capacity - capacity / 10;
+    let mut last_used: Vec<u64> = map.values().map(|entry| entry.last_used.get()).collect();
+    let evicted = last_used.len() - retained;
+    // lookups have distinct ticks, so the threshold splits the map exactly
+    let (_, threshold, _) = last_used.select_nth_unstable(evicted - 1);
+    let threshold = *threshold;
+    map.retain(|_, entry| entry.last_used.get() > threshold);
+}
+
+impl RcPrincipal {
+    /// Bound the number of interned principals, evicting the least recently used ones
+    /// beyond `capacity`, or unbounded if `None`, which is the default. The capacity
+    /// applies to the map of each thread, or is split across the shards of the
+    /// `concurrent` map.
+    ///
+    /// Evicted principals stay valid but are no longer shared with later lookups.
+    pub fn set_capacity(capacity: Option<NonZeroUsize>) {
+        CAPACITY.store(capacity.map_or(0, NonZeroUsize::get), Ordering::Relaxed);
+        enforce_capacity();
+    }
+}
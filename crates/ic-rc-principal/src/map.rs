@@ -0,0 +1,111 @@
+//! Storage of the interned principals.
+//!
+//! Principals are interned per thread, except on native targets with the `concurrent`
+//! feature where every thread shares a sharded map, so multi-threaded mirrors don't build
+//! an intern table per worker thread.
+
+use crate::{InternEntry, RcPrincipal};
+use rustc_hash::FxHashMap;
+
+/// Map of the interned principals
+pub type InternMap = FxHashMap<RcPrincipal, InternEntry>;
+
+/// Number of references the map holds to each interned principal, as key and in the entry
+pub(crate) const MAP_REFERENCES: usize = 2;
+
+#[cfg(any(target_arch = "wasm32", not(feature = "concurrent")))]
+mod backend {
+    use super::InternMap;
+    use candid::Principal;
+    use std::cell::RefCell;
+
+    pub(crate) const SHARDS: usize = 1;
+
+    thread_local! {
+        pub static MAP: RefCell<InternMap> = RefCell::default();
+    }
+
+    /// Run `f` with the map holding `p`
+    #[inline]
+    pub(crate) fn with_shard<R>(_p: &Principal, f: impl FnOnce(&mut InternMap) -> R) -> R {
+        MAP.with(|map| f(&mut map.borrow_mut()))
+    }
+
+    /// Run `f` with every map
+    pub(crate) fn for_each_shard(mut f: impl FnMut(&mut InternMap)) {
+        MAP.with(|map| f(&mut map.borrow_mut()))
+    }
+
+    /// The borrowed map
+    pub(crate) struct Shards<'a>(&'a mut InternMap);
+
+    impl Shards<'_> {
+        /// Return the map holding `p`
+        #[inline]
+        pub(crate) fn shard(&mut self, _p: &Principal) -> &mut InternMap {
+            self.0
+        }
+    }
+
+    /// Run `f` with the map borrowed once
+    pub(crate) fn with_shards<R>(f: impl FnOnce(&mut Shards<'_>) -> R) -> R {
+        MAP.with(|map| f(&mut Shards(&mut map.borrow_mut())))
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "concurrent"))]
+mod backend {
+    use super::InternMap;
+    use candid::Principal;
+    use std::hash::{BuildHasher, Hash};
+    use std::sync::{LazyLock, Mutex, MutexGuard};
+
+    pub(crate) const SHARDS: usize = 16;
+
+    static MAPS: LazyLock<Vec<Mutex<InternMap>>> =
+        LazyLock::new(|| (0..SHARDS).map(|_| Mutex::default()).collect());
+
+    #[inline]
+    fn shard_index(p: &Principal) -> usize {
+        let mut hasher = rustc_hash::FxBuildHasher.build_hasher();
+        p.hash(&mut hasher);
+        std::hash::Hasher::finish(&hasher) as usize % SHARDS
+    }
+
+    /// Run `f` with the shard holding `p`
+    #[inline]
+    pub(crate) fn with_shard<R>(p: &Principal, f: impl FnOnce(&mut InternMap) -> R) -> R {
+        f(&mut MAPS[shard_index(p)].lock().expect("intern map poisoned"))
+    }
+
+    /// Run `f` with every shard, one at a time
+    pub(crate) fn for_each_shard(mut f: impl FnMut(&mut InternMap)) {
+        for map in MAPS.iter() {
+            f(&mut map.lock().expect("intern map poisoned"));
+        }
+    }
+
+    /// Every locked shard
+    pub(crate) struct Shards<'a>(Vec<MutexGuard<'a, InternMap>>);
+
+    impl Shards<'_> {
+        /// Return the shard holding `p`
+        #[inline]
+        pub(crate) fn shard(&mut self, p: &Principal) -> &mut InternMap {
+            &mut self.0[shard_index(p)]
+        }
+    }
+
+    /// Run `f` with every shard locked once, in order so concurrent batches can't deadlock
+    pub(crate) fn with_shards<R>(f: impl FnOnce(&mut Shards<'_>) -> R) -> R {
+        let shards = MAPS
+            .iter()
+            .map(|map| map.lock().expect("intern map poisoned"))
+            .collect();
+        f(&mut Shards(shards))
+    }
+}
+
+#[cfg(any(target_arch = "wasm32", not(feature = "concurrent")))]
+pub use backend::MAP;
+pub(crate) use backend::{for_each_shard, with_shard, with_shards, SHARDS};
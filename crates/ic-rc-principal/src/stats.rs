@@ -0,0 +1,88 @@
+//! Effectiveness of the interning of principals.
+
+use crate::map::{self, InternMap, MAP_REFERENCES};
+use crate::{InnerType, InternEntry, RcPrincipal};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Size of the shared allocation of a principal, with its strong and weak counts
+const ALLOCATION_SIZE: usize = std::mem::size_of::<Principal>() + 2 * std::mem::size_of::<usize>();
+/// Size of a slot of the map, with its control byte
+const SLOT_SIZE: usize =
+    std::mem::size_of::<RcPrincipal>() + std::mem::size_of::<InternEntry>() + 1;
+
+/// Statistics of the interned principals
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub struct InternStats {
+    /// Number of distinct principals interned
+    pub entries: u64,
+    /// Number of lookups that reused an interned principal
+    pub hits: u64,
+    /// Number of lookups that interned a new principal
+    pub misses: u64,
+    /// Approximate bytes saved by the live references compared to plain principals,
+    /// net of the cost of the interning map. Negative if interning costs more than it saves.
+    pub approx_bytes_saved: i64,
+    /// Approximate heap bytes of the interning map, see [`RcPrincipal::map_heap_bytes`]
+    pub approx_heap_bytes: u64,
+}
+
+#[inline]
+pub(crate) fn record_lookups(hits: u64, misses: u64) {
+    HITS.fetch_add(hits, Ordering::Relaxed);
+    MISSES.fetch_add(misses, Ordering::Relaxed);
+}
+
+impl RcPrincipal {
+    /// Return the statistics of the interned principals of the current thread, or of every
+    /// thread with the `concurrent` map. Lookups are counted across every thread.
+    pub fn stats() -> InternStats {
+        let principal_size = std::mem::size_of::<Principal>();
+        let reference_size = std::mem::size_of::<RcPrincipal>();
+
+        let mut entries = 0;
+        let mut references = 0;
+        let mut heap_bytes = 0;
+        map::for_each_shard(|map| {
+            entries += map.len();
+            heap_bytes += map_heap_bytes(map);
+            references += map
+                .keys()
+                .map(|p| InnerType::strong_count(&p.0).saturating_sub(MAP_REFERENCES))
+                .sum::<usize>();
+        });
+        let saved = references * (principal_size - reference_size);
+        // allocation, and the key and entry of the map without its control byte
+        let cost = entries * (ALLOCATION_SIZE + SLOT_SIZE - 1);
+        InternStats {
+            entries: entries as u64,
+            hits: HITS.load(Ordering::Relaxed),
+            misses: MISSES.load(Ordering::Relaxed),
+            approx_bytes_saved: saved as i64 - cost as i64,
+            approx_heap_bytes: heap_bytes as u64,
+        }
+    }
+
+    /// Return the approximate heap bytes of the shared principal. Unlike `DeepSizeOf`,
+    /// which is only available on native targets, this is available in canisters.
+    #[inline]
+    pub fn approx_heap_bytes(&self) -> usize {
+        ALLOCATION_SIZE
+    }
+
+    /// Return the approximate heap bytes of the interning map, including the shared
+    /// principals and the spare capacity of the map
+    pub fn map_heap_bytes() -> usize {
+        let mut heap_bytes = 0;
+        map::for_each_shard(|map| heap_bytes += map_heap_bytes(map));
+        heap_bytes
+    }
+}
+
+fn map_heap_bytes(map: &InternMap) -> usize {
+    map.capacity() * SLOT_SIZE + map.len() * ALLOCATION_SIZE
+}
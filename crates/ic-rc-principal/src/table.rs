@@ -0,0 +1,109 @@
+//! Compact serialization of principal-heavy state.
+//!
+//! While a state is encoded with [`RcPrincipal::encode_indexed`], each principal is serialized
+//! as a `u32` index into a [`PrincipalTable`] holding every distinct principal once. The
+//! table is stored alongside the state and passed to [`RcPrincipal::decode_indexed`] to
+//! resolve the indexes when the state is decoded.
+//!
+//! Only serde is affected, Candid encoding of principals is unchanged.
+
+use crate::RcPrincipal;
+use candid::Principal;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static ENCODING: RefCell<Option<Encoder>> = const { RefCell::new(None) };
+    static DECODING: RefCell<Option<Vec<RcPrincipal>>> = const { RefCell::new(None) };
+}
+
+/// Distinct principals of an indexed state, in index order
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrincipalTable(pub Vec<Principal>);
+
+#[derive(Default)]
+struct Encoder {
+    indexes: FxHashMap<Principal, u32>,
+    principals: Vec<Principal>,
+}
+
+/// Restores the previous mode once the state is encoded or decoded, even on panic
+struct Reset<T: 'static> {
+    key: &'static std::thread::LocalKey<RefCell<Option<T>>>,
+    previous: Option<T>,
+}
+
+impl<T> Drop for Reset<T> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        self.key.with(|mode| *mode.borrow_mut() = previous);
+    }
+}
+
+fn set_mode<T>(key: &'static std::thread::LocalKey<RefCell<Option<T>>>, mode: T) -> Reset<T> {
+    let previous = key.with(|current| current.borrow_mut().replace(mode));
+    Reset { key, previous }
+}
+
+/// Return the index of `p` in the table being encoded, if a state is being encoded
+#[inline]
+pub(crate) fn encode(p: &Principal) -> Option<u32> {
+    ENCODING.with(|encoding| {
+        let mut encoding = encoding.borrow_mut();
+        let encoder = encoding.as_mut()?;
+        if let Some(index) = encoder.indexes.get(p) {
+            return Some(*index);
+        }
+        let index = u32::try_from(encoder.principals.len()).expect("principal table overflow");
+        encoder.indexes.insert(*p, index);
+        encoder.principals.push(*p);
+        Some(index)
+    })
+}
+
+/// Whether a state is being decoded
+#[inline]
+pub(crate) fn is_decoding() -> bool {
+    DECODING.with(|decoding| decoding.borrow().is_some())
+}
+
+/// Return the principal at `index` of the table being decoded
+#[inline]
+pub(crate) fn decode(index: u32) -> Option<RcPrincipal> {
+    DECODING.with(|decoding| decoding.borrow().as_ref()?.get(index as usize).cloned())
+}
+
+impl RcPrincipal {
+    /// Run `f`, typically serializing a state, with principals serialized as indexes into
+    /// the returned table
+    pub fn encode_indexed<R>(f: impl FnOnce() -> R) -> (R, PrincipalTable) {
+        let reset = set_mode(&ENCODING, Encoder::default());
+        let ret = f();
+        let encoder = ENCODING
+            .with(|encoding| encoding.borrow_mut().take())
+            .unwrap_or_default();
+        drop(reset);
+        (ret, PrincipalTable(encoder.principals))
+    }
+
+    /// Run `f`, typically deserializing a state, with principals deserialized from indexes
+    /// into `table`. The principals of the table are interned up front.
+    pub fn decode_indexed<R>(table: &PrincipalTable, f: impl FnOnce() -> R) -> R {
+        let principals = RcPrincipal::intern_all(&table.0);
+        let _reset = set_mode(&DECODING, principals);
+        f()
+    }
+}
+
+impl PrincipalTable {
+    /// Return the number of distinct principals
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Return whether the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,94 @@
+//! Mapping of errors to HTTP responses.
+
+use crate::{Error, ErrorKind};
+use http::StatusCode;
+
+/// Status code an error is returned with over HTTP
+pub trait ErrorStatus {
+    /// Return the status code of the error
+    fn status(&self) -> StatusCode;
+}
+
+impl ErrorStatus for ErrorKind {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Network => StatusCode::SERVICE_UNAVAILABLE,
+            Self::CanisterReject => StatusCode::BAD_GATEWAY,
+            Self::Auth => StatusCode::UNAUTHORIZED,
+            // decode errors mostly come from responses of canisters, not from the request
+            Self::Decode | Self::Config | Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl ErrorStatus for Error {
+    fn status(&self) -> StatusCode {
+        self.kind.status()
+    }
+}
+
+/// Responds with the status of the error category and its [`crate::ErrorReport`] as JSON.
+/// The report is redacted, so the values of sensitive fields never leave the server.
+#[cfg(feature = "axum-06")]
+impl axum06::response::IntoResponse for Error {
+    fn into_response(self) -> axum06::response::Response {
+        let report = self.report();
+        tracing::error!(kind = %self.kind, "{self}");
+        (self.status(), axum06::Json(report)).into_response()
+    }
+}
+
+/// Responds with the status of the error category and its [`crate::ErrorReport`] as JSON.
+/// The report is redacted, so the values of sensitive fields never leave the server.
+#[cfg(feature = "axum-07")]
+impl axum07::response::IntoResponse for Error {
+    fn into_response(self) -> axum07::response::Response {
+        let report = self.report();
+        tracing::error!(kind = %self.kind, "{self}");
+        // axum 0.7 is built on http 1, whose status codes have the same values
+        let status = axum07::http::StatusCode::from_u16(self.status().as_u16())
+            .unwrap_or(axum07::http::StatusCode::INTERNAL_SERVER_ERROR);
+        (status, axum07::Json(report)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntoInstrumentedError;
+
+    #[test]
+    fn decode_errors_are_server_errors() {
+        assert_eq!(
+            ErrorKind::Decode.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(ErrorKind::Network.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error = "denied"
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Auth);
+        assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[cfg(feature = "axum-07")]
+    #[test]
+    fn axum_response_renders_the_redacted_report() {
+        use axum07::response::IntoResponse;
+
+        let error = "denied"
+            .into_instrumented_error()
+            .with_kind(ErrorKind::Auth)
+            .with_sensitive_field("token", "secret");
+        let expected = error.report();
+        let response = error.into_response();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let body =
+            futures::executor::block_on(axum07::body::to_bytes(response.into_body(), usize::MAX))
+                .unwrap();
+        let report: crate::ErrorReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report, expected);
+        assert_eq!(report.span_trace, None);
+        assert!(!String::from_utf8_lossy(&body).contains("secret"));
+    }
+}
@@ -4,27 +4,255 @@
 // have file and line info as well as sufficient context to debug
 // the error.
 
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use tracing_error::ExtractSpanTrace;
 use tracing_error::InstrumentError;
+use tracing_error::SpanTrace;
 use tracing_error::TracedError;
 
+#[cfg(feature = "http")]
+mod http_status;
+mod macros;
+mod multi_error;
+mod wire;
+
+#[cfg(feature = "http")]
+pub use http_status::ErrorStatus;
+pub use multi_error::MultiError;
+pub use wire::{IntoWireResult, WireError};
+
+/// Category of an error, so callers can branch on it instead of its message
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, CandidType, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// The network failed or timed out
+    Network,
+    /// A canister rejected the call
+    CanisterReject,
+    /// Data could not be encoded or decoded
+    Decode,
+    /// The configuration is invalid or incomplete
+    Config,
+    /// The caller is not authenticated or not allowed
+    Auth,
+    /// Any other error
+    #[default]
+    Internal,
+}
+
+impl ErrorKind {
+    /// Whether retrying the operation may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Network)
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Network => "network",
+            Self::CanisterReject => "canister_reject",
+            Self::Decode => "decode",
+            Self::Config => "config",
+            Self::Auth => "auth",
+            Self::Internal => "internal",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Shown instead of the value of sensitive fields
+pub const REDACTED: &str = "<redacted>";
+
+/// Metadata attached to an error
+struct Field {
+    key: Cow<'static, str>,
+    value: String,
+    /// Only shown to privileged sinks, e.g. tokens, PEM paths or principals
+    sensitive: bool,
+}
+
+impl Field {
+    fn value(&self, unredacted: bool) -> &str {
+        if self.sensitive && !unredacted {
+            REDACTED
+        } else {
+            &self.value
+        }
+    }
+}
+
 /// A boxed error that's instrumented via tracing
-pub struct BoxedInstrumentedError(Box<dyn std::error::Error + 'static + Send + Sync>);
+pub struct BoxedInstrumentedError {
+    error: Box<dyn std::error::Error + 'static + Send + Sync>,
+    kind: ErrorKind,
+    fields: Vec<Field>,
+    /// Backtrace captured when the error was converted, honoring `RUST_BACKTRACE`
+    #[cfg(feature = "backtrace")]
+    backtrace: std::backtrace::Backtrace,
+}
 
 impl BoxedInstrumentedError {
     /// Return the inner boxed error
     pub fn into_std_error(self) -> BoxedInstrumentedStdError {
-        BoxedInstrumentedStdError(self.0)
+        BoxedInstrumentedStdError(self.error)
+    }
+
+    /// Wrap the error with a message describing what was being done when it occurred,
+    /// keeping it as the source and capturing the current span
+    pub fn context<C: Display>(self, context: C) -> Self {
+        let error = ContextError {
+            context: context.to_string(),
+            source: self.error,
+        };
+        Self {
+            error: Box::new(error.in_current_span()),
+            kind: self.kind,
+            fields: self.fields,
+            // the origin of the error is more useful than where context was added
+            #[cfg(feature = "backtrace")]
+            backtrace: self.backtrace,
+        }
+    }
+
+    /// Attach the category of the error
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Attach operational metadata to the error, e.g. `with_field("canister_id", id)`
+    pub fn with_field(self, key: impl Into<Cow<'static, str>>, value: impl Display) -> Self {
+        self.push_field(key.into(), value, false)
+    }
+
+    /// Attach metadata that is masked when the error is displayed, logged or reported and
+    /// only available through [`Self::unredacted_fields`] and [`Self::unredacted_report`]
+    pub fn with_sensitive_field(
+        self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Display,
+    ) -> Self {
+        self.push_field(key.into(), value, true)
+    }
+
+    fn push_field(mut self, key: Cow<'static, str>, value: impl Display, sensitive: bool) -> Self {
+        self.fields.push(Field {
+            key,
+            value: value.to_string(),
+            sensitive,
+        });
+        self
+    }
+
+    /// Return the metadata attached to the error, with sensitive values masked
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|field| (field.key.as_ref(), field.value(false)))
+    }
+
+    /// Return the metadata attached to the error including sensitive values, for
+    /// privileged sinks only
+    pub fn unredacted_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|field| (field.key.as_ref(), field.value(true)))
+    }
+
+    /// Return the category of the error
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Whether retrying the operation that failed may succeed
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+
+    /// Return the backtrace captured when the error was converted
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> &std::backtrace::Backtrace {
+        &self.backtrace
+    }
+
+    /// Convert to an `anyhow::Error`, keeping the source chain
+    #[cfg(feature = "anyhow")]
+    pub fn into_anyhow(self) -> anyhow::Error {
+        anyhow::Error::new(self.into_std_error())
+    }
+
+    /// Return the spans that were active when the error was instrumented, walking the
+    /// sources to find the outermost captured trace
+    pub fn span_trace(&self) -> Option<&SpanTrace> {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&*self.error);
+        while let Some(current) = source {
+            if let Some(trace) = current.span_trace() {
+                return Some(trace);
+            }
+            source = current.source();
+        }
+        None
+    }
+
+    /// Log the error with its span trace as an event of `level`
+    pub fn log(&self, level: tracing::Level) {
+        use tracing::Level;
+
+        let kind = self.kind;
+        if level == Level::ERROR {
+            tracing::error!(%kind, "{self}");
+        } else if level == Level::WARN {
+            tracing::warn!(%kind, "{self}");
+        } else if level == Level::INFO {
+            tracing::info!(%kind, "{self}");
+        } else if level == Level::DEBUG {
+            tracing::debug!(%kind, "{self}");
+        } else {
+            tracing::trace!(%kind, "{self}");
+        }
+    }
+
+    /// Return a serializable report of the error
+    pub fn report(&self) -> ErrorReport {
+        ErrorReport::new(self, false)
+    }
+
+    /// Return a serializable report of the error including sensitive values, for
+    /// privileged sinks only
+    pub fn unredacted_report(&self) -> ErrorReport {
+        ErrorReport::new(self, true)
+    }
+}
+
+impl BoxedInstrumentedError {
+    fn fmt_fields(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        f.write_str(" {")?;
+        for (i, (key, value)) in self.fields().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(f, "{separator}{key}={value}")?;
+        }
+        f.write_str("}")
     }
 }
 
 impl Debug for BoxedInstrumentedError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(&self.0, f)?;
-        if let Some(source) = self.0.source() {
-            return Debug::fmt(&source, f);
+        Debug::fmt(&self.error, f)?;
+        self.fmt_fields(f)?;
+        if let Some(source) = self.error.source() {
+            Debug::fmt(&source, f)?;
+        }
+        #[cfg(feature = "backtrace")]
+        if self.backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+            write!(f, "\nbacktrace:\n{}", self.backtrace)?;
         }
         Ok(())
     }
@@ -32,8 +260,9 @@ impl Debug for BoxedInstrumentedError {
 
 impl Display for BoxedInstrumentedError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self.0, f)?;
-        if let Some(source) = self.0.source() {
+        Display::fmt(&self.error, f)?;
+        self.fmt_fields(f)?;
+        if let Some(source) = self.error.source() {
             return Display::fmt(&source, f);
         }
         Ok(())
@@ -51,7 +280,13 @@ where
 {
     #[inline]
     fn from(val: E) -> Self {
-        BoxedInstrumentedError(Box::new(val.in_current_span()))
+        BoxedInstrumentedError {
+            error: Box::new(val.in_current_span()),
+            kind: ErrorKind::default(),
+            fields: vec![],
+            #[cfg(feature = "backtrace")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        }
     }
 }
 
@@ -77,11 +312,26 @@ pub trait IntoInstrumentedResult<T> {
 }
 
 impl IntoInstrumentedError for String {
+    fn into_instrumented_error(self) -> Error {
+        Cow::<'static, str>::Owned(self).into_instrumented_error()
+    }
+}
+
+/// Borrowed messages of any lifetime are copied, literals converted through
+/// `Cow::Borrowed` are not. `From<&str>` is not possible since the blanket `From`
+/// implementation above would conflict with it if `&str` ever implemented `Error`.
+impl IntoInstrumentedError for &str {
+    fn into_instrumented_error(self) -> Error {
+        Cow::<'static, str>::Owned(self.to_owned()).into_instrumented_error()
+    }
+}
+
+impl IntoInstrumentedError for Cow<'static, str> {
     fn into_instrumented_error(self) -> Error {
         use std::fmt;
 
         // This is the same implementation as Box<dyn Error> in the rust library
-        struct StringError(String);
+        struct StringError(Cow<'static, str>);
 
         impl std::error::Error for StringError {
             #[allow(deprecated)]
@@ -110,6 +360,36 @@ impl IntoInstrumentedError for String {
     }
 }
 
+/// `anyhow::Error` doesn't implement `std::error::Error`, but the blanket `From`
+/// implementation above still prevents a `From<anyhow::Error>`, so it converts through
+/// this trait instead, keeping its source chain
+#[cfg(feature = "anyhow")]
+impl IntoInstrumentedError for anyhow::Error {
+    fn into_instrumented_error(self) -> Error {
+        struct AnyhowError(anyhow::Error);
+
+        impl std::error::Error for AnyhowError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                self.0.source()
+            }
+        }
+
+        impl Display for AnyhowError {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                Display::fmt(&self.0, f)
+            }
+        }
+
+        impl Debug for AnyhowError {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                Debug::fmt(&self.0, f)
+            }
+        }
+
+        AnyhowError(self).into()
+    }
+}
+
 impl<T, E> IntoInstrumentedResult<T> for std::result::Result<T, E>
 where
     E: IntoInstrumentedError,
@@ -120,6 +400,155 @@ where
     }
 }
 
+/// Serializable description of a [`BoxedInstrumentedError`], to log it as JSON or hand it
+/// to privileged sinks. Use [`WireError`] for errors returned to callers.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// Message of the error
+    pub message: String,
+    /// Messages of the errors that caused it, outermost first
+    pub sources: Vec<String>,
+    /// Spans that were active when the error was instrumented
+    pub span_trace: Option<String>,
+    /// Metadata attached to the error
+    pub fields: Vec<(String, String)>,
+    /// Category of the error
+    pub kind: ErrorKind,
+}
+
+impl ErrorReport {
+    fn new(error: &BoxedInstrumentedError, unredacted: bool) -> Self {
+        let mut sources = vec![];
+        let mut source = error.error.source();
+        while let Some(current) = source {
+            // the span traces captured by tracing-error are chained as sources
+            if current.span_trace().is_none() {
+                sources.push(current.to_string());
+            }
+            source = current.source();
+        }
+        Self {
+            message: error.error.to_string(),
+            sources,
+            span_trace: error
+                .span_trace()
+                .map(ToString::to_string)
+                .filter(|trace| !trace.is_empty()),
+            fields: error
+                .fields
+                .iter()
+                .map(|field| (field.key.to_string(), field.value(unredacted).to_string()))
+                .collect(),
+            kind: error.kind,
+        }
+    }
+}
+
+impl From<&BoxedInstrumentedError> for ErrorReport {
+    fn from(error: &BoxedInstrumentedError) -> Self {
+        Self::new(error, false)
+    }
+}
+
+impl From<BoxedInstrumentedError> for ErrorReport {
+    fn from(error: BoxedInstrumentedError) -> Self {
+        Self::from(&error)
+    }
+}
+
+/// Extension trait to add context, a category or metadata to the error of a result, or
+/// to log it
+pub trait ResultExt<T> {
+    /// Wrap the error with `context`, see [`BoxedInstrumentedError::context`]
+    fn context<C: Display>(self, context: C) -> Result<T>;
+
+    /// Wrap the error with the context returned by `f`, which is only called on error
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T>;
+
+    /// Attach `kind` to the error, see [`BoxedInstrumentedError::with_kind`]
+    fn with_kind(self, kind: ErrorKind) -> Result<T>;
+
+    /// Attach metadata to the error, see [`BoxedInstrumentedError::with_field`]
+    fn with_field(self, key: impl Into<Cow<'static, str>>, value: impl Display) -> Result<T>;
+
+    /// Attach masked metadata, see [`BoxedInstrumentedError::with_sensitive_field`]
+    fn with_sensitive_field(
+        self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Display,
+    ) -> Result<T>;
+
+    /// Log the error where it is propagated, see [`BoxedInstrumentedError::log`]
+    fn log_err(self, level: tracing::Level) -> Result<T>;
+}
+
+impl<T, E> ResultExt<T> for std::result::Result<T, E>
+where
+    E: Into<Error>,
+{
+    #[inline]
+    fn context<C: Display>(self, context: C) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+
+    #[inline]
+    fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+
+    #[inline]
+    fn with_kind(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|e| e.into().with_kind(kind))
+    }
+
+    #[inline]
+    fn with_field(self, key: impl Into<Cow<'static, str>>, value: impl Display) -> Result<T> {
+        self.map_err(|e| e.into().with_field(key, value))
+    }
+
+    #[inline]
+    fn with_sensitive_field(
+        self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Display,
+    ) -> Result<T> {
+        self.map_err(|e| e.into().with_sensitive_field(key, value))
+    }
+
+    #[inline]
+    fn log_err(self, level: tracing::Level) -> Result<T> {
+        self.map_err(|e| {
+            let e = e.into();
+            e.log(level);
+            e
+        })
+    }
+}
+
+/// An error wrapped with the context it occurred in
+struct ContextError {
+    context: String,
+    source: Box<dyn std::error::Error + 'static + Send + Sync>,
+}
+
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+impl Display for ContextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl Debug for ContextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?}", self.context, self.source)
+    }
+}
+
 /// StdError implementation. Ideally, we would be able implement Error on
 /// `BoxedInstrumentedError` directly. However, the blanket From<E> implementation
 /// for `BoxedInstrumentedError` prevents us from doing this.
@@ -137,3 +566,146 @@ impl std::fmt::Display for BoxedInstrumentedStdError {
         Display::fmt(&self.0, f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Debug)]
+    struct IoError;
+
+    impl std::error::Error for IoError {}
+
+    impl Display for IoError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("connection reset")
+        }
+    }
+
+    fn fail() -> std::result::Result<(), IoError> {
+        Err(IoError)
+    }
+
+    #[test]
+    fn context_keeps_the_source_chain_and_kind() {
+        let error = fail()
+            .with_kind(ErrorKind::Network)
+            .context("fetching root key")
+            .with_context(|| format!("connecting to {}", "replica"))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Network);
+        assert!(error.is_retryable());
+        let report = error.report();
+        assert!(report.message.starts_with("connecting to replica"));
+        assert!(report
+            .sources
+            .iter()
+            .any(|source| source == "connection reset"));
+        assert_eq!(report.kind, ErrorKind::Network);
+    }
+
+    #[test]
+    fn only_network_errors_are_retryable() {
+        assert!(ErrorKind::Network.is_retryable());
+        assert!(!ErrorKind::CanisterReject.is_retryable());
+        assert!(!ErrorKind::default().is_retryable());
+        assert_eq!(ErrorKind::default(), ErrorKind::Internal);
+    }
+
+    #[test]
+    fn report_is_candid_encodable() {
+        let report = fail()
+            .with_field("canister_id", "aaaaa-aa")
+            .unwrap_err()
+            .report();
+        let bytes = candid::encode_one(&report).unwrap();
+        assert_eq!(candid::decode_one::<ErrorReport>(&bytes).unwrap(), report);
+        assert_eq!(
+            report.fields,
+            vec![("canister_id".to_string(), "aaaaa-aa".to_string())]
+        );
+    }
+
+    #[test]
+    fn span_trace_is_captured_in_spans() {
+        let subscriber = tracing_subscriber::registry().with(tracing_error::ErrorLayer::default());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("restore", offset = 7);
+            let error = span.in_scope(|| Error::from(IoError));
+            let trace = error.span_trace().expect("span trace").to_string();
+            assert!(trace.contains("restore"));
+            assert_eq!(error.report().span_trace, Some(trace));
+        });
+        assert_eq!(Error::from(IoError).report().span_trace, None);
+    }
+
+    #[test]
+    fn strings_convert_without_changing_the_message() {
+        let message = "missing module".into_instrumented_error();
+        assert_eq!(message.report().message, "missing module");
+        let owned = format!("missing {}", "module").into_instrumented_error();
+        assert_eq!(owned.report().message, "missing module");
+        let borrowed = String::from("missing module");
+        let borrowed = borrowed.as_str().into_instrumented_error();
+        assert_eq!(borrowed.report().message, "missing module");
+        let cow = Cow::Borrowed("missing module").into_instrumented_error();
+        assert_eq!(cow.kind(), ErrorKind::Internal);
+        let cow = Cow::<'static, str>::Owned(String::from("missing")).into_instrumented_error();
+        assert_eq!(cow.report().message, "missing");
+        let result: std::result::Result<(), _> = Err("missing module");
+        assert!(result.into_instrumented_result().is_err());
+    }
+
+    #[test]
+    fn fields_are_shown_and_sensitive_values_redacted() {
+        let error = fail()
+            .with_field("canister_id", "aaaaa-aa")
+            .with_sensitive_field("pem", "/secrets/id.pem")
+            .unwrap_err();
+        for shown in [error.to_string(), format!("{error:?}")] {
+            assert!(shown.contains("canister_id=aaaaa-aa"));
+            assert!(shown.contains(&format!("pem={REDACTED}")));
+            assert!(!shown.contains("/secrets/id.pem"));
+        }
+        assert_eq!(
+            error.fields().collect::<Vec<_>>(),
+            vec![("canister_id", "aaaaa-aa"), ("pem", REDACTED)]
+        );
+        assert_eq!(
+            error.unredacted_fields().nth(1),
+            Some(("pem", "/secrets/id.pem"))
+        );
+        assert_eq!(error.report().fields[1].1, REDACTED);
+        assert_eq!(error.unredacted_report().fields[1].1, "/secrets/id.pem");
+    }
+
+    #[test]
+    fn log_err_returns_the_error_unchanged() {
+        let error = fail()
+            .with_kind(ErrorKind::Decode)
+            .log_err(tracing::Level::WARN)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Decode);
+        assert_eq!(error.report().message, "connection reset");
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn anyhow_round_trip_keeps_the_message() {
+        let error = anyhow::anyhow!("quota exceeded").into_instrumented_error();
+        assert_eq!(error.report().message, "quota exceeded");
+        let anyhow_error = fail().context("saving").unwrap_err().into_anyhow();
+        assert!(anyhow_error.to_string().starts_with("saving"));
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn backtrace_is_captured_on_conversion() {
+        let error = Error::from(IoError);
+        assert_ne!(
+            error.backtrace().status(),
+            std::backtrace::BacktraceStatus::Unsupported
+        );
+    }
+}
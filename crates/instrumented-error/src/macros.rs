@@ -0,0 +1,77 @@
+//! Macros returning instrumented errors early.
+
+/// Return early with an instrumented error capturing the current span, built from a
+/// format string or converted from an error
+///
+/// ```ignore
+/// bail!("canister {canister_id} has no module");
+/// bail!("expected {} bytes, got {}", expected, actual);
+/// bail!(io_error);
+/// ```
+#[macro_export]
+macro_rules! bail {
+    ($msg:literal $(,)?) => {
+        return ::core::result::Result::Err($crate::IntoInstrumentedError::into_instrumented_error(
+            ::std::format!($msg),
+        ))
+    };
+    ($err:expr $(,)?) => {
+        return ::core::result::Result::Err(::core::convert::Into::<$crate::Error>::into($err))
+    };
+    ($fmt:literal, $($arg:tt)*) => {
+        return ::core::result::Result::Err($crate::IntoInstrumentedError::into_instrumented_error(
+            ::std::format!($fmt, $($arg)*),
+        ))
+    };
+}
+
+/// Return early with an instrumented error if the condition doesn't hold, see [`bail!`]
+///
+/// ```ignore
+/// ensure!(bytes.len() >= len, "truncated section of {len} bytes");
+/// ```
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            return ::core::result::Result::Err(
+                $crate::IntoInstrumentedError::into_instrumented_error(::core::concat!(
+                    "condition failed: ",
+                    ::core::stringify!($cond)
+                )),
+            );
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::bail!($($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ErrorKind, Result};
+
+    fn check_len(bytes: &[u8], len: usize) -> Result<usize> {
+        crate::ensure!(!bytes.is_empty());
+        crate::ensure!(bytes.len() >= len, "truncated section of {len} bytes");
+        if bytes[0] == 0 {
+            crate::bail!("empty section");
+        }
+        if bytes[0] == 1 {
+            crate::bail!(std::fmt::Error);
+        }
+        Ok(len)
+    }
+
+    #[test]
+    fn ensure_and_bail_return_early() {
+        assert_eq!(check_len(&[2, 2], 2).unwrap(), 2);
+        let message = |bytes: &[u8], len| check_len(bytes, len).unwrap_err().report().message;
+        assert_eq!(message(&[], 0), "condition failed: !bytes.is_empty()");
+        assert_eq!(message(&[2], 4), "truncated section of 4 bytes");
+        assert_eq!(message(&[0], 1), "empty section");
+        assert_eq!(check_len(&[1], 1).unwrap_err().kind(), ErrorKind::Internal);
+    }
+}
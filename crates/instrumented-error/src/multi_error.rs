@@ -0,0 +1,173 @@
+//! Aggregation of the errors of batch operations.
+
+use crate::{Error, ErrorKind, Result};
+use std::fmt::{Debug, Display, Formatter};
+use tracing_error::SpanTraceStatus;
+
+/// Collector of the errors of a batch operation, such as a fan-out to several canisters,
+/// that should fail as a whole once every item was attempted
+#[derive(Default)]
+pub struct MultiError {
+    errors: Vec<Error>,
+}
+
+impl MultiError {
+    /// Create an empty collector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a failure
+    pub fn push(&mut self, error: impl Into<Error>) {
+        self.errors.push(error.into());
+    }
+
+    /// Add the failure of `result` if any, returning its value otherwise
+    pub fn push_result<T, E: Into<Error>>(
+        &mut self,
+        result: std::result::Result<T, E>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.push(e);
+                None
+            }
+        }
+    }
+
+    /// Return the number of failures
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// Return whether nothing failed
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Return the failures
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Succeed if nothing failed, fail with the only failure or with an error enumerating
+    /// every failure otherwise
+    pub fn into_result(mut self) -> Result<()> {
+        match self.errors.len() {
+            0 => Ok(()),
+            1 => Err(self.errors.remove(0)),
+            _ => {
+                let kind = self.kind();
+                Err(Error::from(AggregateError(self.errors)).with_kind(kind))
+            }
+        }
+    }
+
+    /// The category shared by every failure, if any
+    fn kind(&self) -> ErrorKind {
+        let mut kinds = self.errors.iter().map(Error::kind);
+        let first = kinds.next().unwrap_or_default();
+        if kinds.all(|kind| kind == first) {
+            first
+        } else {
+            ErrorKind::Internal
+        }
+    }
+}
+
+impl<E: Into<Error>> Extend<E> for MultiError {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, iter: I) {
+        self.errors.extend(iter.into_iter().map(Into::into));
+    }
+}
+
+impl<E: Into<Error>> FromIterator<E> for MultiError {
+    fn from_iter<I: IntoIterator<Item = E>>(iter: I) -> Self {
+        let mut errors = Self::new();
+        errors.extend(iter);
+        errors
+    }
+}
+
+/// The failures of a [`MultiError`]
+struct AggregateError(Vec<Error>);
+
+impl std::error::Error for AggregateError {}
+
+impl Display for AggregateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} errors occurred:", self.0.len())?;
+        for (i, error) in self.0.iter().enumerate() {
+            write!(f, "\n{i}: {}", error.error)?;
+            if let Some(trace) = error.span_trace() {
+                if trace.status() == SpanTraceStatus::CAPTURED {
+                    write!(f, "\n{trace}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for AggregateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.0).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntoInstrumentedError;
+
+    #[test]
+    fn into_result_aggregates_the_failures() {
+        assert!(MultiError::new().into_result().is_ok());
+
+        let mut errors = MultiError::new();
+        assert_eq!(errors.push_result(Ok::<_, Error>(1)), Some(1));
+        errors.push(
+            "first"
+                .into_instrumented_error()
+                .with_kind(ErrorKind::Network),
+        );
+        assert_eq!(errors.len(), 1);
+        let only = errors.into_result().unwrap_err();
+        assert_eq!(only.report().message, "first");
+
+        let mut errors: MultiError = ["first", "second"]
+            .into_iter()
+            .map(|message| {
+                message
+                    .into_instrumented_error()
+                    .with_kind(ErrorKind::Network)
+            })
+            .collect();
+        let error = errors.push_result(Err::<(), _>(
+            "third"
+                .into_instrumented_error()
+                .with_kind(ErrorKind::Network),
+        ));
+        assert_eq!(error, None);
+        let error = errors.into_result().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Network);
+        let message = error.report().message;
+        assert!(message.starts_with("3 errors occurred:"));
+        assert!(message.contains("\n0: first") && message.contains("\n2: third"));
+    }
+
+    #[test]
+    fn mixed_kinds_are_internal() {
+        let errors: MultiError = [
+            "a".into_instrumented_error().with_kind(ErrorKind::Network),
+            "b".into_instrumented_error().with_kind(ErrorKind::Auth),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            errors.into_result().unwrap_err().kind(),
+            ErrorKind::Internal
+        );
+    }
+}
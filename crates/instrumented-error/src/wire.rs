@@ -0,0 +1,149 @@
+//! Compact errors returned across canister boundaries.
+
+use crate::{Error, ErrorKind};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Candid encodable error returned by canister methods. Only keeps the category and the
+/// message of a [`crate::BoxedInstrumentedError`], dropping its sources and span trace
+/// so no internal details leave the canister.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct WireError {
+    /// Code of the [`ErrorKind`] of the error
+    pub code: u16,
+    /// Message of the error
+    pub message: String,
+}
+
+impl WireError {
+    /// Create an error of `kind`
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            code: kind.wire_code(),
+            message: message.into(),
+        }
+    }
+
+    /// Return the category of the error, unknown codes being internal errors
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::from_wire_code(self.code).unwrap_or_default()
+    }
+}
+
+impl ErrorKind {
+    /// Code of the category in a [`WireError`]
+    pub fn wire_code(&self) -> u16 {
+        match self {
+            Self::Internal => 0,
+            Self::Network => 1,
+            Self::CanisterReject => 2,
+            Self::Decode => 3,
+            Self::Config => 4,
+            Self::Auth => 5,
+        }
+    }
+
+    /// Return the category of a [`WireError`] code
+    pub fn from_wire_code(code: u16) -> Option<Self> {
+        Some(match code {
+            0 => Self::Internal,
+            1 => Self::Network,
+            2 => Self::CanisterReject,
+            3 => Self::Decode,
+            4 => Self::Config,
+            5 => Self::Auth,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for WireError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message)
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl From<&Error> for WireError {
+    fn from(error: &Error) -> Self {
+        Self {
+            code: error.kind.wire_code(),
+            message: error.error.to_string(),
+        }
+    }
+}
+
+impl From<Error> for WireError {
+    fn from(error: Error) -> Self {
+        Self::from(&error)
+    }
+}
+
+/// Helper trait to strip the details of an error before returning it from a canister
+pub trait IntoWireResult<T> {
+    /// Convert the error to a [`WireError`]
+    fn into_wire_result(self) -> std::result::Result<T, WireError>;
+}
+
+impl<T, E> IntoWireResult<T> for std::result::Result<T, E>
+where
+    E: Into<Error>,
+{
+    #[inline]
+    fn into_wire_result(self) -> std::result::Result<T, WireError> {
+        self.map_err(|e| WireError::from(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IntoInstrumentedError;
+
+    const KINDS: [ErrorKind; 6] = [
+        ErrorKind::Network,
+        ErrorKind::CanisterReject,
+        ErrorKind::Decode,
+        ErrorKind::Config,
+        ErrorKind::Auth,
+        ErrorKind::Internal,
+    ];
+
+    #[test]
+    fn wire_codes_round_trip() {
+        for kind in KINDS {
+            assert_eq!(ErrorKind::from_wire_code(kind.wire_code()), Some(kind));
+        }
+        assert_eq!(ErrorKind::from_wire_code(99), None);
+        assert_eq!(
+            WireError {
+                code: 99,
+                message: String::new()
+            }
+            .kind(),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn wire_error_drops_the_details() {
+        let result: std::result::Result<(), _> = Err("quota exceeded"
+            .into_instrumented_error()
+            .context("saving")
+            .with_kind(ErrorKind::CanisterReject)
+            .with_sensitive_field("token", "secret"));
+        let wire = result.into_wire_result().unwrap_err();
+        assert_eq!(wire.kind(), ErrorKind::CanisterReject);
+        assert!(wire.message.starts_with("saving: quota exceeded"));
+        assert!(!wire.message.contains("secret"));
+
+        let bytes = candid::encode_one(&wire).unwrap();
+        assert_eq!(candid::decode_one::<WireError>(&bytes).unwrap(), wire);
+        assert_eq!(
+            WireError::new(ErrorKind::Auth, "denied").to_string(),
+            "auth: denied"
+        );
+    }
+}